@@ -0,0 +1,11 @@
+#ifndef HILOW_MATH_H
+#define HILOW_MATH_H
+
+#include <stdint.h>
+#include <stdbool.h>
+
+int32_t add(int32_t a, int32_t b);
+int32_t multiply(int32_t a, int32_t b);
+int32_t subtract(int32_t a, int32_t b);
+
+#endif // HILOW_MATH_H
@@ -1,15 +1,23 @@
+use crate::error::Span;
 use std::fmt;
 use std::collections::HashSet;
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum FStringPart {
     Text(String),
-    Expression(Box<Expression>),
+    /// An interpolated expression and the format spec after its `:`, if any
+    Expression(Box<Expression>, Option<String>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Program {
     pub statements: Vec<Statement>,
+    /// Where each statement of the parsed file starts; imported ones take
+    /// the position of their `import`
+    pub spans: Vec<Span>,
+    /// The file the program was parsed from
+    pub source: Option<Rc<str>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -18,14 +26,23 @@ pub enum Statement {
         name: String,
         params: Vec<Parameter>,
         return_type: Option<Type>,
+        /// Preconditions, checked on entry
+        requires: Vec<Expression>,
+        /// Postconditions, checked before each return with `result` bound
+        ensures: Vec<Expression>,
         body: Block,
         is_export: bool,
+        /// Declared `pure`: no side effects, so calls with constant
+        /// arguments can be evaluated at compile time
+        is_pure: bool,
     },
     VariableDecl {
         name: String,
         var_type: Option<Type>,
         initializer: Option<Expression>,
         is_export: bool,
+        /// Declared `let mut`, so it may be assigned after its declaration
+        is_mut: bool,
     },
     Import {
         names: Vec<String>,
@@ -66,17 +83,38 @@ pub enum Statement {
         statement: Box<Statement>,
     },
     Block(Block),
+    /// `struct Name { field: type, ... }`, a record type usable by name
+    StructDecl {
+        name: String,
+        fields: Vec<Parameter>,
+        /// `function`s declared in the body, called as `value.method()`.
+        /// Each is a FunctionDecl whose first parameter is `self`.
+        methods: Vec<Statement>,
+        is_export: bool,
+    },
+    /// `enum Name { A, B, ... }`; the variants are written `Name.A`
+    EnumDecl {
+        name: String,
+        variants: Vec<String>,
+        is_export: bool,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SwitchCase {
     pub value: Expression,
     pub body: Block,
+    /// Ends in `fallthrough`, so control continues into the next case
+    pub fallthrough: bool,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct Block {
     pub statements: Vec<Statement>,
+    /// Where each statement starts, when the parser saw it
+    pub spans: Vec<Span>,
+    /// The file those lines are in
+    pub source: Option<Rc<str>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -112,9 +150,12 @@ pub enum Type {
         return_type: Box<Type>,
     },
     Object,
+    /// A type declared with `struct` or `enum`
+    Named(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum Expression {
     IntegerLiteral(i64),
     FloatLiteral(f64),
@@ -123,6 +164,7 @@ pub enum Expression {
         parts: Vec<FStringPart>,
     },
     BooleanLiteral(bool),
+    CharLiteral(char),
     NothingLiteral,
     Identifier(String),
     Binary {
@@ -137,6 +179,9 @@ pub enum Expression {
     Call {
         callee: Box<Expression>,
         args: Vec<Expression>,
+        /// The type of the result, when type checking resolved it; see
+        /// `sema::record_types`
+        resolved_type: Option<Type>,
     },
     Assignment {
         target: Box<Expression>,
@@ -145,6 +190,11 @@ pub enum Expression {
     ArrayLiteral {
         elements: Vec<Expression>,
     },
+    /// `[value; count]`
+    ArrayRepeat {
+        value: Box<Expression>,
+        count: Box<Expression>,
+    },
     Index {
         array: Box<Expression>,
         index: Box<Expression>,
@@ -160,6 +210,8 @@ pub enum Expression {
         object: Box<Expression>,
         method: String,
         args: Vec<Expression>,
+        /// As for `Call`
+        resolved_type: Option<Type>,
     },
     FunctionExpression {
         params: Vec<Parameter>,
@@ -174,6 +226,22 @@ pub enum Expression {
         expr: Box<Expression>,
         target_type: Type,
     },
+    Block {
+        body: Block,
+        value: Box<Expression>,
+    },
+    /// `condition ? then : otherwise`
+    Conditional {
+        condition: Box<Expression>,
+        then: Box<Expression>,
+        otherwise: Box<Expression>,
+    },
+    /// `start..end`, or `start..=end` when `inclusive`
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -186,6 +254,8 @@ pub struct MatchArm {
 pub enum MatchPattern {
     Literal(Expression),
     Wildcard,
+    /// Matches anything and binds the scrutinee to the name within the arm
+    Binding(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -269,7 +339,230 @@ impl fmt::Display for Type {
                 write!(f, "): {}", return_type)
             }
             Type::Object => write!(f, "object"),
+            Type::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Read-only traversal of the AST.
+///
+/// Every `visit_*` method defaults to walking into its children, so an
+/// implementation only overrides the nodes it cares about. An override that
+/// still wants to descend calls the matching `walk_*` function.
+pub trait Visitor<'ast> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        walk_statement(self, stmt);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        walk_expression(self, expr);
+    }
+
+    /// Called for every type annotation written in the program
+    fn visit_type(&mut self, _ty: &'ast Type) {}
+
+    /// Called before each statement the parser recorded a position for,
+    /// with the file the statement is in
+    fn visit_position(&mut self, _source: Option<&'ast Rc<str>>, _span: Span) {}
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, program: &'ast Program) {
+    for (i, stmt) in program.statements.iter().enumerate() {
+        if let Some(span) = program.spans.get(i) {
+            visitor.visit_position(program.source.as_ref(), *span);
         }
+        visitor.visit_statement(stmt);
+    }
+}
+
+pub fn walk_block<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, block: &'ast Block) {
+    for (i, stmt) in block.statements.iter().enumerate() {
+        if let Some(span) = block.spans.get(i) {
+            visitor.visit_position(block.source.as_ref(), *span);
+        }
+        visitor.visit_statement(stmt);
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, stmt: &'ast Statement) {
+    match stmt {
+        Statement::FunctionDecl { params, return_type, requires, ensures, body, .. } => {
+            for param in params {
+                visitor.visit_type(&param.param_type);
+            }
+            if let Some(ty) = return_type {
+                visitor.visit_type(ty);
+            }
+            for condition in requires.iter().chain(ensures) {
+                visitor.visit_expression(condition);
+            }
+            visitor.visit_block(body);
+        }
+        Statement::VariableDecl { var_type, initializer, .. } => {
+            if let Some(ty) = var_type {
+                visitor.visit_type(ty);
+            }
+            if let Some(init) = initializer {
+                visitor.visit_expression(init);
+            }
+        }
+        Statement::Return { value: Some(expr) } | Statement::Expression(expr) => {
+            visitor.visit_expression(expr);
+        }
+        Statement::If { condition, then_branch, else_branch } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(then_branch);
+            if let Some(else_stmt) = else_branch {
+                visitor.visit_statement(else_stmt);
+            }
+        }
+        Statement::While { condition, body } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(body);
+        }
+        Statement::For { init, condition, increment, body } => {
+            if let Some(init_stmt) = init {
+                visitor.visit_statement(init_stmt);
+            }
+            if let Some(cond) = condition {
+                visitor.visit_expression(cond);
+            }
+            if let Some(inc) = increment {
+                visitor.visit_expression(inc);
+            }
+            visitor.visit_block(body);
+        }
+        Statement::ForIn { iterable, body, .. } => {
+            visitor.visit_expression(iterable);
+            visitor.visit_block(body);
+        }
+        Statement::Switch { expr, cases, default } => {
+            visitor.visit_expression(expr);
+            for case in cases {
+                visitor.visit_expression(&case.value);
+                visitor.visit_block(&case.body);
+            }
+            if let Some(block) = default {
+                visitor.visit_block(block);
+            }
+        }
+        Statement::Defer { statement } => visitor.visit_statement(statement),
+        Statement::Block(block) => visitor.visit_block(block),
+        Statement::StructDecl { fields, methods, .. } => {
+            for field in fields {
+                visitor.visit_type(&field.param_type);
+            }
+            for method in methods {
+                visitor.visit_statement(method);
+            }
+        }
+        Statement::Return { value: None }
+        | Statement::EnumDecl { .. }
+        | Statement::Import { .. }
+        | Statement::Break
+        | Statement::Continue => {}
+    }
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expr: &'ast Expression) {
+    match expr {
+        Expression::FString { parts } => {
+            for part in parts {
+                if let FStringPart::Expression(e, _) = part {
+                    visitor.visit_expression(e);
+                }
+            }
+        }
+        Expression::Binary { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::Unary { operand, .. } => visitor.visit_expression(operand),
+        Expression::Call { callee, args, .. } => {
+            visitor.visit_expression(callee);
+            for arg in args {
+                visitor.visit_expression(arg);
+            }
+        }
+        Expression::Assignment { target, value } => {
+            visitor.visit_expression(target);
+            visitor.visit_expression(value);
+        }
+        Expression::ArrayLiteral { elements } => {
+            for elem in elements {
+                visitor.visit_expression(elem);
+            }
+        }
+        Expression::ArrayRepeat { value, count } => {
+            visitor.visit_expression(value);
+            visitor.visit_expression(count);
+        }
+        Expression::Index { array, index } => {
+            visitor.visit_expression(array);
+            visitor.visit_expression(index);
+        }
+        Expression::ObjectLiteral { properties } => {
+            for prop in properties {
+                visitor.visit_expression(&prop.value);
+            }
+        }
+        Expression::PropertyAccess { object, .. } => visitor.visit_expression(object),
+        Expression::MethodCall { object, args, .. } => {
+            visitor.visit_expression(object);
+            for arg in args {
+                visitor.visit_expression(arg);
+            }
+        }
+        Expression::FunctionExpression { params, return_type, body } => {
+            for param in params {
+                visitor.visit_type(&param.param_type);
+            }
+            if let Some(ty) = return_type {
+                visitor.visit_type(ty);
+            }
+            visitor.visit_block(body);
+        }
+        Expression::Match { expr, arms } => {
+            visitor.visit_expression(expr);
+            for arm in arms {
+                if let MatchPattern::Literal(pattern) = &arm.pattern {
+                    visitor.visit_expression(pattern);
+                }
+                visitor.visit_expression(&arm.body);
+            }
+        }
+        Expression::Cast { expr, target_type } => {
+            visitor.visit_expression(expr);
+            visitor.visit_type(target_type);
+        }
+        Expression::Block { body, value } => {
+            visitor.visit_block(body);
+            visitor.visit_expression(value);
+        }
+        Expression::Conditional { condition, then, otherwise } => {
+            visitor.visit_expression(condition);
+            visitor.visit_expression(then);
+            visitor.visit_expression(otherwise);
+        }
+        Expression::Range { start, end, .. } => {
+            visitor.visit_expression(start);
+            visitor.visit_expression(end);
+        }
+        Expression::IntegerLiteral(_)
+        | Expression::FloatLiteral(_)
+        | Expression::StringLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::CharLiteral(_)
+        | Expression::NothingLiteral
+        | Expression::Identifier(_) => {}
     }
 }
 
@@ -279,10 +572,8 @@ impl Expression {
         let mut free = HashSet::new();
 
         match self {
-            Expression::Identifier(name) => {
-                if !bound_vars.contains(name) {
-                    free.insert(name.clone());
-                }
+            Expression::Identifier(name) if !bound_vars.contains(name) => {
+                free.insert(name.clone());
             }
             Expression::Binary { left, right, .. } => {
                 free.extend(left.find_free_variables(bound_vars));
@@ -291,7 +582,7 @@ impl Expression {
             Expression::Unary { operand, .. } => {
                 free.extend(operand.find_free_variables(bound_vars));
             }
-            Expression::Call { callee, args } => {
+            Expression::Call { callee, args, .. } => {
                 free.extend(callee.find_free_variables(bound_vars));
                 for arg in args {
                     free.extend(arg.find_free_variables(bound_vars));
@@ -319,6 +610,19 @@ impl Expression {
                     free.extend(elem.find_free_variables(bound_vars));
                 }
             }
+            Expression::ArrayRepeat { value, count } => {
+                free.extend(value.find_free_variables(bound_vars));
+                free.extend(count.find_free_variables(bound_vars));
+            }
+            Expression::Conditional { condition, then, otherwise } => {
+                free.extend(condition.find_free_variables(bound_vars));
+                free.extend(then.find_free_variables(bound_vars));
+                free.extend(otherwise.find_free_variables(bound_vars));
+            }
+            Expression::Range { start, end, .. } => {
+                free.extend(start.find_free_variables(bound_vars));
+                free.extend(end.find_free_variables(bound_vars));
+            }
             Expression::ObjectLiteral { properties } => {
                 for prop in properties {
                     free.extend(prop.value.find_free_variables(bound_vars));
@@ -326,7 +630,7 @@ impl Expression {
             }
             Expression::FString { parts } => {
                 for part in parts {
-                    if let FStringPart::Expression(expr) = part {
+                    if let FStringPart::Expression(expr, _) = part {
                         free.extend(expr.find_free_variables(bound_vars));
                     }
                 }
@@ -339,7 +643,41 @@ impl Expression {
                 }
                 free.extend(body.find_free_variables(&new_bound));
             }
-            _ => {}
+            Expression::Match { expr, arms } => {
+                free.extend(expr.find_free_variables(bound_vars));
+                for arm in arms {
+                    match &arm.pattern {
+                        MatchPattern::Binding(name) => {
+                            let mut arm_bound = bound_vars.clone();
+                            arm_bound.insert(name.clone());
+                            free.extend(arm.body.find_free_variables(&arm_bound));
+                        }
+                        _ => free.extend(arm.body.find_free_variables(bound_vars)),
+                    }
+                }
+            }
+            Expression::Block { body, value } => {
+                // The value can see everything declared in the block
+                let mut local_bound = bound_vars.clone();
+                for stmt in &body.statements {
+                    free.extend(stmt.find_free_variables(&local_bound));
+                    if let Statement::VariableDecl { name, .. } = stmt {
+                        local_bound.insert(name.clone());
+                    }
+                }
+                free.extend(value.find_free_variables(&local_bound));
+            }
+            Expression::Cast { expr, .. } => {
+                free.extend(expr.find_free_variables(bound_vars));
+            }
+            // Spelled out so a new variant can't silently lose captures
+            Expression::Identifier(_)
+            | Expression::IntegerLiteral(_)
+            | Expression::FloatLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::CharLiteral(_)
+            | Expression::NothingLiteral => {}
         }
 
         free
@@ -369,15 +707,11 @@ impl Statement {
         let mut free = HashSet::new();
 
         match self {
-            Statement::VariableDecl { initializer, .. } => {
-                if let Some(init) = initializer {
-                    free.extend(init.find_free_variables(bound_vars));
-                }
+            Statement::VariableDecl { initializer: Some(init), .. } => {
+                free.extend(init.find_free_variables(bound_vars));
             }
-            Statement::Return { value } => {
-                if let Some(val) = value {
-                    free.extend(val.find_free_variables(bound_vars));
-                }
+            Statement::Return { value: Some(val) } => {
+                free.extend(val.find_free_variables(bound_vars));
             }
             Statement::Expression(expr) => {
                 free.extend(expr.find_free_variables(bound_vars));
@@ -442,3 +776,41 @@ impl Statement {
         free
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    struct CallCounter {
+        calls: usize,
+    }
+
+    impl<'ast> Visitor<'ast> for CallCounter {
+        fn visit_expression(&mut self, expr: &'ast Expression) {
+            if let Expression::Call { .. } = expr {
+                self.calls += 1;
+            }
+            walk_expression(self, expr);
+        }
+    }
+
+    #[test]
+    fn test_visitor_counts_calls() {
+        let source = "function double(x: i32): i32 { return add(x, x); }
+            function main(): i32 {
+                let fs: string = f\"{double(1)}\";
+                for (let i: i32 = 0; i < count(); i = i + 1) {
+                    print(fs);
+                }
+                return match (double(add(1, 2))) { 6 => 0, _ => 1 };
+            }";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        let mut counter = CallCounter { calls: 0 };
+        counter.visit_program(&program);
+        assert_eq!(counter.calls, 6);
+    }
+}
@@ -1,36 +1,174 @@
+mod scope;
+pub mod target;
+
 use crate::ast::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use scope::Scopes;
+use target::Target;
+
+/// Settings that shape the generated C and how it is built
+#[derive(Debug, Clone)]
+pub struct CompileOptions {
+    pub optimization: u8,
+    pub target: Target,
+    /// How many rounds of function inlining to run at -O2 and above
+    pub max_inline_depth: usize,
+    /// `log` calls below this level are compiled out
+    pub log_level: LogLevel,
+    /// Run HiLow's own AST passes; when off, `optimization` only reaches GCC
+    pub optimize_ast: bool,
+    /// The function the program starts in; anything but `main` gets a
+    /// generated C `main` that calls it
+    pub entry: String,
+    /// The C compiler to build the generated code with
+    pub cc: String,
+    /// Extra flags passed to the C compiler after HiLow's own
+    pub cflags: Vec<String>,
+    /// Bindings not declared `let mut` are emitted as `const`; sema
+    /// rejects assigning them first
+    pub strict_mutability: bool,
+    /// Track the HiLow call stack at runtime so a failed assert or a
+    /// `panic` prints a backtrace, and bounds-check array indexing
+    pub checked: bool,
+    /// Leave the generated C at `<output>.c` after building
+    pub keep_c: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            optimization: 0,
+            target: Target::default(),
+            max_inline_depth: 3,
+            log_level: LogLevel::Debug,
+            optimize_ast: true,
+            entry: "main".to_string(),
+            cc: "gcc".to_string(),
+            cflags: Vec::new(),
+            strict_mutability: false,
+            checked: false,
+            keep_c: false,
+        }
+    }
+}
+
+/// Severity of a `log(level, message)` call, from most to least verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
 
-#[derive(Clone)]
-struct LambdaInfo {
-    name: String,
-    captured_vars: HashSet<String>,
-    context_struct: String,
+impl LogLevel {
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "debug" => Ok(LogLevel::Debug),
+            "info" => Ok(LogLevel::Info),
+            "warn" => Ok(LogLevel::Warn),
+            "error" => Ok(LogLevel::Error),
+            _ => Err(format!(
+                "Unknown log level '{}' (expected debug, info, warn or error)",
+                name
+            )),
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "DEBUG",
+            LogLevel::Info => "INFO",
+            LogLevel::Warn => "WARN",
+            LogLevel::Error => "ERROR",
+        }
+    }
 }
 
+// An interpolated f-string expression and its format spec
+type FormatArgument<'e> = (&'e Expression, Option<&'e str>);
+
 pub struct CodeGenerator {
     output: String,
     indent_level: usize,
-    variables: HashMap<String, String>,
+    // C type of each variable in scope
+    variables: Scopes<String>,
+    // C element type of each dynamic array variable
+    array_element_types: Scopes<String>,
+    // Annotated HiLow type of each local and parameter
+    declared_types: Scopes<Type>,
+    // Names of the locals some closure assigns; see shared_captures
+    shared_captures: HashSet<String>,
+    // Locals reached through a C pointer, mapped to the pointer's name:
+    // a heap cell `__cell_<name>` for a local some closure assigns, so the
+    // closure and the function share one value, and a method's `self`
+    pointers: Scopes<String>,
+    // The functions struct methods become, `<Struct>_<method>`, whose
+    // first parameter is a pointer to the receiver
+    methods: HashSet<String>,
+    // Element types whose push/pop helpers are generated on demand
+    array_helper_types: BTreeSet<String>,
+    // Callback helpers (map, filter, reduce, forEach, find) other than the
+    // preamble's i32 ones, by operation, element C type and the C type
+    // the callback gives back
+    callback_helpers: BTreeSet<(String, String, String)>,
+    // C return type of each top-level function, for inferring `let x = f()`
+    function_return_types: HashMap<String, String>,
+    // Every function type in the program; the one at index N is lowered
+    // to the typedef `__fn_type_N`
+    function_types: Vec<Type>,
+    // Field names and C types of each object literal shape; the one at
+    // index N is the typedef `__Obj_N`
+    object_shapes: Vec<Vec<(String, String)>>,
+    // Field names and types of each declared struct
+    struct_fields: HashMap<String, Vec<(String, Type)>>,
+    // Names of the declared enums; variant `V` of enum `E` is the C
+    // constant `E_V`
+    enum_names: HashSet<String>,
     lambda_counter: usize,
+    // Suffix for the next compiler temporary, see fresh_temp
+    temp_counter: usize,
     lambda_functions: Vec<String>,
-    lambda_info: Vec<LambdaInfo>,
     defer_stack: Vec<Vec<Statement>>,
+    // defer_stack depth at entry to each enclosing breakable/continuable
+    // construct, so `break`/`continue` run the defers they jump past
+    break_defer_depths: Vec<usize>,
+    continue_defer_depths: Vec<usize>,
+    // Postconditions of the function being generated, checked at each return
+    current_ensures: Vec<Expression>,
+    options: CompileOptions,
 }
 
 impl CodeGenerator {
-    pub fn new() -> Self {
+    pub fn new(options: CompileOptions) -> Self {
         CodeGenerator {
             output: String::new(),
             indent_level: 0,
-            variables: HashMap::new(),
+            variables: Scopes::new(),
+            array_element_types: Scopes::new(),
+            declared_types: Scopes::new(),
+            shared_captures: HashSet::new(),
+            pointers: Scopes::new(),
+            methods: HashSet::new(),
+            array_helper_types: BTreeSet::new(),
+            callback_helpers: BTreeSet::new(),
+            function_return_types: HashMap::new(),
+            function_types: Vec::new(),
+            object_shapes: Vec::new(),
+            struct_fields: HashMap::new(),
+            enum_names: HashSet::new(),
             lambda_counter: 0,
+            temp_counter: 0,
             lambda_functions: Vec::new(),
-            lambda_info: Vec::new(),
             defer_stack: vec![Vec::new()],
+            break_defer_depths: Vec::new(),
+            continue_defer_depths: Vec::new(),
+            current_ensures: Vec::new(),
+            options,
         }
     }
 
@@ -44,13 +182,34 @@ impl CodeGenerator {
         self.output.push('\n');
     }
 
+    /// A name for a compiler temporary, distinct from every other
+    /// temporary and from any user name in scope
+    fn fresh_temp(&mut self, hint: &str) -> String {
+        loop {
+            let name = format!("__{}_{}", hint, self.temp_counter);
+            self.temp_counter += 1;
+            if !self.variables.contains_key(&name) && !self.function_return_types.contains_key(&name) {
+                return name;
+            }
+        }
+    }
+
     fn emit_no_indent(&mut self, code: &str) {
         self.output.push_str(code);
     }
 
     pub fn generate(&mut self, program: &Program) -> Result<String, String> {
+        self.check_target_support(program)?;
+        self.check_entry_point(program)?;
+        let program = &self.hoist_methods(program);
+        self.shared_captures = shared_captures(program);
+
         // Generate C preamble
-        self.emit("#define _GNU_SOURCE");
+        if self.options.target.has_gnu_libc {
+            self.emit("#define _GNU_SOURCE");
+        } else {
+            self.emit("#define _POSIX_C_SOURCE 200809L");
+        }
         self.emit("#include <stdio.h>");
         self.emit("#include <stdlib.h>");
         self.emit("#include <stdint.h>");
@@ -58,7 +217,13 @@ impl CodeGenerator {
         self.emit("#include <string.h>");
         self.emit("#include <ctype.h>");
         self.emit("#include <math.h>");
+        self.emit("#include <errno.h>");
+        self.emit("#include <stdarg.h>");
+        if program.statements.iter().any(has_contracts) {
+            self.emit("#include <assert.h>");
+        }
         self.emit("");
+        self.emit_call_stack_support();
 
         // Generate unknown type structure
         self.emit("// Unknown type structure");
@@ -68,7 +233,7 @@ impl CodeGenerator {
         self.emit("    int32_t option_count;");
         self.emit("} Unknown;");
         self.emit("");
-        self.emit("Unknown* create_unknown(const char* reason) {");
+        self.emit("static Unknown* create_unknown(const char* reason) {");
         self.emit("    Unknown* u = malloc(sizeof(Unknown));");
         self.emit("    u->reason = strdup(reason);");
         self.emit("    u->options = NULL;");
@@ -77,6 +242,33 @@ impl CodeGenerator {
         self.emit("}");
         self.emit("");
 
+        // A function value: capturing lambdas take their context as a
+        // leading `void* env`, plain functions have a NULL env
+        self.emit("typedef struct {");
+        self.emit("    void* fn;");
+        self.emit("    void* env;");
+        self.emit("} Closure;");
+        self.emit("");
+        self.emit("static Closure* closure_new(void* fn, void* env) {");
+        self.emit("    Closure* closure = malloc(sizeof(Closure));");
+        self.emit("    closure->fn = fn;");
+        self.emit("    closure->env = env;");
+        self.emit("    return closure;");
+        self.emit("}");
+        self.emit("");
+        // Callbacks of the i32 array helpers (map, filter, reduce, forEach)
+        self.emit("typedef int32_t (*array_callback_i32)(int32_t, int32_t);");
+        self.emit("typedef int32_t (*array_callback_i32_env)(void*, int32_t, int32_t);");
+        self.emit("");
+        self.emit("static int32_t closure_call_i32(Closure* closure, int32_t a, int32_t b) {");
+        self.emit("    if (closure->env) {");
+        self.emit("        return ((array_callback_i32_env)closure->fn)(closure->env, a, b);");
+        self.emit("    }");
+        self.emit("    return ((array_callback_i32)closure->fn)(a, b);");
+        self.emit("}");
+        self.emit("");
+        self.generate_function_typedefs(program);
+
         // Generate dynamic array structure
         self.emit("// Dynamic array structure");
         self.emit("typedef struct {");
@@ -86,7 +278,7 @@ impl CodeGenerator {
         self.emit("    size_t element_size;");
         self.emit("} DynamicArray;");
         self.emit("");
-        self.emit("DynamicArray* array_new(size_t element_size) {");
+        self.emit("static DynamicArray* array_new(size_t element_size) {");
         self.emit("    DynamicArray* arr = malloc(sizeof(DynamicArray));");
         self.emit("    arr->capacity = 4;");
         self.emit("    arr->length = 0;");
@@ -95,7 +287,7 @@ impl CodeGenerator {
         self.emit("    return arr;");
         self.emit("}");
         self.emit("");
-        self.emit("void array_push_i32(DynamicArray* arr, int32_t item) {");
+        self.emit("static void array_push_i32(DynamicArray* arr, int32_t item) {");
         self.emit("    if (arr->length >= arr->capacity) {");
         self.emit("        arr->capacity *= 2;");
         self.emit("        arr->data = realloc(arr->data, arr->capacity * arr->element_size);");
@@ -103,12 +295,12 @@ impl CodeGenerator {
         self.emit("    ((int32_t*)arr->data)[arr->length++] = item;");
         self.emit("}");
         self.emit("");
-        self.emit("int32_t array_pop_i32(DynamicArray* arr) {");
+        self.emit("static int32_t array_pop_i32(DynamicArray* arr) {");
         self.emit("    if (arr->length == 0) return 0;");
         self.emit("    return ((int32_t*)arr->data)[--arr->length];");
         self.emit("}");
         self.emit("");
-        self.emit("void array_push_string(DynamicArray* arr, char* item) {");
+        self.emit("static void array_push_string(DynamicArray* arr, char* item) {");
         self.emit("    if (arr->length >= arr->capacity) {");
         self.emit("        arr->capacity *= 2;");
         self.emit("        arr->data = realloc(arr->data, arr->capacity * arr->element_size);");
@@ -116,19 +308,129 @@ impl CodeGenerator {
         self.emit("    ((char**)arr->data)[arr->length++] = item;");
         self.emit("}");
         self.emit("");
-        self.emit("DynamicArray* str_split(const char* str, const char* delim) {");
+        self.emit("static DynamicArray* array_fill(size_t element_size, const void* value, int32_t count) {");
+        self.emit("    DynamicArray* arr = array_new(element_size);");
+        self.emit("    if (count < 0) count = 0;");
+        self.emit("    if (count > arr->capacity) {");
+        self.emit("        arr->capacity = count;");
+        self.emit("        arr->data = realloc(arr->data, count * element_size);");
+        self.emit("    }");
+        self.emit("    bool is_zero = true;");
+        self.emit("    for (size_t i = 0; i < element_size; i++) {");
+        self.emit("        if (((const unsigned char*)value)[i] != 0) is_zero = false;");
+        self.emit("    }");
+        self.emit("    if (is_zero) {");
+        self.emit("        memset(arr->data, 0, count * element_size);");
+        self.emit("    } else {");
+        self.emit("        for (int32_t i = 0; i < count; i++) {");
+        self.emit("            memcpy((char*)arr->data + i * element_size, value, element_size);");
+        self.emit("        }");
+        self.emit("    }");
+        self.emit("    arr->length = count;");
+        self.emit("    return arr;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static DynamicArray* array_range(int32_t start, int32_t end) {");
+        self.emit("    DynamicArray* arr = array_new(sizeof(int32_t));");
+        self.emit("    for (int32_t i = start; i < end; i++) {");
+        self.emit("        array_push_i32(arr, i);");
+        self.emit("    }");
+        self.emit("    return arr;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* int_to_binary(uint64_t value) {");
+        self.emit("    char* buffer = malloc(65);");
+        self.emit("    int len = 0;");
+        self.emit("    do {");
+        self.emit("        buffer[len++] = '0' + (value & 1);");
+        self.emit("        value >>= 1;");
+        self.emit("    } while (value);");
+        self.emit("    for (int i = 0; i < len / 2; i++) {");
+        self.emit("        char c = buffer[i];");
+        self.emit("        buffer[i] = buffer[len - 1 - i];");
+        self.emit("        buffer[len - 1 - i] = c;");
+        self.emit("    }");
+        self.emit("    buffer[len] = '\\0';");
+        self.emit("    return buffer;");
+        self.emit("}");
+        self.emit("");
+        // Floats print with %f, inside arrays and structs too: inf, -inf,
+        // nan, and -0.000000 for negative zero. NaN is normalized because
+        // glibc prints a negative one as -nan.
+        self.emit("static double float_for_print(double value) {");
+        self.emit("    return isnan(value) ? NAN : value;");
+        self.emit("}");
+        self.emit("");
+        // A string that is nothing, as env() of an unset variable is,
+        // prints as the word rather than crashing printf
+        self.emit("static const char* str_for_print(const char* str) {");
+        self.emit("    return str ? str : \"nothing\";");
+        self.emit("}");
+        self.emit("");
+        // Byte arrays carry their own length, so they may hold NULs
+        self.emit("static DynamicArray* str_bytes(const char* str) {");
+        self.emit("    size_t len = strlen(str);");
+        self.emit("    DynamicArray* arr = array_new(sizeof(uint8_t));");
+        self.emit("    if ((int32_t)len > arr->capacity) {");
+        self.emit("        arr->capacity = len;");
+        self.emit("        arr->data = realloc(arr->data, len);");
+        self.emit("    }");
+        self.emit("    memcpy(arr->data, str, len);");
+        self.emit("    arr->length = len;");
+        self.emit("    return arr;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* str_from_bytes(DynamicArray* bytes) {");
+        self.emit("    char* result = malloc(bytes->length + 1);");
+        self.emit("    memcpy(result, bytes->data, bytes->length);");
+        self.emit("    result[bytes->length] = '\\0';");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        // Command-line arguments after the program name, captured by main
+        self.emit("static DynamicArray* program_arguments = NULL;");
+        self.emit("");
+        self.emit("static DynamicArray* program_args(void) {");
+        self.emit("    if (program_arguments == NULL) program_arguments = array_new(sizeof(char*));");
+        self.emit("    return program_arguments;");
+        self.emit("}");
+        self.emit("");
+        // Splits on the whole delimiter string; a negative limit means no limit,
+        // otherwise the remainder after `limit` splits stays in the last element
+        self.emit("static DynamicArray* str_split_n(const char* str, const char* delim, int32_t limit) {");
+        self.emit("    DynamicArray* result = array_new(sizeof(char*));");
+        self.emit("    size_t delim_len = strlen(delim);");
+        self.emit("    const char* start = str;");
+        self.emit("    const char* found;");
+        self.emit("    int32_t splits = 0;");
+        self.emit("    while (delim_len > 0 && (limit < 0 || splits < limit) && (found = strstr(start, delim)) != NULL) {");
+        self.emit("        array_push_string(result, strndup(start, found - start));");
+        self.emit("        start = found + delim_len;");
+        self.emit("        splits++;");
+        self.emit("    }");
+        self.emit("    array_push_string(result, strdup(start));");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static DynamicArray* str_split(const char* str, const char* delim) {");
+        self.emit("    return str_split_n(str, delim, -1);");
+        self.emit("}");
+        self.emit("");
+        self.emit("static DynamicArray* str_split_lines(const char* str) {");
         self.emit("    DynamicArray* result = array_new(sizeof(char*));");
-        self.emit("    char* str_copy = strdup(str);");
-        self.emit("    char* token = strtok(str_copy, delim);");
-        self.emit("    while (token != NULL) {");
-        self.emit("        array_push_string(result, strdup(token));");
-        self.emit("        token = strtok(NULL, delim);");
+        self.emit("    const char* start = str;");
+        self.emit("    while (*start != '\\0') {");
+        self.emit("        const char* end = strchr(start, '\\n');");
+        self.emit("        if (end == NULL) end = start + strlen(start);");
+        self.emit("        size_t len = end - start;");
+        self.emit("        if (len > 0 && start[len - 1] == '\\r') len--;");
+        self.emit("        array_push_string(result, strndup(start, len));");
+        self.emit("        start = *end == '\\n' ? end + 1 : end;");
         self.emit("    }");
-        self.emit("    free(str_copy);");
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* array_join_string(DynamicArray* arr, const char* sep) {");
+        self.emit("static char* array_join_string(DynamicArray* arr, const char* sep) {");
         self.emit("    if (arr->length == 0) return strdup(\"\");");
         self.emit("    int total_len = 0;");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
@@ -144,7 +446,7 @@ impl CodeGenerator {
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("void array_reverse_i32(DynamicArray* arr) {");
+        self.emit("static void array_reverse_i32(DynamicArray* arr) {");
         self.emit("    int32_t* data = (int32_t*)arr->data;");
         self.emit("    for (int i = 0; i < arr->length / 2; i++) {");
         self.emit("        int32_t temp = data[i];");
@@ -153,44 +455,44 @@ impl CodeGenerator {
         self.emit("    }");
         self.emit("}");
         self.emit("");
-        self.emit("DynamicArray* array_map_i32(DynamicArray* arr, int32_t(*func)(int32_t, int32_t)) {");
+        self.emit("static DynamicArray* array_map_i32(DynamicArray* arr, Closure* func) {");
         self.emit("    DynamicArray* result = array_new(sizeof(int32_t));");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
         self.emit("        int32_t val = ((int32_t*)arr->data)[i];");
-        self.emit("        int32_t mapped = func(val, 0);");
+        self.emit("        int32_t mapped = closure_call_i32(func, val, 0);");
         self.emit("        array_push_i32(result, mapped);");
         self.emit("    }");
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("DynamicArray* array_filter_i32(DynamicArray* arr, int32_t(*func)(int32_t, int32_t)) {");
+        self.emit("static DynamicArray* array_filter_i32(DynamicArray* arr, Closure* func) {");
         self.emit("    DynamicArray* result = array_new(sizeof(int32_t));");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
         self.emit("        int32_t val = ((int32_t*)arr->data)[i];");
-        self.emit("        if (func(val, 0)) {");
+        self.emit("        if (closure_call_i32(func, val, 0)) {");
         self.emit("            array_push_i32(result, val);");
         self.emit("        }");
         self.emit("    }");
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("int32_t array_reduce_i32(DynamicArray* arr, int32_t(*func)(int32_t, int32_t), int32_t initial) {");
+        self.emit("static int32_t array_reduce_i32(DynamicArray* arr, Closure* func, int32_t initial) {");
         self.emit("    int32_t result = initial;");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
         self.emit("        int32_t val = ((int32_t*)arr->data)[i];");
-        self.emit("        result = func(result, val);");
+        self.emit("        result = closure_call_i32(func, result, val);");
         self.emit("    }");
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("void array_forEach_i32(DynamicArray* arr, int32_t(*func)(int32_t, int32_t)) {");
+        self.emit("static void array_forEach_i32(DynamicArray* arr, Closure* func) {");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
         self.emit("        int32_t val = ((int32_t*)arr->data)[i];");
-        self.emit("        func(val, 0);");
+        self.emit("        closure_call_i32(func, val, 0);");
         self.emit("    }");
         self.emit("}");
         self.emit("");
-        self.emit("int32_t array_contains_i32(DynamicArray* arr, int32_t value) {");
+        self.emit("static int32_t array_contains_i32(DynamicArray* arr, int32_t value) {");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
         self.emit("        if (((int32_t*)arr->data)[i] == value) {");
         self.emit("            return 1;");
@@ -199,20 +501,129 @@ impl CodeGenerator {
         self.emit("    return 0;");
         self.emit("}");
         self.emit("");
-        self.emit("int32_t array_find_i32(DynamicArray* arr, int32_t(*func)(int32_t, int32_t)) {");
+        self.emit("static int32_t array_find_i32(DynamicArray* arr, Closure* func) {");
         self.emit("    for (int i = 0; i < arr->length; i++) {");
         self.emit("        int32_t val = ((int32_t*)arr->data)[i];");
-        self.emit("        if (func(val, 0)) {");
+        self.emit("        if (closure_call_i32(func, val, 0)) {");
         self.emit("            return val;");
         self.emit("        }");
         self.emit("    }");
         self.emit("    return 0;");
         self.emit("}");
         self.emit("");
+        // min/max/avg have no meaningful result for an empty array
+        self.emit("static void array_require_elements(DynamicArray* arr, const char* method) {");
+        self.emit("    if (arr->length == 0) {");
+        self.emit("        fprintf(stderr, \"error: %s() of an empty array\\n\", method);");
+        self.emit("        print_backtrace();");
+        self.emit("        exit(1);");
+        self.emit("    }");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_sum_i32(DynamicArray* arr) {");
+        self.emit("    int32_t total = 0;");
+        self.emit("    for (int i = 0; i < arr->length; i++) {");
+        self.emit("        total += ((int32_t*)arr->data)[i];");
+        self.emit("    }");
+        self.emit("    return total;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_min_i32(DynamicArray* arr) {");
+        self.emit("    array_require_elements(arr, \"min\");");
+        self.emit("    int32_t* data = (int32_t*)arr->data;");
+        self.emit("    int32_t result = data[0];");
+        self.emit("    for (int i = 1; i < arr->length; i++) {");
+        self.emit("        if (data[i] < result) result = data[i];");
+        self.emit("    }");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_max_i32(DynamicArray* arr) {");
+        self.emit("    array_require_elements(arr, \"max\");");
+        self.emit("    int32_t* data = (int32_t*)arr->data;");
+        self.emit("    int32_t result = data[0];");
+        self.emit("    for (int i = 1; i < arr->length; i++) {");
+        self.emit("        if (data[i] > result) result = data[i];");
+        self.emit("    }");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static double array_avg_i32(DynamicArray* arr) {");
+        self.emit("    array_require_elements(arr, \"avg\");");
+        self.emit("    int64_t total = 0;");
+        self.emit("    for (int i = 0; i < arr->length; i++) {");
+        self.emit("        total += ((int32_t*)arr->data)[i];");
+        self.emit("    }");
+        self.emit("    return (double)total / arr->length;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_count_i32(DynamicArray* arr, int32_t value) {");
+        self.emit("    int32_t count = 0;");
+        self.emit("    for (int i = 0; i < arr->length; i++) {");
+        self.emit("        if (((int32_t*)arr->data)[i] == value) count++;");
+        self.emit("    }");
+        self.emit("    return count;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_count_string(DynamicArray* arr, const char* value) {");
+        self.emit("    int32_t count = 0;");
+        self.emit("    for (int i = 0; i < arr->length; i++) {");
+        self.emit("        if (strcmp(((char**)arr->data)[i], value) == 0) count++;");
+        self.emit("    }");
+        self.emit("    return count;");
+        self.emit("}");
+        self.emit("");
+        // Position of value at or after from (or last overall), or -1
+        self.emit("static int32_t array_index_of_i32(DynamicArray* arr, int32_t value, int32_t from) {");
+        self.emit("    for (int i = from < 0 ? 0 : from; i < arr->length; i++) {");
+        self.emit("        if (((int32_t*)arr->data)[i] == value) return i;");
+        self.emit("    }");
+        self.emit("    return -1;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_index_of_string(DynamicArray* arr, const char* value, int32_t from) {");
+        self.emit("    for (int i = from < 0 ? 0 : from; i < arr->length; i++) {");
+        self.emit("        if (strcmp(((char**)arr->data)[i], value) == 0) return i;");
+        self.emit("    }");
+        self.emit("    return -1;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_last_index_of_i32(DynamicArray* arr, int32_t value) {");
+        self.emit("    for (int i = arr->length - 1; i >= 0; i--) {");
+        self.emit("        if (((int32_t*)arr->data)[i] == value) return i;");
+        self.emit("    }");
+        self.emit("    return -1;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t array_last_index_of_string(DynamicArray* arr, const char* value) {");
+        self.emit("    for (int i = arr->length - 1; i >= 0; i--) {");
+        self.emit("        if (strcmp(((char**)arr->data)[i], value) == 0) return i;");
+        self.emit("    }");
+        self.emit("    return -1;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static DynamicArray* array_unique_i32(DynamicArray* arr) {");
+        self.emit("    DynamicArray* result = array_new(sizeof(int32_t));");
+        self.emit("    for (int i = 0; i < arr->length; i++) {");
+        self.emit("        int32_t val = ((int32_t*)arr->data)[i];");
+        self.emit("        if (!array_contains_i32(result, val)) array_push_i32(result, val);");
+        self.emit("    }");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static DynamicArray* array_unique_string(DynamicArray* arr) {");
+        self.emit("    DynamicArray* result = array_new(sizeof(char*));");
+        self.emit("    for (int i = 0; i < arr->length; i++) {");
+        self.emit("        char* val = ((char**)arr->data)[i];");
+        self.emit("        if (array_count_string(result, val) == 0) array_push_string(result, val);");
+        self.emit("    }");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
 
         // Generate string helper functions
         self.emit("// String helper functions");
-        self.emit("char* str_to_upper(const char* str) {");
+        self.emit("static char* str_to_upper(const char* str) {");
         self.emit("    int len = strlen(str);");
         self.emit("    char* result = malloc(len + 1);");
         self.emit("    for (int i = 0; i < len; i++) { result[i] = toupper(str[i]); }");
@@ -220,7 +631,7 @@ impl CodeGenerator {
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* str_to_lower(const char* str) {");
+        self.emit("static char* str_to_lower(const char* str) {");
         self.emit("    int len = strlen(str);");
         self.emit("    char* result = malloc(len + 1);");
         self.emit("    for (int i = 0; i < len; i++) { result[i] = tolower(str[i]); }");
@@ -228,7 +639,7 @@ impl CodeGenerator {
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* str_trim(const char* str) {");
+        self.emit("static char* str_trim(const char* str) {");
         self.emit("    while (*str && isspace(*str)) str++;");
         self.emit("    if (*str == '\\0') return strdup(\"\");");
         self.emit("    const char* end = str + strlen(str) - 1;");
@@ -240,7 +651,31 @@ impl CodeGenerator {
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* str_char_at(const char* str, int32_t index) {");
+        self.emit("static char* str_trim_start(const char* str) {");
+        self.emit("    while (*str && isspace(*str)) str++;");
+        self.emit("    return strdup(str);");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* str_trim_end(const char* str) {");
+        self.emit("    int len = strlen(str);");
+        self.emit("    while (len > 0 && isspace(str[len - 1])) len--;");
+        self.emit("    char* result = malloc(len + 1);");
+        self.emit("    strncpy(result, str, len);");
+        self.emit("    result[len] = '\\0';");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* str_trim_chars(const char* str, const char* chars) {");
+        self.emit("    while (*str && strchr(chars, *str)) str++;");
+        self.emit("    int len = strlen(str);");
+        self.emit("    while (len > 0 && strchr(chars, str[len - 1])) len--;");
+        self.emit("    char* result = malloc(len + 1);");
+        self.emit("    strncpy(result, str, len);");
+        self.emit("    result[len] = '\\0';");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* str_char_at(const char* str, int32_t index) {");
         self.emit("    if (index < 0 || index >= strlen(str)) return strdup(\"\");");
         self.emit("    char* result = malloc(2);");
         self.emit("    result[0] = str[index];");
@@ -248,7 +683,7 @@ impl CodeGenerator {
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* str_substring(const char* str, int32_t start, int32_t end) {");
+        self.emit("static char* str_substring(const char* str, int32_t start, int32_t end) {");
         self.emit("    int len = strlen(str);");
         self.emit("    if (start < 0) start = 0;");
         self.emit("    if (end > len) end = len;");
@@ -260,15 +695,36 @@ impl CodeGenerator {
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* str_concat(const char* s1, const char* s2) {");
-        self.emit("    int len = strlen(s1) + strlen(s2);");
+        self.emit("static char* str_format(const char* format, ...) {");
+        self.emit("    va_list args;");
+        self.emit("    va_start(args, format);");
+        self.emit("    int len = vsnprintf(NULL, 0, format, args);");
+        self.emit("    va_end(args);");
         self.emit("    char* result = malloc(len + 1);");
-        self.emit("    strcpy(result, s1);");
-        self.emit("    strcat(result, s2);");
+        self.emit("    va_start(args, format);");
+        self.emit("    vsnprintf(result, len + 1, format, args);");
+        self.emit("    va_end(args);");
         self.emit("    return result;");
         self.emit("}");
         self.emit("");
-        self.emit("char* str_replace(const char* str, const char* from, const char* to) {");
+        self.emit("static void assertion_failed(const char* message) {");
+        self.emit("    fprintf(stderr, \"Assertion failed: %s\\n\", message);");
+        self.emit("    print_backtrace();");
+        self.emit("    exit(1);");
+        self.emit("}");
+        self.emit("");
+        // Every string `+` and `.concat` goes through here, so the result
+        // always has room for both halves
+        self.emit("static char* str_concat(const char* s1, const char* s2) {");
+        self.emit("    size_t len1 = strlen(s1);");
+        self.emit("    size_t len2 = strlen(s2);");
+        self.emit("    char* result = malloc(len1 + len2 + 1);");
+        self.emit("    memcpy(result, s1, len1);");
+        self.emit("    memcpy(result + len1, s2, len2 + 1);");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* str_replace(const char* str, const char* from, const char* to) {");
         self.emit("    char* pos = strstr(str, from);");
         self.emit("    if (!pos) return strdup(str);");
         self.emit("    int from_len = strlen(from);");
@@ -283,13 +739,162 @@ impl CodeGenerator {
         self.emit("}");
         self.emit("");
 
+        self.emit("static void str_require_pattern(const char* pattern, const char* method) {");
+        self.emit("    if (pattern[0] == '\\0') {");
+        self.emit("        fprintf(stderr, \"error: %s() needs a non-empty search string\\n\", method);");
+        self.emit("        print_backtrace();");
+        self.emit("        exit(1);");
+        self.emit("    }");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int32_t str_count(const char* str, const char* sub) {");
+        self.emit("    str_require_pattern(sub, \"count\");");
+        self.emit("    int32_t count = 0;");
+        self.emit("    size_t sub_len = strlen(sub);");
+        self.emit("    for (const char* p = strstr(str, sub); p; p = strstr(p + sub_len, sub)) count++;");
+        self.emit("    return count;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static char* str_replace_all(const char* str, const char* from, const char* to) {");
+        self.emit("    str_require_pattern(from, \"replaceAll\");");
+        self.emit("    size_t from_len = strlen(from);");
+        self.emit("    size_t to_len = strlen(to);");
+        self.emit("    char* result = malloc(strlen(str) + (size_t)str_count(str, from) * to_len + 1);");
+        self.emit("    char* out = result;");
+        self.emit("    const char* p;");
+        self.emit("    while ((p = strstr(str, from))) {");
+        self.emit("        memcpy(out, str, p - str);");
+        self.emit("        out += p - str;");
+        self.emit("        memcpy(out, to, to_len);");
+        self.emit("        out += to_len;");
+        self.emit("        str = p + from_len;");
+        self.emit("    }");
+        self.emit("    strcpy(out, str);");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+
+        // hash() is 64-bit FNV-1a: over a string's bytes, or over an
+        // integer's eight bytes least significant first. Stable across runs
+        // and platforms, but not suitable against adversarial input.
+        self.emit("static uint64_t fnv1a_step(uint64_t hash, uint8_t byte) {");
+        self.emit("    return (hash ^ byte) * 0x100000001b3ULL;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static uint64_t str_hash(const char* str) {");
+        self.emit("    uint64_t hash = 0xcbf29ce484222325ULL;");
+        self.emit("    for (const unsigned char* p = (const unsigned char*)str; *p; p++) hash = fnv1a_step(hash, *p);");
+        self.emit("    return hash;");
+        self.emit("}");
+        self.emit("");
+        self.emit("static uint64_t int_hash(uint64_t value) {");
+        self.emit("    uint64_t hash = 0xcbf29ce484222325ULL;");
+        self.emit("    for (int i = 0; i < 8; i++) hash = fnv1a_step(hash, (uint8_t)(value >> (8 * i)));");
+        self.emit("    return hash;");
+        self.emit("}");
+        self.emit("");
+
+        self.emit("static char* str_pad(const char* str, int32_t width, const char* fill, bool at_start) {");
+        self.emit("    int len = strlen(str);");
+        self.emit("    int fill_len = strlen(fill);");
+        self.emit("    if (width <= len || fill_len == 0) return strdup(str);");
+        self.emit("    int pad = width - len;");
+        self.emit("    char* result = malloc(width + 1);");
+        self.emit("    char* padding = at_start ? result : result + len;");
+        self.emit("    for (int i = 0; i < pad; i++) padding[i] = fill[i % fill_len];");
+        self.emit("    memcpy(at_start ? result + pad : result, str, len);");
+        self.emit("    result[width] = '\\0';");
+        self.emit("    return result;");
+        self.emit("}");
+        self.emit("");
+        self.emit("// Print cells padded to fixed column widths (negative width right-aligns)");
+        // Every cell needs a width; a mismatch is a bug in the caller
+        self.emit("static void print_row(char** cells, int32_t count, int32_t* widths, int32_t width_count) {");
+        self.emit("    if (count != width_count) {");
+        self.emit("        fprintf(stderr, \"error: print_row() got %d cells but %d widths\\n\", count, width_count);");
+        self.emit("        print_backtrace();");
+        self.emit("        exit(1);");
+        self.emit("    }");
+        self.emit("    for (int i = 0; i < count; i++) {");
+        self.emit("        if (i > 0) printf(\" \");");
+        self.emit("        if (widths[i] < 0) printf(\"%*s\", -widths[i], cells[i]);");
+        self.emit("        else printf(\"%-*s\", widths[i], cells[i]);");
+        self.emit("    }");
+        self.emit("    printf(\"\\n\");");
+        self.emit("}");
+        self.emit("");
+
+        // Integer parsing helpers
+        self.emit("static bool str_is_int_radix(const char* str, int32_t base) {");
+        self.emit("    if (base < 2 || base > 36 || *str == '\\0' || isspace(*str)) return false;");
+        self.emit("    char* end;");
+        self.emit("    errno = 0;");
+        self.emit("    strtoll(str, &end, base);");
+        self.emit("    return errno == 0 && *end == '\\0';");
+        self.emit("}");
+        self.emit("");
+        self.emit("static int64_t str_to_int_radix(const char* str, int32_t base) {");
+        self.emit("    if (!str_is_int_radix(str, base)) {");
+        self.emit("        fprintf(stderr, \"error: invalid integer \\\"%s\\\" for base %d\\n\", str, base);");
+        self.emit("        print_backtrace();");
+        self.emit("        exit(1);");
+        self.emit("    }");
+        self.emit("    return strtoll(str, NULL, base);");
+        self.emit("}");
+        self.emit("");
+
+        for stmt in &program.statements {
+            if let Statement::FunctionDecl { name, return_type, .. } = stmt {
+                let ret_type = return_type
+                    .as_ref()
+                    .map(|t| self.type_to_c(t))
+                    .unwrap_or_else(|| "void".to_string());
+                self.function_return_types.insert(name.clone(), ret_type);
+            }
+        }
+
+        for stmt in ordered_type_declarations(program)? {
+            match stmt {
+                Statement::StructDecl { name, fields, .. } => self.generate_struct_typedef(name, fields),
+                Statement::EnumDecl { name, variants, .. } => {
+                    self.emit(&enum_typedef(name, variants));
+                    self.emit("");
+                    self.enum_names.insert(name.clone());
+                }
+                _ => {}
+            }
+        }
+
         // First pass: Process all statements to collect lambda functions
         // We need to do this to know what lambdas to forward-declare
-        let mut temp_gen = CodeGenerator::new();
+        let mut temp_gen = CodeGenerator::new(self.options.clone());
+        temp_gen.function_return_types = self.function_return_types.clone();
+        temp_gen.struct_fields = self.struct_fields.clone();
+        temp_gen.enum_names = self.enum_names.clone();
+        temp_gen.function_types = self.function_types.clone();
+        temp_gen.shared_captures = self.shared_captures.clone();
+        temp_gen.methods = self.methods.clone();
         for stmt in &program.statements {
             temp_gen.generate_statement(stmt)?;
         }
 
+        for elem_c_type in &temp_gen.array_helper_types {
+            self.generate_array_helpers(elem_c_type)?;
+        }
+        for (operation, elem_c_type, result_c_type) in &temp_gen.callback_helpers {
+            self.generate_callback_helper(operation, elem_c_type, result_c_type)?;
+        }
+
+        self.object_shapes = temp_gen.object_shapes.clone();
+        for (index, fields) in self.object_shapes.clone().iter().enumerate() {
+            self.emit("typedef struct {");
+            for (field, c_type) in fields {
+                self.emit(&format!("    {} {};", c_type, field));
+            }
+            self.emit(&format!("}} __Obj_{};", index));
+            self.emit("");
+        }
+
         // Now emit the collected lambda functions
         for lambda_func in &temp_gen.lambda_functions {
             self.output.push_str(lambda_func);
@@ -297,93 +902,629 @@ impl CodeGenerator {
 
         // Generate forward declarations for regular functions
         for stmt in &program.statements {
-            if let Statement::FunctionDecl { name, params, return_type, is_export: _, .. } = stmt {
-                self.generate_function_declaration(name, params, return_type)?;
+            if let Statement::FunctionDecl { name, params, return_type, is_export, .. } = stmt {
+                self.generate_function_declaration(name, params, return_type, *is_export)?;
             }
         }
 
         self.emit("");
 
         // Second pass: Generate actual function definitions
-        // Copy the lambda_functions and lambda_info from temp_gen
+        // Copy the lambda_functions from temp_gen
         self.lambda_functions = temp_gen.lambda_functions.clone();
-        self.lambda_info = temp_gen.lambda_info.clone();
         for stmt in &program.statements {
             self.generate_statement(stmt)?;
         }
 
+        if self.options.entry != "main" {
+            self.generate_entry_wrapper(program);
+        }
+
         Ok(self.output.clone())
     }
 
-    fn generate_function_declaration(
-        &mut self,
-        name: &str,
-        params: &[Parameter],
-        return_type: &Option<Type>,
-    ) -> Result<(), String> {
-        let ret_type = return_type
-            .as_ref()
-            .map(|t| self.type_to_c(t))
-            .unwrap_or_else(|| "void".to_string());
-
-        self.emit_no_indent(&ret_type);
-        self.emit_no_indent(" ");
-        self.emit_no_indent(name);
-        self.emit_no_indent("(");
-
-        for (i, param) in params.iter().enumerate() {
-            if i > 0 {
-                self.emit_no_indent(", ");
+    // `program` with each struct method also declared as a top-level
+    // function named `<Struct>_<method>`
+    fn hoist_methods(&mut self, program: &Program) -> Program {
+        let mut hoisted = program.clone();
+        for stmt in &program.statements {
+            if let Statement::StructDecl { name: struct_name, methods, .. } = stmt {
+                for method in methods {
+                    let mut function = method.clone();
+                    if let Statement::FunctionDecl { name, .. } = &mut function {
+                        *name = format!("{}_{}", struct_name, name);
+                        self.methods.insert(name.clone());
+                    }
+                    hoisted.statements.push(function);
+                }
             }
-            self.emit_no_indent(&self.type_to_c(&param.param_type));
-            self.emit_no_indent(" ");
-            self.emit_no_indent(&param.name);
         }
-
-        self.emit_no_indent(")");
-        self.output.push_str(";\n");
-
-        Ok(())
+        hoisted
     }
 
-    fn generate_statement(&mut self, stmt: &Statement) -> Result<(), String> {
-        match stmt {
-            Statement::FunctionDecl {
-                name,
-                params,
-                return_type,
-                body,
-                is_export: _,
-            } => {
-                let ret_type = return_type
-                    .as_ref()
-                    .map(|t| self.type_to_c(t))
-                    .unwrap_or_else(|| "void".to_string());
+    // The function a call of `method` on `object` goes to, when `object`
+    // is a struct with that method
+    fn method_function(&self, object: &Expression, method: &str) -> Option<String> {
+        let function = format!("{}_{}", self.expression_c_type(object)?, method);
+        self.methods.contains(&function).then_some(function)
+    }
 
-                self.emit_no_indent(&ret_type);
-                self.emit_no_indent(" ");
-                self.emit_no_indent(name);
-                self.emit_no_indent("(");
+    // C type of parameter `index` of `function`; a method takes its
+    // receiver by pointer
+    fn param_c_type(&self, function: &str, index: usize, param: &Parameter) -> String {
+        let c_type = self.type_to_c(&param.param_type);
+        if index == 0 && self.methods.contains(function) {
+            format!("{}*", c_type)
+        } else {
+            c_type
+        }
+    }
 
-                for (i, param) in params.iter().enumerate() {
-                    if i > 0 {
-                        self.emit_no_indent(", ");
-                    }
-                    let c_type = self.type_to_c(&param.param_type);
-                    self.emit_no_indent(&c_type);
-                    self.emit_no_indent(" ");
-                    self.emit_no_indent(&param.name);
+    /// With `--entry`, check that the chosen function can start a program:
+    /// it takes nothing and returns an integer exit code or nothing
+    fn check_entry_point(&self, program: &Program) -> Result<(), String> {
+        let entry = &self.options.entry;
+        if entry == "main" {
+            return Ok(());
+        }
 
-                    // Store variable type
-                    self.variables.insert(param.name.clone(), c_type);
+        let mut found = None;
+        for stmt in &program.statements {
+            if let Statement::FunctionDecl { name, params, return_type, .. } = stmt {
+                if name == "main" {
+                    return Err(format!(
+                        "Function `main` would clash with the C `main` generated for entry function `{}`",
+                        entry
+                    ));
+                }
+                if name == entry {
+                    found = Some((params, return_type));
+                }
+            }
+        }
+
+        let Some((params, return_type)) = found else {
+            return Err(format!("Entry function `{}` is not defined", entry));
+        };
+        if !params.is_empty() {
+            return Err(format!("Entry function `{}` must not take parameters", entry));
+        }
+        match return_type {
+            None
+            | Some(
+                Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::Nothing,
+            ) => Ok(()),
+            Some(other) => Err(format!(
+                "Entry function `{}` must return an integer exit code or nothing, not {}",
+                entry, other
+            )),
+        }
+    }
+
+    // The C `main` for a program whose entry function has another name
+    fn generate_entry_wrapper(&mut self, program: &Program) {
+        let returns_code = program.statements.iter().any(|stmt| {
+            matches!(stmt, Statement::FunctionDecl { name, return_type: Some(ty), .. }
+                if *name == self.options.entry && *ty != Type::Nothing)
+        });
+
+        self.emit("int main(int __argc, char** __argv) {");
+        self.emit("    DynamicArray* __args = program_args();");
+        self.emit("    for (int __i = 1; __i < __argc; __i++) array_push_string(__args, __argv[__i]);");
+        if returns_code {
+            self.emit(&format!("    return {}();", self.options.entry));
+        } else {
+            self.emit(&format!("    {}();", self.options.entry));
+            self.emit("    return 0;");
+        }
+        self.emit("}");
+    }
+
+    // `print_backtrace` and `hilow_panic`. Under `checked`, every function
+    // pushes its name on a thread-local stack on entry and a cleanup
+    // handler pops it on each way out, so the backtrace names the calls
+    // that led to the failure.
+    fn emit_call_stack_support(&mut self) {
+        if self.options.checked {
+            self.emit("#define CALL_STACK_DEPTH 256");
+            self.emit("static _Thread_local const char* call_stack[CALL_STACK_DEPTH];");
+            self.emit("static _Thread_local int call_stack_size = 0;");
+            self.emit("");
+            self.emit("static int call_stack_enter(const char* name) {");
+            self.emit("    if (call_stack_size < CALL_STACK_DEPTH) call_stack[call_stack_size] = name;");
+            self.emit("    return call_stack_size++;");
+            self.emit("}");
+            self.emit("");
+            self.emit("static void call_stack_leave(int* frame) {");
+            self.emit("    call_stack_size = *frame;");
+            self.emit("}");
+            self.emit("");
+            self.emit("static void print_backtrace(void) {");
+            self.emit("    fprintf(stderr, \"backtrace (most recent call first):\\n\");");
+            self.emit("    for (int i = call_stack_size - 1; i >= 0; i--) {");
+            self.emit("        if (i >= CALL_STACK_DEPTH) continue;");
+            self.emit("        fprintf(stderr, \"    %s\\n\", call_stack[i]);");
+            self.emit("    }");
+            self.emit("}");
+        } else {
+            self.emit("static void print_backtrace(void) {}");
+        }
+        self.emit("");
+        self.emit("_Noreturn static void hilow_panic(const char* message) {");
+        self.emit("    fprintf(stderr, \"panic: %s\\n\", message);");
+        self.emit("    print_backtrace();");
+        self.emit("    exit(1);");
+        self.emit("}");
+        self.emit("");
+
+        if self.options.checked {
+            self.emit("static int64_t array_index_checked(int64_t index, int64_t length) {");
+            self.emit("    if (index < 0 || index >= length) {");
+            self.emit("        char message[96];");
+            self.emit("        snprintf(message, sizeof(message), \"index %lld out of bounds for length %lld\", (long long)index, (long long)length);");
+            self.emit("        hilow_panic(message);");
+            self.emit("    }");
+            self.emit("    return index;");
+            self.emit("}");
+            self.emit("");
+        }
+    }
+
+    // Typedefs for each function type in the program: `__fn_type_N` for a
+    // closure of the type, and `_plain`/`_env` for the two ways to call it
+    fn generate_function_typedefs(&mut self, program: &Program) {
+        let mut collector = TypeCollector::default();
+        collector.visit_program(program);
+        for ty in collector.types {
+            self.register_function_type(ty);
+        }
+
+        for (index, ty) in self.function_types.clone().iter().enumerate() {
+            let Type::Function { params, return_type } = ty else {
+                continue;
+            };
+            let params: Vec<String> = params.iter().map(|param| self.type_to_c(param)).collect();
+            let env_params: Vec<String> = std::iter::once("void*".to_string()).chain(params.clone()).collect();
+            let ret_type = self.type_to_c(return_type);
+            self.emit(&format!("// {}", ty));
+            self.emit(&format!("typedef Closure* __fn_type_{};", index));
+            self.emit(&format!("typedef {} (*__fn_type_{}_plain)({});", ret_type, index, params.join(", ")));
+            self.emit(&format!("typedef {} (*__fn_type_{}_env)({});", ret_type, index, env_params.join(", ")));
+            self.emit("");
+        }
+    }
+
+    // The `__Obj_N` typedef for an object literal with these properties.
+    // Field types come from the values; nested objects get their own
+    // typedef first, and anything that can't be typed stays an int32_t.
+    fn object_shape_type(&mut self, properties: &[Property]) -> String {
+        let fields: Vec<(String, String)> = properties
+            .iter()
+            .map(|prop| {
+                let c_type = match &prop.value {
+                    Expression::ObjectLiteral { properties } => self.object_shape_type(properties),
+                    value => self.expression_c_type(value).unwrap_or_else(|| "int32_t".to_string()),
+                };
+                (prop.key.clone(), c_type)
+            })
+            .collect();
+
+        let index = match self.object_shapes.iter().position(|shape| *shape == fields) {
+            Some(index) => index,
+            None => {
+                self.object_shapes.push(fields);
+                self.object_shapes.len() - 1
+            }
+        };
+        format!("__Obj_{}", index)
+    }
+
+    // The C type of field `property` of a value of C type `c_type`, when
+    // that is an object typedef or a declared struct
+    fn object_field_c_type(&self, c_type: &str, property: &str) -> Option<String> {
+        let fields = match c_type.strip_prefix("__Obj_") {
+            Some(index) => self.object_shapes.get(index.parse::<usize>().ok()?)?,
+            None => {
+                let (_, ty) = self.struct_fields.get(c_type)?.iter().find(|(field, _)| field == property)?;
+                return Some(self.type_to_c(ty));
+            }
+        };
+        fields
+            .iter()
+            .find(|(field, _)| field == property)
+            .map(|(_, c_type)| c_type.clone())
+    }
+
+    // The enum named by `object` when `object.Variant` is one of its
+    // variants rather than a field
+    fn enum_variant_of<'e>(&self, object: &'e Expression) -> Option<&'e str> {
+        match object {
+            Expression::Identifier(name) if self.enum_names.contains(name) && !self.variables.contains_key(name) => {
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    // Function types inside `ty` are registered before `ty` itself, so
+    // every typedef only refers to earlier ones
+    fn register_function_type(&mut self, ty: &Type) {
+        match ty {
+            Type::Function { params, return_type } => {
+                for param in params {
+                    self.register_function_type(param);
+                }
+                self.register_function_type(return_type);
+                if !self.function_types.contains(ty) {
+                    self.function_types.push(ty.clone());
+                }
+            }
+            Type::Array { element_type, .. } => self.register_function_type(element_type),
+            _ => {}
+        }
+    }
+
+    // push/pop, and sum/min/max/avg for numbers, for dynamic arrays of
+    // `elem_c_type`, except the versions the preamble already defines
+    fn generate_array_helpers(&mut self, elem_c_type: &str) -> Result<(), String> {
+        let suffix = c_type_suffix(elem_c_type)?;
+        // A C array can't be assigned or returned: it is copied in, and
+        // there is no pop
+        if is_fixed_array_c_type(elem_c_type) {
+            self.emit(&format!("static void array_push_{}(DynamicArray* arr, const void* item) {{", suffix));
+            self.emit("    if (arr->length >= arr->capacity) {");
+            self.emit("        arr->capacity *= 2;");
+            self.emit("        arr->data = realloc(arr->data, arr->capacity * arr->element_size);");
+            self.emit("    }");
+            self.emit("    memcpy((char*)arr->data + arr->length++ * arr->element_size, item, arr->element_size);");
+            self.emit("}");
+            self.emit("");
+            return Ok(());
+        }
+        if suffix != "i32" && suffix != "string" {
+            self.emit(&format!("static void array_push_{}(DynamicArray* arr, {} item) {{", suffix, elem_c_type));
+            self.emit("    if (arr->length >= arr->capacity) {");
+            self.emit("        arr->capacity *= 2;");
+            self.emit("        arr->data = realloc(arr->data, arr->capacity * arr->element_size);");
+            self.emit("    }");
+            self.emit(&format!("    (({}*)arr->data)[arr->length++] = item;", elem_c_type));
+            self.emit("}");
+            self.emit("");
+        }
+        if suffix != "i32" {
+            self.emit(&format!("static {} array_pop_{}(DynamicArray* arr) {{", elem_c_type, suffix));
+            self.emit(&format!("    if (arr->length == 0) return ({}){{0}};", elem_c_type));
+            self.emit(&format!("    return (({}*)arr->data)[--arr->length];", elem_c_type));
+            self.emit("}");
+            self.emit("");
+        }
+        if suffix != "i32" && is_number_c_type(elem_c_type) {
+            self.emit(&format!("static {} array_sum_{}(DynamicArray* arr) {{", elem_c_type, suffix));
+            self.emit(&format!("    {} total = 0;", elem_c_type));
+            self.emit("    for (int i = 0; i < arr->length; i++) {");
+            self.emit(&format!("        total += (({}*)arr->data)[i];", elem_c_type));
+            self.emit("    }");
+            self.emit("    return total;");
+            self.emit("}");
+            self.emit("");
+            for (method, comparison) in [("min", "<"), ("max", ">")] {
+                self.emit(&format!("static {} array_{}_{}(DynamicArray* arr) {{", elem_c_type, method, suffix));
+                self.emit(&format!("    array_require_elements(arr, \"{}\");", method));
+                self.emit(&format!("    {0}* data = ({0}*)arr->data;", elem_c_type));
+                self.emit(&format!("    {} result = data[0];", elem_c_type));
+                self.emit("    for (int i = 1; i < arr->length; i++) {");
+                self.emit(&format!("        if (data[i] {} result) result = data[i];", comparison));
+                self.emit("    }");
+                self.emit("    return result;");
+                self.emit("}");
+                self.emit("");
+            }
+            self.emit(&format!("static double array_avg_{}(DynamicArray* arr) {{", suffix));
+            self.emit("    array_require_elements(arr, \"avg\");");
+            self.emit("    double total = 0;");
+            self.emit("    for (int i = 0; i < arr->length; i++) {");
+            self.emit(&format!("        total += (({}*)arr->data)[i];", elem_c_type));
+            self.emit("    }");
+            self.emit("    return total / arr->length;");
+            self.emit("}");
+            self.emit("");
+        }
+        Ok(())
+    }
+
+    // `operation` over a dynamic array of `elem_c_type` with a callback
+    // giving `result_c_type`. The callback is called with exactly its own
+    // parameters, so pointers and floats reach it intact.
+    fn generate_callback_helper(&mut self, operation: &str, elem_c_type: &str, result_c_type: &str) -> Result<(), String> {
+        let suffix = c_type_suffix(elem_c_type)?;
+        let result_suffix = c_type_suffix(result_c_type)?;
+        let call = |args: &str, params: &str| {
+            format!(
+                "(func->env ? (({ret} (*)(void*, {params}))func->fn)(func->env, {args}) : (({ret} (*)({params}))func->fn)({args}))",
+                ret = result_c_type,
+                params = params,
+                args = args
+            )
+        };
+        let element = format!("        {0} val = (({0}*)arr->data)[i];", elem_c_type);
+        match operation {
+            "map" => {
+                self.emit(&format!("static DynamicArray* array_map_{}_{}(DynamicArray* arr, Closure* func) {{", suffix, result_suffix));
+                self.emit(&format!("    DynamicArray* result = array_new(sizeof({}));", result_c_type));
+                self.emit("    for (int i = 0; i < arr->length; i++) {");
+                self.emit(&element);
+                self.emit(&format!("        array_push_{}(result, {});", result_suffix, call("val", elem_c_type)));
+                self.emit("    }");
+                self.emit("    return result;");
+            }
+            "filter" => {
+                self.emit(&format!("static DynamicArray* array_filter_{}(DynamicArray* arr, Closure* func) {{", suffix));
+                self.emit(&format!("    DynamicArray* result = array_new(sizeof({}));", elem_c_type));
+                self.emit("    for (int i = 0; i < arr->length; i++) {");
+                self.emit(&element);
+                self.emit(&format!("        if {} {{", call("val", elem_c_type)));
+                self.emit(&format!("            array_push_{}(result, val);", suffix));
+                self.emit("        }");
+                self.emit("    }");
+                self.emit("    return result;");
+            }
+            "reduce" => {
+                self.emit(&format!(
+                    "static {0} array_reduce_{1}_{2}(DynamicArray* arr, Closure* func, {0} initial) {{",
+                    result_c_type, suffix, result_suffix
+                ));
+                self.emit(&format!("    {} result = initial;", result_c_type));
+                self.emit("    for (int i = 0; i < arr->length; i++) {");
+                self.emit(&element);
+                self.emit(&format!(
+                    "        result = {};",
+                    call("result, val", &format!("{}, {}", result_c_type, elem_c_type))
+                ));
+                self.emit("    }");
+                self.emit("    return result;");
+            }
+            "forEach" => {
+                self.emit(&format!("static void array_forEach_{}(DynamicArray* arr, Closure* func) {{", suffix));
+                self.emit("    for (int i = 0; i < arr->length; i++) {");
+                self.emit(&element);
+                self.emit(&format!("        {};", call("val", elem_c_type)));
+                self.emit("    }");
+            }
+            _ => {
+                self.emit(&format!("static {0} array_find_{1}(DynamicArray* arr, Closure* func) {{", elem_c_type, suffix));
+                self.emit("    for (int i = 0; i < arr->length; i++) {");
+                self.emit(&element);
+                self.emit(&format!("        if {} {{", call("val", elem_c_type)));
+                self.emit("            return val;");
+                self.emit("        }");
+                self.emit("    }");
+                self.emit(&format!("    return ({}){{0}};", elem_c_type));
+            }
+        }
+        self.emit("}");
+        self.emit("");
+        Ok(())
+    }
+
+    // Name of the helper running `operation` with a callback over `array`,
+    // whose callback gives `result_c_type`, recording that it's needed.
+    // The preamble covers i32 elements with i32 callbacks.
+    fn callback_helper(&mut self, operation: &str, array: &Expression, result_c_type: &str) -> Result<String, String> {
+        let elem_c_type = self.dynamic_element_c_type(array).unwrap_or_else(|| "int32_t".to_string());
+        if is_fixed_array_c_type(&elem_c_type) || is_fixed_array_c_type(result_c_type) {
+            return Err(format!("`{}` is not supported on arrays of fixed-size arrays", operation));
+        }
+        if elem_c_type == "int32_t" && result_c_type == "int32_t" {
+            return Ok(format!("array_{}_i32", operation));
+        }
+        let suffix = c_type_suffix(&elem_c_type)?;
+        let result_suffix = c_type_suffix(result_c_type)?;
+        self.array_helper_types.insert(elem_c_type.clone());
+        self.array_helper_types.insert(result_c_type.to_string());
+        self.callback_helpers.insert((operation.to_string(), elem_c_type, result_c_type.to_string()));
+        Ok(match operation {
+            "map" | "reduce" => format!("array_{}_{}_{}", operation, suffix, result_suffix),
+            _ => format!("array_{}_{}", operation, suffix),
+        })
+    }
+
+    // Name of the helper for `operation` on `array`'s element type,
+    // recording that the helper is needed
+    fn typed_array_helper(&mut self, operation: &str, array: &Expression) -> Result<String, String> {
+        let elem_c_type = self.dynamic_element_c_type(array).unwrap_or_else(|| "int32_t".to_string());
+        if operation != "push" && is_fixed_array_c_type(&elem_c_type) {
+            return Err(format!("`{}` is not supported on arrays of fixed-size arrays", operation));
+        }
+        let suffix = c_type_suffix(&elem_c_type)?;
+        self.array_helper_types.insert(elem_c_type);
+        Ok(format!("array_{}_{}", operation, suffix))
+    }
+
+    /// A C header defining the program's exported types and declaring its
+    /// exported functions and variables, or None when nothing is exported
+    pub fn generate_header(&self, program: &Program, guard: &str) -> Option<String> {
+        // Types come first, since the declarations may use them
+        let types: Vec<String> = ordered_type_declarations(program)
+            .ok()?
+            .into_iter()
+            .filter_map(|stmt| match stmt {
+                Statement::StructDecl { name, fields, is_export: true, .. } => Some(self.struct_typedef(name, fields)),
+                Statement::EnumDecl { name, variants, is_export: true } => Some(enum_typedef(name, variants)),
+                _ => None,
+            })
+            .collect();
+        let mut declarations = Vec::new();
+        for stmt in &program.statements {
+            match stmt {
+                Statement::FunctionDecl { name, params, return_type, is_export: true, .. } => {
+                    let ret_type = return_type
+                        .as_ref()
+                        .map(|t| self.type_to_c(t))
+                        .unwrap_or_else(|| "void".to_string());
+                    let params: Vec<String> = params
+                        .iter()
+                        .map(|p| format!("{} {}", self.type_to_c(&p.param_type), p.name))
+                        .collect();
+                    let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+                    declarations.push(format!("{} {}({});", ret_type, name, params));
+                }
+                Statement::VariableDecl { name, var_type: Some(ty), is_export: true, .. } => {
+                    declarations.push(format!("extern {} {};", self.type_to_c(ty), name));
+                }
+                _ => {}
+            }
+        }
+
+        if types.is_empty() && declarations.is_empty() {
+            return None;
+        }
+
+        let guard = format!(
+            "HILOW_{}_H",
+            guard.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect::<String>()
+        );
+        let mut header = format!("#ifndef {guard}\n#define {guard}\n\n");
+        header.push_str("#include <stdint.h>\n#include <stdbool.h>\n\n");
+        for declaration in types.into_iter().chain(declarations) {
+            header.push_str(&declaration);
+            header.push('\n');
+        }
+        header.push_str(&format!("\n#endif // {}\n", guard));
+        Some(header)
+    }
+
+    /// Reject programs that rely on features the target can't provide
+    fn check_target_support(&self, program: &Program) -> Result<(), String> {
+        if self.options.target.has_int128 {
+            return Ok(());
+        }
+
+        let mut collector = TypeCollector::default();
+        collector.visit_program(program);
+
+        for ty in collector.types {
+            if type_uses_int128(ty) {
+                return Err(format!(
+                    "Type '{}' requires 128-bit integers, which target '{}' does not support",
+                    ty, self.options.target
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn generate_function_declaration(
+        &mut self,
+        name: &str,
+        params: &[Parameter],
+        return_type: &Option<Type>,
+        is_export: bool,
+    ) -> Result<(), String> {
+        let ret_type = return_type
+            .as_ref()
+            .map(|t| self.type_to_c(t))
+            .unwrap_or_else(|| "void".to_string());
+
+        self.emit_no_indent(linkage(name, is_export));
+        self.emit_no_indent(&ret_type);
+        self.emit_no_indent(" ");
+        self.emit_no_indent(name);
+        self.emit_no_indent("(");
+
+        if is_entry_point(name, params) {
+            self.emit_no_indent("int __argc, char** __argv");
+        }
+
+        for (i, param) in params.iter().enumerate() {
+            if i > 0 {
+                self.emit_no_indent(", ");
+            }
+            self.emit_no_indent(&self.param_c_type(name, i, param));
+            self.emit_no_indent(" ");
+            self.emit_no_indent(&param.name);
+        }
+
+        self.emit_no_indent(")");
+        self.output.push_str(";\n");
+
+        Ok(())
+    }
+
+    fn generate_statement(&mut self, stmt: &Statement) -> Result<(), String> {
+        match stmt {
+            Statement::FunctionDecl {
+                name,
+                params,
+                return_type,
+                requires,
+                ensures,
+                body,
+                is_export,
+                is_pure: _,
+            } => {
+                let ret_type = return_type
+                    .as_ref()
+                    .map(|t| self.type_to_c(t))
+                    .unwrap_or_else(|| "void".to_string());
+
+                self.emit_no_indent(linkage(name, *is_export));
+                self.emit_no_indent(&ret_type);
+                self.emit_no_indent(" ");
+                self.emit_no_indent(name);
+                self.emit_no_indent("(");
+
+                self.enter_scope();
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        self.emit_no_indent(", ");
+                    }
+                    self.emit_no_indent(&self.param_c_type(name, i, param));
+                    self.emit_no_indent(" ");
+                    self.emit_no_indent(&param.name);
+
+                    // Store variable type; the receiver is used through
+                    // its pointer
+                    if i == 0 && self.methods.contains(name) {
+                        self.pointers.insert(param.name.clone(), param.name.clone());
+                    }
+                    self.variables.insert(param.name.clone(), self.type_to_c(&param.param_type));
+                    self.declared_types.insert(param.name.clone(), param.param_type.clone());
+                    self.record_array_elements(&param.name, &param.param_type);
+                }
+
+                if is_entry_point(name, params) {
+                    self.emit_no_indent("int __argc, char** __argv");
                 }
 
                 self.emit_no_indent(") {");
                 self.output.push('\n');
 
                 self.indent_level += 1;
+                if self.options.checked {
+                    self.emit(&format!(
+                        "__attribute__((cleanup(call_stack_leave))) int __frame = call_stack_enter(\"{}\");",
+                        name
+                    ));
+                }
+                if is_entry_point(name, params) {
+                    self.emit("DynamicArray* __args = program_args();");
+                    self.emit("for (int __i = 1; __i < __argc; __i++) array_push_string(__args, __argv[__i]);");
+                }
+                for condition in requires {
+                    self.generate_assert(condition)?;
+                }
+
+                let outer_ensures = std::mem::replace(&mut self.current_ensures, ensures.clone());
                 self.generate_block(body)?;
+                if return_type.is_none() && !matches!(body.statements.last(), Some(Statement::Return { .. })) {
+                    for condition in ensures {
+                        self.generate_assert(condition)?;
+                    }
+                }
+                self.current_ensures = outer_ensures;
                 self.indent_level -= 1;
+                self.leave_scope();
 
                 self.emit("}");
                 self.emit("");
@@ -394,41 +1535,84 @@ impl CodeGenerator {
                 var_type,
                 initializer,
                 is_export: _,
+                is_mut,
             } => {
+                match var_type {
+                    Some(ty) => self.declared_types.insert(name.clone(), ty.clone()),
+                    None => self.declared_types.remove(name),
+                };
+
                 // Special handling for dynamic arrays (no size)
                 if let Some(Type::Array { element_type, size: None }) = var_type {
-                    let elem_c_type = self.type_to_c(element_type);
+                    let elem_c_type = self.element_c_type(element_type);
                     self.variables.insert(name.clone(), "DynamicArray*".to_string());
+                    self.array_element_types.insert(name.clone(), elem_c_type.clone());
 
                     self.emit_no_indent(&self.indent());
                     self.emit_no_indent("DynamicArray* ");
                     self.emit_no_indent(name);
 
-                    if let Some(init) = initializer {
-                        // Use the initializer (e.g., from split())
-                        self.emit_no_indent(" = ");
-                        self.generate_expression(init)?;
-                    } else {
-                        // Create new empty array
-                        self.emit_no_indent(" = ");
-                        self.emit_no_indent(&format!("array_new(sizeof({}))", elem_c_type));
+                    match initializer {
+                        // A literal starts empty and pushes each element below
+                        Some(init) if !matches!(init, Expression::ArrayLiteral { .. }) => {
+                            // Use the initializer (e.g., from split())
+                            self.emit_no_indent(" = ");
+                            self.generate_expression(init)?;
+                        }
+                        _ => {
+                            // Create new empty array
+                            self.emit_no_indent(" = ");
+                            self.emit_no_indent(&format!("array_new(sizeof({}))", elem_c_type));
+                        }
                     }
 
                     self.output.push_str(";\n");
-                } else if let Some(Type::Array { element_type, size: Some(size) }) = var_type {
-                    // Fixed-size arrays
+
+                    if let Some(Expression::ArrayLiteral { elements }) = initializer {
+                        let push = self.typed_array_helper("push", &Expression::Identifier(name.clone()))?;
+                        for element in elements {
+                            self.emit_no_indent(&self.indent());
+                            self.emit_no_indent(&format!("{}({}, ", push, name));
+                            self.generate_array_element(element, element_type)?;
+                            self.output.push_str(");\n");
+                        }
+                    }
+                } else if let Some(array_type @ Type::Array { element_type, size: Some(size) }) = var_type {
+                    // Fixed-size arrays; nested ones become a C array
+                    // of arrays, `int32_t m[3][2]`
                     let elem_c_type = self.type_to_c(element_type);
                     self.variables.insert(name.clone(), format!("{}*", elem_c_type));
 
+                    let mut base_type = element_type.as_ref();
+                    let mut dimensions = format!("[{}]", size);
+                    while let Type::Array { element_type, size: Some(size) } = base_type {
+                        dimensions.push_str(&format!("[{}]", size));
+                        base_type = element_type;
+                    }
+
                     self.emit_no_indent(&self.indent());
-                    self.emit_no_indent(&elem_c_type);
+                    self.emit_no_indent(&self.type_to_c(base_type));
                     self.emit_no_indent(" ");
                     self.emit_no_indent(name);
-                    self.emit_no_indent(&format!("[{}]", size));
-
-                    if let Some(init) = initializer {
+                    self.emit_no_indent(&dimensions);
+
+                    if let Some(Expression::ArrayRepeat { value, count }) = initializer {
+                        if **count != Expression::IntegerLiteral(*size as i64) {
+                            return Err(format!(
+                                "Repeat count for '{}' must be the array size {}",
+                                name, size
+                            ));
+                        }
+                        if is_zero_literal(value) {
+                            self.emit_no_indent(" = {0}");
+                        } else if *size > 0 {
+                            self.emit_no_indent(&format!(" = {{ [0 ... {}] = ", size - 1));
+                            self.generate_expression(value)?;
+                            self.emit_no_indent(" }");
+                        }
+                    } else if let Some(init) = initializer {
                         self.emit_no_indent(" = ");
-                        self.generate_expression(init)?;
+                        self.generate_typed_value(init, array_type)?;
                     }
 
                     self.output.push_str(";\n");
@@ -436,27 +1620,34 @@ impl CodeGenerator {
                     let c_type = if let Some(t) = var_type {
                         self.type_to_c(t)
                     } else {
-                        // Try to infer from initializer
-                        if let Some(Expression::IntegerLiteral(_)) = initializer {
-                            "int32_t".to_string()
-                        } else if let Some(Expression::FloatLiteral(_)) = initializer {
-                            "double".to_string()
-                        } else if let Some(Expression::StringLiteral(_)) = initializer {
-                            "char*".to_string()
-                        } else if let Some(Expression::BooleanLiteral(_)) = initializer {
-                            "bool".to_string()
+                        // Type checking records the numbers, bools and strings
+                        // it resolves (see sema::record_types); infer the rest
+                        // from the initializer
+                        if let Some(Expression::CharLiteral(_)) = initializer {
+                            "char".to_string()
+                        } else if let Some(Expression::Cast { target_type, .. }) = initializer {
+                            self.type_to_c(target_type)
+                        } else if let Some(c_type) = initializer
+                            .as_ref()
+                            .filter(|init| {
+                                matches!(
+                                    init,
+                                    Expression::Conditional { .. }
+                                        | Expression::PropertyAccess { .. }
+                                        | Expression::Binary { .. }
+                                        | Expression::Index { .. }
+                                        | Expression::MethodCall { .. }
+                                )
+                            })
+                            .and_then(|init| self.expression_c_type(init))
+                        {
+                            c_type
                         } else if let Some(Expression::ObjectLiteral { properties }) = initializer {
-                            // Generate struct type from object literal
-                            let mut struct_def = "struct { ".to_string();
-                            for (i, prop) in properties.iter().enumerate() {
-                                if i > 0 {
-                                    struct_def.push_str("; ");
-                                }
-                                struct_def.push_str("int32_t ");
-                                struct_def.push_str(&prop.key);
-                            }
-                            struct_def.push_str("; }");
-                            struct_def
+                            self.object_shape_type(properties)
+                        } else if matches!(initializer, Some(e) if self.is_float_expression(e)) {
+                            "double".to_string()
+                        } else if let Some(ret_type) = self.called_function_return_type(initializer) {
+                            ret_type
                         } else {
                             return Err("Cannot infer type for variable".to_string());
                         }
@@ -466,15 +1657,46 @@ impl CodeGenerator {
 
                     self.emit_no_indent(&self.indent());
                     self.emit_no_indent(&c_type);
+                    // Struct members can still be assigned, so only the
+                    // binding itself is made const
+                    if self.options.strict_mutability && !is_mut && initializer.is_some() && !c_type.starts_with("__Obj_")
+                        && !self.struct_fields.contains_key(&c_type)
+                    {
+                        self.emit_no_indent(" const");
+                    }
                     self.emit_no_indent(" ");
                     self.emit_no_indent(name);
 
-                    if let Some(init) = initializer {
-                        self.emit_no_indent(" = ");
-                        self.generate_expression(init)?;
+                    match (initializer, var_type) {
+                        (Some(Expression::ObjectLiteral { properties }), Some(Type::Named(struct_name)))
+                            if self.struct_fields.contains_key(struct_name) =>
+                        {
+                            self.emit_no_indent(" = ");
+                            self.generate_struct_literal(properties, struct_name)?;
+                        }
+                        (Some(init), Some(ty)) => {
+                            self.emit_no_indent(" = ");
+                            self.generate_typed_value(init, ty)?;
+                        }
+                        (Some(init), None) => {
+                            self.emit_no_indent(" = ");
+                            self.generate_expression(init)?;
+                        }
+                        _ => {}
                     }
 
                     self.output.push_str(";\n");
+
+                    // A local some closure assigns moves into a heap cell
+                    // the closure can point to
+                    if self.shared_captures.contains(name) && !self.variables.at_global_scope() {
+                        self.emit(&format!(
+                            "{t}* __cell_{n} = malloc(sizeof({t})); *__cell_{n} = {n};",
+                            t = c_type,
+                            n = name
+                        ));
+                        self.pointers.insert(name.clone(), format!("__cell_{}", name));
+                    }
                 }
             }
 
@@ -493,15 +1715,33 @@ impl CodeGenerator {
                     self.generate_statement(defer_stmt)?;
                 }
 
-                self.emit_no_indent(&self.indent());
-                self.emit_no_indent("return");
+                if self.current_ensures.is_empty() {
+                    self.emit_no_indent(&self.indent());
+                    self.emit_no_indent("return");
 
-                if let Some(expr) = value {
-                    self.emit_no_indent(" ");
-                    self.generate_expression(expr)?;
-                }
+                    if let Some(expr) = value {
+                        self.emit_no_indent(" ");
+                        self.generate_expression(expr)?;
+                    }
 
-                self.output.push_str(";\n");
+                    self.output.push_str(";\n");
+                } else {
+                    // Bind the value to `result` so postconditions can name it
+                    self.emit("{");
+                    self.indent_level += 1;
+                    if let Some(expr) = value {
+                        self.emit_no_indent(&self.indent());
+                        self.emit_no_indent("__auto_type result = ");
+                        self.generate_expression(expr)?;
+                        self.output.push_str(";\n");
+                    }
+                    for condition in self.current_ensures.clone() {
+                        self.generate_assert(&condition)?;
+                    }
+                    self.emit(if value.is_some() { "return result;" } else { "return;" });
+                    self.indent_level -= 1;
+                    self.emit("}");
+                }
             }
 
             Statement::Expression(expr) => {
@@ -547,7 +1787,7 @@ impl CodeGenerator {
                 self.emit_no_indent(") {\n");
 
                 self.indent_level += 1;
-                self.generate_block(body)?;
+                self.generate_loop_body(body)?;
                 self.indent_level -= 1;
 
                 self.emit("}");
@@ -562,6 +1802,8 @@ impl CodeGenerator {
                 self.emit_no_indent(&self.indent());
                 self.emit_no_indent("for (");
 
+                // The loop variable is only visible in the loop
+                self.enter_scope();
                 if let Some(init_stmt) = init {
                     match init_stmt.as_ref() {
                         Statement::VariableDecl {
@@ -569,6 +1811,7 @@ impl CodeGenerator {
                             var_type,
                             initializer,
                             is_export: _,
+                            is_mut: _,
                         } => {
                             let c_type = var_type
                                 .as_ref()
@@ -581,6 +1824,7 @@ impl CodeGenerator {
                                 self.emit_no_indent(" = ");
                                 self.generate_expression(init)?;
                             }
+                            self.variables.insert(name.clone(), c_type);
                         }
                         _ => return Err("Invalid for loop initializer".to_string()),
                     }
@@ -601,51 +1845,140 @@ impl CodeGenerator {
                 self.emit_no_indent(") {\n");
 
                 self.indent_level += 1;
-                self.generate_block(body)?;
+                self.generate_loop_body(body)?;
                 self.indent_level -= 1;
+                self.leave_scope();
 
                 self.emit("}");
             }
 
-            Statement::ForIn { variable, iterable, body } => {
-                // For now, we'll generate a C-style for loop that iterates over the array
-                // This requires the array to be stored in a variable
-                // We'll use a temporary index variable
-                let index_var = format!("__idx_{}", variable);
+            Statement::ForIn { variable, iterable: Expression::Range { start, end, inclusive }, body } => {
+                // A counting loop; both bounds are evaluated once, in
+                // order, before the first iteration
+                let elem_c_type = match (self.expression_c_type(start), self.expression_c_type(end)) {
+                    (Some(start), Some(end)) => arithmetic_c_type(&start, &end),
+                    _ => "int32_t",
+                };
+                let start_var = self.fresh_temp(&format!("start_{}", variable));
+                let end_var = self.fresh_temp(&format!("end_{}", variable));
+                self.emit("{");
+                self.indent_level += 1;
+                for (bound_var, bound) in [(&start_var, start), (&end_var, end)] {
+                    self.emit_no_indent(&self.indent());
+                    self.emit_no_indent(&format!("{} {} = ", elem_c_type, bound_var));
+                    self.generate_expression(bound)?;
+                    self.output.push_str(";\n");
+                }
+                self.emit(&format!(
+                    "for ({ty} {var} = {start}; {var} {cmp} {end}; {var}++) {{",
+                    ty = elem_c_type,
+                    var = variable,
+                    start = start_var,
+                    cmp = if *inclusive { "<=" } else { "<" },
+                    end = end_var
+                ));
+                self.indent_level += 1;
 
-                self.emit_no_indent(&self.indent());
-                self.emit_no_indent(&format!("for (int32_t {} = 0; {} < ", index_var, index_var));
+                self.enter_scope();
+                self.variables.insert(variable.clone(), elem_c_type.to_string());
+                self.generate_loop_body(body)?;
+                self.leave_scope();
 
-                // We need to know the array size - for now we'll use a macro-style approach
-                // that requires the array to be in a variable
-                self.emit_no_indent("sizeof(");
-                self.generate_expression(iterable)?;
-                self.emit_no_indent(")/sizeof((");
-                self.generate_expression(iterable)?;
-                self.emit_no_indent(")[0]); ");
+                self.indent_level -= 1;
+                self.emit("}");
+                self.indent_level -= 1;
+                self.emit("}");
+            }
 
-                self.emit_no_indent(&format!("{}++) {{\n", index_var));
+            Statement::ForIn { variable, iterable, body } => {
+                // Bound the loop by the array's real length: `->length` for
+                // a dynamic array, the declared size for a fixed one
+                let index_var = self.fresh_temp(&format!("idx_{}", variable));
+                let array_name = match iterable {
+                    Expression::Identifier(name) => Some(name.as_str()),
+                    _ => None,
+                };
+                let is_dynamic = array_name
+                    .and_then(|name| self.variables.get(name))
+                    .is_some_and(|t| t == "DynamicArray*");
+
+                let (length, elem_c_type) = match (iterable, array_name) {
+                    (_, Some(name)) if is_dynamic => (
+                        format!("{}->length", name),
+                        self.array_element_types.get(name).cloned().unwrap_or_else(|| "int32_t".to_string()),
+                    ),
+                    (_, Some(name)) => match self.declared_types.get(name) {
+                        Some(Type::Array { element_type, size: Some(size) }) => {
+                            (size.to_string(), self.type_to_c(element_type))
+                        }
+                        _ => return Err(format!("Cannot iterate over '{}': it is not an array", name)),
+                    },
+                    (Expression::ArrayLiteral { elements }, None) => (
+                        elements.len().to_string(),
+                        elements
+                            .first()
+                            .and_then(|first| self.expression_c_type(first))
+                            .unwrap_or_else(|| "int32_t".to_string()),
+                    ),
+                    _ => return Err("for-in can only iterate over an array variable or literal".to_string()),
+                };
 
+                self.emit(&format!(
+                    "for (int32_t {i} = 0; {i} < {len}; {i}++) {{",
+                    i = index_var,
+                    len = length
+                ));
                 self.indent_level += 1;
 
-                // Declare the loop variable
-                self.emit(&format!("int32_t {} = (", variable));
+                // Declare the loop variable; a fixed-size array element is
+                // reached through a pointer to it
+                let element_type = match array_name.and_then(|name| self.declared_types.get(name)) {
+                    Some(Type::Array { element_type, .. }) => Some(element_type.as_ref().clone()),
+                    _ => None,
+                };
+                let variable_c_type = match &element_type {
+                    Some(element_type) if is_fixed_array_c_type(&elem_c_type) => self.type_to_c(element_type),
+                    _ => elem_c_type.clone(),
+                };
                 self.emit_no_indent(&self.indent());
-                self.generate_expression(iterable)?;
-                self.emit_no_indent(&format!(")[{}];\n", index_var));
-
-                // Generate loop body
-                self.generate_block(body)?;
+                self.emit_no_indent(&format!("{} {} = ", variable_c_type, variable));
+                if is_dynamic {
+                    self.emit_no_indent(&format!("(({}*)", elem_c_type));
+                    self.generate_expression(iterable)?;
+                    self.emit_no_indent("->data)");
+                } else if let Expression::ArrayLiteral { .. } = iterable {
+                    self.emit_no_indent(&format!("({}[])", elem_c_type));
+                    self.generate_expression(iterable)?;
+                } else {
+                    self.generate_expression(iterable)?;
+                }
+                self.output.push_str(&format!("[{}];\n", index_var));
+
+                self.enter_scope();
+                self.variables.insert(variable.clone(), variable_c_type);
+                // An array element keeps its type, for indexing it
+                if let Some(element_type @ Type::Array { .. }) = element_type {
+                    self.record_array_elements(variable, &element_type);
+                    self.declared_types.insert(variable.clone(), element_type);
+                }
+                self.generate_loop_body(body)?;
+                self.leave_scope();
 
                 self.indent_level -= 1;
                 self.emit("}");
             }
 
             Statement::Break => {
+                if let Some(&depth) = self.break_defer_depths.last() {
+                    self.generate_defers_from(depth)?;
+                }
                 self.emit("break;");
             }
 
             Statement::Continue => {
+                if let Some(&depth) = self.continue_defer_depths.last() {
+                    self.generate_defers_from(depth)?;
+                }
                 self.emit("continue;");
             }
 
@@ -658,28 +1991,48 @@ impl CodeGenerator {
             }
 
             Statement::Switch { expr, cases, default } => {
+                // A bool scrutinee is switched on as an int, which C
+                // accepts without a warning
+                let is_bool = self.expression_c_type(expr).as_deref() == Some("bool");
                 self.emit_no_indent(&self.indent());
-                self.emit_no_indent("switch (");
+                self.emit_no_indent(if is_bool { "switch ((int)(" } else { "switch (" });
                 self.generate_expression(expr)?;
-                self.emit_no_indent(") {\n");
+                self.emit_no_indent(if is_bool { ")) {\n" } else { ") {\n" });
 
                 self.indent_level += 1;
 
                 for case in cases {
                     self.emit_no_indent(&self.indent());
                     self.emit_no_indent("case ");
-                    self.generate_expression(&case.value)?;
+                    // C case labels must be integer constants
+                    match &case.value {
+                        Expression::BooleanLiteral(b) => self.emit_no_indent(if *b { "1" } else { "0" }),
+                        Expression::StringLiteral(_) | Expression::FString { .. } | Expression::FloatLiteral(_) => {
+                            return Err("`switch` cases must be integer, bool or character constants; use `match` for other values".to_string());
+                        }
+                        value => self.generate_expression(value)?,
+                    }
                     self.emit_no_indent(":\n");
 
                     self.indent_level += 1;
+                    self.break_defer_depths.push(self.defer_stack.len());
                     self.generate_block(&case.body)?;
+                    self.break_defer_depths.pop();
+
+                    // Cases don't fall through unless asked to; an empty case
+                    // shares the next case's body
+                    if !case.fallthrough && !case.body.statements.is_empty() && !ends_in_jump(&case.body) {
+                        self.emit("break;");
+                    }
                     self.indent_level -= 1;
                 }
 
                 if let Some(default_block) = default {
                     self.emit("default:");
                     self.indent_level += 1;
+                    self.break_defer_depths.push(self.defer_stack.len());
                     self.generate_block(default_block)?;
+                    self.break_defer_depths.pop();
                     self.indent_level -= 1;
                 }
 
@@ -687,9 +2040,10 @@ impl CodeGenerator {
                 self.emit("}");
             }
 
-            Statement::Import { .. } => {
-                // Import statements don't generate code (handled at link time)
-                // For now, we're single-file, so ignore
+            Statement::Import { names, module } => {
+                // The module loader has already merged in the imported
+                // declarations; leave a marker
+                self.emit(&format!("// import {} from \"{}\"", names.join(", "), module));
             }
 
             Statement::Block(block) => {
@@ -699,16 +2053,55 @@ impl CodeGenerator {
                 self.indent_level -= 1;
                 self.emit("}");
             }
+
+            // Top-level structs and enums are emitted before any function
+            Statement::StructDecl { name, .. } | Statement::EnumDecl { name, .. } => {
+                if self.indent_level > 0 {
+                    return Err(format!("Struct `{}` must be declared at the top level", name));
+                }
+            }
         }
 
         Ok(())
     }
 
+    fn generate_struct_typedef(&mut self, name: &str, fields: &[Parameter]) {
+        self.emit(&self.struct_typedef(name, fields));
+        self.emit("");
+        let fields = fields
+            .iter()
+            .map(|field| (field.name.clone(), field.param_type.clone()))
+            .collect();
+        self.struct_fields.insert(name.to_string(), fields);
+    }
+
+    // `typedef struct { ... } Name;`, without a trailing newline
+    fn struct_typedef(&self, name: &str, fields: &[Parameter]) -> String {
+        let mut typedef = "typedef struct {\n".to_string();
+        for field in fields {
+            // A fixed-size array is held inline, `int32_t scores[3]`
+            let mut base_type = &field.param_type;
+            let mut dimensions = String::new();
+            while let Type::Array { element_type, size: Some(size) } = base_type {
+                dimensions.push_str(&format!("[{}]", size));
+                base_type = element_type;
+            }
+            typedef.push_str(&format!("    {} {}{};\n", self.type_to_c(base_type), field.name, dimensions));
+        }
+        typedef.push_str(&format!("}} {};", name));
+        typedef
+    }
+
     fn generate_block(&mut self, block: &Block) -> Result<(), String> {
         // Push new defer scope
         self.defer_stack.push(Vec::new());
+        self.enter_scope();
 
-        for stmt in &block.statements {
+        for (i, stmt) in block.statements.iter().enumerate() {
+            // Point the C compiler and debuggers back at the HiLow line
+            if let (Some(source), Some(line)) = (&block.source, block.spans.get(i).map(|span| span.line)) {
+                self.emit_no_indent(&format!("#line {} \"{}\"\n", line, escape_c_string(source)));
+            }
             self.generate_statement(stmt)?;
         }
 
@@ -718,62 +2111,640 @@ impl CodeGenerator {
                 self.generate_statement(defer_stmt)?;
             }
         }
+        self.leave_scope();
 
         Ok(())
     }
 
-    fn generate_expression(&mut self, expr: &Expression) -> Result<(), String> {
-        match expr {
-            Expression::IntegerLiteral(n) => {
-                self.emit_no_indent(&n.to_string());
-            }
+    // Variables declared from here on are forgotten at the matching
+    // leave_scope, uncovering any outer variables they shadowed
+    fn enter_scope(&mut self) {
+        self.variables.push();
+        self.array_element_types.push();
+        self.declared_types.push();
+        self.pointers.push();
+    }
 
-            Expression::FloatLiteral(f) => {
-                self.emit_no_indent(&f.to_string());
-            }
+    fn leave_scope(&mut self) {
+        self.variables.pop();
+        self.array_element_types.pop();
+        self.declared_types.pop();
+        self.pointers.pop();
+    }
 
-            Expression::StringLiteral(s) => {
-                self.emit_no_indent(&format!("\"{}\"", s.escape_default()));
+    // The pointer `name` is reached through, unless something declared
+    // since shadows it
+    fn pointer_to(&self, name: &str) -> Option<&String> {
+        let depth = self.pointers.depth_of(name)?;
+        (Some(depth) == self.variables.depth_of(name)).then(|| self.pointers.get(name)).flatten()
+    }
+
+    fn generate_loop_body(&mut self, body: &Block) -> Result<(), String> {
+        let depth = self.defer_stack.len();
+        self.break_defer_depths.push(depth);
+        self.continue_defer_depths.push(depth);
+
+        let result = self.generate_block(body);
+
+        self.break_defer_depths.pop();
+        self.continue_defer_depths.pop();
+        result
+    }
+
+    // Emit, innermost first, the defers registered in scopes at or above
+    // `depth` without popping them (used when jumping out early)
+    fn generate_defers_from(&mut self, depth: usize) -> Result<(), String> {
+        let pending: Vec<Statement> = self.defer_stack[depth..]
+            .iter()
+            .flatten()
+            .cloned()
+            .collect();
+
+        for defer_stmt in pending.iter().rev() {
+            self.generate_statement(defer_stmt)?;
+        }
+
+        Ok(())
+    }
+
+    // The typedef, parameter types and return type of a closure variable
+    fn closure_signature(&self, name: &str) -> Option<(String, Vec<String>, String)> {
+        let typedef = self.variables.get(name)?;
+        let index: usize = typedef.strip_prefix("__fn_type_")?.parse().ok()?;
+        let Type::Function { params, return_type } = self.function_types.get(index)? else {
+            return None;
+        };
+        let param_types = params.iter().map(|param| self.type_to_c(param)).collect();
+        Some((typedef.clone(), param_types, self.type_to_c(return_type)))
+    }
+
+    fn called_function_return_type(&self, initializer: &Option<Expression>) -> Option<String> {
+        if let Some(Expression::Call { resolved_type: Some(ty), .. }) = initializer {
+            return Some(self.type_to_c(ty));
+        }
+        if let Some(Expression::Call { callee, .. }) = initializer {
+            if let Expression::Identifier(name) = callee.as_ref() {
+                if let Some((_, _, ret_type)) = self.closure_signature(name) {
+                    return Some(ret_type);
+                }
+                if let Some(ret_type) = builtin_return_type(name) {
+                    return Some(ret_type.to_string());
+                }
+                return self.function_return_types.get(name).cloned();
             }
+        }
+        None
+    }
 
-            Expression::FString { parts } => {
-                use crate::ast::FStringPart;
-
-                // For f-strings, we'll generate a format string and printf call
-                // First pass: build the format string and collect arguments
-                let mut format_str = String::new();
-                let mut args = Vec::new();
-
-                for part in parts {
-                    match part {
-                        FStringPart::Text(text) => {
-                            // Escape % signs for printf
-                            format_str.push_str(&text.replace("%", "%%"));
-                        }
-                        FStringPart::Expression(expr) => {
-                            // Simple type inference for format specifier
-                            // This is a simplification - a real implementation would use proper type checking
-                            format_str.push_str("%d"); // Default to integer for now
-                            args.push(expr);
-                        }
+    // Bind an array passed to a C helper taking a plain element pointer and
+    // a count to a temp, evaluating it once, and give back the pointer and
+    // count to pass. A literal becomes a compound literal, a fixed-size
+    // array passes itself and its declared size, and a dynamic array its
+    // data buffer and length.
+    fn bind_array_argument(&mut self, array: &Expression, elem_c_type: &str) -> Result<(String, String), String> {
+        let temp = self.fresh_temp("array");
+        match (array, self.array_type_of(array)) {
+            (Expression::ArrayLiteral { elements }, _) => {
+                self.emit_no_indent(&format!("{}* {} = ({}[])", elem_c_type, temp, elem_c_type));
+                self.generate_expression(array)?;
+                self.emit_no_indent("; ");
+                Ok((temp, elements.len().to_string()))
+            }
+            (_, Some(Type::Array { size: Some(size), .. })) => {
+                self.emit_no_indent(&format!("{}* {} = ", elem_c_type, temp));
+                self.generate_expression(array)?;
+                self.emit_no_indent("; ");
+                Ok((temp, size.to_string()))
+            }
+            _ if self.is_dynamic_array(array) => {
+                self.emit_no_indent(&format!("DynamicArray* {} = ", temp));
+                self.generate_expression(array)?;
+                self.emit_no_indent("; ");
+                Ok((format!("({}*){}->data", elem_c_type, temp), format!("{}->length", temp)))
+            }
+            _ => Err("Cannot tell the length of an array passed to print_row(); declare its type".to_string()),
+        }
+    }
+
+    // printf format string and arguments for an f-string's parts
+    fn fstring_format<'e>(&self, parts: &'e [FStringPart]) -> Result<(String, Vec<FormatArgument<'e>>), String> {
+        let mut format_str = String::new();
+        let mut fstring_args = Vec::new();
+
+        for part in parts {
+            match part {
+                FStringPart::Text(text) => {
+                    format_str.push_str(&escape_c_string(text).replace("%", "%%"));
+                }
+                FStringPart::Expression(expr, spec) => {
+                    let c_type = self.expression_c_type(expr);
+                    match spec.as_deref() {
+                        Some(spec) => format_str.push_str(&spec_format_specifier(spec, c_type.as_deref())?),
+                        None => format_str.push_str(format_specifier(c_type.as_deref())),
+                    }
+                    fstring_args.push((expr.as_ref(), spec.as_deref()));
+                }
+            }
+        }
+
+        Ok((format_str, fstring_args))
+    }
+
+    fn is_float_expression(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::FloatLiteral(_) => true,
+            Expression::Identifier(name) => match self.variables.get(name) {
+                Some(c_type) => c_type == "double" || c_type == "float",
+                None => name == "inf" || name == "nan",
+            },
+            Expression::Unary { operand, .. } => self.is_float_expression(operand),
+            Expression::Cast { target_type, .. } => matches!(target_type, Type::F32 | Type::F64),
+            _ => false,
+        }
+    }
+
+    // Best-effort C type of an expression, from the types sema resolved,
+    // literal kinds, the types recorded for variables and functions, and
+    // the types of operands
+    fn expression_c_type(&self, expr: &Expression) -> Option<String> {
+        if let Some(ty) = resolved_type(expr) {
+            return Some(self.type_to_c(ty));
+        }
+        let c_type = match expr {
+            Expression::IntegerLiteral(n) if i32::try_from(*n).is_ok() => "int32_t",
+            Expression::IntegerLiteral(_) => "int64_t",
+            Expression::FloatLiteral(_) => "double",
+            Expression::StringLiteral(_) | Expression::FString { .. } => "char*",
+            Expression::BooleanLiteral(_) => "bool",
+            Expression::CharLiteral(_) => "char",
+            Expression::Identifier(name) => {
+                return match self.variables.get(name) {
+                    Some(c_type) => Some(c_type.clone()),
+                    None if name == "inf" || name == "nan" => Some("double".to_string()),
+                    None => None,
+                };
+            }
+            Expression::Unary { op: UnaryOp::Not, .. } => "bool",
+            Expression::Unary { operand, .. } => return self.expression_c_type(operand),
+            Expression::Binary { left, op, right } => match op {
+                BinaryOp::Add
+                | BinaryOp::Subtract
+                | BinaryOp::Multiply
+                | BinaryOp::Divide
+                | BinaryOp::Modulo
+                | BinaryOp::BitwiseAnd
+                | BinaryOp::BitwiseOr
+                | BinaryOp::BitwiseXor => {
+                    let left = self.expression_c_type(left)?;
+                    let right = self.expression_c_type(right)?;
+                    if matches!(op, BinaryOp::Add) && left == "char*" && right == "char*" {
+                        "char*"
+                    } else {
+                        arithmetic_c_type(&left, &right)
+                    }
+                }
+                BinaryOp::ShiftLeft | BinaryOp::ShiftRight => return self.expression_c_type(left),
+                _ => "bool",
+            },
+            Expression::Cast { target_type, .. } => return Some(self.type_to_c(target_type)),
+            // The first arm whose type is known decides
+            Expression::Match { arms, .. } => return arms.iter().find_map(|arm| self.expression_c_type(&arm.body)),
+            Expression::Conditional { then, otherwise, .. } => {
+                return match (self.expression_c_type(then), self.expression_c_type(otherwise)) {
+                    (Some(l), Some(r)) if l != r && l != "char*" && r != "char*" => Some(arithmetic_c_type(&l, &r).to_string()),
+                    (l, r) => l.or(r),
+                };
+            }
+            Expression::Call { .. } => return self.called_function_return_type(&Some(expr.clone())),
+            Expression::Index { array, .. } => match self.array_type_of(array) {
+                Some(Type::Array { element_type, .. }) => return Some(self.type_to_c(&element_type)),
+                None => return self.dynamic_element_c_type(array),
+                Some(_) => return None,
+            },
+            Expression::PropertyAccess { property, .. } if property == "length" => "int32_t",
+            Expression::PropertyAccess { object, .. } if self.enum_variant_of(object).is_some() => {
+                return self.enum_variant_of(object).map(str::to_string);
+            }
+            Expression::PropertyAccess { object, property } => {
+                return self.object_field_c_type(&self.expression_c_type(object)?, property);
+            }
+            Expression::MethodCall { object, method, .. } if self.method_function(object, method).is_some() => {
+                return self.method_function(object, method).and_then(|function| self.function_return_types.get(&function).cloned());
+            }
+            Expression::MethodCall { object, method, .. } => match method.as_str() {
+                "pop" => match object.as_ref() {
+                    Expression::Identifier(name) => return self.array_element_types.get(name).cloned(),
+                    _ => return None,
+                },
+                "toUpperCase" | "toLowerCase" | "trim" | "trimStart" | "trimEnd" | "charAt" | "substring" | "concat" | "padStart"
+                | "padEnd" | "replace" | "replaceAll" | "join" => "char*",
+                "indexOf" | "lastIndexOf" | "compare" | "count" | "occurrences" => "int32_t",
+                "unique" | "dedup" => return self.expression_c_type(object),
+                "avg" => "double",
+                "hash" => "uint64_t",
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(c_type.to_string())
+    }
+
+    // Position of `needle` in `haystack`, or -1; the haystack is only
+    // evaluated once
+    fn generate_index_of(&mut self, haystack: &Expression, needle: &Expression) -> Result<(), String> {
+        let str_var = self.fresh_temp("str");
+        let pos_var = self.fresh_temp("pos");
+        self.emit_no_indent(&format!("({{const char* {} = ", str_var));
+        self.generate_expression(haystack)?;
+        self.emit_no_indent(&format!("; char* {} = strstr({}, ", pos_var, str_var));
+        self.generate_expression(needle)?;
+        self.emit_no_indent(&format!(
+            "); {pos} ? (int32_t)({pos} - {str}) : -1; }})",
+            pos = pos_var,
+            str = str_var
+        ));
+        Ok(())
+    }
+
+    fn generate_assert(&mut self, condition: &Expression) -> Result<(), String> {
+        self.emit_no_indent(&self.indent());
+        self.emit_no_indent("assert(");
+        self.generate_expression(condition)?;
+        self.output.push_str(");\n");
+        Ok(())
+    }
+
+    // The declared array type of `expr`, following indexing into nested
+    // arrays, or the array type sema resolved for a call
+    fn array_type_of(&self, expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Call { resolved_type: Some(ty @ Type::Array { .. }), .. }
+            | Expression::MethodCall { resolved_type: Some(ty @ Type::Array { .. }), .. } => Some(ty.clone()),
+            Expression::Identifier(name) => match self.declared_types.get(name) {
+                Some(ty @ Type::Array { .. }) => Some(ty.clone()),
+                _ => None,
+            },
+            Expression::Index { array, .. } => match self.array_type_of(array)? {
+                Type::Array { element_type, .. } if matches!(*element_type, Type::Array { .. }) => Some(*element_type),
+                _ => None,
+            },
+            // An array field of a struct
+            Expression::PropertyAccess { object, property } => {
+                let struct_name = self.expression_c_type(object)?;
+                let (_, ty) = self.struct_fields.get(&struct_name)?.iter().find(|(field, _)| field == property)?;
+                matches!(ty, Type::Array { .. }).then(|| ty.clone())
+            }
+            _ => None,
+        }
+    }
+
+    // C type of the elements of the dynamic array `array`, when known
+    fn dynamic_element_c_type(&self, array: &Expression) -> Option<String> {
+        match (self.array_type_of(array), array) {
+            (Some(Type::Array { element_type, size: None }), _) => Some(self.element_c_type(&element_type)),
+            (_, Expression::Identifier(name)) => self.array_element_types.get(name).cloned(),
+            _ => None,
+        }
+    }
+
+    // A value of type `ty`, such as an array element or a struct field.
+    // A literal for a dynamic array is built in place:
+    // `({ DynamicArray* row = array_new(...); array_push_i32(row, 1); row; })`,
+    // and an object literal for a struct becomes a compound literal whose
+    // fields are built the same way
+    fn generate_typed_value(&mut self, value: &Expression, ty: &Type) -> Result<(), String> {
+        match (value, ty) {
+            (Expression::ArrayLiteral { elements }, Type::Array { element_type: inner, size: None }) => {
+                let inner_c_type = self.element_c_type(inner);
+                let suffix = c_type_suffix(&inner_c_type)?;
+                self.array_helper_types.insert(inner_c_type.clone());
+                let row = self.fresh_temp("row");
+                self.emit_no_indent(&format!("({{ DynamicArray* {} = array_new(sizeof({})); ", row, inner_c_type));
+                for item in elements {
+                    self.emit_no_indent(&format!("array_push_{}({}, ", suffix, row));
+                    self.generate_array_element(item, inner)?;
+                    self.emit_no_indent("); ");
+                }
+                self.emit_no_indent(&format!("{}; }})", row));
+            }
+            // The braced initializer of a fixed-size array
+            (Expression::ArrayLiteral { elements }, Type::Array { element_type: inner, size: Some(_) }) => {
+                self.emit_no_indent("{");
+                for (i, item) in elements.iter().enumerate() {
+                    if i > 0 {
+                        self.emit_no_indent(", ");
+                    }
+                    self.generate_typed_value(item, inner)?;
+                }
+                self.emit_no_indent("}");
+            }
+            (Expression::ObjectLiteral { properties }, Type::Named(name)) if self.struct_fields.contains_key(name) => {
+                self.emit_no_indent(&format!("({})", name));
+                self.generate_struct_literal(properties, name)?;
+            }
+            _ => self.generate_expression(value)?,
+        }
+        Ok(())
+    }
+
+    // The initializer `{ .name = ..., .scores = ... }` of struct `name`,
+    // each field built as a value of its declared type
+    fn generate_struct_literal(&mut self, properties: &[Property], name: &str) -> Result<(), String> {
+        let fields = self.struct_fields.get(name).cloned().unwrap_or_default();
+        self.emit_no_indent("{ ");
+        for (i, prop) in properties.iter().enumerate() {
+            if i > 0 {
+                self.emit_no_indent(", ");
+            }
+            self.emit_no_indent(&format!(".{} = ", prop.key));
+            match fields.iter().find(|(field, _)| *field == prop.key) {
+                Some((_, field_type)) => self.generate_typed_value(&prop.value, field_type)?,
+                None => self.generate_expression(&prop.value)?,
+            }
+        }
+        self.emit_no_indent(" }");
+        Ok(())
+    }
+
+    // An element pushed onto a dynamic array. A fixed-size array literal
+    // becomes a compound literal, which the push helper copies.
+    fn generate_array_element(&mut self, element: &Expression, element_type: &Type) -> Result<(), String> {
+        if let (Expression::ArrayLiteral { .. }, Type::Array { size: Some(_), .. }) = (element, element_type) {
+            self.emit_no_indent(&format!("({})", self.element_c_type(element_type)));
+        }
+        self.generate_typed_value(element, element_type)
+    }
+
+    // The C type a dynamic array stores elements of `element_type` as. A
+    // fixed-size array is stored by value, as `__typeof__(int32_t[2])`,
+    // rather than as a pointer to it.
+    fn element_c_type(&self, element_type: &Type) -> String {
+        let mut base = element_type;
+        let mut dimensions = String::new();
+        while let Type::Array { element_type, size: Some(size) } = base {
+            dimensions.push_str(&format!("[{}]", size));
+            base = element_type;
+        }
+        if dimensions.is_empty() {
+            self.type_to_c(element_type)
+        } else {
+            format!("__typeof__({}{})", self.type_to_c(base), dimensions)
+        }
+    }
+
+    // Remember the element type of a `[T]` binding, which indexing casts
+    // the array's data to
+    fn record_array_elements(&mut self, name: &str, ty: &Type) {
+        if let Type::Array { element_type, size: None } = ty {
+            let elem_c_type = self.element_c_type(element_type);
+            self.array_element_types.insert(name.to_string(), elem_c_type);
+        }
+    }
+
+    fn is_float_operand(&self, expr: &Expression) -> bool {
+        self.is_float_expression(expr) || matches!(self.expression_c_type(expr).as_deref(), Some("double" | "float"))
+    }
+
+    // `expr` as a double, casting it when it is an integer
+    fn generate_float_operand(&mut self, expr: &Expression) -> Result<(), String> {
+        if self.is_float_operand(expr) {
+            return self.generate_expression(expr);
+        }
+        self.emit_no_indent("(double)(");
+        self.generate_expression(expr)?;
+        self.emit_no_indent(")");
+        Ok(())
+    }
+
+    fn is_string_expression(&self, expr: &Expression) -> bool {
+        self.expression_c_type(expr).as_deref() == Some("char*")
+    }
+
+    // An f-string argument; floats go through float_for_print so NaN
+    // prints the same whatever its sign bit, and 64-bit integers are cast
+    // to match their `%ll` specifier
+    fn generate_format_argument(&mut self, (expr, spec): FormatArgument) -> Result<(), String> {
+        let c_type = self.expression_c_type(expr);
+        let wide = matches!(c_type.as_deref(), Some("int64_t" | "uint64_t"));
+        let conversion = spec.and_then(|spec| split_format_spec(spec).1);
+        let wrapper = match (conversion, c_type.as_deref()) {
+            // printf has no binary conversion
+            (Some('b'), _) if wide => "int_to_binary((uint64_t)(",
+            (Some('b'), _) => "int_to_binary((uint32_t)(",
+            (Some('u' | 'x' | 'X' | 'o'), _) if wide => "(unsigned long long)(",
+            (Some('u' | 'x' | 'X' | 'o'), _) => "(unsigned)(",
+            (Some('f' | 'F' | 'e' | 'E' | 'g' | 'G'), Some("double" | "float")) => "float_for_print(",
+            (Some('f' | 'F' | 'e' | 'E' | 'g' | 'G'), _) => "(double)(",
+            (Some('s') | None, Some("char*")) => "str_for_print(",
+            (Some('s' | 'c'), _) => return self.generate_expression(expr),
+            (_, Some("double" | "float")) => "float_for_print(",
+            (_, Some("int64_t")) => "(long long)(",
+            (_, Some("uint64_t")) => "(unsigned long long)(",
+            (_, _) => return self.generate_expression(expr),
+        };
+        self.emit_no_indent(wrapper);
+        self.generate_expression(expr)?;
+        self.emit_no_indent(if conversion == Some('b') { "))" } else { ")" });
+        Ok(())
+    }
+
+    // Statements printing the C lvalue `value` of HiLow type `ty`, recursing
+    // into array elements. Strings are quoted when `quoted`, which is how
+    // they appear inside containers.
+    fn generate_value_print(&mut self, value: &str, ty: &Type, quoted: bool) -> Result<(), String> {
+        match ty {
+            Type::Array { element_type, size } => {
+                let index = self.fresh_temp("i");
+                let (length, element) = match size {
+                    Some(size) => (size.to_string(), format!("{}[{}]", value, index)),
+                    None => {
+                        let elem_c_type = self.element_c_type(element_type);
+                        (
+                            format!("{}->length", value),
+                            format!("(({}*){}->data)[{}]", elem_c_type, value, index),
+                        )
                     }
+                };
+                self.emit_no_indent(&format!(
+                    "putchar('['); for (size_t {i} = 0; {i} < (size_t)({len}); {i}++) {{ if ({i} > 0) fputs(\", \", stdout); ",
+                    i = index,
+                    len = length
+                ));
+                self.generate_value_print(&element, element_type, true)?;
+                self.emit_no_indent(" } putchar(']');");
+            }
+            Type::String if quoted => self.emit_no_indent(&format!(
+                "if ({v}) printf(\"\\\"%s\\\"\", {v}); else fputs(\"nothing\", stdout);",
+                v = value
+            )),
+            Type::String => self.emit_no_indent(&format!("fputs(str_for_print({}), stdout);", value)),
+            Type::Bool => self.emit_no_indent(&format!("fputs({} ? \"true\" : \"false\", stdout);", value)),
+            Type::F32 | Type::F64 => {
+                self.emit_no_indent(&format!("printf(\"%f\", float_for_print({}));", value))
+            }
+            Type::I64 => self.emit_no_indent(&format!("printf(\"%lld\", (long long){});", value)),
+            Type::U64 => self.emit_no_indent(&format!("printf(\"%llu\", (unsigned long long){});", value)),
+            Type::U32 => self.emit_no_indent(&format!("printf(\"%u\", {});", value)),
+            Type::I8 | Type::I16 | Type::I32 | Type::U8 | Type::U16 => {
+                self.emit_no_indent(&format!("printf(\"%d\", {});", value))
+            }
+            Type::Named(name) if self.enum_names.contains(name) => {
+                self.emit_no_indent(&format!("printf(\"%d\", (int){});", value))
+            }
+            // Structs and objects print as `{name: "Ada", scores: [90, 85]}`
+            Type::Named(name) => {
+                let fields = self
+                    .printed_fields(name)
+                    .ok_or_else(|| format!("Cannot print a value of type {}", name))?;
+                self.emit_no_indent("putchar('{'); ");
+                for (i, (field, field_type)) in fields.iter().enumerate() {
+                    let separator = if i > 0 { ", " } else { "" };
+                    self.emit_no_indent(&format!("fputs(\"{}{}: \", stdout); ", separator, field));
+                    self.generate_value_print(&format!("({}).{}", value, field), field_type, true)?;
+                    self.emit_no_indent(" ");
+                }
+                self.emit_no_indent("putchar('}');");
+            }
+            other => return Err(format!("Cannot print a value of type {:?}", other)),
+        }
+        Ok(())
+    }
+
+    // One printf for all of `args`, separated by spaces. Bools print as
+    // words, as they do in arrays.
+    fn generate_printf(&mut self, args: &[Expression], newline: &str) -> Result<(), String> {
+        let mut format_str = String::new();
+        let mut format_args = Vec::new();
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                format_str.push(' ');
+            }
+            match arg {
+                Expression::StringLiteral(text) => {
+                    format_str.push_str(&escape_c_string(text).replace("%", "%%"));
+                }
+                Expression::FString { parts } => {
+                    let (fstring_format, fstring_args) = self.fstring_format(parts)?;
+                    format_str.push_str(&fstring_format);
+                    format_args.extend(fstring_args.into_iter().map(|arg| (arg, false)));
                 }
+                _ => {
+                    let c_type = self.expression_c_type(arg).ok_or_else(|| {
+                        "Cannot print a value of unknown type; bind it to a typed variable or convert it with `as`".to_string()
+                    })?;
+                    let is_bool = c_type == "bool";
+                    format_str.push_str(if is_bool { "%s" } else { format_specifier(Some(&c_type)) });
+                    format_args.push(((arg, None), is_bool));
+                }
+            }
+        }
 
-                // Generate the printf-style expression
-                // We'll use a compound literal (string expression) in C
+        self.emit_no_indent(&format!("printf(\"{}{}\"", format_str, newline));
+        for (arg, is_bool) in format_args {
+            self.emit_no_indent(", ");
+            if is_bool {
                 self.emit_no_indent("(");
-                self.emit_no_indent(&format!("\"{}\"", format_str));
+                self.generate_expression(arg.0)?;
+                self.emit_no_indent(") ? \"true\" : \"false\"");
+            } else {
+                self.generate_format_argument(arg)?;
+            }
+        }
+        self.emit_no_indent(")");
+        Ok(())
+    }
+
+    // The type of a print argument that is an array, struct or object. A
+    // dynamic array whose HiLow type isn't declared is printed by its
+    // element C type.
+    fn printed_value_type(&self, arg: &Expression) -> Option<Type> {
+        if let Some(ty) = self.array_type_of(arg) {
+            return Some(ty);
+        }
+        let c_type = self.expression_c_type(arg)?;
+        if c_type == "DynamicArray*" {
+            let element_type = self.dynamic_element_c_type(arg).map_or(Type::I32, |c_type| c_type_to_type(&c_type));
+            return Some(Type::Array { element_type: Box::new(element_type), size: None });
+        }
+        self.printed_fields(&c_type).map(|_| Type::Named(c_type))
+    }
+
+    // Fields of the struct or object typedef `c_type`, for printing
+    fn printed_fields(&self, c_type: &str) -> Option<Vec<(String, Type)>> {
+        match c_type.strip_prefix("__Obj_") {
+            Some(index) => {
+                let shape = self.object_shapes.get(index.parse::<usize>().ok()?)?;
+                Some(shape.iter().map(|(field, c_type)| (field.clone(), c_type_to_type(c_type))).collect())
+            }
+            None => self.struct_fields.get(c_type).cloned(),
+        }
+    }
+
+    // The C code for `expr`, without emitting it
+    fn expression_code(&mut self, expr: &Expression) -> Result<String, String> {
+        let saved_output = std::mem::take(&mut self.output);
+        let result = self.generate_expression(expr);
+        let code = std::mem::replace(&mut self.output, saved_output);
+        result.map(|_| code)
+    }
+
+    // A dynamic array holding `count` copies of `value`, of `element_type`
+    // when sema resolved it
+    fn generate_array_fill(&mut self, value: &Expression, count: &Expression, element_type: Option<&Type>) -> Result<(), String> {
+        let elem_c_type = match (element_type, value) {
+            (Some(ty), _) => self.type_to_c(ty),
+            (None, Expression::StringLiteral(_) | Expression::FString { .. }) => "char*".to_string(),
+            (None, Expression::FloatLiteral(_)) => "double".to_string(),
+            (None, Expression::BooleanLiteral(_)) => "bool".to_string(),
+            (None, Expression::Identifier(name)) => self
+                .variables
+                .get(name)
+                .cloned()
+                .unwrap_or_else(|| "int32_t".to_string()),
+            _ => "int32_t".to_string(),
+        };
+
+        self.emit_no_indent(&format!("array_fill(sizeof({0}), &({0}){{ ", elem_c_type));
+        self.generate_expression(value)?;
+        self.emit_no_indent(" }, ");
+        self.generate_expression(count)?;
+        self.emit_no_indent(")");
+        Ok(())
+    }
+
+    // The optional second argument of the integer-parsing builtins
+    fn generate_int_radix_argument(&mut self, args: &[Expression]) -> Result<(), String> {
+        match args.get(1) {
+            Some(base) => self.generate_expression(base),
+            None => {
+                self.emit_no_indent("10");
+                Ok(())
+            }
+        }
+    }
+
+    fn generate_expression(&mut self, expr: &Expression) -> Result<(), String> {
+        match expr {
+            Expression::IntegerLiteral(n) => {
+                self.emit_no_indent(&n.to_string());
+            }
+
+            Expression::FloatLiteral(f) => {
+                // Debug formatting keeps the `.0` so C sees a double
+                self.emit_no_indent(&format!("{:?}", f));
+            }
+
+            Expression::StringLiteral(s) => {
+                self.emit_no_indent(&format!("\"{}\"", escape_c_string(s)));
+            }
 
-                // For now, if we have expressions, we'll need to use sprintf or similar
-                // Let's generate a simple concatenation for basic cases
-                if !args.is_empty() {
+            Expression::FString { parts } => {
+                // Format into a freshly allocated string
+                let (format_str, fstring_args) = self.fstring_format(parts)?;
+                self.emit_no_indent(&format!("str_format(\"{}\"", format_str));
+                for arg in fstring_args {
                     self.emit_no_indent(", ");
-                    for (i, arg) in args.iter().enumerate() {
-                        if i > 0 {
-                            self.emit_no_indent(", ");
-                        }
-                        self.generate_expression(arg)?;
-                    }
+                    self.generate_format_argument(arg)?;
                 }
                 self.emit_no_indent(")");
             }
@@ -782,12 +2753,74 @@ impl CodeGenerator {
                 self.emit_no_indent(if *b { "true" } else { "false" });
             }
 
+            Expression::CharLiteral(c) => {
+                self.emit_no_indent(&escape_c_char(*c));
+            }
+
             Expression::NothingLiteral => {
                 self.emit_no_indent("NULL");
             }
 
             Expression::Identifier(name) => {
-                self.emit_no_indent(name);
+                // `inf` and `nan` are builtin constants unless shadowed
+                match name.as_str() {
+                    "inf" if !self.variables.contains_key(name) => self.emit_no_indent("INFINITY"),
+                    "nan" if !self.variables.contains_key(name) => self.emit_no_indent("NAN"),
+                    // A top-level function used as a value becomes a
+                    // closure without a context
+                    _ if self.function_return_types.contains_key(name) && !self.variables.contains_key(name) => {
+                        self.emit_no_indent(&format!("closure_new((void*){}, NULL)", name));
+                    }
+                    _ => match self.pointer_to(name) {
+                        Some(pointer) => self.emit_no_indent(&format!("(*{})", pointer)),
+                        None => self.emit_no_indent(name),
+                    },
+                }
+            }
+
+            Expression::Binary { left, op, right }
+                if matches!(
+                    op,
+                    BinaryOp::Equal | BinaryOp::StrictEqual | BinaryOp::NotEqual | BinaryOp::StrictNotEqual
+                ) && self.is_string_expression(left)
+                    && self.is_string_expression(right) =>
+            {
+                // Strings compare by content, not by pointer
+                self.emit_no_indent("(strcmp(");
+                self.generate_expression(left)?;
+                self.emit_no_indent(", ");
+                self.generate_expression(right)?;
+                self.emit_no_indent(&format!(") {} 0)", self.binary_op_to_c(op)));
+            }
+
+            Expression::Binary { left, op: BinaryOp::Add, right }
+                if self.is_string_expression(left) && self.is_string_expression(right) =>
+            {
+                self.emit_no_indent("str_concat(");
+                self.generate_expression(left)?;
+                self.emit_no_indent(", ");
+                self.generate_expression(right)?;
+                self.emit_no_indent(")");
+            }
+
+            // With a float on either side `/` divides as floats, and `%`
+            // becomes fmod since C's `%` only takes integers
+            Expression::Binary { left, op: op @ (BinaryOp::Divide | BinaryOp::Modulo), right }
+                if self.is_float_operand(left) || self.is_float_operand(right) =>
+            {
+                if matches!(op, BinaryOp::Modulo) {
+                    self.emit_no_indent("fmod(");
+                    self.generate_expression(left)?;
+                    self.emit_no_indent(", ");
+                    self.generate_expression(right)?;
+                    self.emit_no_indent(")");
+                } else {
+                    self.emit_no_indent("(");
+                    self.generate_float_operand(left)?;
+                    self.emit_no_indent(" / ");
+                    self.generate_float_operand(right)?;
+                    self.emit_no_indent(")");
+                }
             }
 
             Expression::Binary { left, op, right } => {
@@ -807,7 +2840,7 @@ impl CodeGenerator {
                 self.emit_no_indent(")");
             }
 
-            Expression::Call { callee, args } => {
+            Expression::Call { callee, args, resolved_type } => {
                 // Special cases for built-in functions
                 if let Expression::Identifier(name) = callee.as_ref() {
                     // String operations
@@ -819,14 +2852,7 @@ impl CodeGenerator {
                     }
 
                     if name == "string_index_of" && args.len() == 2 {
-                        // Generate C code for indexOf using strstr
-                        self.emit_no_indent("({char* __p = strstr(");
-                        self.generate_expression(&args[0])?;
-                        self.emit_no_indent(", ");
-                        self.generate_expression(&args[1])?;
-                        self.emit_no_indent("); __p ? (int32_t)(__p - (char*)(");
-                        self.generate_expression(&args[0])?;
-                        self.emit_no_indent(")) : -1; })");
+                        self.generate_index_of(&args[0], &args[1])?;
                         return Ok(());
                     }
 
@@ -838,8 +2864,7 @@ impl CodeGenerator {
                     }
 
                     if name == "string_concat" && args.len() == 2 {
-                        // Simple concatenation using strcat (unsafe but works for demo)
-                        self.emit_no_indent("strcat(");
+                        self.emit_no_indent("str_concat(");
                         self.generate_expression(&args[0])?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[1])?;
@@ -899,6 +2924,25 @@ impl CodeGenerator {
                         return Ok(());
                     }
 
+                    if (name == "to_int" && args.len() == 1) || (name == "to_int_radix" && args.len() == 2) {
+                        check_int_literal_argument(args)?;
+                        self.emit_no_indent("str_to_int_radix(");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(", ");
+                        self.generate_int_radix_argument(args)?;
+                        self.emit_no_indent(")");
+                        return Ok(());
+                    }
+
+                    if (name == "is_int" && args.len() == 1) || (name == "is_int_radix" && args.len() == 2) {
+                        self.emit_no_indent("str_is_int_radix(");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(", ");
+                        self.generate_int_radix_argument(args)?;
+                        self.emit_no_indent(")");
+                        return Ok(());
+                    }
+
                     if name == "sqrt" && args.len() == 1 {
                         self.emit_no_indent("(int32_t)sqrt(");
                         self.generate_expression(&args[0])?;
@@ -906,107 +2950,204 @@ impl CodeGenerator {
                         return Ok(());
                     }
 
-                    if name == "print" {
-                        self.emit_no_indent("printf(");
-                        for (i, arg) in args.iter().enumerate() {
-                            if i > 0 {
-                                self.emit_no_indent(", ");
-                            }
-
-                            // Generate format string for print
-                            match arg {
-                                Expression::StringLiteral(_) => {
-                                    self.emit_no_indent("\"%s\\n\"");
-                                    self.emit_no_indent(", ");
-                                    self.generate_expression(arg)?;
-                                }
-                                Expression::FString { parts } => {
-                                    use crate::ast::FStringPart;
-
-                                    // Build format string for the f-string
-                                    let mut format_str = String::new();
-                                    let mut fstring_args = Vec::new();
-
-                                    for part in parts {
-                                        match part {
-                                            FStringPart::Text(text) => {
-                                                format_str.push_str(&text.replace("%", "%%"));
-                                            }
-                                            FStringPart::Expression(expr) => {
-                                                // Try to determine format specifier from expression type
-                                                let format_spec = match expr.as_ref() {
-                                                    Expression::StringLiteral(_) => "%s",
-                                                    Expression::Identifier(name) => {
-                                                        // Check if it's a string variable
-                                                        if let Some(var_type) = self.variables.get(name) {
-                                                            if var_type.contains("char*") {
-                                                                "%s"
-                                                            } else {
-                                                                "%d"
-                                                            }
-                                                        } else {
-                                                            "%d"
-                                                        }
-                                                    }
-                                                    Expression::FloatLiteral(_) => "%f",
-                                                    _ => "%d",
-                                                };
-                                                format_str.push_str(format_spec);
-                                                fstring_args.push(expr);
-                                            }
-                                        }
-                                    }
+                    if name == "hash" && args.len() == 1 {
+                        let (open, close) = if self.is_string_expression(&args[0]) {
+                            ("str_hash(", ")")
+                        } else {
+                            ("int_hash((uint64_t)(", "))")
+                        };
+                        self.emit_no_indent(open);
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(close);
+                        return Ok(());
+                    }
 
-                                    format_str.push_str("\\n");
-                                    self.emit_no_indent(&format!("\"{}\"", format_str));
+                    if (name == "is_nan" || name == "is_inf") && args.len() == 1 {
+                        self.emit_no_indent(if name == "is_nan" { "(bool)isnan(" } else { "(bool)isinf(" });
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(")");
+                        return Ok(());
+                    }
 
-                                    for expr in fstring_args {
-                                        self.emit_no_indent(", ");
-                                        self.generate_expression(expr)?;
-                                    }
-                                }
-                                _ => {
-                                    self.generate_expression(arg)?;
-                                }
-                            }
-                        }
+                    if name == "string_from_bytes" && args.len() == 1 {
+                        self.emit_no_indent("str_from_bytes(");
+                        self.generate_expression(&args[0])?;
                         self.emit_no_indent(")");
                         return Ok(());
                     }
-                }
 
-                // Check if callee is a function pointer variable (void*)
-                let is_function_ptr = if let Expression::Identifier(name) = callee.as_ref() {
-                    self.variables.get(name)
-                        .map(|t| t == "void*")
-                        .unwrap_or(false)
-                } else {
-                    false
-                };
+                    if name == "fill" && args.len() == 2 {
+                        let element_type = match resolved_type {
+                            Some(Type::Array { element_type, .. }) => Some(element_type.as_ref()),
+                            _ => None,
+                        };
+                        self.generate_array_fill(&args[0], &args[1], element_type)?;
+                        return Ok(());
+                    }
 
-                if is_function_ptr {
-                    // Cast void* to function pointer and call
-                    // For now, assume signature is int32_t(*)(int32_t, int32_t)
-                    self.emit_no_indent("((int32_t(*)(");
-                    for (i, _arg) in args.iter().enumerate() {
-                        if i > 0 {
-                            self.emit_no_indent(", ");
-                        }
-                        self.emit_no_indent("int32_t");
+                    if name == "args" && args.is_empty() {
+                        self.emit_no_indent("program_args()");
+                        return Ok(());
                     }
-                    self.emit_no_indent("))");
-                    self.generate_expression(callee)?;
-                    self.emit_no_indent(")(");
-                    for (i, arg) in args.iter().enumerate() {
-                        if i > 0 {
-                            self.emit_no_indent(", ");
+
+                    if name == "env" && args.len() == 1 {
+                        self.emit_no_indent("getenv(");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(")");
+                        return Ok(());
+                    }
+
+                    if name == "print_row" && args.len() == 2 {
+                        self.emit_no_indent("({ ");
+                        let (cells, count) = self.bind_array_argument(&args[0], "char*")?;
+                        let (widths, width_count) = self.bind_array_argument(&args[1], "int32_t")?;
+                        self.emit_no_indent(&format!("print_row({}, {}, {}, {}); }})", cells, count, widths, width_count));
+                        return Ok(());
+                    }
+
+                    if name == "assert" && (args.len() == 1 || args.len() == 2) {
+                        // The message is only built when the check fails
+                        self.emit_no_indent("((");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(") ? (void)0 : assertion_failed(");
+                        match args.get(1) {
+                            Some(message) => self.generate_expression(message)?,
+                            None => self.emit_no_indent("\"assert\""),
+                        }
+                        self.emit_no_indent("))");
+                        return Ok(());
+                    }
+
+                    if name == "panic" && args.len() == 1 {
+                        self.emit_no_indent("hilow_panic(");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(")");
+                        return Ok(());
+                    }
+
+                    if name == "log" && args.len() == 2 {
+                        let level = match &args[0] {
+                            Expression::StringLiteral(level) => LogLevel::from_name(level)?,
+                            _ => return Err("The log level must be a string literal such as \"info\"".to_string()),
+                        };
+
+                        // Filtered logs are dropped entirely, message and all
+                        if level < self.options.log_level {
+                            self.emit_no_indent("((void)0)");
+                            return Ok(());
+                        }
+
+                        self.emit_no_indent(&format!("fprintf(stderr, \"[{}] ", level.label()));
+                        if let Expression::FString { parts } = &args[1] {
+                            let (format_str, fstring_args) = self.fstring_format(parts)?;
+                            self.emit_no_indent(&format!("{}\\n\"", format_str));
+                            for arg in fstring_args {
+                                self.emit_no_indent(", ");
+                                self.generate_format_argument(arg)?;
+                            }
+                        } else {
+                            self.emit_no_indent("%s\\n\", ");
+                            self.generate_expression(&args[1])?;
+                        }
+                        self.emit_no_indent(")");
+                        return Ok(());
+                    }
+
+                    // `print` leaves the cursor where the output ends;
+                    // `println` finishes the line
+                    if name == "print" || name == "println" {
+                        let newline = if name == "println" { "\\n" } else { "" };
+
+                        // Whole arrays, structs and objects print element by
+                        // element, so a print with one becomes a statement
+                        // per argument
+                        let value_types: Vec<Option<Type>> = args.iter().map(|arg| self.printed_value_type(arg)).collect();
+                        if value_types.iter().any(Option::is_some) {
+                            self.emit_no_indent("({ ");
+                            for (i, (arg, value_type)) in args.iter().zip(value_types).enumerate() {
+                                if i > 0 {
+                                    self.emit_no_indent("putchar(' '); ");
+                                }
+                                match value_type {
+                                    Some(ty @ (Type::Array { size: None, .. } | Type::Named(_))) => {
+                                        let value = self.fresh_temp("printed");
+                                        self.emit_no_indent(&format!("{} {} = ", self.type_to_c(&ty), value));
+                                        self.generate_expression(arg)?;
+                                        self.emit_no_indent("; ");
+                                        self.generate_value_print(&value, &ty, false)?;
+                                    }
+                                    // A fixed array can't be copied, so it is
+                                    // printed in place
+                                    Some(ty) => {
+                                        let array = self.expression_code(arg)?;
+                                        self.generate_value_print(&array, &ty, false)?;
+                                    }
+                                    None => {
+                                        self.generate_printf(std::slice::from_ref(arg), "")?;
+                                        self.emit_no_indent(";");
+                                    }
+                                }
+                                self.emit_no_indent(" ");
+                            }
+                            if name == "println" {
+                                self.emit_no_indent("putchar('\\n'); ");
+                            }
+                            self.emit_no_indent("})");
+                            return Ok(());
                         }
+
+                        self.generate_printf(args, newline)?;
+                        return Ok(());
+                    }
+                }
+
+                // A variable of function type holds a closure
+                let signature = match callee.as_ref() {
+                    Expression::Identifier(name) => self.closure_signature(name),
+                    _ => None,
+                };
+
+                if let Some((typedef, mut param_types, _)) = signature {
+                    // Call through the closure, passing its context first
+                    // when it has one. A bare `function` is called as
+                    // taking one i32 per argument.
+                    let (plain_fn, env_fn) = if param_types.len() == args.len() {
+                        (format!("{}_plain", typedef), format!("{}_env", typedef))
+                    } else {
+                        param_types = vec!["int32_t".to_string(); args.len()];
+                        let params = param_types.join(", ");
+                        let env_params = std::iter::once("void*".to_string()).chain(param_types.clone()).collect::<Vec<_>>();
+                        (format!("int32_t(*)({})", params), format!("int32_t(*)({})", env_params.join(", ")))
+                    };
+
+                    let closure = self.fresh_temp("closure");
+                    self.emit_no_indent(&format!("({{ Closure* {} = ", closure));
+                    self.generate_expression(callee)?;
+                    self.emit_no_indent("; ");
+                    let mut arg_names = Vec::new();
+                    for (arg, param_type) in args.iter().zip(&param_types) {
+                        let arg_name = self.fresh_temp("arg");
+                        self.emit_no_indent(&format!("{} {} = ", param_type, arg_name));
                         self.generate_expression(arg)?;
+                        self.emit_no_indent("; ");
+                        arg_names.push(arg_name);
                     }
-                    self.emit_no_indent(")");
+                    let mut env_args = vec![format!("{}->env", closure)];
+                    env_args.extend(arg_names.iter().cloned());
+                    self.emit_no_indent(&format!(
+                        "{c}->env ? (({})({c}->fn))({}) : (({})({c}->fn))({}); }})",
+                        env_fn,
+                        env_args.join(", "),
+                        plain_fn,
+                        arg_names.join(", "),
+                        c = closure,
+                    ));
                 } else {
                     // Regular function call
-                    self.generate_expression(callee)?;
+                    match callee.as_ref() {
+                        Expression::Identifier(name) => self.emit_no_indent(name),
+                        _ => self.generate_expression(callee)?,
+                    }
                     self.emit_no_indent("(");
                     for (i, arg) in args.iter().enumerate() {
                         if i > 0 {
@@ -1035,35 +3176,70 @@ impl CodeGenerator {
                 self.emit_no_indent("}");
             }
 
-            Expression::Index { array, index } => {
-                // Check if it's a dynamic array
-                let is_dynamic_array = if let Expression::Identifier(name) = array.as_ref() {
-                    self.variables.get(name)
-                        .map(|t| t.contains("DynamicArray"))
-                        .unwrap_or(false)
-                } else {
-                    false
-                };
+            Expression::ArrayRepeat { value, count } => {
+                self.generate_array_fill(value, count, None)?;
+            }
 
-                if is_dynamic_array {
+            Expression::Index { array, index } => {
+                if self.is_dynamic_array(array) {
                     // Dynamic array indexing: arr->data[index]
-                    self.emit_no_indent("((int32_t*)");
+                    let elem_c_type = self.dynamic_element_c_type(array).unwrap_or_else(|| "int32_t".to_string());
+                    if self.options.checked && !matches!(array.as_ref(), Expression::Identifier(_)) {
+                        // The array is needed for its length too, so it's
+                        // evaluated once into a temp; the element is reached
+                        // through a pointer to stay assignable:
+                        // `(*({ DynamicArray* a = mk(); int64_t i = ...; &((T*)a->data)[i]; }))`
+                        let arr = self.fresh_temp("arr");
+                        let idx = self.fresh_temp("idx");
+                        self.emit_no_indent(&format!("(*({{ DynamicArray* {} = ", arr));
+                        self.generate_expression(array)?;
+                        self.emit_no_indent(&format!("; int64_t {} = array_index_checked(", idx));
+                        self.generate_expression(index)?;
+                        self.emit_no_indent(&format!(
+                            ", {arr}->length); &(({t}*){arr}->data)[{idx}]; }}))",
+                            arr = arr,
+                            t = elem_c_type,
+                            idx = idx
+                        ));
+                        return Ok(());
+                    }
+                    self.emit_no_indent(&format!("(({}*)", elem_c_type));
                     self.generate_expression(array)?;
                     self.emit_no_indent("->data)[");
-                    self.generate_expression(index)?;
+                    if self.options.checked {
+                        self.emit_no_indent("array_index_checked(");
+                        self.generate_expression(index)?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(array)?;
+                        self.emit_no_indent("->length)");
+                    } else {
+                        self.generate_expression(index)?;
+                    }
                     self.emit_no_indent("]");
                 } else {
-                    // Regular array indexing
+                    // Regular array indexing; a fixed array is checked
+                    // against its declared size
+                    let size = match self.array_type_of(array) {
+                        Some(Type::Array { size: Some(size), .. }) => Some(size),
+                        _ => None,
+                    };
                     self.generate_expression(array)?;
                     self.emit_no_indent("[");
-                    self.generate_expression(index)?;
+                    match size {
+                        Some(size) if self.options.checked => {
+                            self.emit_no_indent("array_index_checked(");
+                            self.generate_expression(index)?;
+                            self.emit_no_indent(&format!(", {})", size));
+                        }
+                        _ => self.generate_expression(index)?,
+                    }
                     self.emit_no_indent("]");
                 }
             }
 
             Expression::ObjectLiteral { properties } => {
                 // For objects in C, we'll generate a compound literal with a struct
-                self.emit_no_indent("{");
+                self.emit_no_indent("{ ");
                 for (i, prop) in properties.iter().enumerate() {
                     if i > 0 {
                         self.emit_no_indent(", ");
@@ -1073,23 +3249,19 @@ impl CodeGenerator {
                     self.emit_no_indent(" = ");
                     self.generate_expression(&prop.value)?;
                 }
-                self.emit_no_indent("}");
+                self.emit_no_indent(" }");
             }
 
             Expression::PropertyAccess { object, property } => {
+                if let Some(name) = self.enum_variant_of(object) {
+                    self.emit_no_indent(&format!("{}_{}", name, property));
+                    return Ok(());
+                }
+
                 // Special case for .length
                 if property == "length" {
                     // Check if it's an array (DynamicArray*) or string
-                    // For now, we'll check the variable type
-                    let is_dynamic_array = if let Expression::Identifier(name) = object.as_ref() {
-                        self.variables.get(name)
-                            .map(|t| t.contains("DynamicArray"))
-                            .unwrap_or(false)
-                    } else {
-                        false
-                    };
-
-                    if is_dynamic_array {
+                    if self.is_dynamic_array(object) {
                         // Dynamic array length
                         self.generate_expression(object)?;
                         self.emit_no_indent("->length");
@@ -1106,24 +3278,78 @@ impl CodeGenerator {
                 }
             }
 
-            Expression::MethodCall { object, method, args } => {
+            // A struct method gets the address of its receiver; one that
+            // isn't stored anywhere is copied into a temporary first
+            Expression::MethodCall { object, method, args, .. } if self.method_function(object, method).is_some() => {
+                let function = self.method_function(object, method).unwrap_or_default();
+                let is_lvalue = matches!(object.as_ref(), Expression::Identifier(_) | Expression::PropertyAccess { .. });
+                if is_lvalue {
+                    self.emit_no_indent(&format!("{}(&", function));
+                    self.generate_expression(object)?;
+                } else {
+                    let temp = self.fresh_temp("receiver");
+                    let c_type = self.expression_c_type(object).unwrap_or_default();
+                    self.emit_no_indent(&format!("({{ {} {} = ", c_type, temp));
+                    self.generate_expression(object)?;
+                    self.emit_no_indent(&format!("; {}(&{}", function, temp));
+                }
+                for arg in args {
+                    self.emit_no_indent(", ");
+                    self.generate_expression(arg)?;
+                }
+                self.emit_no_indent(")");
+                if !is_lvalue {
+                    self.emit_no_indent("; })");
+                }
+            }
+
+            Expression::MethodCall { object, method, args, .. }
+                if method == "collect" && args.is_empty() && matches!(object.as_ref(), Expression::Range { .. }) =>
+            {
+                if let Expression::Range { start, end, inclusive } = object.as_ref() {
+                    self.emit_no_indent("array_range(");
+                    self.generate_expression(start)?;
+                    self.emit_no_indent(", ");
+                    self.generate_expression(end)?;
+                    self.emit_no_indent(if *inclusive { " + 1)" } else { ")" });
+                }
+            }
+
+            Expression::Range { .. } => {
+                return Err("A range can only be used with collect() or as a for-in iterable".to_string());
+            }
+
+            Expression::MethodCall { object, method, args, .. } => {
                 // Handle string methods
                 match method.as_str() {
-                    "indexOf" if args.len() == 1 => {
-                        self.emit_no_indent("({char* __p = strstr(");
+                    "indexOf" | "lastIndexOf" if self.is_dynamic_array(object) && (1..=2).contains(&args.len()) => {
+                        let suffix = self.array_helper_suffix(object);
+                        let helper = if method == "indexOf" { "index_of" } else { "last_index_of" };
+                        self.emit_no_indent(&format!("array_{}_{}(", helper, suffix));
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[0])?;
-                        self.emit_no_indent("); __p ? (int32_t)(__p - (char*)(");
-                        self.generate_expression(object)?;
-                        self.emit_no_indent(")) : -1; })");
+                        if method == "indexOf" {
+                            self.emit_no_indent(", ");
+                            match args.get(1) {
+                                Some(from) => self.generate_expression(from)?,
+                                None => self.emit_no_indent("0"),
+                            }
+                        } else if args.len() == 2 {
+                            return Err("lastIndexOf takes a single value to search for".to_string());
+                        }
+                        self.emit_no_indent(")");
+                    }
+                    "indexOf" if args.len() == 1 => {
+                        self.generate_index_of(object, &args[0])?;
                     }
-                    "slice" if args.len() >= 1 && args.len() <= 2 => {
-                        self.emit_no_indent("({char* __s = (char*)(");
+                    "slice" if !args.is_empty() && args.len() <= 2 => {
+                        let slice = self.fresh_temp("slice");
+                        self.emit_no_indent(&format!("({{char* {} = (char*)(", slice));
                         self.generate_expression(object)?;
                         self.emit_no_indent(") + ");
                         self.generate_expression(&args[0])?;
-                        self.emit_no_indent("; __s; })");
+                        self.emit_no_indent(&format!("; {}; }})", slice));
                     }
                     "compare" if args.len() == 1 => {
                         self.emit_no_indent("strcmp(");
@@ -1147,6 +3373,23 @@ impl CodeGenerator {
                         self.generate_expression(object)?;
                         self.emit_no_indent(")");
                     }
+                    "trim" if args.len() == 1 => {
+                        self.emit_no_indent("str_trim_chars(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(")");
+                    }
+                    "trimStart" if args.is_empty() => {
+                        self.emit_no_indent("str_trim_start(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
+                    "trimEnd" if args.is_empty() => {
+                        self.emit_no_indent("str_trim_end(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
                     "charAt" if args.len() == 1 => {
                         self.emit_no_indent("str_char_at(");
                         self.generate_expression(object)?;
@@ -1170,6 +3413,18 @@ impl CodeGenerator {
                         self.generate_expression(&args[0])?;
                         self.emit_no_indent(")");
                     }
+                    "padStart" | "padEnd" if !args.is_empty() && args.len() <= 2 => {
+                        self.emit_no_indent("str_pad(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(", ");
+                        match args.get(1) {
+                            Some(fill) => self.generate_expression(fill)?,
+                            None => self.emit_no_indent("\" \""),
+                        }
+                        self.emit_no_indent(if method == "padStart" { ", true)" } else { ", false)" });
+                    }
                     "replace" if args.len() == 2 => {
                         self.emit_no_indent("str_replace(");
                         self.generate_expression(object)?;
@@ -1179,6 +3434,22 @@ impl CodeGenerator {
                         self.generate_expression(&args[1])?;
                         self.emit_no_indent(")");
                     }
+                    "replaceAll" if args.len() == 2 => {
+                        self.emit_no_indent("str_replace_all(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[1])?;
+                        self.emit_no_indent(")");
+                    }
+                    "count" | "occurrences" if args.len() == 1 && self.is_string_expression(object) => {
+                        self.emit_no_indent("str_count(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(")");
+                    }
                     "split" if args.len() == 1 => {
                         self.emit_no_indent("str_split(");
                         self.generate_expression(object)?;
@@ -1186,6 +3457,30 @@ impl CodeGenerator {
                         self.generate_expression(&args[0])?;
                         self.emit_no_indent(")");
                     }
+                    "split" if args.len() == 2 => {
+                        self.emit_no_indent("str_split_n(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[0])?;
+                        self.emit_no_indent(", ");
+                        self.generate_expression(&args[1])?;
+                        self.emit_no_indent(")");
+                    }
+                    "hash" if args.is_empty() => {
+                        self.emit_no_indent("str_hash(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
+                    "bytes" if args.is_empty() => {
+                        self.emit_no_indent("str_bytes(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
+                    "splitLines" if args.is_empty() => {
+                        self.emit_no_indent("str_split_lines(");
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
                     // Array methods
                     "join" if args.len() == 1 => {
                         self.emit_no_indent("array_join_string(");
@@ -1195,15 +3490,19 @@ impl CodeGenerator {
                         self.emit_no_indent(")");
                     }
                     "push" if args.len() == 1 => {
-                        // For now, assume i32 arrays - needs type system improvement
-                        self.emit_no_indent("array_push_i32(");
+                        let helper = self.typed_array_helper("push", object)?;
+                        self.emit_no_indent(&format!("{}(", helper));
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
-                        self.generate_expression(&args[0])?;
+                        match self.array_type_of(object) {
+                            Some(Type::Array { element_type, .. }) => self.generate_array_element(&args[0], &element_type)?,
+                            _ => self.generate_expression(&args[0])?,
+                        }
                         self.emit_no_indent(")");
                     }
                     "pop" if args.is_empty() => {
-                        self.emit_no_indent("array_pop_i32(");
+                        let helper = self.typed_array_helper("pop", object)?;
+                        self.emit_no_indent(&format!("{}(", helper));
                         self.generate_expression(object)?;
                         self.emit_no_indent(")");
                     }
@@ -1212,22 +3511,27 @@ impl CodeGenerator {
                         self.generate_expression(object)?;
                         self.emit_no_indent(")");
                     }
-                    "map" if args.len() == 1 => {
-                        self.emit_no_indent("array_map_i32(");
-                        self.generate_expression(object)?;
-                        self.emit_no_indent(", ");
-                        self.generate_expression(&args[0])?;
-                        self.emit_no_indent(")");
-                    }
-                    "filter" if args.len() == 1 => {
-                        self.emit_no_indent("array_filter_i32(");
+                    "map" | "filter" | "forEach" if args.len() == 1 => {
+                        // A predicate or a callback run for its effect
+                        // gives an int, as an arrow function's body does
+                        let result_c_type = match (method.as_str(), resolved_type(expr)) {
+                            ("map", Some(Type::Array { element_type, .. })) => self.element_c_type(element_type),
+                            _ => "int32_t".to_string(),
+                        };
+                        let helper = self.callback_helper(method, object, &result_c_type)?;
+                        self.emit_no_indent(&format!("{}(", helper));
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[0])?;
                         self.emit_no_indent(")");
                     }
                     "reduce" if args.len() == 2 => {
-                        self.emit_no_indent("array_reduce_i32(");
+                        let result_c_type = match resolved_type(expr) {
+                            Some(ty) => Some(self.type_to_c(ty)),
+                            None => self.expression_c_type(&args[1]),
+                        };
+                        let helper = self.callback_helper("reduce", object, result_c_type.as_deref().unwrap_or("int32_t"))?;
+                        self.emit_no_indent(&format!("{}(", helper));
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[0])?;
@@ -1235,22 +3539,36 @@ impl CodeGenerator {
                         self.generate_expression(&args[1])?;
                         self.emit_no_indent(")");
                     }
-                    "forEach" if args.len() == 1 => {
-                        self.emit_no_indent("array_forEach_i32(");
+                    "contains" if args.len() == 1 => {
+                        self.emit_no_indent("array_contains_i32(");
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[0])?;
                         self.emit_no_indent(")");
                     }
-                    "contains" if args.len() == 1 => {
-                        self.emit_no_indent("array_contains_i32(");
+                    "find" if args.len() == 1 => {
+                        let helper = self.callback_helper("find", object, "int32_t")?;
+                        self.emit_no_indent(&format!("{}(", helper));
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[0])?;
                         self.emit_no_indent(")");
                     }
-                    "find" if args.len() == 1 => {
-                        self.emit_no_indent("array_find_i32(");
+                    "unique" | "dedup" if args.is_empty() => {
+                        let suffix = self.array_helper_suffix(object);
+                        self.emit_no_indent(&format!("array_unique_{}(", suffix));
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
+                    "sum" | "min" | "max" | "avg" if args.is_empty() => {
+                        let helper = self.typed_array_helper(method, object)?;
+                        self.emit_no_indent(&format!("{}(", helper));
+                        self.generate_expression(object)?;
+                        self.emit_no_indent(")");
+                    }
+                    "count" if args.len() == 1 => {
+                        let suffix = self.array_helper_suffix(object);
+                        self.emit_no_indent(&format!("array_count_{}(", suffix));
                         self.generate_expression(object)?;
                         self.emit_no_indent(", ");
                         self.generate_expression(&args[0])?;
@@ -1273,6 +3591,19 @@ impl CodeGenerator {
                 }
             }
 
+            Expression::Conditional { condition, then, otherwise } => {
+                // C converts the branches to a common type. Binary
+                // operands bring their own parentheses, and the parser
+                // only allows an assignment in the middle.
+                self.emit_no_indent("(");
+                self.generate_expression(condition)?;
+                self.emit_no_indent(" ? ");
+                self.generate_expression(then)?;
+                self.emit_no_indent(" : ");
+                self.generate_expression(otherwise)?;
+                self.emit_no_indent(")");
+            }
+
             Expression::Cast { expr, target_type } => {
                 self.emit_no_indent("(");
                 self.emit_no_indent(&self.type_to_c(target_type));
@@ -1281,135 +3612,219 @@ impl CodeGenerator {
                 self.emit_no_indent(")");
             }
 
+            Expression::Block { body, value } => {
+                // Lower to a GCC statement expression whose last
+                // expression statement is the block's value
+                self.emit_no_indent("({\n");
+                self.indent_level += 1;
+                self.defer_stack.push(Vec::new());
+                self.enter_scope();
+
+                for stmt in &body.statements {
+                    self.generate_statement(stmt)?;
+                }
+
+                let defers = self.defer_stack.pop().unwrap_or_default();
+                self.emit_no_indent(&self.indent());
+                if defers.is_empty() {
+                    self.generate_expression(value)?;
+                    self.output.push_str(";\n");
+                } else {
+                    // Evaluate the value before running defers
+                    let block_value = self.fresh_temp("block_value");
+                    self.emit_no_indent("__typeof__(");
+                    self.generate_expression(value)?;
+                    self.emit_no_indent(&format!(") {} = ", block_value));
+                    self.generate_expression(value)?;
+                    self.output.push_str(";\n");
+                    for defer_stmt in defers.iter().rev() {
+                        self.generate_statement(defer_stmt)?;
+                    }
+                    self.emit(&format!("{};", block_value));
+                }
+                self.leave_scope();
+
+                self.indent_level -= 1;
+                self.emit_no_indent(&self.indent());
+                self.emit_no_indent("})");
+            }
+
             Expression::Match { expr, arms } => {
                 use crate::ast::MatchPattern;
 
-                // Generate match as a statement expression with switch
-                self.emit_no_indent("({ int32_t __match_result; switch (");
+                // Generate match as a statement expression with an if/else
+                // chain rather than a switch, so `break`/`continue` in an arm
+                // reach the enclosing loop. The scrutinee is evaluated once
+                // so binding arms can reuse it.
+                let result = self.fresh_temp("match_result");
+                let value = self.fresh_temp("match_value");
+                let result_type = arms
+                    .iter()
+                    .find_map(|arm| self.expression_c_type(&arm.body))
+                    .unwrap_or_else(|| "int32_t".to_string());
+                self.emit_no_indent(&format!("({{ {} {}; __auto_type {} = ", result_type, result, value));
                 self.generate_expression(expr)?;
-                self.emit_no_indent(") {");
+                self.emit_no_indent(";");
 
-                for (i, arm) in arms.iter().enumerate() {
+                let mut first = true;
+                for arm in arms {
+                    let keyword = if first { " " } else { " else " };
+                    first = false;
                     match &arm.pattern {
                         MatchPattern::Literal(lit_expr) => {
-                            self.emit_no_indent(" case ");
+                            self.emit_no_indent(&format!("{}if ({} == ", keyword, value));
                             self.generate_expression(lit_expr)?;
-                            self.emit_no_indent(": __match_result = ");
+                            self.emit_no_indent(&format!(") {{ {} = ", result));
                             self.generate_expression(&arm.body)?;
-                            self.emit_no_indent("; break;");
+                            self.emit_no_indent("; }");
                         }
                         MatchPattern::Wildcard => {
-                            self.emit_no_indent(" default: __match_result = ");
+                            self.emit_no_indent(&format!("{}{{ {} = ", keyword, result));
+                            self.generate_expression(&arm.body)?;
+                            self.emit_no_indent("; }");
+                        }
+                        MatchPattern::Binding(name) => {
+                            self.emit_no_indent(&format!(
+                                "{}{{ __auto_type {} = {}; {} = ",
+                                keyword, name, value, result
+                            ));
+                            self.enter_scope();
+                            self.variables.insert(name.clone(), "int32_t".to_string());
                             self.generate_expression(&arm.body)?;
-                            self.emit_no_indent("; break;");
+                            self.leave_scope();
+                            self.emit_no_indent("; }");
                         }
                     }
+
+                    // Later arms can never match once a catch-all has been seen
+                    if !matches!(arm.pattern, MatchPattern::Literal(_)) {
+                        break;
+                    }
                 }
 
-                self.emit_no_indent(" } __match_result; })");
+                self.emit_no_indent(&format!(" {}; }})", result));
             }
 
             Expression::FunctionExpression { params, return_type, body } => {
-                use std::collections::HashSet;
-
                 // Generate a unique lambda function name
                 let lambda_name = format!("__lambda_{}", self.lambda_counter);
                 let context_name = format!("__context_{}", self.lambda_counter);
                 self.lambda_counter += 1;
 
-                // Detect captured variables
-                let mut param_names = HashSet::new();
+                // Captured variables are the free locals of the body, in a
+                // fixed order so the context layout is deterministic
+                let param_names: HashSet<String> = params.iter().map(|param| param.name.clone()).collect();
+                let mut captured_vars: Vec<String> = body
+                    .find_free_variables(&param_names)
+                    .into_iter()
+                    .filter(|name| self.variables.contains_key(name))
+                    .collect();
+                captured_vars.sort();
+                // A cell or receiver is captured as its pointer, so both
+                // sides share it
+                let captured_fields: Vec<(String, String)> = captured_vars
+                    .iter()
+                    .filter_map(|name| {
+                        let c_type = self.variables.get(name)?;
+                        Some(match self.pointer_to(name) {
+                            Some(pointer) => (pointer.clone(), format!("{}*", c_type)),
+                            None => (name.clone(), c_type.clone()),
+                        })
+                    })
+                    .collect();
+
+                // The parameters are in scope from here on, so the body's
+                // type can be inferred from them
+                self.enter_scope();
                 for param in params {
-                    param_names.insert(param.name.clone());
+                    let c_type = self.type_to_c(&param.param_type);
+                    self.variables.insert(param.name.clone(), c_type);
+                    self.record_array_elements(&param.name, &param.param_type);
                 }
-                let captured_vars = body.find_free_variables(&param_names);
 
-                // Build the function signature
-                let ret_type = return_type
-                    .as_ref()
-                    .map(|t| self.type_to_c(t))
-                    .unwrap_or_else(|| "void".to_string());
+                // Build the function signature; an unannotated arrow
+                // function's type comes from its expression body
+                let ret_type = match (return_type, body.statements.as_slice()) {
+                    (Some(t), _) => self.type_to_c(t),
+                    (None, [Statement::Return { value: Some(value) }]) => {
+                        if self.is_float_expression(value) {
+                            "double".to_string()
+                        } else if self.is_string_expression(value) {
+                            "char*".to_string()
+                        } else {
+                            "int32_t".to_string()
+                        }
+                    }
+                    (None, _) => "void".to_string(),
+                };
 
                 let mut func_def = String::new();
 
-                // Generate global variables for captured variables (simplified approach)
+                // Each capturing lambda gets a context struct holding the
+                // captured values, allocated when the lambda is created
                 if !captured_vars.is_empty() {
-                    for var_name in &captured_vars {
-                        func_def.push_str(&format!("int32_t __captured_{};\n", var_name));
+                    func_def.push_str(&format!("struct {} {{\n", context_name));
+                    for (field, c_type) in &captured_fields {
+                        func_def.push_str(&format!("    {} {};\n", c_type, field));
                     }
-                    func_def.push_str("\n");
+                    func_def.push_str("};\n\n");
                 }
 
-                func_def.push_str(&ret_type);
-                func_def.push_str(" ");
-                func_def.push_str(&lambda_name);
-                func_def.push_str("(");
-
-                for (i, param) in params.iter().enumerate() {
-                    if i > 0 {
-                        func_def.push_str(", ");
-                    }
-                    func_def.push_str(&self.type_to_c(&param.param_type));
-                    func_def.push_str(" ");
-                    func_def.push_str(&param.name);
+                let mut c_params = Vec::new();
+                if !captured_vars.is_empty() {
+                    c_params.push("void* __env".to_string());
                 }
+                for param in params {
+                    c_params.push(format!("{} {}", self.type_to_c(&param.param_type), param.name));
+                }
+                func_def.push_str(&format!("static {} {}({}) {{\n", ret_type, lambda_name, c_params.join(", ")));
 
-                func_def.push_str(") {\n");
-
-                // For captured variables, use #define to alias them to globals
-                // This way assignments work correctly
+                // Unpack the context into locals at entry. Only what is
+                // reached through a pointer is ever assigned.
                 if !captured_vars.is_empty() {
-                    for var_name in &captured_vars {
-                        func_def.push_str(&format!("#define {} __captured_{}\n", var_name, var_name));
+                    func_def.push_str(&format!("    struct {c}* __ctx = (struct {c}*)__env;\n", c = context_name));
+                    for (field, c_type) in &captured_fields {
+                        func_def.push_str(&format!("    {} {} = __ctx->{};\n", c_type, field, field));
                     }
                 }
 
-                // Save current output and indent to generate body
-                let saved_output = self.output.clone();
+                // Generate the body on its own, outside the enclosing
+                // function's defers and postconditions
+                let saved_output = std::mem::take(&mut self.output);
                 let saved_indent = self.indent_level;
-                self.output = String::new();
+                let saved_defers = std::mem::replace(&mut self.defer_stack, vec![Vec::new()]);
+                let saved_ensures = std::mem::take(&mut self.current_ensures);
                 self.indent_level = 1;
 
-                // Generate the function body
-                self.generate_block(body)?;
-
-                // Capture generated body
-                let body_code = self.output.clone();
+                let result = self.generate_block(body);
 
-                // Restore output and indent
-                self.output = saved_output;
+                let body_code = std::mem::replace(&mut self.output, saved_output);
                 self.indent_level = saved_indent;
+                self.leave_scope();
+                self.defer_stack = saved_defers;
+                self.current_ensures = saved_ensures;
+                result?;
 
                 func_def.push_str(&body_code);
-
-                // Undefine the captured variable aliases
-                if !captured_vars.is_empty() {
-                    for var_name in &captured_vars {
-                        func_def.push_str(&format!("#undef {}\n", var_name));
-                    }
-                }
-
                 func_def.push_str("}\n\n");
 
                 self.lambda_functions.push(func_def);
 
-                // Store lambda info for later use
-                self.lambda_info.push(LambdaInfo {
-                    name: lambda_name.clone(),
-                    captured_vars: captured_vars.clone(),
-                    context_struct: context_name.clone(),
-                });
-
-                // Set captured variables before emitting the function reference
-                if !captured_vars.is_empty() {
-                    self.emit_no_indent("({");
-                    for var_name in &captured_vars {
-                        self.emit_no_indent(&format!("__captured_{} = {}; ", var_name, var_name));
-                    }
-                    self.emit_no_indent(&format!("{}; ", lambda_name));
-                    self.emit_no_indent("})");
+                // Creating the closure copies the captured values into a
+                // fresh context
+                if captured_vars.is_empty() {
+                    self.emit_no_indent(&format!("closure_new((void*){}, NULL)", lambda_name));
                 } else {
-                    self.emit_no_indent(&lambda_name);
+                    let ctx = self.fresh_temp("ctx");
+                    self.emit_no_indent(&format!(
+                        "({{ struct {c}* {ctx} = malloc(sizeof(struct {c})); ",
+                        c = context_name,
+                        ctx = ctx
+                    ));
+                    for (field, _) in &captured_fields {
+                        self.emit_no_indent(&format!("{}->{} = {}; ", ctx, field, field));
+                    }
+                    self.emit_no_indent(&format!("closure_new((void*){}, {}); }})", lambda_name, ctx));
                 }
             }
         }
@@ -1417,6 +3832,22 @@ impl CodeGenerator {
         Ok(())
     }
 
+    fn is_dynamic_array(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Identifier(name) => self.variables.get(name).is_some_and(|t| t == "DynamicArray*"),
+            _ => self.expression_c_type(expr).as_deref() == Some("DynamicArray*"),
+        }
+    }
+
+    // Helpers come in `_i32` and `_string` flavors; pick one from the
+    // element type of the array
+    fn array_helper_suffix(&self, array: &Expression) -> &'static str {
+        match self.dynamic_element_c_type(array).as_deref() {
+            Some("char*") => "string",
+            _ => "i32",
+        }
+    }
+
     fn type_to_c(&self, ty: &Type) -> String {
         match ty {
             Type::I8 => "int8_t".to_string(),
@@ -1435,22 +3866,16 @@ impl CodeGenerator {
             Type::String => "char*".to_string(),
             Type::Nothing => "void*".to_string(),
             Type::Unknown => "void*".to_string(),
-            Type::Array { element_type, size } => {
-                if let Some(_s) = size {
-                    format!("{}*", self.type_to_c(element_type))
-                } else {
-                    format!("{}*", self.type_to_c(element_type))
-                }
-            }
-            Type::Function { params, return_type } => {
-                // Generate proper function pointer typedef
-                // For simplicity, we'll use a generic function pointer signature
-                // In C: int32_t (*name)(int32_t, int32_t)
-                // But for variables, we can't include the name, so we use typedef
-                // For now, simplified: assume all function pointers take (i32, i32) -> i32
-                "void*".to_string()  // Will cast at call site
+            Type::Array { size: None, .. } => "DynamicArray*".to_string(),
+            Type::Array { element_type, size: Some(_) } => format!("{}*", self.type_to_c(element_type)),
+            // Function values are closures; each function type has a
+            // typedef recording its signature, see generate_function_typedefs
+            Type::Function { .. } => match self.function_types.iter().position(|known| known == ty) {
+                Some(index) => format!("__fn_type_{}", index),
+                None => "Closure*".to_string(),
             },
             Type::Object => "void*".to_string(),
+            Type::Named(name) => name.clone(),
         }
     }
 
@@ -1486,35 +3911,445 @@ impl CodeGenerator {
     }
 }
 
-pub fn compile(program: &Program, output_path: &str, optimization: u8) -> Result<(), String> {
-    let mut codegen = CodeGenerator::new();
-    let c_code = codegen.generate(program)?;
-
-    // Write C code to temporary file
-    let c_file_path = format!("{}.c", output_path);
-    let mut c_file = File::create(&c_file_path)
-        .map_err(|e| format!("Failed to create C file: {}", e))?;
+// Suffix naming the typed array helpers for elements of `c_type`
+fn c_type_suffix(c_type: &str) -> Result<String, String> {
+    // A fixed-size array stored by value: `__typeof__(int32_t[2])` is `i32x2`
+    if let Some(array) = c_type.strip_prefix("__typeof__(").and_then(|array| array.strip_suffix(')')) {
+        let (base, dimensions) = array.split_at(array.find('[').unwrap_or(array.len()));
+        return Ok(format!("{}{}", c_type_suffix(base)?, dimensions.replace('[', "x").replace(']', "")));
+    }
+    let suffix = match c_type {
+        "int8_t" => "i8",
+        "int16_t" => "i16",
+        "int32_t" => "i32",
+        "int64_t" => "i64",
+        "uint8_t" => "u8",
+        "uint16_t" => "u16",
+        "uint32_t" => "u32",
+        "uint64_t" => "u64",
+        "float" => "f32",
+        "double" => "f64",
+        "bool" => "bool",
+        "char*" => "string",
+        "DynamicArray*" => "array",
+        // Structs, enums and object shapes go by their typedef's name
+        name if name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => name,
+        _ => return Err(format!("Dynamic arrays of '{}' are not supported", c_type)),
+    };
+    Ok(suffix.to_string())
+}
 
-    c_file
-        .write_all(c_code.as_bytes())
-        .map_err(|e| format!("Failed to write C file: {}", e))?;
+// Whether `c_type` is the by-value element type of a fixed-size array,
+// see `CodeGenerator::element_c_type`
+fn is_fixed_array_c_type(c_type: &str) -> bool {
+    c_type.starts_with("__typeof__(")
+}
 
-    // Compile C code with GCC
-    let opt_flag = format!("-O{}", optimization);
-    let output = Command::new("gcc")
-        .args(&[
-            &c_file_path,
-            "-o",
-            output_path,
-            &opt_flag,
-            "-std=c11",
-        ])
-        .output()
-        .map_err(|e| format!("Failed to run GCC: {}", e))?;
+// Whether `c_type` is a number the array reductions can add up
+fn is_number_c_type(c_type: &str) -> bool {
+    matches!(
+        c_type,
+        "int8_t" | "int16_t" | "int32_t" | "int64_t" | "uint8_t" | "uint16_t" | "uint32_t" | "uint64_t" | "float" | "double"
+    )
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(format!("GCC compilation failed:\n{}", stderr));
+// The struct and enum declarations in the order their C typedefs must
+// come: enums first, then each struct after the structs it holds by value
+fn ordered_type_declarations(program: &Program) -> Result<Vec<&Statement>, String> {
+    // The struct a field of type `ty` holds by value, if any
+    fn held_struct(ty: &Type) -> Option<&str> {
+        match ty {
+            Type::Named(name) => Some(name),
+            Type::Array { element_type, size: Some(_) } => held_struct(element_type),
+            _ => None,
+        }
+    }
+
+    fn visit<'a>(
+        name: &'a str,
+        structs: &HashMap<&'a str, &'a Statement>,
+        done: &mut HashMap<&'a str, bool>,
+        ordered: &mut Vec<&'a Statement>,
+    ) -> Result<(), String> {
+        match done.get(name) {
+            Some(true) => return Ok(()),
+            Some(false) => return Err(format!("Struct `{}` contains itself", name)),
+            None => {}
+        }
+        let Some(&stmt @ Statement::StructDecl { fields, .. }) = structs.get(name) else {
+            return Ok(());
+        };
+        done.insert(name, false);
+        for held in fields.iter().filter_map(|field| held_struct(&field.param_type)) {
+            visit(held, structs, done, ordered)?;
+        }
+        done.insert(name, true);
+        ordered.push(stmt);
+        Ok(())
+    }
+
+    let mut ordered: Vec<&Statement> =
+        program.statements.iter().filter(|stmt| matches!(stmt, Statement::EnumDecl { .. })).collect();
+    let structs: HashMap<&str, &Statement> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::StructDecl { name, .. } => Some((name.as_str(), stmt)),
+            _ => None,
+        })
+        .collect();
+    // Whether each struct is placed, or still being placed
+    let mut done = HashMap::new();
+    for stmt in &program.statements {
+        if let Statement::StructDecl { name, .. } = stmt {
+            visit(name, &structs, &mut done, &mut ordered)?;
+        }
+    }
+    Ok(ordered)
+}
+
+// The type sema resolved for a call or method call
+fn resolved_type(expr: &Expression) -> Option<&Type> {
+    match expr {
+        Expression::Call { resolved_type, .. } | Expression::MethodCall { resolved_type, .. } => resolved_type.as_ref(),
+        _ => None,
+    }
+}
+
+// The HiLow type of a value of C type `c_type`, as far as printing it
+// goes: the elements of a dynamic array are taken to be ints
+fn c_type_to_type(c_type: &str) -> Type {
+    match c_type {
+        "int8_t" => Type::I8,
+        "int16_t" => Type::I16,
+        "int64_t" => Type::I64,
+        "uint8_t" => Type::U8,
+        "uint16_t" => Type::U16,
+        "uint32_t" => Type::U32,
+        "uint64_t" => Type::U64,
+        "float" => Type::F32,
+        "double" => Type::F64,
+        "bool" => Type::Bool,
+        "char*" => Type::String,
+        "DynamicArray*" => Type::Array { element_type: Box::new(Type::I32), size: None },
+        "int32_t" => Type::I32,
+        // Struct, enum and object typedefs
+        name => Type::Named(name.to_string()),
+    }
+}
+
+// printf conversion for a value of C type `c_type`; anything unknown is
+// assumed to be an int
+fn format_specifier(c_type: Option<&str>) -> &'static str {
+    match c_type {
+        Some("char*") => "%s",
+        Some("char") => "%c",
+        Some("double" | "float") => "%f",
+        Some("int64_t") => "%lld",
+        Some("uint64_t") => "%llu",
+        Some("uint32_t") => "%u",
+        _ => "%d",
+    }
+}
+
+// Split an f-string spec into printf's `[flags][width][.precision]` and
+// the conversion letter, if there is one
+fn split_format_spec(spec: &str) -> (&str, Option<char>) {
+    match spec.chars().last() {
+        Some(c) if c.is_ascii_alphabetic() => (&spec[..spec.len() - 1], Some(c)),
+        _ => (spec, None),
+    }
+}
+
+// printf conversion for a value of C type `c_type` printed with an
+// f-string spec. Without a conversion letter the inferred one is kept, so
+// `{pi:.2}` works like `{pi:.2f}`. Binary is converted to a string first.
+fn spec_format_specifier(spec: &str, c_type: Option<&str>) -> Result<String, String> {
+    let (options, conversion) = split_format_spec(spec);
+    let is_string = c_type == Some("char*");
+    let is_float = matches!(c_type, Some("double" | "float"));
+    let length = if matches!(c_type, Some("int64_t" | "uint64_t")) { "ll" } else { "" };
+    let needs = |what: &str| Err(format!("Format spec `{}` needs {}, not a {}", spec, what, c_type.unwrap_or_default()));
+
+    match conversion {
+        None => Ok(format_specifier(c_type).replacen('%', &format!("%{}", options), 1)),
+        Some('d' | 'i' | 'u' | 'x' | 'X' | 'o' | 'b' | 'c') if is_string || is_float => needs("an integer"),
+        Some('b') if options.contains(['0', '.']) => {
+            Err(format!("Format spec `{}`: binary output only takes a width", spec))
+        }
+        Some('b') => Ok(format!("%{}s", options)),
+        Some(c @ ('d' | 'i' | 'u' | 'x' | 'X' | 'o')) => Ok(format!("%{}{}{}", options, length, c)),
+        Some('c') => Ok(format!("%{}c", options)),
+        Some('f' | 'F' | 'e' | 'E' | 'g' | 'G') if is_string => needs("a number"),
+        Some(c @ ('f' | 'F' | 'e' | 'E' | 'g' | 'G')) => Ok(format!("%{}{}", options, c)),
+        Some('s') if c_type.is_some() && !is_string => needs("a string"),
+        Some('s') => Ok(format!("%{}s", options)),
+        Some(_) => Err(format!("Unsupported f-string format spec `{}`", spec)),
+    }
+}
+
+// Result type of arithmetic on two operands under C's usual arithmetic
+// conversions, as far as format specifiers need to distinguish
+fn arithmetic_c_type(left: &str, right: &str) -> &'static str {
+    ["double", "float", "uint64_t", "int64_t", "uint32_t"]
+        .into_iter()
+        .find(|wide| left == *wide || right == *wide)
+        .unwrap_or("int32_t")
+}
+
+// C type produced by builtins whose result can initialize an untyped
+// `let`, for calls sema left untyped
+fn builtin_return_type(name: &str) -> Option<&'static str> {
+    match name {
+        "to_int" | "to_int_radix" => Some("int64_t"),
+        "is_int" | "is_int_radix" | "is_nan" | "is_inf" => Some("bool"),
+        "string_length" | "string_index_of" | "string_compare" => Some("int32_t"),
+        "args" | "fill" => Some("DynamicArray*"),
+        "env" | "string_from_bytes" | "string_concat" => Some("char*"),
+        "hash" => Some("uint64_t"),
+        _ => None,
+    }
+}
+
+/// `c` as a C character constant
+fn escape_c_char(c: char) -> String {
+    match c {
+        '\'' => "'\\''".to_string(),
+        c => format!("'{}'", escape_c_string(&c.to_string())),
+    }
+}
+
+/// Escape `text` for the inside of a C string literal. Other control
+/// characters and non-ASCII ones (as UTF-8 bytes) become octal escapes,
+/// and a `?` after a `?` is escaped so it can't start a trigraph.
+fn escape_c_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let mut previous = None;
+    for ch in text.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            '?' if previous == Some('?') => escaped.push_str("\\?"),
+            c if c.is_ascii_control() => escaped.push_str(&format!("\\{:03o}", c as u32)),
+            c if c.is_ascii() => escaped.push(c),
+            // UTF-8 bytes, whatever the C compiler takes the source charset to be
+            c => {
+                for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                    escaped.push_str(&format!("\\{:03o}", byte));
+                }
+            }
+        }
+        previous = Some(ch);
+    }
+    escaped
+}
+
+// Storage class for a top-level function: only exported functions (and
+// the entry point) are visible outside the generated C file
+fn linkage(name: &str, is_export: bool) -> &'static str {
+    if is_export || name == "main" {
+        ""
+    } else {
+        "static "
+    }
+}
+
+fn has_contracts(stmt: &Statement) -> bool {
+    matches!(stmt, Statement::FunctionDecl { requires, ensures, .. } if !requires.is_empty() || !ensures.is_empty())
+}
+
+fn ends_in_jump(block: &Block) -> bool {
+    matches!(
+        block.statements.last(),
+        Some(Statement::Break | Statement::Continue | Statement::Return { .. })
+    )
+}
+
+fn is_zero_literal(expr: &Expression) -> bool {
+    match expr {
+        Expression::IntegerLiteral(n) => *n == 0,
+        Expression::FloatLiteral(f) => *f == 0.0,
+        Expression::BooleanLiteral(b) => !*b,
+        _ => false,
+    }
+}
+
+// A parameterless `main` receives argc/argv so `args()` can see them
+fn is_entry_point(name: &str, params: &[Parameter]) -> bool {
+    name == "main" && params.is_empty()
+}
+
+// Reject `to_int("xyz")` at compile time when the input is a literal
+fn check_int_literal_argument(args: &[Expression]) -> Result<(), String> {
+    let base = match args.get(1) {
+        None => 10,
+        Some(Expression::IntegerLiteral(base)) => *base,
+        Some(_) => return Ok(()),
+    };
+
+    if !(2..=36).contains(&base) {
+        return Err(format!("Invalid radix {} for to_int (expected 2-36)", base));
+    }
+
+    if let Expression::StringLiteral(text) = &args[0] {
+        let digits = text.strip_prefix(['+', '-']).unwrap_or(text);
+        let digits = if base == 16 {
+            digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")).unwrap_or(digits)
+        } else {
+            digits
+        };
+
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(base as u32)) {
+            return Err(format!("Invalid integer \"{}\" for base {}", text, base));
+        }
+    }
+
+    Ok(())
+}
+
+fn type_uses_int128(ty: &Type) -> bool {
+    match ty {
+        Type::I128 | Type::U128 => true,
+        Type::Array { element_type, .. } => type_uses_int128(element_type),
+        Type::Function { params, return_type } => {
+            params.iter().any(type_uses_int128) || type_uses_int128(return_type)
+        }
+        _ => false,
+    }
+}
+
+// The locals that some closure both captures and assigns. They are kept
+// in a heap cell the closure points to, so an assignment on either side
+// is seen by the other; every other capture is a copy.
+fn shared_captures(program: &Program) -> HashSet<String> {
+    let mut collector = SharedCaptureCollector::default();
+    collector.visit_program(program);
+    collector.names
+}
+
+#[derive(Default)]
+struct SharedCaptureCollector {
+    names: HashSet<String>,
+}
+
+impl<'a> Visitor<'a> for SharedCaptureCollector {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        if let Expression::FunctionExpression { params, body, .. } = expr {
+            let param_names: HashSet<String> = params.iter().map(|param| param.name.clone()).collect();
+            let free = body.find_free_variables(&param_names);
+            let mut assigned = AssignedNames::default();
+            assigned.visit_block(body);
+            self.names.extend(assigned.names.into_iter().filter(|name| free.contains(name)));
+        }
+        walk_expression(self, expr);
+    }
+}
+
+// Names assigned with `name = ...` anywhere below
+#[derive(Default)]
+struct AssignedNames {
+    names: HashSet<String>,
+}
+
+impl<'a> Visitor<'a> for AssignedNames {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        if let Expression::Assignment { target, .. } = expr {
+            if let Expression::Identifier(name) = target.as_ref() {
+                self.names.insert(name.clone());
+            }
+        }
+        walk_expression(self, expr);
+    }
+}
+
+// `typedef enum { Name_A, Name_B } Name;`
+fn enum_typedef(name: &str, variants: &[String]) -> String {
+    let constants: Vec<String> = variants.iter().map(|variant| format!("{}_{}", name, variant)).collect();
+    format!("typedef enum {{ {} }} {};", constants.join(", "), name)
+}
+
+// Collects every type annotation written in the program
+#[derive(Default)]
+struct TypeCollector<'a> {
+    types: Vec<&'a Type>,
+}
+
+impl<'a> Visitor<'a> for TypeCollector<'a> {
+    fn visit_type(&mut self, ty: &'a Type) {
+        self.types.push(ty);
+    }
+}
+
+pub fn compile(program: &Program, output_path: &str, options: &CompileOptions) -> Result<(), String> {
+    let mut codegen = CodeGenerator::new(options.clone());
+    let c_code = codegen.generate(program)?;
+    run_gcc(&c_code, output_path, &[], options)?;
+    write_header(&codegen, program, output_path)
+}
+
+/// Write the generated C for `program` next to `output_path` and stop
+/// there, returning the path of the C file
+pub fn emit_c(program: &Program, output_path: &str, options: &CompileOptions) -> Result<String, String> {
+    let mut codegen = CodeGenerator::new(options.clone());
+    let c_code = codegen.generate(program)?;
+    let c_file_path = generate_c_file(&c_code, output_path)?;
+    write_header(&codegen, program, output_path)?;
+    Ok(c_file_path)
+}
+
+/// Compile a module to an object file, for linking or archiving later
+pub fn compile_object(program: &Program, object_path: &str, options: &CompileOptions) -> Result<(), String> {
+    let mut codegen = CodeGenerator::new(options.clone());
+    let c_code = codegen.generate(program)?;
+    run_gcc(&c_code, object_path, &["-c"], options)?;
+    write_header(&codegen, program, object_path)
+}
+
+// Write `<output>.h` next to the output when the program exports anything
+fn write_header(codegen: &CodeGenerator, program: &Program, output_path: &str) -> Result<(), String> {
+    let header_path = Path::new(output_path).with_extension("h");
+    let stem = header_path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+
+    if let Some(header) = codegen.generate_header(program, &stem) {
+        std::fs::write(&header_path, header)
+            .map_err(|e| format!("Failed to write {}: {}", header_path.display(), e))?;
+    }
+
+    Ok(())
+}
+
+/// Bundle object files into a static archive with `ar`, replacing any
+/// existing archive at that path
+pub fn create_archive(objects: &[PathBuf], archive_path: &Path) -> Result<(), String> {
+    if archive_path.exists() {
+        std::fs::remove_file(archive_path)
+            .map_err(|e| format!("Failed to replace {}: {}", archive_path.display(), e))?;
+    }
+
+    let output = Command::new("ar")
+        .arg("rcs")
+        .arg(archive_path)
+        .args(objects)
+        .output()
+        .map_err(|e| format!("Failed to run ar: {}", e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("ar failed:\n{}", stderr));
+    }
+
+    Ok(())
+}
+
+// Write the generated C next to the output and build it with the C compiler
+fn run_gcc(c_code: &str, output_path: &str, extra_args: &[&str], options: &CompileOptions) -> Result<(), String> {
+    let c_file_path = generate_c_file(c_code, output_path)?;
+    invoke_gcc(&c_file_path, output_path, extra_args, options)?;
+    if options.keep_c {
+        return Ok(());
     }
 
     // Clean up C file
@@ -1523,3 +4358,2097 @@ pub fn compile(program: &Program, output_path: &str, optimization: u8) -> Result
 
     Ok(())
 }
+
+// Write the C code to `<output_path>.c`, returning that path
+fn generate_c_file(c_code: &str, output_path: &str) -> Result<String, String> {
+    let c_file_path = format!("{}.c", output_path);
+    let mut c_file = File::create(&c_file_path)
+        .map_err(|e| format!("Failed to create C file: {}", e))?;
+
+    c_file
+        .write_all(c_code.as_bytes())
+        .map_err(|e| format!("Failed to write C file: {}", e))?;
+
+    Ok(c_file_path)
+}
+
+fn invoke_gcc(c_file_path: &str, output_path: &str, extra_args: &[&str], options: &CompileOptions) -> Result<(), String> {
+    let opt_flag = format!("-O{}", options.optimization);
+    let output = Command::new(&options.cc)
+        .args([
+            c_file_path,
+            "-o",
+            output_path,
+            &opt_flag,
+            "-std=c11",
+        ])
+        .args(extra_args)
+        .args(&options.cflags)
+        // libm, for fmod and sqrt; an object file isn't linked yet
+        .args(if extra_args.contains(&"-c") { &[][..] } else { &["-lm"][..] })
+        .output()
+        .map_err(|e| format!("Failed to run {}: {}", options.cc, e))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} compilation failed:\n{}", options.cc, stderr));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn generate_c(source: &str, options: CompileOptions) -> Result<String, String> {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        let mut program = parser.parse().map_err(|errors| errors[0].message.clone())?;
+        crate::sema::record_types(&mut program);
+        CodeGenerator::new(options).generate(&program)
+    }
+
+    // Compile to a native binary in a scratch directory and run it,
+    // returning its exit code and stdout
+    fn compile_and_run(source: &str) -> (i32, String) {
+        compile_and_run_with(source, &[], &[])
+    }
+
+    fn compile_and_run_with(source: &str, args: &[&str], envs: &[(&str, &str)]) -> (i32, String) {
+        let output = compile_and_capture(source, args, envs);
+        (
+            output.status.code().unwrap_or(-1),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+        )
+    }
+
+    fn compile_and_capture(source: &str, args: &[&str], envs: &[(&str, &str)]) -> std::process::Output {
+        compile_and_capture_with(source, args, envs, &CompileOptions::default())
+    }
+
+    fn compile_and_capture_with(
+        source: &str,
+        args: &[&str],
+        envs: &[(&str, &str)],
+        options: &CompileOptions,
+    ) -> std::process::Output {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let mut program = parser.parse().unwrap();
+        crate::sema::record_types(&mut program);
+
+        let dir = std::env::temp_dir().join(format!(
+            "hilowc-test-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("program");
+
+        compile(&program, binary.to_str().unwrap(), options).unwrap();
+        let output = Command::new(&binary)
+            .args(args)
+            .envs(envs.iter().copied())
+            .output()
+            .unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+
+        output
+    }
+
+    fn options_for(triple: &str) -> CompileOptions {
+        CompileOptions {
+            target: Target::from_triple(triple).unwrap(),
+            ..CompileOptions::default()
+        }
+    }
+
+    #[test]
+    fn test_entry_function_other_than_main() {
+        let source = "function start(): i32 {
+            let given: [string] = args();
+            println(f\"starting with {given.length} args\");
+            return 3;
+        }";
+        let options = CompileOptions { entry: "start".to_string(), ..CompileOptions::default() };
+
+        let c_code = generate_c(source, options.clone()).unwrap();
+        assert!(c_code.contains("static int32_t start() {"), "{}", c_code);
+        assert!(c_code.contains("    return start();"), "{}", c_code);
+
+        let output = compile_and_capture_with(source, &["a", "b"], &[], &options);
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "starting with 2 args\n");
+        assert_eq!(output.status.code(), Some(3));
+
+        let err = generate_c("function begin(): i32 { return 0; }", options.clone()).unwrap_err();
+        assert_eq!(err, "Entry function `start` is not defined");
+        let err = generate_c("function start(n: i32): i32 { return n; }", options.clone()).unwrap_err();
+        assert_eq!(err, "Entry function `start` must not take parameters");
+        let err = generate_c("function start(): i32 { return 0; } function main(): i32 { return 0; }", options)
+            .unwrap_err();
+        assert!(err.contains("would clash"), "{}", err);
+    }
+
+    #[test]
+    fn test_emit_c_writes_source_without_compiling() {
+        let dir = std::env::temp_dir().join(format!("hilowc-emit-c-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("program");
+
+        let tokens = Lexer::new("function main(): i32 { return 0; }").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let c_path = emit_c(&program, output.to_str().unwrap(), &CompileOptions::default()).unwrap();
+
+        let c_code = std::fs::read_to_string(&c_path).unwrap();
+        assert!(c_code.starts_with("#define _GNU_SOURCE\n#include <stdio.h>"), "{}", c_code);
+        assert!(c_code.contains("int32_t main("), "{}", c_code);
+        assert!(!output.exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_keep_c_leaves_source_beside_binary() {
+        let dir = std::env::temp_dir().join(format!("hilowc-keep-c-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("program");
+
+        let tokens = Lexer::new("function main(): i32 { return 4; }").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let options = CompileOptions {
+            keep_c: true,
+            ..CompileOptions::default()
+        };
+        compile(&program, output.to_str().unwrap(), &options).unwrap();
+
+        let c_code = std::fs::read_to_string(dir.join("program.c")).unwrap();
+        assert!(c_code.contains("int32_t main("), "{}", c_code);
+        let status = Command::new(&output).status().unwrap();
+        assert_eq!(status.code(), Some(4));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_custom_c_compiler_receives_flags() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("hilowc-cc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("args.txt");
+        let wrapper = dir.join("cc.sh");
+        std::fs::write(&wrapper, format!("#!/bin/sh\nprintf '%s\\n' \"$@\" > {}\nexec gcc \"$@\"\n", log.display()))
+            .unwrap();
+        std::fs::set_permissions(&wrapper, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let output = dir.join("program");
+        let output_path = output.to_str().unwrap();
+        let options = CompileOptions {
+            cc: wrapper.to_str().unwrap().to_string(),
+            cflags: vec!["-Wall".to_string(), "-DEXTRA=1".to_string()],
+            ..CompileOptions::default()
+        };
+        let tokens = Lexer::new("function main(): i32 { return 0; }").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        compile(&program, output_path, &options).unwrap();
+
+        let args: Vec<String> = std::fs::read_to_string(&log).unwrap().lines().map(String::from).collect();
+        let c_path = format!("{}.c", output_path);
+        let expected = [c_path.as_str(), "-o", output_path, "-O0", "-std=c11", "-Wall", "-DEXTRA=1", "-lm"];
+        assert_eq!(args, expected);
+        assert!(output.exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_target_without_int128_rejects_i128() {
+        let source = "function main(): i32 { let big: i128 = 1; return 0; }";
+
+        let err = generate_c(source, options_for("i686-unknown-linux-gnu")).unwrap_err();
+        assert!(err.contains("i128"), "unexpected error: {}", err);
+
+        let c_code = generate_c(source, options_for("x86_64-unknown-linux-gnu")).unwrap();
+        assert!(c_code.contains("__int128 big = 1;"));
+    }
+
+    #[test]
+    fn test_strict_mutability_emits_const() {
+        let source = "function main(): i32 {
+            let limit: i32 = 3;
+            let name: string = \"hilow\";
+            let mut total: i32 = 0;
+            let pending: i32;
+            pending = 1;
+            for (let mut i: i32 = 0; i < limit; i = i + 1) { total = total + i; }
+            return total + pending + name.length;
+        }";
+        let options = CompileOptions { strict_mutability: true, ..CompileOptions::default() };
+
+        let c_code = generate_c(source, options.clone()).unwrap();
+        assert!(c_code.contains("int32_t const limit = 3;"), "{}", c_code);
+        assert!(c_code.contains("char* const name = "), "{}", c_code);
+        assert!(c_code.contains("int32_t total = 0;"), "{}", c_code);
+        assert!(c_code.contains("int32_t pending;"), "{}", c_code);
+
+        let output = compile_and_capture_with(source, &[], &[], &options);
+        assert_eq!(output.status.code(), Some(3 + 1 + 5));
+    }
+
+    #[test]
+    fn test_panic_prints_backtrace_when_checked() {
+        let source = "function inner(n: i32): i32 {
+            if (n > 1) { panic(\"boom\"); }
+            return n;
+        }
+        function outer(n: i32): i32 {
+            let first: i32 = inner(1);
+            return first + inner(n);
+        }
+        function main(): i32 {
+            return outer(2);
+        }";
+        let options = CompileOptions { checked: true, ..CompileOptions::default() };
+
+        let output = compile_and_capture_with(source, &[], &[], &options);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // The finished `inner(1)` call is popped and not listed
+        assert_eq!(
+            stderr,
+            "panic: boom\nbacktrace (most recent call first):\n    inner\n    outer\n    main\n"
+        );
+
+        // Unchecked builds still panic, just without the stack
+        let output = compile_and_capture_with(source, &[], &[], &CompileOptions::default());
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "panic: boom\n");
+    }
+
+    #[test]
+    fn test_checked_indexing_aborts_out_of_bounds() {
+        let source = "function main(): i32 {
+            let mut xs: [i32] = [1, 2, 3];
+            let fixed: [i32; 2] = [4, 5];
+            let i: i32 = 1;
+            println(xs[i], fixed[i]);
+            println(xs[i + 2]);
+            return 0;
+        }";
+        let options = CompileOptions { checked: true, ..CompileOptions::default() };
+
+        let c_code = generate_c(source, options.clone()).unwrap();
+        assert!(c_code.contains("((int32_t*)xs->data)[array_index_checked(i, xs->length)]"), "{}", c_code);
+        assert!(c_code.contains("fixed[array_index_checked(i, 2)]"), "{}", c_code);
+
+        let output = compile_and_capture_with(source, &[], &[], &options);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "2 5\n");
+        assert!(
+            String::from_utf8_lossy(&output.stderr).starts_with("panic: index 3 out of bounds for length 3\n"),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        // Unchecked builds index directly
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(!c_code.contains("array_index_checked"), "{}", c_code);
+        assert!(c_code.contains("((int32_t*)xs->data)[i]"), "{}", c_code);
+    }
+
+    #[test]
+    fn test_checked_indexing_evaluates_the_array_once() {
+        let source = "function mk(): [i32] {
+            println(\"called\");
+            let xs: [i32] = [4, 5, 6];
+            return xs;
+        }
+
+        function main(): i32 {
+            println(mk()[1]);
+            let grid: [[i32]] = [[1, 2], [3, 4]];
+            grid[1][0] = 7;
+            println(grid[1][0]);
+            return 0;
+        }";
+        let options = CompileOptions { checked: true, ..CompileOptions::default() };
+
+        let output = compile_and_capture_with(source, &[], &[], &options);
+        assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "called\n5\n7\n");
+    }
+
+    #[test]
+    fn test_target_controls_gnu_source() {
+        let source = "function main(): i32 { return 0; }";
+
+        let linux = generate_c(source, options_for("x86_64-unknown-linux-gnu")).unwrap();
+        assert!(linux.starts_with("#define _GNU_SOURCE"));
+
+        let darwin = generate_c(source, options_for("aarch64-apple-darwin")).unwrap();
+        assert!(darwin.starts_with("#define _POSIX_C_SOURCE"));
+    }
+
+    #[test]
+    fn test_block_expression_yields_value() {
+        let source = "function main(): i32 {
+            let x: i32 = { let a: i32 = 2; a * 21 };
+            return x;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("int32_t x = ({"));
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 42);
+    }
+
+    #[test]
+    fn test_array_unique_and_count() {
+        let source = "function main(): i32 {
+            let nums: [i32];
+            nums.push(1); nums.push(1); nums.push(2); nums.push(3); nums.push(3);
+            let u: [i32] = nums.unique();
+            println(f\"{u.length}: {u[0]} {u[1]} {u[2]}\");
+            println(f\"threes={nums.count(3)}\");
+
+            let words: [string] = \"a,b,a,c,a\".split(\",\");
+            let distinct: [string] = words.unique();
+            println(distinct.join(\",\"));
+
+            // The results have the receiver's type without a declaration
+            let names = words.dedup();
+            println(words.unique(), names.length, nums.unique());
+            println(words.unique().count(\"b\"));
+            return words.count(\"a\");
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "3: 1 2 3\nthrees=2\na,b,c\n[\"a\", \"b\", \"c\"] 3 [1, 2, 3]\n1\n");
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn test_using_releases_on_every_exit() {
+        let source = "function acquire(): i32 { println(\"acquire\"); return 7; }
+        function release(id: i32) { println(f\"release {id}\"); }
+
+        function work(early: bool): i32 {
+            using (r = acquire(); release(r)) {
+                if (early) {
+                    return 1;
+                }
+                println(\"working\");
+            }
+            return 0;
+        }
+
+        function main(): i32 {
+            work(true);
+            work(false);
+            for (let i: i32 = 0; i < 1; i = i + 1) {
+                using (r = acquire(); release(r)) {
+                    break;
+                }
+            }
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(
+            stdout,
+            "acquire\nrelease 7\nacquire\nworking\nrelease 7\nacquire\nrelease 7\n"
+        );
+    }
+
+    #[test]
+    fn test_struct_methods() {
+        let source = "struct Point {
+            x: f64,
+            y: f64,
+
+            function distanceTo(other: Point): f64 {
+                let dx: f64 = self.x - other.x;
+                let dy: f64 = self.y - other.y;
+                return dx * dx + dy * dy;
+            }
+
+            function moveBy(dx: f64, dy: f64) {
+                self.x = self.x + dx;
+                self.y = self.y + dy;
+            }
+
+            function scaled(factor: f64): Point {
+                let p: Point = { x: self.x * factor, y: self.y * factor };
+                return p;
+            }
+        }
+
+        function main(): i32 {
+            let origin: Point = { x: 0.0, y: 0.0 };
+            let p: Point = { x: 3.0, y: 4.0 };
+            println(p.distanceTo(origin));
+            p.moveBy(3.0, 4.0);
+            let far: f64 = p.scaled(2.0).distanceTo(origin);
+            println(p.x, p.y, far);
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("double Point_distanceTo(Point* self, Point other)"), "{}", c_code);
+        assert!(c_code.contains("(*self).x = ((*self).x + dx);"), "{}", c_code);
+        assert!(c_code.contains("Point_moveBy(&p, 3.0, 4.0)"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "25.000000\n6.000000 8.000000 400.000000\n");
+    }
+
+    #[test]
+    fn test_to_int_with_radix() {
+        let source = "function main(): i32 {
+            let hex = to_int_radix(\"ff\", 16);
+            let bin = to_int_radix(\"101\", 2);
+            let dec = to_int(\"-42\");
+            println(f\"{hex as i32} {bin as i32} {dec as i32}\");
+            let input: string = \"xyz\";
+            if (not is_int(input)) {
+                println(\"rejected\");
+            }
+            return 0;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "255 5 -42\nrejected\n");
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn test_to_int_rejects_invalid_input() {
+        let literal = "function main(): i32 { let n = to_int(\"xyz\"); return 0; }";
+        let err = generate_c(literal, CompileOptions::default()).unwrap_err();
+        assert!(err.contains("Invalid integer \"xyz\""), "unexpected error: {}", err);
+
+        let runtime = "function main(): i32 {
+            let input: string = \"xyz\";
+            let n = to_int(input);
+            return 0;
+        }";
+        let (status, _) = compile_and_run(runtime);
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn test_padding_and_print_row() {
+        let source = "function main(): i32 {
+            let left: string = \"a\".padEnd(5);
+            let right: string = \"7\".padStart(3, \"0\");
+            println(f\"[{left}] [{right}]\");
+            print_row([\"a\", \"bb\"], [5, 5]);
+            print_row([\"x\", \"42\"], [5, -5]);
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "[a    ] [007]\na     bb   \nx        42\n");
+
+        // Each argument is evaluated once, and a fixed-size one passes its
+        // declared size
+        let evaluated_once = "function cells(): [string] {
+            println(\"called\");
+            return \"a,bb\".split(\",\");
+        }
+
+        function main(): i32 {
+            let widths: [i32; 2] = [4, 4];
+            print_row(cells(), widths);
+            print_row(cells(), [2, 2]);
+            return 0;
+        }";
+        let (_, stdout) = compile_and_run(evaluated_once);
+        assert_eq!(stdout, "called\na    bb  \ncalled\na  bb\n");
+
+        let mismatched = "function main(): i32 {
+            let widths: [i32] = [5];
+            print_row([\"a\", \"bb\"], widths);
+            return 0;
+        }";
+        let output = compile_and_capture(mismatched, &[], &[]);
+        assert_eq!(output.status.code(), Some(1));
+        assert!(output.stdout.is_empty());
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("error: print_row() got 2 cells but 1 widths\n"), "{}", stderr);
+    }
+
+    #[test]
+    fn test_split_with_limit_and_lines() {
+        let source = "function main(): i32 {
+            let parts: [string] = \"key=value=more\".split(\"=\", 1);
+            println(f\"{parts.length} \");
+            print(parts.join(\"|\"));
+            let fields: [string] = \"a::b::c\".split(\"::\");
+            print(fields.join(\",\"));
+
+            let lines: [string] = \"one\r\ntwo\nthree\r\n\".splitLines();
+            print(lines.join(\"|\"));
+            return lines.length;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "2 \nkey|value=morea,b,cone|two|three");
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn test_args_and_env() {
+        let source = "function main(): i32 {
+            let argv: [string] = args();
+            let first: string = argv[0];
+            println(f\"{first}\");
+            let home: string = env(\"HILOW_TEST_VAR\");
+            println(f\"{home}\");
+            // Printed, the arguments are strings and an unset variable is
+            // nothing
+            println(args());
+            println(env(\"HILOW_TEST_UNSET\"));
+            if (env(\"HILOW_TEST_UNSET\") ??= nothing) {
+                return argv.length;
+            }
+            return 0;
+        }";
+
+        let (status, stdout) =
+            compile_and_run_with(source, &["hello", "world"], &[("HILOW_TEST_VAR", "set")]);
+        assert_eq!(stdout, "hello\nset\n[\"hello\", \"world\"]\nnothing\n");
+        assert_eq!(status, 2);
+    }
+
+    #[test]
+    fn test_match_binding_evaluates_scrutinee_once() {
+        let source = "function compute(): i32 {
+            println(\"computing\");
+            return 21;
+        }
+
+        function main(): i32 {
+            let small: i32 = match (3) { 3 => 30, n => n };
+            return match (compute()) { 0 => small, x => x * 2 };
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "computing\n");
+        assert_eq!(status, 42);
+    }
+
+    #[test]
+    fn test_loop_control_inside_match_arms() {
+        let source = "function main(): i32 {
+            let total: i32 = 0;
+            for (let i: i32 = 0; i < 10; i = i + 1) {
+                total = total + match (i % 3) {
+                    0 => { continue; 0 },
+                    1 => i,
+                    _ => match (i) { 8 => { break; 0 }, n => n * 10 }
+                };
+            }
+            return total;
+        }";
+
+        // i = 1, 2, 4, 5, 7 contribute 1 + 20 + 4 + 50 + 7; 8 breaks out
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 82);
+    }
+
+    #[test]
+    fn test_fill_and_repeat_literals() {
+        let source = "function main(): i32 {
+            let sevens: [i32] = fill(7, 3);
+            println(f\"{sevens.length}: {sevens[0]} {sevens[1]} {sevens[2]}\");
+
+            let zeros: [i32; 5] = [0; 5];
+            let ones: [i32; 4] = [1; 4];
+            let n: i32 = 2;
+            let names: [string] = [\"x\"; n];
+            print(names.join(\",\"));
+            return zeros[4] + ones[0] + ones[3];
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "3: 7 7 7\nx,x");
+        assert_eq!(status, 2);
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let source = "function main(): i32 {
+            let data: [u8] = \"Hi there\".bytes();
+            let first: i32 = data[0];
+            let copy: string = string_from_bytes(data);
+            println(f\"{copy} {first}\");
+            return data.length;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "Hi there 72\n");
+        assert_eq!(status, 8);
+    }
+
+    #[test]
+    fn test_range_collect() {
+        let source = "function main(): i32 {
+            let nums: [i32] = (0..5).collect();
+            println(f\"{nums[0]} {nums[1]} {nums[2]} {nums[3]} {nums[4]}\");
+            let n: i32 = 3;
+            let empty: [i32] = (n..n).collect();
+            return nums.length * 10 + empty.length;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "0 1 2 3 4\n");
+        assert_eq!(status, 50);
+    }
+
+    #[test]
+    fn test_log_level_filters_at_compile_time() {
+        let source = "function main(): i32 {
+            let n: i32 = 3;
+            log(\"debug\", f\"n is {n}\");
+            log(\"info\", \"starting\");
+            log(\"error\", f\"failed after {n} tries\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("fprintf(stderr, \"[DEBUG] n is %d\\n\", n)"));
+
+        let options = CompileOptions {
+            log_level: LogLevel::Info,
+            ..CompileOptions::default()
+        };
+        let c_code = generate_c(source, options).unwrap();
+        assert!(!c_code.contains("[DEBUG]"));
+        assert!(c_code.contains("fprintf(stderr, \"[INFO] %s\\n\", \"starting\")"));
+        assert!(c_code.contains("[ERROR] failed after %d tries"));
+
+        let err = generate_c("function main(): i32 { log(\"loud\", \"x\"); return 0; }", CompileOptions::default());
+        assert!(err.unwrap_err().contains("Unknown log level 'loud'"));
+    }
+
+    #[test]
+    fn test_array_reductions() {
+        let source = "function main(): i32 {
+            let nums: [i32];
+            nums.push(4); nums.push(8); nums.push(1); nums.push(6);
+            let total: i32 = nums.sum();
+            let lowest: i32 = nums.min();
+            let highest: i32 = nums.max();
+            let mean: f64 = nums.avg();
+            println(f\"{total} {lowest} {highest}\");
+
+            let empty: [i32];
+            return empty.sum() + (mean * 10.0) as i32;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "19 1 8\n");
+        assert_eq!(status, 47);
+
+        let (status, _) = compile_and_run("function main(): i32 { let empty: [i32]; return empty.max(); }");
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn test_array_reductions_print_and_infer() {
+        let source = "function main(): i32 {
+            let nums: [i32] = [4, 8, 1, 6];
+            let total = nums.sum();
+            let mean = nums.avg();
+            println(nums.sum(), nums.min(), nums.max(), nums.avg());
+            println(f\"{total} {mean}\");
+            return total;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("int32_t total = array_sum_i32(nums);"), "{}", c_code);
+        assert!(c_code.contains("double mean = array_avg_i32(nums);"), "{}", c_code);
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "19 1 8 4.750000\n19 4.750000\n");
+        assert_eq!(status, 19);
+    }
+
+    #[test]
+    fn test_reductions_of_float_arrays() {
+        let source = "function main(): i32 {
+            let xs: [f64] = [1.5, -2.25, 4.0];
+            let total = xs.sum();
+            println(xs.sum(), xs.min(), xs.max(), xs.avg());
+            let small: [i64] = [5000000000, 1];
+            println(total, small.sum());
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("double total = array_sum_f64(xs);"), "{}", c_code);
+        assert!(c_code.contains("static double array_sum_f64(DynamicArray* arr)"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "3.250000 -2.250000 4.000000 1.083333\n3.250000 5000000001\n");
+    }
+
+    #[test]
+    fn test_reductions_of_an_empty_array() {
+        // The sum of nothing is 0; min, max and avg have no value and stop
+        // the program
+        let (status, stdout) = compile_and_run("function main(): i32 { let empty: [i32]; println(empty.sum()); return 0; }");
+        assert_eq!((status, stdout.as_str()), (0, "0\n"));
+
+        for method in ["min", "max", "avg"] {
+            let source = format!("function main(): i32 {{ let empty: [i32]; println(empty.{}()); return 0; }}", method);
+            let output = compile_and_capture_with(&source, &[], &[], &CompileOptions::default());
+            assert_eq!(output.status.code(), Some(1));
+            assert!(output.stdout.is_empty());
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(stderr.starts_with(&format!("error: {}() of an empty array\n", method)), "{}", stderr);
+        }
+    }
+
+    #[test]
+    fn test_switch_runs_only_matching_case() {
+        let source = "function main(): i32 {
+            for (let n: i32 = 1; n <= 3; n = n + 1) {
+                switch (n) {
+                    case 1:
+                        println(\"one\");
+                    case 2:
+                        println(\"two\");
+                    case 3:
+                        println(\"three\");
+                }
+            }
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn test_switch_fallthrough() {
+        let source = "function describe(n: i32): i32 {
+            let flags: i32 = 0;
+            switch (n) {
+                case 1:
+                    flags = flags + 1;
+                    fallthrough;
+                case 2:
+                    flags = flags + 10;
+                case 3:
+                    flags = flags + 100;
+                default:
+                    flags = flags + 1000;
+            }
+            return flags;
+        }
+
+        function main(): i32 {
+            println(f\"{describe(1)} {describe(2)} {describe(3)} {describe(4)}\");
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "11 10 100 1000\n");
+    }
+
+    #[test]
+    fn test_switch_and_match_on_bool() {
+        let source = "function describe(flag: bool): string {
+            let mut label: string = \"?\";
+            switch (flag) {
+                case true:
+                    label = \"on\";
+                case false:
+                    label = \"off\";
+            }
+            let digit: string = match (flag) { true => \"1\", false => \"0\" };
+            return f\"{label}/{digit}\";
+        }
+
+        function main(): i32 {
+            println(f\"{describe(2 > 1)} {describe(1 > 2)}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("switch ((int)(flag)) {"), "{}", c_code);
+        assert!(c_code.contains("case 1:") && c_code.contains("case 0:"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "on/1 off/0\n");
+
+        let err = generate_c("function main(): i32 { switch (\"a\") { case \"a\": return 1; } return 0; }", CompileOptions::default())
+            .unwrap_err();
+        assert!(err.starts_with("`switch` cases must be integer, bool or character constants"), "{}", err);
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let source = "function kind(c: i8): string {
+            switch (c) {
+                case 'a':
+                    return \"vowel\";
+                case '\\n':
+                    return \"newline\";
+            }
+            return \"other\";
+        }
+
+        function main(): i32 {
+            let letter = 'a';
+            let newline = '\\n';
+            let quote = '\\'';
+            println(f\"{letter}{quote}{newline}\");
+            println(f\"{kind(letter)} {kind(newline)} {kind('z')}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("char letter = 'a';"), "{}", c_code);
+        assert!(c_code.contains("char newline = '\\n';"), "{}", c_code);
+        assert!(c_code.contains("char quote = '\\'';"), "{}", c_code);
+        assert!(c_code.contains("case 'a':"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "a'\n\nvowel newline other\n");
+    }
+
+    #[test]
+    fn test_special_float_values() {
+        let source = "function main(): i32 {
+            let big: f64 = inf;
+            let small = -inf;
+            let missing: f64 = nan;
+            let computed: f64 = 0.0 / 0.0;
+            let negative_zero: f64 = -0.0;
+            println(f\"{big} {small} {missing} {computed} {negative_zero}\");
+
+            if (is_nan(computed) and is_inf(small) and not is_nan(big)) {
+                return 1;
+            }
+            return 0;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "inf -inf nan nan -0.000000\n");
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn test_floats_print_the_same_inside_containers() {
+        let source = "function main(): i32 {
+            let x: f64 = 1.5;
+            let xs: [f64] = [x, 2.0, -0.0, 0.0 / 0.0];
+            let point = { v: x, w: inf };
+            println(x, xs, point);
+            println(f\"{x}\");
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(
+            stdout,
+            "1.500000 [1.500000, 2.000000, -0.000000, nan] {v: 1.500000, w: inf}\n1.500000\n"
+        );
+    }
+
+    #[test]
+    fn test_defer_runs_at_end_of_void_function() {
+        let source = "function work(early: bool) {
+            defer println(\"cleanup\");
+            if (early) {
+                return;
+            }
+            println(\"working\");
+        }
+
+        function main(): i32 {
+            work(false);
+            work(true);
+            println(\"done\");
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "working\ncleanup\ncleanup\ndone\n");
+    }
+
+    #[test]
+    fn test_defers_run_in_reverse_before_return() {
+        let source = "function work(): i32 {
+            defer println(\"first\");
+            defer {
+                println(\"second\");
+            }
+            println(\"body\");
+            return 7;
+        }
+
+        function main(): i32 {
+            return work();
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "body\nsecond\nfirst\n");
+        assert_eq!(status, 7);
+    }
+
+    #[test]
+    fn test_defers_belong_to_each_recursive_call() {
+        let source = "function descend(n: i32): i32 {
+            defer println(f\"leave {n}\");
+            println(f\"enter {n}\");
+            if (n > 0) {
+                descend(n - 1);
+            }
+            return n;
+        }
+
+        function main(): i32 {
+            return descend(2);
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "enter 2\nenter 1\nenter 0\nleave 0\nleave 1\nleave 2\n");
+        assert_eq!(status, 2);
+    }
+
+    #[test]
+    fn test_static_archive_contains_module_objects() {
+        let dir = std::env::temp_dir().join(format!("hilowc-archive-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let modules = [
+            ("mathlib", "export function square(x: i32): i32 { return x * x; }"),
+            ("strlib", "export function shout(s: string): string { return s.toUpperCase(); }"),
+        ];
+        let mut objects = Vec::new();
+        for (name, source) in modules {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            let program = Parser::new(tokens).parse().unwrap();
+            let object = dir.join(format!("{}.o", name));
+            compile_object(&program, object.to_str().unwrap(), &CompileOptions::default()).unwrap();
+            objects.push(object);
+        }
+
+        let archive = dir.join("libhilow.a");
+        create_archive(&objects, &archive).unwrap();
+
+        let listing = Command::new("ar").arg("t").arg(&archive).output().unwrap();
+        let members = String::from_utf8_lossy(&listing.stdout);
+        assert_eq!(members.lines().collect::<Vec<_>>(), ["mathlib.o", "strlib.o"]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_string_replace_all_and_count() {
+        let source = "function main(): i32 {
+            let s: string = \"aaa\";
+            let replaced: string = s.replaceAll(\"a\", \"b\");
+            println(f\"{replaced}\");
+            let spaced: string = \"a-b-c\".replaceAll(\"-\", \" - \");
+            println(f\"{spaced}\");
+            let overlapping: i32 = \"aaaa\".count(\"aa\");
+            let numbers: [i32];
+            numbers.push(1);
+            numbers.push(2);
+            numbers.push(1);
+            return s.count(\"a\") * 16 + overlapping * 4 + numbers.count(1);
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "bbb\na - b - c\n");
+        // 3 a's, 2 non-overlapping "aa", 2 ones in the array
+        assert_eq!(status, 3 * 16 + 2 * 4 + 2);
+    }
+
+    #[test]
+    fn test_string_escapes_reach_output() {
+        let source = r#"function main(): i32 {
+            let quoted: string = "say \"hi\"\tnow??=";
+            print(quoted);
+            println("\n");
+            println(f"{quoted.length}\\done");
+            println(r"raw\n");
+            return 0;
+        }"#;
+
+        let (status, stdout) = compile_and_run(source);
+        // print() of a variable adds no newline
+        assert_eq!(stdout, "say \"hi\"\tnow??=\n\n15\\done\nraw\\n\n");
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn test_conditional_expressions() {
+        let source = "function sign(n: i32): string {
+            return n > 0 ? \"positive\" : n < 0 ? \"negative\" : \"zero\";
+        }
+
+        function main(): i32 {
+            let a: i32 = 3;
+            let b: i32 = 7;
+            let m = a > b ? a : b;
+            let half = a > 0 ? 1.5 : 0;
+            println(f\"{m} {half} {sign(-2)} {sign(0)} {sign(a)} {a < b ? a : b}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("int32_t m = ((a > b) ? a : b);"), "{}", c_code);
+        assert!(c_code.contains("double half = "), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "7 1.500000 negative zero positive 3\n");
+    }
+
+    #[test]
+    fn test_numeric_suffixes_set_types() {
+        let source = "function main(): i32 {
+            let small = 42u8;
+            let ratio = 0.5f32;
+            let big: i64 = 10i64 * 1000000000;
+            println(f\"{small} {ratio} {big}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("uint8_t small = (uint8_t)(42);"), "{}", c_code);
+        assert!(c_code.contains("float ratio = (float)(0.5);"), "{}", c_code);
+
+        // Without the suffix the multiplication would overflow an int
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "42 0.500000 10000000000\n");
+    }
+
+    #[test]
+    fn test_unicode_escapes_reach_output() {
+        let source = r#"function main(): i32 {
+            println("caf\u{e9} \u{1F600}");
+            return 0;
+        }"#;
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains(r#""caf\303\251 \360\237\230\200\n""#), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "café 😀\n");
+    }
+
+    #[test]
+    fn test_raw_string_keeps_backslashes() {
+        let source = r#"function main(): i32 {
+            let path: string = r"C:\Users\ada\notes.txt";
+            print(path);
+            println(r"\n is not a newline");
+            return 0;
+        }"#;
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains(r#"char* path = "C:\\Users\\ada\\notes.txt";"#), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "C:\\Users\\ada\\notes.txt\\n is not a newline\n");
+    }
+
+    #[test]
+    fn test_trim_variants() {
+        let source = "function main(): i32 {
+            let padded: string = \"  ab  \";
+            let start: string = padded.trimStart();
+            let end: string = padded.trimEnd();
+            let both: string = padded.trim();
+            println(f\"[{start}][{end}][{both}]\");
+            let custom: string = \"xxabcxx\".trim(\"x\");
+            println(f\"{custom}\");
+            let mixed: string = \"-=abc=-\".trim(\"=-\");
+            println(f\"{mixed}\");
+            return 0;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "[ab  ][  ab][ab]\nabc\nabc\n");
+        assert_eq!(status, 0);
+    }
+
+    #[test]
+    fn test_replace_all_rejects_empty_pattern() {
+        let source = "function main(): i32 {
+            let empty: string = \"abc\".substring(0, 0);
+            let s: string = \"abc\".replaceAll(empty, \"x\");
+            return 0;
+        }";
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 1);
+    }
+
+    #[test]
+    fn test_export_controls_linkage_and_header() {
+        let source = "export function add(a: i32, b: i32): i32 { return a + b; }
+        export let VERSION: i32 = 2;
+        export struct Pair { a: i32, b: i32 }
+        export enum Mode { Fast, Safe }
+        struct Hidden { x: i32 }
+        function helper(): i32 { return 42; }
+        function main(): i32 { return add(helper(), VERSION); }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("\nint32_t add(int32_t a, int32_t b) {"), "{}", c_code);
+        assert!(c_code.contains("\nstatic int32_t helper() {"), "{}", c_code);
+        assert!(c_code.contains("\nint32_t main(int __argc, char** __argv) {"), "{}", c_code);
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let header = CodeGenerator::new(CompileOptions::default())
+            .generate_header(&program, "math-lib")
+            .unwrap();
+        assert!(header.starts_with("#ifndef HILOW_MATH_LIB_H\n"), "{}", header);
+        assert!(header.contains("int32_t add(int32_t a, int32_t b);\n"), "{}", header);
+        assert!(header.contains("extern int32_t VERSION;\n"), "{}", header);
+        // Enums come before the structs that may hold them
+        assert!(
+            header.contains("\n\ntypedef enum { Mode_Fast, Mode_Safe } Mode;\ntypedef struct {\n    int32_t a;\n    int32_t b;\n} Pair;\nint32_t add("),
+            "{}",
+            header
+        );
+        assert!(!header.contains("helper"), "{}", header);
+        assert!(!header.contains("Hidden"), "{}", header);
+    }
+
+    #[test]
+    fn test_contracts() {
+        let source = "function divide(a: i32, b: i32): i32 requires b != 0 ensures result >= 0 {
+            return a / b;
+        }
+
+        function main(): i32 {
+            return divide(12, 4);
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("#include <assert.h>"));
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 3);
+
+        // A violated precondition aborts
+        let (status, _) = compile_and_run(&source.replace("divide(12, 4)", "divide(12, 0)"));
+        assert_eq!(status, -1);
+
+        // So does a violated postcondition
+        let (status, _) = compile_and_run(&source.replace("divide(12, 4)", "divide(-12, 4)"));
+        assert_eq!(status, -1);
+
+        let plain = generate_c("function main(): i32 { return 0; }", CompileOptions::default()).unwrap();
+        assert!(!plain.contains("assert.h"));
+    }
+
+    #[test]
+    fn test_nested_for_in_same_variable() {
+        let source = "function main(): i32 {
+            let outer: [i32; 3] = [1, 2, 3];
+            let inner: [i32; 2] = [10, 20];
+            let total: i32 = 0;
+            for (x in outer) {
+                for (x in inner) {
+                    total = total + x;
+                }
+                total = total + x * 10;
+            }
+            return total;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("__idx_x_0"), "{}", c_code);
+        assert!(c_code.contains("__idx_x_1"), "{}", c_code);
+
+        // Each inner loop sums 30; the outer loop adds 10 + 20 + 30
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 3 * 30 + 60);
+    }
+
+    #[test]
+    fn test_for_in_over_dynamic_array() {
+        let source = "function main(): i32 {
+            let nums: [i32] = [5, 6];
+            nums.push(7);
+            let total: i32 = 0;
+            for (n in nums) {
+                total = total + n;
+            }
+            let words: [string];
+            words.push(\"hi\");
+            words.push(\"there\");
+            for (word in words) {
+                println(f\"{word}!\");
+            }
+            for (x in [1, 2, 3]) {
+                total = total + x * 100;
+            }
+            return total;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("__idx_n_0 < nums->length"), "{}", c_code);
+        assert!(!c_code.contains("sizeof(nums)"), "{}", c_code);
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "hi!\nthere!\n");
+        assert_eq!(status, (18 + 600) % 256);
+    }
+
+    #[test]
+    fn test_for_in_range() {
+        let source = "function limit(): i32 {
+            println(\"limit\");
+            return 3;
+        }
+
+        function main(): i32 {
+            for i in 0..5 {
+                println(f\"{i}\");
+            }
+            let total: i32 = 0;
+            for (n in 1..=limit()) {
+                total = total + n;
+            }
+            return total;
+        }";
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "0\n1\n2\n3\n4\nlimit\n");
+        assert_eq!(status, 6);
+    }
+
+    #[test]
+    fn test_temporaries_do_not_capture_user_names() {
+        let source = "function main(): i32 {
+            let __str_0: string = \"needle\";
+            let haystack: string = \"find the needle\";
+            return haystack.indexOf(__str_0);
+        }";
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 9);
+    }
+
+    #[test]
+    fn test_arrow_function_passed_to_map() {
+        let source = "function main(): i32 {
+            let nums: [i32];
+            nums.push(1);
+            nums.push(2);
+            nums.push(3);
+            let offset: i32 = 10;
+            let shifted: [i32] = nums.map((x: i32, i: i32) => x + offset);
+            let doubled: [i32] = shifted.map(function(x: i32, i: i32): i32 { return x * 2; });
+            return doubled[0] + doubled[2];
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("static int32_t __lambda_0(void* __env, int32_t x, int32_t i) {"), "{}", c_code);
+        assert!(c_code.contains("struct __context_0 {\n    int32_t offset;\n};"), "{}", c_code);
+        assert!(c_code.contains("static int32_t __lambda_1(int32_t x, int32_t i) {"), "{}", c_code);
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 22 + 26);
+    }
+
+    #[test]
+    fn test_find_takes_a_closure() {
+        let source = "function main(): i32 {
+            let nums: [i32] = [1, 5, 8];
+            let limit: i32 = 4;
+            return nums.find((x: i32, i: i32) => x > limit);
+        }";
+
+        // GCC 14 rejects passing a Closure* where a function pointer is
+        // expected, so make older compilers do the same
+        let options = CompileOptions {
+            cflags: vec!["-Werror=incompatible-pointer-types".to_string()],
+            ..CompileOptions::default()
+        };
+        let output = compile_and_capture_with(source, &[], &[], &options);
+        assert_eq!(output.status.code(), Some(5));
+    }
+
+    #[test]
+    fn test_closure_captures_variable_inside_cast() {
+        let source = "function main(): i32 {
+            let n: i32 = 3;
+            let f: function(i32): f64 = function(x: i32): f64 { return (n as f64) + (x as f64); };
+            println(f(2));
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("struct __context_0 {\n    int32_t n;\n};"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "5.000000\n");
+    }
+
+    #[test]
+    fn test_closures_capture_their_own_values() {
+        let source = "function main(): i32 {
+            let x: i32 = 1;
+            let first: function = function(a: i32, b: i32): i32 { return x; };
+            x = 2;
+            let second: function = function(a: i32, b: i32): i32 { return x; };
+            x = 3;
+            return first(0, 0) * 10 + second(0, 0);
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(!c_code.contains("__captured_"), "{}", c_code);
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 12);
+    }
+
+    #[test]
+    fn test_closures_share_the_locals_they_assign() {
+        let source = "function main(): i32 {
+            let mut count: i32 = 0;
+            let bump: function = function(a: i32, b: i32): i32 { count = count + 1; return count; };
+            let peek: function = function(a: i32, b: i32): i32 { return count; };
+            println(bump(0, 0));
+            println(bump(0, 0));
+            count = count + 1;
+            println(peek(0, 0));
+            return count;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("int32_t* __cell_count = malloc(sizeof(int32_t));"), "{}", c_code);
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "1\n2\n3\n");
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn test_function_types_get_exact_signatures() {
+        let source = "function apply(f: function(string): i32, s: string): i32 {
+            return f(s);
+        }
+
+        function main(): i32 {
+            let bonus: i32 = 100;
+            let measure: function(string): i32 = function(s: string): i32 { return s.length + bonus; };
+            return apply(measure, \"hello\") + apply(function(s: string): i32 { return s.length; }, \"hi\");
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("typedef int32_t (*__fn_type_0_plain)(char*);"), "{}", c_code);
+        assert!(c_code.contains("typedef int32_t (*__fn_type_0_env)(void*, char*);"), "{}", c_code);
+        assert!(c_code.contains("int32_t apply(__fn_type_0 f, char* s)"), "{}", c_code);
+        assert!(c_code.contains("((__fn_type_0_env)(__closure_"), "{}", c_code);
+        assert!(c_code.contains("((__fn_type_0_plain)(__closure_"), "{}", c_code);
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 105 + 2);
+    }
+
+    #[test]
+    fn test_object_literal_initializer() {
+        let source = "function main(): i32 {
+            let p = { x: 1, y: 2 };
+            return p.x + p.y;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("typedef struct {\n    int32_t x;\n    int32_t y;\n} __Obj_0;"), "{}", c_code);
+        assert!(c_code.contains("__Obj_0 p = { .x = 1, .y = 2 };"), "{}", c_code);
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 3);
+    }
+
+    #[test]
+    fn test_object_literals_share_typed_typedefs() {
+        let source = "function main(): i32 {
+            let person = { name: \"x\", age: 3 };
+            let other = { name: \"y\", age: 40 };
+            let point = { x: 2.5, y: 1.0, owner: { name: \"z\", age: 5 } };
+            println(f\"{person.name} is {person.age}, {other.name} is {other.age}\");
+            println(f\"{point.x:.1} {point.owner.name}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("typedef struct {\n    char* name;\n    int32_t age;\n} __Obj_0;"), "{}", c_code);
+        assert!(c_code.contains("typedef struct {\n    double x;\n    double y;\n    __Obj_0 owner;\n} __Obj_1;"), "{}", c_code);
+        assert!(c_code.contains("__Obj_0 person = "), "{}", c_code);
+        assert!(c_code.contains("__Obj_0 other = "), "{}", c_code);
+        assert!(!c_code.contains("__Obj_2"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "x is 3, y is 40\n2.5 z\n");
+    }
+
+    #[test]
+    fn test_struct_declarations() {
+        let source = "struct Point { x: i32, y: i32 }
+        struct Person { name: string, age: i32, home: Point, }
+
+        function manhattan(p: Point): i32 {
+            return p.x + p.y;
+        }
+
+        function main(): i32 {
+            let p: Point = { x: 3, y: 4 };
+            let ada: Person = { name: \"Ada\", age: 36, home: p };
+            println(f\"{ada.name} is {ada.age} and lives at {ada.home.x},{ada.home.y}\");
+            p.x = 10;
+            return manhattan(p);
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("typedef struct {\n    int32_t x;\n    int32_t y;\n} Point;"), "{}", c_code);
+        assert!(c_code.contains("typedef struct {\n    char* name;\n    int32_t age;\n    Point home;\n} Person;"), "{}", c_code);
+        assert!(c_code.contains("int32_t manhattan(Point p)"), "{}", c_code);
+        assert!(c_code.contains("Point p = { .x = 3, .y = 4 };"), "{}", c_code);
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "Ada is 36 and lives at 3,4\n");
+        assert_eq!(status, 14);
+    }
+
+    #[test]
+    fn test_struct_literals_build_array_fields() {
+        let source = "struct Student { name: string, scores: [i32] }
+
+        function main(): i32 {
+            let ada: Student = { name: \"Ada\", scores: [90, 85] };
+            ada.scores.push(70);
+            println(ada.scores[2], ada.scores.length);
+            let class: [Student] = [ada, { name: \"Bo\", scores: [] }];
+            class.push({ name: \"Cy\", scores: [1] });
+            for student in class {
+                println(student.name, student.scores);
+            }
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("array_push_Student(class, (Student){ .name = \"Cy\", .scores = ({ DynamicArray* "), "{}", c_code);
+
+        let (status, stdout) = compile_and_run(source);
+        assert_eq!(status, 0);
+        assert_eq!(stdout, "70 3\nAda [90, 85, 70]\nBo []\nCy [1]\n");
+    }
+
+    #[test]
+    fn test_types_used_before_their_declaration() {
+        let source = "function area(r: Rect): i32 {
+            return r.size.w * r.size.h;
+        }
+
+        struct Rect { origin: Point, size: Size, kind: Kind }
+        struct Size { w: i32, h: i32 }
+        struct Point { x: i32, y: i32 }
+        enum Kind { Filled, Outline }
+
+        function main(): i32 {
+            let r: Rect = { origin: { x: 1, y: 2 }, size: { w: 3, h: 4 }, kind: Kind.Outline };
+            return area(r) + r.origin.y;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        let position = |typedef: &str| c_code.find(typedef).unwrap_or_else(|| panic!("no {} in {}", typedef, c_code));
+        assert!(position("} Size;") < position("} Rect;"), "{}", c_code);
+        assert!(position("} Point;") < position("} Rect;"), "{}", c_code);
+        assert!(position("} Kind;") < position("} Rect;"), "{}", c_code);
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 14);
+
+        let err = generate_c("struct A { b: B } struct B { a: [A; 2] } function main(): i32 { return 0; }", CompileOptions::default())
+            .unwrap_err();
+        assert_eq!(err, "Struct `A` contains itself");
+    }
+
+    #[test]
+    fn test_enums_in_match_and_switch() {
+        let source = "enum Color { Red, Green, Blue }
+
+        pure function weight(c: Color): i32 {
+            return match c {
+                Color.Red => 10,
+                Color.Green => 20,
+                _ => 30,
+            };
+        }
+
+        function main(): i32 {
+            let c: Color = Color.Green;
+            let d = Color.Blue;
+            switch (d) {
+                case Color.Red:
+                    println(\"red\");
+                case Color.Blue:
+                    println(\"blue\");
+                default:
+                    println(\"other\");
+            }
+            println(f\"{c} {weight(c)} {weight(Color.Red)} {weight(d)}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("typedef enum { Color_Red, Color_Green, Color_Blue } Color;"), "{}", c_code);
+        assert!(c_code.contains("int32_t weight(Color c)"), "{}", c_code);
+        assert!(c_code.contains("== Color_Red)"), "{}", c_code);
+        assert!(c_code.contains("Color d = Color_Blue;"), "{}", c_code);
+        assert!(c_code.contains("case Color_Blue:"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "blue\n1 20 10 30\n");
+    }
+
+    #[test]
+    fn test_cast_expressions() {
+        let source = "function main(): i32 {
+            let y: i32 = 7;
+            let x = (y as f64) / 2.0;
+            let n: i32 = 5;
+            let big = n as i64 * 3000000000;
+            let truncated = 9.75 as i32;
+            println(f\"{x} {big} {truncated}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("double x = ((double)(y) / 2.0);"), "{}", c_code);
+        assert!(c_code.contains("int64_t big = ((int64_t)(n) * 3000000000);"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "3.500000 15000000000 9\n");
+    }
+
+    #[test]
+    fn test_division_follows_operand_types() {
+        let source = "function main(): i32 {
+            let n: i32 = 7;
+            let half = 7.0 / 2;
+            let whole = 7 / 2;
+            let ratio: f64 = n / 2.0;
+            let floored: f64 = n / 2;
+            let rest = 7.5 % n;
+            println(f\"{half:.1} {whole} {ratio:.1} {floored:.1} {rest:.1} {n % 4}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("(7.0 / (double)(2))"), "{}", c_code);
+        assert!(c_code.contains("((double)(n) / 2.0)"), "{}", c_code);
+        assert!(c_code.contains("fmod(7.5, n)"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "3.5 3 3.5 3.0 0.5 3\n");
+    }
+
+    #[test]
+    fn test_index_writes_use_element_type() {
+        let source = "function relabel(names: [string], i: i32, name: string): nothing {
+            names[i] = name;
+        }
+
+        function main(): i32 {
+            let mut names: [string] = [\"a\", \"b\", \"c\"];
+            names[1] = \"x\";
+            relabel(names, 2, \"y\");
+            let mut xs: [f64] = [1.5, 2.5];
+            xs[0] = 4.25;
+            xs[1] += xs[0];
+            let middle = names[1];
+            let total = xs[1];
+            println(names[0], middle, names[2], xs[0], total);
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("((char**)names->data)[1] = \"x\";"), "{}", c_code);
+        assert!(c_code.contains("((char**)names->data)[i] = name;"), "{}", c_code);
+        assert!(c_code.contains("((double*)xs->data)[0] = 4.25;"), "{}", c_code);
+        assert!(c_code.contains("char* middle = ((char**)names->data)[1];"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "a x y 4.250000 6.750000\n");
+    }
+
+    #[test]
+    fn test_nested_arrays() {
+        let source = "function main(): i32 {
+            let mut grid: [[i32]] = [[1, 2], [3]];
+            grid.push([4, 5, 6]);
+            grid[1].push(7);
+            grid[0][1] = 20;
+            let corner = grid[2][2];
+            println(grid[0][1], grid[1][1], corner, grid[2].length);
+            println(grid);
+
+            let m: [[i32; 2]; 3] = [[1, 2], [3, 4], [5, 6]];
+            let i: i32 = 2;
+            println(m[i][1], m[0][0] + m[1][1]);
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("array_push_array(grid, ({ DynamicArray* __row_0 = array_new(sizeof(int32_t));"), "{}", c_code);
+        assert!(c_code.contains("((int32_t*)((DynamicArray**)grid->data)[0]->data)[1] = 20;"), "{}", c_code);
+        assert!(c_code.contains("int32_t m[3][2] = {{1, 2}, {3, 4}, {5, 6}};"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "20 7 6 3\n[[1, 20], [3, 7], [4, 5, 6]]\n6 5\n");
+    }
+
+    #[test]
+    fn test_dynamic_arrays_of_fixed_arrays() {
+        let source = "function main(): i32 {
+            let pairs: [[i32; 2]] = [[1, 2], [3, 4]];
+            pairs.push([5, 6]);
+            pairs[1][0] = 7;
+            println(pairs, pairs.length, pairs[2][1]);
+            for pair in pairs {
+                println(pair[0] + pair[1]);
+            }
+            return 0;
+        }";
+
+        // Each element is stored by value
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("DynamicArray* pairs = array_new(sizeof(__typeof__(int32_t[2])));"), "{}", c_code);
+        assert!(c_code.contains("array_push_i32x2(pairs, (__typeof__(int32_t[2])){1, 2});"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "[[1, 2], [7, 4], [5, 6]] 3 6\n3\n11\n11\n");
+
+        let err = generate_c("function main(): i32 { let pairs: [[i32; 2]] = []; pairs.pop(); return 0; }", CompileOptions::default())
+            .unwrap_err();
+        assert_eq!(err, "`pop` is not supported on arrays of fixed-size arrays");
+    }
+
+    #[test]
+    fn test_block_scoped_variables() {
+        let source = "function main(): i32 {
+            let x: i32 = 7;
+            {
+                let x: string = \"inner\";
+                println(x);
+            }
+            println(x, x + 1);
+            for (let i: f64 = 0.5; i < 1.0; i = i + 1.0) {
+                println(i);
+            }
+            let i: i32 = 3;
+            println(i);
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("printf(\"%d %d\\n\", x, (x + 1));"), "{}", c_code);
+        assert!(c_code.contains("printf(\"%d\\n\", i);"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "inner\n7 8\n0.500000\n3\n");
+    }
+
+    #[test]
+    fn test_assert_with_fstring_message() {
+        let source = "function main(): i32 {
+            let x: i32 = 7;
+            assert(x > 0, f\"x was {x}\");
+            let label: string = f\"x={x}\";
+            print(label);
+            assert(x < 5, f\"x was {x}\");
+            println(\"unreachable\");
+            return 0;
+        }";
+
+        let output = compile_and_capture(source, &[], &[]);
+        assert_eq!(output.status.code(), Some(1));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "x=7");
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "Assertion failed: x was 7\n");
+    }
+
+    #[test]
+    fn test_fstring_format_specifiers_follow_types() {
+        let source = "function half(x: i32): f64 { return x / 2.0; }
+
+        function main(): i32 {
+            let name: string = \"Ada\";
+            let age: i32 = 36;
+            let big: i64 = 5000000000;
+            println(f\"{name} is {age}\");
+            println(f\"{name.toUpperCase()} {age + 1} {half(age)} {big * 2} {age > 30}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("printf(\"%s is %d\\n\", str_for_print(name), age)"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "Ada is 36\nADA 37 18.000000 10000000000 1\n");
+    }
+
+    #[test]
+    fn test_fstring_radix_specs() {
+        let source = "function main(): i32 {
+            let big: i64 = 4294967296;
+            println(f\"{255:x} {5:b} {8:o} {255:X} {big:x} {0:b}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("printf(\"%x %s %o %X %llx %s\\n\""), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "ff 101 10 FF 100000000 0\n");
+
+        let err = generate_c("function main(): i32 { println(f\"{1.5:x}\"); return 0; }", CompileOptions::default())
+            .unwrap_err();
+        assert!(err.contains("needs an integer"), "{}", err);
+    }
+
+    #[test]
+    fn test_fstring_printf_specs() {
+        let source = "function main(): i32 {
+            let pi: f64 = 3.14159;
+            let n: i32 = 42;
+            let big: i64 = 5000000000;
+            let name: string = \"hi\";
+            println(f\"{pi:.2f} {n:04d} [{name:-4s}] [{n:5}] {pi:.1} {n:.1f} {big:12d} {5:4b} {pi:e}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(
+            c_code.contains("printf(\"%.2f %04d [%-4s] [%5d] %.1f %.1f %12lld %4s %e\\n\""),
+            "{}",
+            c_code
+        );
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "3.14 0042 [hi  ] [   42] 3.1 42.0   5000000000  101 3.141590e+00\n");
+
+        for (spec, error) in [("{name:d}", "needs an integer"), ("{name:.2f}", "needs a number"), ("{n:s}", "needs a string"), ("{n:08b}", "only takes a width")] {
+            let source = format!("function main(): i32 {{ let n: i32 = 1; let name: string = \"a\"; println(f\"{}\"); return 0; }}", spec);
+            let err = generate_c(&source, CompileOptions::default()).unwrap_err();
+            assert!(err.contains(error), "{}: {}", spec, err);
+        }
+    }
+
+    #[test]
+    fn test_print_whole_arrays() {
+        let source = "function main(): i32 {
+            let nums: [i32];
+            nums.push(1);
+            nums.push(22);
+            let names: [string; 2] = [\"a\", \"b\"];
+            let flags: [bool; 3] = [true, false, true];
+            let empty: [i32];
+            println(nums);
+            println(names);
+            println(flags);
+            println(empty);
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "[1, 22]\n[\"a\", \"b\"]\n[true, false, true]\n[]\n");
+    }
+
+    #[test]
+    fn test_print_uses_resolved_call_types() {
+        // Sema resolves what the calls return, so the arrays print by
+        // their element type and the untyped `let` gets one
+        let source = "function main(): i32 {
+            let parts = \"b,a\".split(\",\");
+            println(parts, \"x y\".split(\" \"));
+            println(parts.join(\"+\"), \"abc\".bytes());
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("DynamicArray* parts = str_split("), "{}", c_code);
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "[\"b\", \"a\"] [\"x\", \"y\"]\nb+a [97, 98, 99]\n");
+    }
+
+    #[test]
+    fn test_print_structs_and_objects() {
+        let source = "struct Pair { left: [i32; 2], right: [string] }
+
+        function main(): i32 {
+            let class: [{ name: string, scores: [i32] }] = [{ name: \"Ada\", scores: [90, 85] }, { name: \"Bo\", scores: [] }];
+            class[1].scores.push(70);
+            println(class);
+            let best: { name: string, scores: [i32] } = class[0];
+            println(\"best:\", best);
+            let pairs: [Pair; 1] = [{ left: [1, 2], right: [\"a\"] }];
+            println(pairs);
+            let point = { x: 1, label: \"origin\", offset: { dx: 0.5 } };
+            println(point);
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(
+            stdout,
+            "[{name: \"Ada\", scores: [90, 85]}, {name: \"Bo\", scores: [70]}]\n\
+             best: {name: \"Ada\", scores: [90, 85]}\n\
+             [{left: [1, 2], right: [\"a\"]}]\n\
+             {x: 1, label: \"origin\", offset: {dx: 0.500000}}\n"
+        );
+    }
+
+    #[test]
+    fn test_print_arrays_among_other_values() {
+        let source = "function main(): i32 {
+            let nums: [i32] = [1, 2];
+            let grid: [[i32; 2]; 2] = [[1, 2], [3, 4]];
+            let words: [string] = \"a,b\".split(\",\");
+            println(\"nums:\", nums, 3);
+            println(grid[1], words);
+            print(7, nums);
+            println();
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "nums: [1, 2] 3\n[3, 4] [\"a\", \"b\"]\n7 [1, 2]\n");
+    }
+
+    #[test]
+    fn test_print_rejects_values_of_unknown_type() {
+        let error = generate_c("function main(): i32 { println(1, mystery()); return 0; }", CompileOptions::default())
+            .unwrap_err();
+        assert!(error.starts_with("Cannot print a value of unknown type"), "{}", error);
+    }
+
+    #[test]
+    fn test_print_and_println() {
+        let (_, stdout) = compile_and_run("function main(): i32 {
+            print(\"a\");
+            print(\"b\");
+            println(\" c\");
+            println(\"a\");
+            let n: i32 = 3;
+            print(f\"{n} \");
+            println(f\"{n + 1}\");
+            return 0;
+        }");
+        assert_eq!(stdout, "ab c\na\n3 4\n");
+    }
+
+    #[test]
+    fn test_print_several_values() {
+        let source = "function main(): i32 {
+            let x: i32 = 3;
+            let y: i64 = 40;
+            let ratio: f64 = 2.5;
+            let ok: bool = x < y;
+            println(\"x=\", x, \"y=\", y);
+            print(\"100%\", ratio, ok);
+            println(f\" {x}\", \"done\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("printf(\"x= %d y= %lld\\n\", x, (long long)(y))"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "x= 3 y= 40\n100% 2.500000 true 3 done\n");
+    }
+
+    #[test]
+    fn test_hash_is_fnv1a() {
+        let source = "function main(): i32 {
+            let word: string = \"abc\";
+            println(f\"{hash(\"abc\")} {word.hash()} {hash(\"abc\".substring(0, 0))}\");
+            println(f\"{hash(0)} {hash(1) ?= hash(1)} {hash(1) ?= hash(2)}\");
+            return 0;
+        }";
+
+        // FNV-1a 64 of "abc", of "" (the offset basis), and of eight zero bytes
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(
+            stdout,
+            "16654208175385433931 16654208175385433931 14695981039346656037\n\
+             12161962213042174405 1 0\n"
+        );
+    }
+
+    #[test]
+    fn test_string_dynamic_array_round_trip() {
+        let source = "function main(): i32 {
+            let words: [string];
+            words.push(\"alpha\");
+            words.push(\"beta\");
+            words.push(\"gamma\");
+            let last: string = words.pop();
+            println(f\"{last} {words[1]} {words.length}\");
+            print(words.join(\"+\"));
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("array_push_string(words, \"alpha\")"), "{}", c_code);
+        assert!(c_code.contains("static char* array_pop_string(DynamicArray* arr)"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "gamma beta 2\nalpha+beta");
+    }
+
+    #[test]
+    fn test_f64_dynamic_array_sums() {
+        let source = "function main(): i32 {
+            let xs: [f64];
+            xs.push(1.5);
+            xs.push(2.25);
+            xs.push(4.0);
+            let total: f64 = 0.0;
+            for (let i: i32 = 0; i < xs.length; i = i + 1) {
+                total = total + xs[i];
+            }
+            println(f\"{total} {xs.pop()}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("static void array_push_f64(DynamicArray* arr, double item)"), "{}", c_code);
+        assert!(!c_code.contains("array_push_i8"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "7.750000 4.000000\n");
+    }
+
+    #[test]
+    fn test_array_index_of_from_and_last_index_of() {
+        let source = "function main(): i32 {
+            let nums: [i32] = [4, 7, 4, 9, 4];
+            let words: [string] = [\"a\", \"b\", \"a\"];
+            let key: string = \"cat\".substring(1, 2);
+            println(f\"{nums.indexOf(4)} {nums.indexOf(4, 1)} {nums.lastIndexOf(4)} {nums.indexOf(5)}\");
+            println(f\"{words.indexOf(key, 1)} {words.lastIndexOf(key)} {words.lastIndexOf(\"z\")}\");
+            return 0;
+        }";
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "0 2 4 -1\n2 2 -1\n");
+    }
+
+    #[test]
+    fn test_string_equality_compares_contents() {
+        let source = "function main(): i32 {
+            let a: string = \"hello\";
+            let b: string = \"oh hello\".substring(3, 8);
+            let c: string = \"world\";
+            println(f\"{a ?= b} {a != b} {b ?= \"hello\"} {a ?= c} {a != c}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("(strcmp(a, b) == 0)"), "{}", c_code);
+
+        let (_, stdout) = compile_and_run(source);
+        assert_eq!(stdout, "1 0 1 0 1\n");
+    }
+
+    #[test]
+    fn test_string_concatenation_allocates_result() {
+        let source = "function main(): i32 {
+            let text: string = \"start\";
+            for (let i: i32 = 0; i < 200; i = i + 1) {
+                text = text + \"-abcdefgh\";
+            }
+            let head: string = \"x\";
+            let joined: string = string_concat(head, text);
+            let more: string = head.concat(\"yz\");
+            println(f\"{string_length(text)} {string_length(joined)} {more} {head}\");
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("text = str_concat(text, \"-abcdefgh\");"), "{}", c_code);
+        assert!(c_code.contains("str_concat(head, text)"), "{}", c_code);
+        assert!(!c_code.contains("strcat(head"), "{}", c_code);
+
+        // AddressSanitizer aborts on any write past an allocation; the
+        // intermediate strings are never freed, so leaks aren't checked
+        let options = CompileOptions {
+            cflags: vec!["-fsanitize=address".to_string()],
+            ..CompileOptions::default()
+        };
+        let output = compile_and_capture_with(source, &[], &[("ASAN_OPTIONS", "detect_leaks=0")], &options);
+        assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "1805 1806 xyz x\n");
+    }
+
+    #[test]
+    fn test_method_calls_through_pipeline() {
+        let source = "function twice(x: i32, i: i32): i32 { return x * 2; }
+
+        function main(): i32 {
+            let parts: [string] = \"a,b\".split(\",\");
+            let nums: [i32];
+            nums.push(4);
+            nums.push(5);
+            let doubled: [i32] = nums.map(twice);
+            return parts.length * 100 + doubled[1];
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("str_split(\"a,b\", \",\")"), "{}", c_code);
+        assert!(c_code.contains("array_map_i32(nums, closure_new((void*)twice, NULL))"), "{}", c_code);
+
+        let (status, _) = compile_and_run(source);
+        assert_eq!(status, 2 * 100 + 10);
+    }
+
+    #[test]
+    fn test_callbacks_take_the_element_type() {
+        let source = "function main(): i32 {
+            let names: [string] = [\"ann\", \"bobby\", \"cy\"];
+            println(names.map((n: string) => n.length));
+            println(names.filter((n: string) => n.length > 2));
+            println(names.reduce((total: i32, n: string) => total + n.length, 0));
+            names.forEach((n: string) => println(n.toUpperCase()));
+            println(names.find((n: string) => n.length ?= 2));
+            let xs: [f64] = [1.5, 2.5];
+            println(xs.map((x: f64) => x / 2));
+            return 0;
+        }";
+
+        let c_code = generate_c(source, CompileOptions::default()).unwrap();
+        assert!(c_code.contains("static DynamicArray* array_map_string_i32(DynamicArray* arr, Closure* func) {"), "{}", c_code);
+        assert!(c_code.contains("array_filter_string(names, "), "{}", c_code);
+
+        let (status, output) = compile_and_run(source);
+        assert_eq!(status, 0);
+        assert_eq!(output, "[3, 5, 2]\n[\"ann\", \"bobby\"]\n10\nANN\nBOBBY\nCY\ncy\n[0.750000, 1.250000]\n");
+    }
+
+    #[test]
+    fn test_invalid_target_triple() {
+        assert!(Target::from_triple("x86_64").is_err());
+        assert!(Target::from_triple("x86_64--linux").is_err());
+    }
+}
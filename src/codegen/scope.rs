@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+/// Names bound in each enclosing block, innermost last. A lookup finds the
+/// nearest binding, so a name declared in a block shadows an outer one
+/// only until the block ends.
+#[derive(Debug, Clone)]
+pub struct Scopes<T> {
+    scopes: Vec<HashMap<String, T>>,
+}
+
+impl<T> Scopes<T> {
+    /// A stack holding just the global scope
+    pub fn new() -> Self {
+        Scopes { scopes: vec![HashMap::new()] }
+    }
+
+    pub fn push(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Drop the innermost scope and everything bound in it. The global
+    /// scope is never popped.
+    pub fn pop(&mut self) {
+        if self.scopes.len() > 1 {
+            self.scopes.pop();
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&T> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    /// Index of the scope holding the nearest binding of `name`; the
+    /// global scope is 0
+    pub fn depth_of(&self, name: &str) -> Option<usize> {
+        self.scopes.iter().rposition(|scope| scope.contains_key(name))
+    }
+
+    /// Whether bindings made now are global
+    pub fn at_global_scope(&self) -> bool {
+        self.scopes.len() == 1
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Bind `name` in the innermost scope, returning what it replaced there
+    pub fn insert(&mut self, name: String, value: T) -> Option<T> {
+        self.innermost().insert(name, value)
+    }
+
+    /// Unbind `name` in the innermost scope, uncovering any outer binding
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        self.innermost().remove(name)
+    }
+
+    fn innermost(&mut self) -> &mut HashMap<String, T> {
+        self.scopes.last_mut().expect("the global scope is never popped")
+    }
+}
+
+impl<T> Default for Scopes<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inner_binding_shadows_until_popped() {
+        let mut scopes = Scopes::new();
+        scopes.insert("x".to_string(), "int32_t");
+        scopes.push();
+        assert_eq!(scopes.get("x"), Some(&"int32_t"));
+
+        assert_eq!(scopes.insert("x".to_string(), "char*"), None);
+        scopes.insert("y".to_string(), "double");
+        assert_eq!(scopes.get("x"), Some(&"char*"));
+
+        assert_eq!(scopes.depth_of("x"), Some(1));
+        scopes.pop();
+        assert_eq!(scopes.get("x"), Some(&"int32_t"));
+        assert_eq!(scopes.depth_of("x"), Some(0));
+        assert!(!scopes.contains_key("y"));
+        assert!(scopes.at_global_scope());
+
+        scopes.pop();
+        assert_eq!(scopes.get("x"), Some(&"int32_t"));
+    }
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+
+/// The platform the generated C is intended to run on.
+///
+/// HiLow only records the triple; the host C compiler still does the
+/// actual build. What the triple controls is which platform-specific
+/// constructs codegen is allowed to emit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub triple: String,
+    /// `__int128` is only provided by GCC/Clang on 64-bit targets
+    pub has_int128: bool,
+    /// glibc/musl style libc where `_GNU_SOURCE` unlocks `strdup` and friends
+    pub has_gnu_libc: bool,
+}
+
+impl Target {
+    pub fn from_triple(triple: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = triple.split('-').collect();
+        if parts.len() < 2 || parts.iter().any(|p| p.is_empty()) {
+            return Err(format!(
+                "Invalid target triple '{}' (expected <arch>-<vendor>-<os>[-<env>])",
+                triple
+            ));
+        }
+
+        let arch = parts[0];
+        // Two-part triples like `wasm32-wasi` have no vendor component
+        let os = if parts.len() == 2 { parts[1] } else { parts[2] };
+
+        let has_int128 = matches!(
+            arch,
+            "x86_64" | "aarch64" | "arm64" | "s390x" | "sparc64" | "loongarch64"
+        ) || arch.starts_with("riscv64")
+            || arch.starts_with("powerpc64")
+            || arch.starts_with("mips64");
+
+        let has_gnu_libc = os == "linux";
+
+        Ok(Target {
+            triple: triple.to_string(),
+            has_int128,
+            has_gnu_libc,
+        })
+    }
+
+    /// The triple of the machine running the compiler
+    pub fn host() -> Self {
+        let arch = std::env::consts::ARCH;
+        let triple = match std::env::consts::OS {
+            "linux" => format!("{}-unknown-linux-gnu", arch),
+            "macos" => format!("{}-apple-darwin", arch),
+            "windows" => format!("{}-pc-windows-gnu", arch),
+            os => format!("{}-unknown-{}", arch, os),
+        };
+
+        Target::from_triple(&triple).expect("host triple is well-formed")
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::host()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.triple)
+    }
+}
@@ -0,0 +1,75 @@
+use std::fmt;
+
+/// The step of compilation an error came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Lexer,
+    Parser,
+    Import,
+    Semantic,
+    Codegen,
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Stage::Lexer => "Lexer",
+            Stage::Parser => "Parser",
+            Stage::Import => "Import",
+            Stage::Semantic => "Semantic",
+            Stage::Codegen => "Code generation",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A 1-based position in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A problem that stops a program from compiling. Lexer and parser errors
+/// know where in the source they are, and semantic errors know the
+/// statement they were found in; the message cites the same place.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub stage: Stage,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl CompileError {
+    pub fn new(stage: Stage, message: impl Into<String>) -> Self {
+        CompileError { stage, message: message.into(), span: None }
+    }
+
+    pub fn at(stage: Stage, message: impl Into<String>, line: usize, column: usize) -> Self {
+        CompileError { stage, message: message.into(), span: Some(Span { line, column }) }
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.span.map(|span| span.line)
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.span.map(|span| span.column)
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} error: {}", self.stage, self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+// Lets code that still reports errors as strings use `?` on the lexer and
+// parser
+impl From<CompileError> for String {
+    fn from(error: CompileError) -> String {
+        error.message
+    }
+}
@@ -1,5 +1,6 @@
 pub mod token;
 
+use crate::error::{CompileError, Stage};
 use token::{Token, TokenKind, keyword};
 
 pub struct Lexer {
@@ -19,7 +20,7 @@ impl Lexer {
         }
     }
 
-    pub fn tokenize(&mut self) -> Result<Vec<Token>, String> {
+    pub fn tokenize(&mut self) -> Result<Vec<Token>, CompileError> {
         let mut tokens = Vec::new();
 
         loop {
@@ -35,7 +36,7 @@ impl Lexer {
         Ok(tokens)
     }
 
-    fn next_token(&mut self) -> Result<Token, String> {
+    fn next_token(&mut self) -> Result<Token, CompileError> {
         self.skip_whitespace_and_comments();
 
         let start_line = self.line;
@@ -52,6 +53,10 @@ impl Lexer {
             return self.read_string(start_line, start_column);
         }
 
+        if ch == '\'' {
+            return self.read_char(start_line, start_column);
+        }
+
         // Identifiers and keywords
         if ch.is_alphabetic() || ch == '_' {
             return Ok(self.read_identifier(start_line, start_column));
@@ -104,7 +109,12 @@ impl Lexer {
                 self.advance();
                 if self.current() == '.' {
                     self.advance();
-                    TokenKind::DotDot
+                    if self.current() == '=' {
+                        self.advance();
+                        TokenKind::DotDotEqual
+                    } else {
+                        TokenKind::DotDot
+                    }
                 } else {
                     TokenKind::Dot
                 }
@@ -173,7 +183,7 @@ impl Lexer {
                     self.advance();
                     TokenKind::EqualQuestionDouble
                 } else {
-                    return Err(format!("Unexpected character '?' at {}:{}", start_line, start_column));
+                    TokenKind::Question
                 }
             }
             '!' => {
@@ -186,7 +196,7 @@ impl Lexer {
                     self.advance();
                     TokenKind::BangEqualDouble
                 } else {
-                    return Err(format!("Unexpected character '!' at {}:{}", start_line, start_column));
+                    return Err(error_at(start_line, start_column, "Unexpected character '!'"));
                 }
             }
             '<' => {
@@ -230,7 +240,7 @@ impl Lexer {
                 TokenKind::Tilde
             }
             _ => {
-                return Err(format!("Unexpected character '{}' at {}:{}", ch, start_line, start_column));
+                return Err(error_at(start_line, start_column, format!("Unexpected character '{}'", ch)));
             }
         };
 
@@ -256,60 +266,155 @@ impl Lexer {
         Token::new(kind, lexeme, start_line, start_column)
     }
 
-    fn read_number(&mut self, start_line: usize, start_column: usize) -> Result<Token, String> {
+    fn read_number(&mut self, start_line: usize, start_column: usize) -> Result<Token, CompileError> {
         let start = self.position;
 
-        while !self.is_at_end() && self.current().is_numeric() {
-            self.advance();
+        let radix = match (self.current(), self.peek_ahead(1)) {
+            ('0', Some('x' | 'X')) => Some((16, "hexadecimal")),
+            ('0', Some('b' | 'B')) => Some((2, "binary")),
+            ('0', Some('o' | 'O')) => Some((8, "octal")),
+            _ => None,
+        };
+        let misplaced_underscore = |lexer: &Self| {
+            let lexeme: String = lexer.input[start..lexer.position].iter().collect();
+            error_at(start_line, start_column, format!("Misplaced `_` in numeric literal '{}'", lexeme))
+        };
+
+        if let Some((radix, base_name)) = radix {
+            self.advance(); // consume '0'
+            self.advance(); // consume the base letter
+            // Take every alphanumeric so `0b12` is one bad literal, not `0b1` then `2`
+            let mut digits = self.read_digit_run(|c| c.is_ascii_alphanumeric()).ok_or_else(|| misplaced_underscore(self))?;
+            let lexeme: String = self.input[start..self.position].iter().collect();
+
+            // `f` is a hex digit, so only integer suffixes apply
+            let suffix = digits.find(['i', 'u']).map(|at| digits.split_off(at));
+            if let Some(bad) = digits.chars().find(|c| !c.is_digit(radix)) {
+                return Err(error_at(start_line, start_column, format!("Invalid digit `{}` in {} literal '{}'", bad, base_name, lexeme)));
+            }
+            let value = i64::from_str_radix(&digits, radix)
+                .map_err(|_| error_at(start_line, start_column, format!("Invalid integer literal '{}'", lexeme)))?;
+
+            let kind = match suffix {
+                Some(suffix) if !NUMERIC_SUFFIXES.contains(&suffix.as_str()) => {
+                    return Err(error_at(start_line, start_column, format!("Unknown numeric suffix `{}`", suffix)));
+                }
+                Some(suffix) => typed_integer(value, suffix, &lexeme, start_line, start_column)?,
+                None => TokenKind::IntegerLiteral(value),
+            };
+            return Ok(Token::new(kind, lexeme, start_line, start_column));
         }
 
+        let mut number = self.read_digit_run(|c| c.is_numeric()).ok_or_else(|| misplaced_underscore(self))?;
+
         // Check for decimal point
         let is_float = !self.is_at_end() && self.current() == '.' &&
-                       self.peek_ahead(1).map_or(false, |c| c.is_numeric());
+                       self.peek_ahead(1).is_some_and(|c| c.is_numeric());
 
         if is_float {
             self.advance(); // consume '.'
-            while !self.is_at_end() && self.current().is_numeric() {
-                self.advance();
-            }
+            number.push('.');
+            number += &self.read_digit_run(|c| c.is_numeric()).ok_or_else(|| misplaced_underscore(self))?;
         }
 
         // Check for scientific notation (e or E)
-        if !self.is_at_end() && (self.current() == 'e' || self.current() == 'E') {
+        let has_exponent = !self.is_at_end() && (self.current() == 'e' || self.current() == 'E');
+        if has_exponent {
             self.advance(); // consume 'e' or 'E'
+            number.push('e');
 
             // Optional + or - sign
             if !self.is_at_end() && (self.current() == '+' || self.current() == '-') {
+                number.push(self.current());
                 self.advance();
             }
 
             // Exponent digits
             if !self.is_at_end() && self.current().is_numeric() {
-                while !self.is_at_end() && self.current().is_numeric() {
-                    self.advance();
-                }
+                number += &self.read_digit_run(|c| c.is_numeric()).ok_or_else(|| misplaced_underscore(self))?;
             } else {
-                return Err(format!("Invalid scientific notation at {}:{}", start_line, start_column));
+                return Err(error_at(start_line, start_column, "Invalid scientific notation"));
             }
         }
 
-        // Determine if this is a float
+        let number_end = self.position;
+        let suffix = self.read_number_suffix(start_line, start_column)?;
         let lexeme: String = self.input[start..self.position].iter().collect();
-        if is_float || lexeme.contains('e') || lexeme.contains('E') {
-            let value = lexeme.parse::<f64>()
-                .map_err(|_| format!("Invalid float literal '{}' at {}:{}", lexeme, start_line, start_column))?;
+        let literal: String = self.input[start..number_end].iter().collect();
+
+        let kind = if is_float || has_exponent {
+            let value = number.parse::<f64>()
+                .map_err(|_| error_at(start_line, start_column, format!("Invalid float literal '{}'", lexeme)))?;
 
-            Ok(Token::new(TokenKind::FloatLiteral(value), lexeme, start_line, start_column))
+            match suffix {
+                Some(suffix) if suffix.starts_with('f') => TokenKind::TypedFloatLiteral(value, suffix),
+                Some(suffix) => {
+                    return Err(error_at(start_line, start_column, format!("Suffix `{}` needs an integer literal, not '{}'", suffix, literal)));
+                }
+                None => TokenKind::FloatLiteral(value),
+            }
         } else {
-            let lexeme: String = self.input[start..self.position].iter().collect();
-            let value = lexeme.parse::<i64>()
-                .map_err(|_| format!("Invalid integer literal '{}' at {}:{}", lexeme, start_line, start_column))?;
+            let value = number.parse::<i64>()
+                .map_err(|_| error_at(start_line, start_column, format!("Invalid integer literal '{}'", lexeme)))?;
+
+            match suffix {
+                Some(suffix) => typed_integer(value, suffix, &lexeme, start_line, start_column)?,
+                None => TokenKind::IntegerLiteral(value),
+            }
+        };
+        Ok(Token::new(kind, lexeme, start_line, start_column))
+    }
 
-            Ok(Token::new(TokenKind::IntegerLiteral(value), lexeme, start_line, start_column))
+    // A type suffix such as `u8` or `f32` directly after a decimal number.
+    // Letters that don't look like one are left for the next token.
+    fn read_number_suffix(&mut self, start_line: usize, start_column: usize) -> Result<Option<String>, CompileError> {
+        if self.is_at_end() || !matches!(self.current(), 'i' | 'u' | 'f') {
+            return Ok(None);
+        }
+        let mut end = self.position + 1;
+        while end < self.input.len() && self.input[end].is_ascii_digit() {
+            end += 1;
+        }
+        let continues_word = end < self.input.len() && (self.input[end].is_alphanumeric() || self.input[end] == '_');
+        if end == self.position + 1 || continues_word {
+            return Ok(None);
+        }
+
+        let suffix: String = self.input[self.position..end].iter().collect();
+        if !NUMERIC_SUFFIXES.contains(&suffix.as_str()) {
+            return Err(error_at(start_line, start_column, format!("Unknown numeric suffix `{}`", suffix)));
+        }
+        while self.position < end {
+            self.advance();
+        }
+        Ok(Some(suffix))
+    }
+
+    // Consume digits (as decided by `is_digit`) and `_` separators, returning
+    // the digits without separators. `None` when a `_` doesn't sit between
+    // two digits.
+    fn read_digit_run(&mut self, is_digit: impl Fn(char) -> bool) -> Option<String> {
+        let mut digits = String::new();
+        let mut previous = None;
+        let mut well_placed = true;
+        while !self.is_at_end() && (is_digit(self.current()) || self.current() == '_') {
+            let c = self.current();
+            if c == '_' {
+                well_placed &= previous.is_some_and(|p| p != '_');
+            } else {
+                digits.push(c);
+            }
+            previous = Some(c);
+            self.advance();
+        }
+        if well_placed && previous != Some('_') {
+            Some(digits)
+        } else {
+            None
         }
     }
 
-    fn read_string(&mut self, start_line: usize, start_column: usize) -> Result<Token, String> {
+    fn read_string(&mut self, start_line: usize, start_column: usize) -> Result<Token, CompileError> {
         use crate::lexer::token::FStringPart;
 
         // Check for prefix (f, r, rf)
@@ -327,7 +432,7 @@ impl Lexer {
         // Count opening quotes
         let quote_count = self.count_quotes();
         if quote_count == 0 {
-            return Err(format!("Expected string opening quotes at {}:{}", start_line, start_column));
+            return Err(error_at(start_line, start_column, "Expected string opening quotes"));
         }
 
         // Skip opening quotes
@@ -353,7 +458,7 @@ impl Lexer {
                         self.advance();
                     }
 
-                    let lexeme = format!("f\"...\"");
+                    let lexeme = "f\"...\"".to_string();
                     return Ok(Token::new(TokenKind::FStringLiteral(parts), lexeme, start_line, start_column));
                 }
 
@@ -388,24 +493,22 @@ impl Lexer {
                     }
 
                     if self.is_at_end() {
-                        return Err(format!("Unterminated expression in f-string at {}:{}", start_line, start_column));
+                        return Err(error_at(start_line, start_column, "Unterminated expression in f-string"));
                     }
 
                     // Skip the closing '}'
                     self.advance();
 
                     parts.push(FStringPart::Expression(expr.trim().to_string()));
+                } else if ch == '\\' && !is_raw {
+                    current_text.push(self.read_escape()?);
                 } else {
-                    if ch == '\n' {
-                        self.line += 1;
-                        self.column = 0;
-                    }
                     current_text.push(ch);
                     self.advance();
                 }
             }
 
-            Err(format!("Unterminated f-string at {}:{}", start_line, start_column))
+            Err(error_at(start_line, start_column, "Unterminated f-string"))
         } else {
             // Regular string
             let mut value = String::new();
@@ -430,19 +533,105 @@ impl Lexer {
 
                 let ch = self.current();
 
-                if ch == '\n' {
-                    self.line += 1;
-                    self.column = 0;
+                if ch == '\\' && !is_raw {
+                    value.push(self.read_escape()?);
+                    continue;
                 }
 
                 value.push(ch);
                 self.advance();
             }
 
-            Err(format!("Unterminated string at {}:{}", start_line, start_column))
+            Err(error_at(start_line, start_column, "Unterminated string"))
         }
     }
 
+    // A single ASCII character between single quotes, such as `'a'` or `'\n'`
+    fn read_char(&mut self, start_line: usize, start_column: usize) -> Result<Token, CompileError> {
+        let start = self.position;
+        self.advance(); // consume opening '
+
+        let unterminated = || error_at(start_line, start_column, "Unterminated character literal");
+        let value = match self.current() {
+            _ if self.is_at_end() => return Err(unterminated()),
+            '\n' => return Err(unterminated()),
+            '\'' => return Err(error_at(start_line, start_column, "Empty character literal")),
+            '\\' => self.read_escape()?,
+            c => {
+                self.advance();
+                c
+            }
+        };
+
+        if self.is_at_end() || self.current() == '\n' {
+            return Err(unterminated());
+        }
+        if self.current() != '\'' {
+            return Err(error_at(start_line, start_column, "Character literal holds more than one character (use a string for text)"));
+        }
+        self.advance(); // consume closing '
+
+        // C char constants are a single byte
+        if !value.is_ascii() {
+            return Err(error_at(start_line, start_column, format!("Character literal '{}' is not ASCII", value)));
+        }
+
+        let lexeme: String = self.input[start..self.position].iter().collect();
+        Ok(Token::new(TokenKind::CharLiteral(value), lexeme, start_line, start_column))
+    }
+
+    // Decode the escape sequence starting at the current backslash and
+    // move past it
+    fn read_escape(&mut self) -> Result<char, CompileError> {
+        let (line, column) = (self.line, self.column);
+        self.advance();
+        if self.is_at_end() {
+            return Err(error_at(line, column, "Unterminated escape sequence"));
+        }
+
+        let escaped = match self.current() {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            '0' => '\0',
+            'u' => return self.read_unicode_escape(line, column),
+            other => return Err(error_at(line, column, format!("Unknown escape sequence `\\{}`", other))),
+        };
+        self.advance();
+        Ok(escaped)
+    }
+
+    // The `u{1F600}` after a backslash at `line:column`: one to six hex
+    // digits naming a Unicode scalar value
+    fn read_unicode_escape(&mut self, line: usize, column: usize) -> Result<char, CompileError> {
+        self.advance(); // consume 'u'
+        if self.current() != '{' || self.is_at_end() {
+            return Err(error_at(line, column, "Expected `{` after `\\u`"));
+        }
+        self.advance();
+
+        let start = self.position;
+        while !self.is_at_end() && self.current().is_ascii_hexdigit() {
+            self.advance();
+        }
+        let digits: String = self.input[start..self.position].iter().collect();
+        if self.current() != '}' || self.is_at_end() {
+            return Err(error_at(line, column, format!("Unterminated unicode escape `\\u{{{}`", digits)));
+        }
+        self.advance();
+
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(error_at(line, column, format!("Unicode escape `\\u{{{}}}` needs one to six hex digits", digits)));
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| error_at(line, column, format!("Unicode escape `\\u{{{}}}` is not a valid character", digits)))
+    }
+
     fn count_quotes(&self) -> usize {
         let mut count = 0;
         let mut pos = self.position;
@@ -472,10 +661,6 @@ impl Lexer {
             let ch = self.current();
 
             if ch.is_whitespace() {
-                if ch == '\n' {
-                    self.line += 1;
-                    self.column = 0;
-                }
                 self.advance();
             } else if ch == '/' && self.peek_ahead(1) == Some('/') {
                 // Single-line comment
@@ -515,10 +700,17 @@ impl Lexer {
         true
     }
 
+    // Move past the current character; every line, including those inside
+    // strings and f-string expressions, starts again at column 1
     fn advance(&mut self) {
         if !self.is_at_end() {
+            if self.input[self.position] == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
             self.position += 1;
-            self.column += 1;
         }
     }
 
@@ -527,6 +719,34 @@ impl Lexer {
     }
 }
 
+// An error at `line:column`, which the message ends by citing
+fn error_at(line: usize, column: usize, message: impl Into<String>) -> CompileError {
+    CompileError::at(Stage::Lexer, format!("{} at {}:{}", message.into(), line, column), line, column)
+}
+
+const NUMERIC_SUFFIXES: [&str; 12] = ["i8", "i16", "i32", "i64", "i128", "u8", "u16", "u32", "u64", "u128", "f32", "f64"];
+
+// The token for integer `value` written with a type `suffix`, which it
+// has to fit
+fn typed_integer(value: i64, suffix: String, lexeme: &str, line: usize, column: usize) -> Result<TokenKind, CompileError> {
+    if suffix.starts_with('f') {
+        return Ok(TokenKind::TypedFloatLiteral(value as f64, suffix));
+    }
+    let max = match suffix.as_str() {
+        "i8" => i8::MAX as i64,
+        "i16" => i16::MAX as i64,
+        "i32" => i32::MAX as i64,
+        "u8" => u8::MAX as i64,
+        "u16" => u16::MAX as i64,
+        "u32" => u32::MAX as i64,
+        _ => i64::MAX,
+    };
+    if value > max {
+        return Err(error_at(line, column, format!("Literal '{}' does not fit in {}", lexeme, suffix)));
+    }
+    Ok(TokenKind::TypedIntegerLiteral(value, suffix))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -544,6 +764,19 @@ mod tests {
         assert!(matches!(tokens[4].kind, TokenKind::Return));
     }
 
+    #[test]
+    fn test_columns_restart_on_each_line() {
+        let tokens = Lexer::new("let a = 1;\nreturn a;").tokenize().unwrap();
+        let ret = &tokens[5];
+        assert_eq!(ret.kind, TokenKind::Return);
+        assert_eq!((ret.line, ret.column), (2, 1));
+        assert_eq!((tokens[6].line, tokens[6].column), (2, 8));
+
+        // Newlines inside strings, f-string expressions and comments count too
+        let tokens = Lexer::new("\"a\nb\" f\"{\nx\n}\" // note\n  y").tokenize().unwrap();
+        assert_eq!((tokens[2].line, tokens[2].column), (5, 3));
+    }
+
     #[test]
     fn test_identifiers() {
         let mut lexer = Lexer::new("foo bar_baz x123");
@@ -566,6 +799,130 @@ mod tests {
         assert!(matches!(tokens[2].kind, TokenKind::IntegerLiteral(0)));
     }
 
+    #[test]
+    fn test_numeric_suffixes() {
+        let tokens = Lexer::new("42u8 10i64 2.5f32 2f64 0xFFu16 1_000i32 7 in").tokenize().unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::TypedIntegerLiteral(42, "u8".to_string()),
+                TokenKind::TypedIntegerLiteral(10, "i64".to_string()),
+                TokenKind::TypedFloatLiteral(2.5, "f32".to_string()),
+                TokenKind::TypedFloatLiteral(2.0, "f64".to_string()),
+                TokenKind::TypedIntegerLiteral(0xFF, "u16".to_string()),
+                TokenKind::TypedIntegerLiteral(1000, "i32".to_string()),
+                TokenKind::IntegerLiteral(7),
+                TokenKind::In,
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[2].lexeme, "2.5f32");
+
+        assert_eq!(
+            Lexer::new("let x = 3.14i32;").tokenize().unwrap_err().message,
+            "Suffix `i32` needs an integer literal, not '3.14' at 1:9"
+        );
+        assert_eq!(Lexer::new("300u8").tokenize().unwrap_err().message, "Literal '300u8' does not fit in u8 at 1:1");
+        assert!(Lexer::new("5i7").tokenize().unwrap_err().message.starts_with("Unknown numeric suffix `i7`"));
+        assert!(Lexer::new("0x1u9").tokenize().unwrap_err().message.starts_with("Unknown numeric suffix `u9`"));
+    }
+
+    #[test]
+    fn test_digit_separators() {
+        let tokens = Lexer::new("1_000 0xFF_FF 0b1010_1010 1_000.000_5 2e1_0").tokenize().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::IntegerLiteral(1000));
+        assert_eq!(tokens[0].lexeme, "1_000");
+        assert_eq!(tokens[1].kind, TokenKind::IntegerLiteral(0xFFFF));
+        assert_eq!(tokens[2].kind, TokenKind::IntegerLiteral(0b1010_1010));
+        assert_eq!(tokens[3].kind, TokenKind::FloatLiteral(1000.0005));
+        assert_eq!(tokens[4].kind, TokenKind::FloatLiteral(2e10));
+
+        let err = Lexer::new("let n = 1__0;").tokenize().unwrap_err().message;
+        assert_eq!(err, "Misplaced `_` in numeric literal '1__0' at 1:9");
+        for source in ["1_", "0x_FF", "1_.5", "1.5_", "1e5_"] {
+            assert!(Lexer::new(source).tokenize().is_err(), "{} should not lex", source);
+        }
+        // A leading underscore starts an identifier instead
+        assert_eq!(Lexer::new("_1").tokenize().unwrap()[0].kind, TokenKind::Identifier("_1".to_string()));
+    }
+
+    #[test]
+    fn test_prefixed_integers() {
+        let tokens = Lexer::new("0xFF 0x1a 0b1010 0o17 0B11").tokenize().unwrap();
+        let values: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(
+            values,
+            vec![
+                TokenKind::IntegerLiteral(255),
+                TokenKind::IntegerLiteral(26),
+                TokenKind::IntegerLiteral(10),
+                TokenKind::IntegerLiteral(15),
+                TokenKind::IntegerLiteral(3),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[0].lexeme, "0xFF");
+
+        let err = Lexer::new("let b = 0b2;").tokenize().unwrap_err().message;
+        assert_eq!(err, "Invalid digit `2` in binary literal '0b2' at 1:9");
+        assert!(Lexer::new("0o8").tokenize().unwrap_err().message.contains("octal"));
+        assert!(Lexer::new("0xG").tokenize().unwrap_err().message.contains("hexadecimal"));
+        // No digits after the prefix
+        assert!(Lexer::new("0x;").tokenize().unwrap_err().message.starts_with("Invalid integer literal '0x'"));
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let tokens = Lexer::new(r"'a' '\n' '\'' '\\' ' '").tokenize().unwrap();
+        let kinds: Vec<_> = tokens.iter().map(|t| t.kind.clone()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::CharLiteral('a'),
+                TokenKind::CharLiteral('\n'),
+                TokenKind::CharLiteral('\''),
+                TokenKind::CharLiteral('\\'),
+                TokenKind::CharLiteral(' '),
+                TokenKind::Eof,
+            ]
+        );
+        assert_eq!(tokens[1].lexeme, r"'\n'");
+
+        assert_eq!(Lexer::new("let c = '';").tokenize().unwrap_err().message, "Empty character literal at 1:9");
+        assert!(Lexer::new("'ab'").tokenize().unwrap_err().message.starts_with("Character literal holds more than one character"));
+        assert!(Lexer::new("'a").tokenize().unwrap_err().message.starts_with("Unterminated character literal"));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let tokens = Lexer::new(r#""\t" r"\t" "a\"b\\c\n\0""#).tokenize().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::StringLiteral("\t".to_string()));
+        assert_eq!(tokens[1].kind, TokenKind::RawStringLiteral("\\t".to_string()));
+        assert_eq!(tokens[2].kind, TokenKind::StringLiteral("a\"b\\c\n\0".to_string()));
+
+        let err = Lexer::new("let s = \"bad \\q\";").tokenize().unwrap_err().message;
+        assert_eq!(err, "Unknown escape sequence `\\q` at 1:14");
+    }
+
+    #[test]
+    fn test_unicode_escapes() {
+        let tokens = Lexer::new(r#""\u{41}" "\u{1F600}!" "\u{e9}""#).tokenize().unwrap();
+        assert_eq!(tokens[0].kind, TokenKind::StringLiteral("A".to_string()));
+        match &tokens[1].kind {
+            TokenKind::StringLiteral(s) => assert_eq!(s.as_bytes(), &[0xF0, 0x9F, 0x98, 0x80, b'!']),
+            other => panic!("Expected a string, got {:?}", other),
+        }
+        assert_eq!(tokens[2].kind, TokenKind::StringLiteral("é".to_string()));
+
+        let err = Lexer::new(r#"let s = "\u{D800}";"#).tokenize().unwrap_err().message;
+        assert_eq!(err, "Unicode escape `\\u{D800}` is not a valid character at 1:10");
+        assert!(Lexer::new(r#""\u{110000}""#).tokenize().unwrap_err().message.contains("not a valid character"));
+        assert!(Lexer::new(r#""\u{}""#).tokenize().unwrap_err().message.contains("one to six hex digits"));
+        assert!(Lexer::new(r#""\u{41""#).tokenize().unwrap_err().message.starts_with("Unterminated unicode escape"));
+        assert!(Lexer::new(r#""\u41""#).tokenize().unwrap_err().message.starts_with("Expected `{` after `\\u`"));
+    }
+
     #[test]
     fn test_strings() {
         let mut lexer = Lexer::new(r#""hello" "world""#);
@@ -581,7 +938,7 @@ mod tests {
 
     #[test]
     fn test_operators() {
-        let mut lexer = Lexer::new("+ - * / ?= ??= != !!=");
+        let mut lexer = Lexer::new("+ - * / ?= ??= != !!= ? :");
         let tokens = lexer.tokenize().unwrap();
 
         assert!(matches!(tokens[0].kind, TokenKind::Plus));
@@ -592,6 +949,8 @@ mod tests {
         assert!(matches!(tokens[5].kind, TokenKind::EqualQuestionDouble));
         assert!(matches!(tokens[6].kind, TokenKind::BangEqual));
         assert!(matches!(tokens[7].kind, TokenKind::BangEqualDouble));
+        assert!(matches!(tokens[8].kind, TokenKind::Question));
+        assert!(matches!(tokens[9].kind, TokenKind::Colon));
     }
 
     #[test]
@@ -19,6 +19,7 @@ pub enum TokenKind {
     // Keywords
     Function,
     Let,
+    Mut,
     If,
     Else,
     While,
@@ -38,6 +39,7 @@ pub enum TokenKind {
     Switch,
     Case,
     Default,
+    Fallthrough,
     Break,
     Continue,
     And,
@@ -47,15 +49,23 @@ pub enum TokenKind {
     Unknown,
     Requires,
     Ensures,
+    Pure,
     When,
     As,
+    Using,
+    Struct,
+    Enum,
 
     // Literals
     IntegerLiteral(i64),
     FloatLiteral(f64),
+    // A number with a type suffix, as in `42u8` or `1.5f32`
+    TypedIntegerLiteral(i64, String),
+    TypedFloatLiteral(f64, String),
     StringLiteral(String),
     RawStringLiteral(String),
     FStringLiteral(Vec<FStringPart>),
+    CharLiteral(char),
     BooleanLiteral(bool),
 
     // Identifiers
@@ -103,14 +113,15 @@ pub enum TokenKind {
     RightBracket,   // ]
     Semicolon,      // ;
     Colon,          // :
+    Question,       // ?
     Comma,          // ,
     Dot,            // .
     DotDot,         // ..
+    DotDotEqual,    // ..=
     Arrow,          // =>
 
     // Special
     Eof,
-    Error(String),
 }
 
 impl Token {
@@ -129,6 +140,7 @@ pub fn keyword(s: &str) -> Option<TokenKind> {
     match s {
         "function" => Some(TokenKind::Function),
         "let" => Some(TokenKind::Let),
+        "mut" => Some(TokenKind::Mut),
         "if" => Some(TokenKind::If),
         "else" => Some(TokenKind::Else),
         "while" => Some(TokenKind::While),
@@ -148,6 +160,7 @@ pub fn keyword(s: &str) -> Option<TokenKind> {
         "switch" => Some(TokenKind::Switch),
         "case" => Some(TokenKind::Case),
         "default" => Some(TokenKind::Default),
+        "fallthrough" => Some(TokenKind::Fallthrough),
         "break" => Some(TokenKind::Break),
         "continue" => Some(TokenKind::Continue),
         "and" => Some(TokenKind::And),
@@ -157,8 +170,12 @@ pub fn keyword(s: &str) -> Option<TokenKind> {
         "unknown" => Some(TokenKind::Unknown),
         "requires" => Some(TokenKind::Requires),
         "ensures" => Some(TokenKind::Ensures),
+        "pure" => Some(TokenKind::Pure),
         "when" => Some(TokenKind::When),
         "as" => Some(TokenKind::As),
+        "using" => Some(TokenKind::Using),
+        "struct" => Some(TokenKind::Struct),
+        "enum" => Some(TokenKind::Enum),
         "true" => Some(TokenKind::BooleanLiteral(true)),
         "false" => Some(TokenKind::BooleanLiteral(false)),
         _ => None,
@@ -0,0 +1,197 @@
+//! The HiLow compiler as a library: everything `hilowc` does, callable
+//! from Rust.
+//!
+//! [`compile_source`] turns a program into C and [`compile_to_binary`]
+//! builds it with the C compiler. The stages are public too, for tools
+//! that need the tokens, the AST or the checked program along the way.
+
+pub mod ast;
+pub mod codegen;
+pub mod error;
+pub mod lexer;
+pub mod modules;
+pub mod optimize;
+pub mod parser;
+pub mod sema;
+
+pub use codegen::CompileOptions;
+pub use error::{CompileError, Stage};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Settings for compiling a program from source
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// How the C is generated and built
+    pub codegen: CompileOptions,
+    /// Directories searched for imported modules, after the importer's own
+    pub include_dirs: Vec<PathBuf>,
+    /// Constants for `when` blocks
+    pub defines: HashMap<String, ast::Expression>,
+    /// Report an error for a missing semicolon instead of tolerating it
+    pub strict_semicolons: bool,
+    /// The file the source came from; when set, the generated C has
+    /// `#line` directives pointing back to it
+    pub source_name: Option<String>,
+}
+
+/// Compile `source` to C. Imports are resolved from the current directory,
+/// then `include_dirs`.
+pub fn compile_source(source: &str, options: &Options) -> Result<String, Vec<CompileError>> {
+    let mut program = parse_source(source, options)?;
+    check_program(&mut program, Path::new(""), options)?;
+    optimize::optimize(&mut program, &options.codegen);
+
+    codegen::CodeGenerator::new(options.codegen.clone())
+        .generate(&program)
+        .map_err(|e| vec![CompileError::new(Stage::Codegen, e)])
+}
+
+/// Compile `source` to an executable at `output_path`
+pub fn compile_to_binary(source: &str, output_path: &Path, options: &Options) -> Result<(), Vec<CompileError>> {
+    let mut program = parse_source(source, options)?;
+    check_program(&mut program, Path::new(""), options)?;
+    optimize::optimize(&mut program, &options.codegen);
+
+    codegen::compile(&program, &output_path.to_string_lossy(), &options.codegen)
+        .map_err(|e| vec![CompileError::new(Stage::Codegen, e)])
+}
+
+/// Lex and parse `source` into a program
+pub fn parse_source(source: &str, options: &Options) -> Result<ast::Program, Vec<CompileError>> {
+    let tokens = lexer::Lexer::new(source)
+        .tokenize()
+        .map_err(|e| vec![e])?;
+
+    let mut parser = parser::Parser::new(tokens)
+        .with_strict_semicolons(options.strict_semicolons)
+        .with_defines(options.defines.clone());
+    if let Some(name) = &options.source_name {
+        parser = parser.with_source_name(name);
+    }
+    parser.parse()
+}
+
+/// Merge in the declarations `program` imports, resolving modules from
+/// `importer`'s directory, run the semantic checks, and record the types
+/// they resolve on the declarations and calls. Returns the warnings for code that
+/// compiles but is probably a mistake.
+pub fn check_program(program: &mut ast::Program, importer: &Path, options: &Options) -> Result<Vec<String>, Vec<CompileError>> {
+    let resolver = modules::ModuleResolver::new(options.include_dirs.clone());
+    let parse_module = |module_path: &Path| -> Result<ast::Program, String> {
+        let source = std::fs::read_to_string(module_path)
+            .map_err(|e| format!("Error reading {}: {}", module_path.display(), e))?;
+        let module_options = Options {
+            source_name: Some(module_path.display().to_string()),
+            ..options.clone()
+        };
+        parse_source(&source, &module_options).map_err(|errors| {
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n")
+        })
+    };
+    modules::load_imports(program, importer, &resolver, &parse_module)
+        .map_err(|e| vec![CompileError::new(Stage::Import, e)])?;
+
+    program
+        .validate()
+        .and_then(|_| sema::check_names(program))
+        .and_then(|_| sema::check_purity(program))
+        .and_then(|_| sema::check_builtin_calls(program))
+        .and_then(|_| sema::check_definite_assignment(program))
+        .and_then(|_| sema::check_captures(program))
+        .and_then(|_| sema::check_types(program))
+        .and_then(|_| {
+            if options.codegen.strict_mutability {
+                sema::check_mutability(program)
+            } else {
+                Ok(())
+            }
+        })?;
+
+    sema::record_types(program);
+    Ok(sema::lint_infinite_loops(program))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_source_returns_c() {
+        let c_code = compile_source("function main(): i32 { let answer: i32 = 42; return answer; }", &Options::default())
+            .unwrap();
+        assert!(c_code.contains("int32_t main("), "{}", c_code);
+        assert!(c_code.contains("int32_t answer = 42;"), "{}", c_code);
+    }
+
+    #[test]
+    fn test_compile_source_reports_stage() {
+        let errors = compile_source("function main(): i32 { return 1 }", &Options {
+            strict_semicolons: true,
+            ..Options::default()
+        })
+        .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].stage, Stage::Parser);
+
+        let errors = compile_source("function main(): i32 { let a = missing; let b = gone; return 0; }", &Options::default())
+            .unwrap_err();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors.iter().all(|e| e.stage == Stage::Semantic));
+        assert!(errors[0].to_string().starts_with("Semantic error: Unknown identifier `missing`"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_errors_carry_source_position() {
+        let errors = compile_source("function main(): i32 {\n  let x = (1 + ;\n  return 0;\n}", &Options::default())
+            .unwrap_err();
+        assert_eq!(errors[0].stage, Stage::Parser);
+        assert_eq!((errors[0].line(), errors[0].column()), (Some(2), Some(16)), "{}", errors[0]);
+        assert_eq!(errors[0].to_string(), "Parser error: Unexpected token: Semicolon at 2:16");
+
+        let errors = compile_source("function main(): i32 {\n  return 1 @ 2;\n}", &Options::default()).unwrap_err();
+        assert_eq!(errors[0].stage, Stage::Lexer);
+        assert_eq!((errors[0].line(), errors[0].column()), (Some(2), Some(12)), "{}", errors[0]);
+
+        // Semantic errors point at the statement they were found in
+        let errors = compile_source("function main(): i32 {\n  let a = 1;\n  if (a > 0) { a = missing; }\n  return 0;\n}", &Options::default())
+            .unwrap_err();
+        assert_eq!(errors[0].stage, Stage::Semantic);
+        assert_eq!((errors[0].line(), errors[0].column()), (Some(3), Some(16)), "{}", errors[0]);
+        assert_eq!(errors[0].to_string(), "Semantic error: Unknown identifier `missing` in function `main` at 3:16");
+    }
+
+    #[test]
+    fn test_compile_to_binary() {
+        let dir = std::env::temp_dir().join(format!("hilow-lib-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join("program");
+
+        compile_to_binary("function main(): i32 { return 3; }", &binary, &Options::default()).unwrap();
+        let status = std::process::Command::new(&binary).status().unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_c_compiler_errors_point_at_source_lines() {
+        let dir = std::env::temp_dir().join(format!("hilow-lines-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let options = Options {
+            source_name: Some("prog.hl".to_string()),
+            ..Options::default()
+        };
+
+        let c_code = compile_source("function main(): i32 {\n  let a: i32 = 1;\n  return a;\n}", &options).unwrap();
+        assert!(c_code.contains("#line 2 \"prog.hl\"\n    int32_t a = 1;"), "{}", c_code);
+
+        // `short` gets past HiLow but isn't a valid C name
+        let source = "function main(): i32 {\n  let a: i32 = 1;\n\n  let short: i32 = 2;\n  return a;\n}";
+        let errors = compile_to_binary(source, &dir.join("program"), &options).unwrap_err();
+        assert!(errors[0].message.contains("prog.hl:4:"), "{}", errors[0]);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,23 +1,76 @@
-mod lexer;
-mod parser;
-mod ast;
-mod codegen;
-
-use clap::{Parser as ClapParser, Subcommand};
+use clap::{Args, Parser as ClapParser, Subcommand};
+use hilow::{ast, codegen, lexer, optimize, parser, CompileError};
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 #[derive(ClapParser)]
 #[command(name = "hilowc")]
 #[command(about = "The HiLow programming language compiler", long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
-    /// Input file to compile
-    input: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compile a program (what `hilowc <input>` does without a subcommand)
+    Build(BuildArgs),
+    /// Compile a program to a temporary binary, run it, and exit with its status
+    Run(RunArgs),
+}
+
+#[derive(Args)]
+struct BuildArgs {
+    /// Input file to compile, or `-` to read standard input (only optional
+    /// when a subcommand is given)
+    #[arg(required = true)]
+    input: Option<PathBuf>,
+
+    /// Additional modules to bundle into a --static-lib archive
+    modules: Vec<PathBuf>,
 
     /// Output file
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Compile every input to an object file and archive them into a static library
+    #[arg(long)]
+    static_lib: bool,
+
+    /// Write the generated C to <output>.c and stop without running GCC
+    #[arg(long)]
+    emit_c: bool,
+
+    /// Build as usual but keep the generated C at <output>.c
+    #[arg(long)]
+    keep_c: bool,
+
+    #[command(flatten)]
+    flags: CompileFlags,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Program to compile and run, or `-` to read standard input
+    input: PathBuf,
+
+    /// Arguments passed on to the program
+    #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+    args: Vec<String>,
+
+    #[command(flatten)]
+    flags: CompileFlags,
+}
+
+/// Options shared by every way of compiling
+#[derive(Args)]
+struct CompileFlags {
     /// Print tokens (lexer output)
     #[arg(long)]
     print_tokens: bool,
@@ -29,73 +82,449 @@ struct Cli {
     /// Optimization level (0-3)
     #[arg(short = 'O', default_value = "0")]
     optimization: u8,
+
+    /// Target triple the generated code is intended for (defaults to the host)
+    #[arg(long)]
+    target_triple: Option<String>,
+
+    /// Directory to search for imported modules (repeatable, searched in order)
+    #[arg(short = 'I', long = "include-dir", value_name = "DIR")]
+    include_dirs: Vec<PathBuf>,
+
+    /// Define a constant for `when` blocks (NAME or NAME=VALUE, repeatable)
+    #[arg(long = "define", short = 'D', value_name = "NAME[=VALUE]")]
+    defines: Vec<String>,
+
+    /// Report an error for a missing semicolon instead of tolerating it
+    #[arg(long)]
+    strict_semicolons: bool,
+
+    /// Reject assignment to bindings not declared `let mut`
+    #[arg(long)]
+    strict_mutability: bool,
+
+    /// Print a backtrace of HiLow function names when an assert or panic
+    /// fails, and bounds-check array indexing
+    #[arg(long)]
+    checked: bool,
+
+    /// Compile out log() calls below this level (debug, info, warn, error)
+    #[arg(long, default_value = "debug")]
+    log_level: String,
+
+    /// Rounds of small-function inlining to run at -O2 and above
+    #[arg(long, default_value = "3")]
+    max_inline_depth: usize,
+
+    /// Skip HiLow's AST optimization passes, leaving -O to GCC alone
+    #[arg(long)]
+    no_optimize_ast: bool,
+
+    /// Function the program starts in, instead of `main`
+    #[arg(long, default_value = "main")]
+    entry: String,
+
+    /// Don't warn about `while` loops that can never terminate
+    #[arg(long)]
+    allow_infinite_loops: bool,
+
+    /// C compiler to build with (defaults to $CC, then gcc)
+    #[arg(long, value_name = "PATH")]
+    cc: Option<String>,
+
+    /// Extra flag for the C compiler (repeatable)
+    #[arg(long = "cflag", value_name = "FLAG", allow_hyphen_values = true)]
+    cflags: Vec<String>,
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    // Read input file
-    let source = match fs::read_to_string(&cli.input) {
-        Ok(content) => content,
+    match cli.command {
+        Some(Command::Run(args)) => run(&args),
+        Some(Command::Build(args)) => build(&args),
+        None => build(&cli.build),
+    }
+}
+
+fn build(cli: &BuildArgs) {
+    let input = cli.input.as_ref().expect("clap requires an input");
+    let flags = &cli.flags;
+    let mut options = options(flags);
+    options.codegen.keep_c = cli.keep_c;
+
+    if cli.static_lib {
+        build_static_lib(cli, input, &options);
+        return;
+    }
+    if !cli.modules.is_empty() {
+        eprintln!("Error: additional modules can only be given with --static-lib");
+        std::process::exit(1);
+    }
+
+    let mut program = load_program(flags, input, &options);
+    let options = options.codegen;
+
+    let output_path = cli.output.clone().unwrap_or_else(|| default_output_path(input));
+
+    // Code generation
+    optimize::optimize(&mut program, &options);
+
+    if cli.emit_c {
+        match codegen::emit_c(&program, output_path.to_str().unwrap(), &options) {
+            Ok(c_path) => println!("C source written: {}", c_path),
+            Err(e) => {
+                eprintln!("Code generation error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let result = codegen::compile(&program, output_path.to_str().unwrap(), &options);
+
+    match result {
+        Ok(_) => {
+            println!("Compilation successful: {}", output_path.display());
+        }
         Err(e) => {
-            eprintln!("Error reading file: {}", e);
+            eprintln!("Code generation error: {}", e);
             std::process::exit(1);
         }
-    };
+    }
+}
 
-    // Lexer
-    let mut lexer = lexer::Lexer::new(&source);
-    let tokens = match lexer.tokenize() {
-        Ok(tokens) => {
-            if cli.print_tokens {
-                println!("=== TOKENS ===");
-                for token in &tokens {
-                    println!("{}", token);
-                }
-                println!();
-            }
-            tokens
+/// Compile to a temporary binary and run it, exiting with its status
+fn run(cli: &RunArgs) {
+    let options = options(&cli.flags);
+
+    let mut program = load_program(&cli.flags, &cli.input, &options);
+    let options = options.codegen;
+    optimize::optimize(&mut program, &options);
+
+    match run_program(&program, &cli.args, &options, |mut command| command.status()) {
+        Ok(status) => std::process::exit(status.code().unwrap_or(1)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
         }
+    }
+}
+
+/// Compile `program` into a scratch directory and hand `execute` the
+/// command that runs it with `args`; the directory is removed afterwards
+fn run_program<T>(
+    program: &ast::Program,
+    args: &[String],
+    options: &codegen::CompileOptions,
+    execute: impl FnOnce(std::process::Command) -> std::io::Result<T>,
+) -> Result<T, String> {
+    static RUNS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+    let run_id = RUNS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!("hilowc-run-{}-{}", std::process::id(), run_id));
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    let binary = dir.join("program");
+    let result = codegen::compile(program, binary.to_str().unwrap(), options).and_then(|_| {
+        let mut command = std::process::Command::new(&binary);
+        command.args(args);
+        execute(command).map_err(|e| format!("Failed to run the program: {}", e))
+    });
+
+    fs::remove_dir_all(&dir).ok();
+    result
+}
+
+/// The input with its extension removed, or `a.out` for standard input
+fn default_output_path(input: &Path) -> PathBuf {
+    if is_stdin(input) {
+        PathBuf::from("a.out")
+    } else {
+        input.with_extension("")
+    }
+}
+
+fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Read the source at `path`, taking it from `stdin` when the path is `-`
+fn read_source(path: &Path, stdin: &mut dyn Read) -> std::io::Result<String> {
+    if is_stdin(path) {
+        let mut source = String::new();
+        stdin.read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+fn options(flags: &CompileFlags) -> hilow::Options {
+    hilow::Options {
+        codegen: compile_options(flags),
+        include_dirs: flags.include_dirs.clone(),
+        defines: parse_defines(flags),
+        strict_semicolons: flags.strict_semicolons,
+        source_name: None,
+    }
+}
+
+fn compile_options(flags: &CompileFlags) -> codegen::CompileOptions {
+    let target = match &flags.target_triple {
+        Some(triple) => match codegen::target::Target::from_triple(triple) {
+            Ok(target) => target,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => codegen::target::Target::host(),
+    };
+
+    let log_level = match codegen::LogLevel::from_name(&flags.log_level) {
+        Ok(level) => level,
         Err(e) => {
-            eprintln!("Lexer error: {}", e);
+            eprintln!("Error: {}", e);
             std::process::exit(1);
         }
     };
 
-    // Parser
-    let mut parser = parser::Parser::new(tokens);
-    let program = match parser.parse() {
-        Ok(program) => {
-            if cli.print_ast {
-                println!("=== AST ===");
-                println!("{:#?}", program);
-                println!();
+    codegen::CompileOptions {
+        optimization: flags.optimization,
+        target,
+        max_inline_depth: flags.max_inline_depth,
+        log_level,
+        optimize_ast: !flags.no_optimize_ast,
+        entry: flags.entry.clone(),
+        cc: flags
+            .cc
+            .clone()
+            .or_else(|| std::env::var("CC").ok().filter(|cc| !cc.is_empty()))
+            .unwrap_or_else(|| "gcc".to_string()),
+        cflags: flags.cflags.clone(),
+        strict_mutability: flags.strict_mutability,
+        checked: flags.checked,
+        keep_c: false,
+    }
+}
+
+fn parse_defines(flags: &CompileFlags) -> HashMap<String, ast::Expression> {
+    let mut defines = HashMap::new();
+    for spec in &flags.defines {
+        match parser::parse_define(spec) {
+            Ok((name, value)) => {
+                defines.insert(name, value);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
             }
-            program
         }
+    }
+    defines
+}
+
+/// Read, parse and check one source file, exiting on any error
+fn load_program(cli: &CompileFlags, path: &Path, options: &hilow::Options) -> ast::Program {
+    // Read input file
+    let source = match read_source(path, &mut std::io::stdin()) {
+        Ok(content) => content,
         Err(e) => {
-            eprintln!("Parser error: {}", e);
+            eprintln!("Error reading file: {}", e);
             std::process::exit(1);
         }
     };
 
-    // Determine output path
-    let output_path = cli.output.unwrap_or_else(|| {
-        let mut path = cli.input.clone();
-        path.set_extension("");
-        path
+    if cli.print_tokens {
+        if let Ok(tokens) = lexer::Lexer::new(&source).tokenize() {
+            println!("=== TOKENS ===");
+            for token in &tokens {
+                println!("{}", token);
+            }
+            println!();
+        }
+    }
+
+    let parse_options = hilow::Options {
+        source_name: (!is_stdin(path)).then(|| path.display().to_string()),
+        ..options.clone()
+    };
+    let mut program = hilow::parse_source(&source, &parse_options).unwrap_or_else(|errors| exit_with(&errors, &source));
+    if cli.print_ast {
+        println!("=== AST ===");
+        println!("{:#?}", program);
+        println!();
+    }
+
+    let warnings = hilow::check_program(&mut program, path, options).unwrap_or_else(|errors| exit_with(&errors, &source));
+    if !cli.allow_infinite_loops {
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
+    program
+}
+
+fn exit_with(errors: &[CompileError], source: &str) -> ! {
+    for error in errors {
+        eprintln!("{}", render_error(error, source));
+    }
+    std::process::exit(1);
+}
+
+const TAB_WIDTH: usize = 4;
+
+/// The error message followed, when it has a position, by the source line
+/// it points into with a `^` under the column
+fn render_error(error: &CompileError, source: &str) -> String {
+    let message = error.to_string();
+    let Some(span) = error.span else {
+        return message;
+    };
+    let Some(text) = source.lines().nth(span.line.saturating_sub(1)) else {
+        return message;
+    };
+
+    // Tabs are shown as spaces so the caret lines up however the
+    // terminal would have drawn them; a column past the end points just
+    // after the last character
+    let shown = text.replace('\t', &" ".repeat(TAB_WIDTH));
+    let offset: usize = text
+        .chars()
+        .take(span.column.saturating_sub(1))
+        .map(|c| if c == '\t' { TAB_WIDTH } else { 1 })
+        .sum();
+
+    let gutter = " ".repeat(span.line.to_string().len());
+    format!(
+        "{}\n{} |\n{} | {}\n{} | {}^",
+        message,
+        gutter,
+        span.line,
+        shown,
+        gutter,
+        " ".repeat(offset)
+    )
+}
+
+/// Compile the input and any extra modules to object files and bundle
+/// them into a static archive
+fn build_static_lib(cli: &BuildArgs, input: &Path, options: &hilow::Options) {
+    let archive_path = cli.output.clone().unwrap_or_else(|| {
+        let stem = input.file_stem().unwrap_or_default().to_string_lossy();
+        input.with_file_name(format!("lib{}.a", stem))
     });
 
-    // Code generation
-    let result = codegen::compile(&program, output_path.to_str().unwrap(), cli.optimization);
+    // Library modules have no entry point
+    let options = hilow::Options {
+        codegen: codegen::CompileOptions {
+            entry: "main".to_string(),
+            ..options.codegen.clone()
+        },
+        ..options.clone()
+    };
+
+    let mut objects = Vec::new();
+    for path in std::iter::once(input).chain(cli.modules.iter().map(PathBuf::as_path)) {
+        let mut program = load_program(&cli.flags, path, &options);
+        optimize::optimize(&mut program, &options.codegen);
+
+        let object_path = path.with_extension("o");
+        if let Err(e) = codegen::compile_object(&program, object_path.to_str().unwrap(), &options.codegen) {
+            eprintln!("Code generation error in {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+        objects.push(object_path);
+    }
+
+    let result = codegen::create_archive(&objects, &archive_path);
+    for object in &objects {
+        fs::remove_file(object).ok();
+    }
 
     match result {
         Ok(_) => {
-            println!("Compilation successful: {}", output_path.display());
+            println!("Archive created: {}", archive_path.display());
         }
         Err(e) => {
-            eprintln!("Code generation error: {}", e);
+            eprintln!("Archive error: {}", e);
             std::process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_program_forwards_args_and_output() {
+        let source = "function main(): i32 {
+            let given: [string] = args();
+            println(f\"hello {given[0]}\");
+            return 0;
+        }";
+        let tokens = lexer::Lexer::new(source).tokenize().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+
+        let output = run_program(
+            &program,
+            &["world".to_string()],
+            &codegen::CompileOptions::default(),
+            |mut command| command.output(),
+        )
+        .unwrap();
+
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "hello world\n");
+        assert_eq!(output.status.code(), Some(0));
+    }
+
+    #[test]
+    fn test_render_error_points_at_column() {
+        let source = "function main(): i32 {\n  let x = (1 + ;\n  return 0;\n}";
+        let errors = hilow::parse_source(source, &hilow::Options::default()).unwrap_err();
+        assert_eq!(
+            render_error(&errors[0], source),
+            "Parser error: Unexpected token: Semicolon at 2:16\n  |\n2 |   let x = (1 + ;\n  |                ^"
+        );
+
+        let source = "function main(): i32 {\n\treturn 1 @ 2;\n}";
+        let errors = hilow::parse_source(source, &hilow::Options::default()).unwrap_err();
+        let rendered = render_error(&errors[0], source);
+        assert!(rendered.ends_with("2 |     return 1 @ 2;\n  |              ^"), "{}", rendered);
+    }
+
+    #[test]
+    fn test_render_error_out_of_range() {
+        let error = CompileError::at(hilow::Stage::Parser, "Unexpected end", 1, 40);
+        assert_eq!(render_error(&error, "let x"), "Parser error: Unexpected end\n  |\n1 | let x\n  |      ^");
+
+        let error = CompileError::at(hilow::Stage::Parser, "Unexpected end", 9, 1);
+        assert_eq!(render_error(&error, "let x"), "Parser error: Unexpected end");
+
+        let error = CompileError::new(hilow::Stage::Semantic, "Unknown identifier `x`");
+        assert_eq!(render_error(&error, "let x"), "Semantic error: Unknown identifier `x`");
+    }
+
+    #[test]
+    fn test_source_from_stdin() {
+        let mut stdin = std::io::Cursor::new("function main(): i32 { return 7; }");
+        let source = read_source(Path::new("-"), &mut stdin).unwrap();
+        let tokens = lexer::Lexer::new(&source).tokenize().unwrap();
+        let program = parser::Parser::new(tokens).parse().unwrap();
+
+        let output = default_output_path(Path::new("-"));
+        assert_eq!(output, PathBuf::from("a.out"));
+        assert_eq!(default_output_path(Path::new("src/prog.hl")), PathBuf::from("src/prog"));
+
+        let dir = std::env::temp_dir().join(format!("hilowc-stdin-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let binary = dir.join(output);
+        codegen::compile(&program, binary.to_str().unwrap(), &codegen::CompileOptions::default()).unwrap();
+        let status = std::process::Command::new(&binary).status().unwrap();
+        assert_eq!(status.code(), Some(7));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
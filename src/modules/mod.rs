@@ -0,0 +1,709 @@
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Finds the file behind an `import ... from "module"` path
+#[derive(Debug, Clone, Default)]
+pub struct ModuleResolver {
+    /// Extra directories searched, in order, after the importer's own directory
+    pub search_paths: Vec<PathBuf>,
+}
+
+impl ModuleResolver {
+    pub fn new(search_paths: Vec<PathBuf>) -> Self {
+        ModuleResolver { search_paths }
+    }
+
+    /// Resolve `module` as imported from the file `importer`. The importer's
+    /// directory is tried first, then each search path; `.hl` is added when
+    /// the module path has no extension.
+    pub fn resolve(&self, importer: &Path, module: &str) -> Result<PathBuf, String> {
+        let mut relative = PathBuf::from(module);
+        if relative.extension().is_none() {
+            relative.set_extension("hl");
+        }
+
+        let importer_dir = importer.parent().unwrap_or_else(|| Path::new(""));
+        let mut tried = Vec::new();
+
+        for dir in std::iter::once(importer_dir).chain(self.search_paths.iter().map(|p| p.as_path())) {
+            let candidate = dir.join(&relative);
+            if candidate.is_file() {
+                return Ok(candidate);
+            }
+            tried.push(candidate.display().to_string());
+        }
+
+        Err(format!(
+            "Cannot find module '{}' imported from {} (tried: {})",
+            module,
+            importer.display(),
+            tried.join(", ")
+        ))
+    }
+}
+
+/// Replace each `import` in `program` with the imported declarations and
+/// whatever they depend on, leaving the rest of the module out. Imported
+/// modules have their own imports loaded first; an import cycle is an error.
+///
+/// Only exported declarations can be imported. The declarations they depend
+/// on come along under a module-private name, so they can't be referenced
+/// from the importer or clash with its own declarations.
+///
+/// `parse_module` reads and parses a resolved module path.
+pub fn load_imports(
+    program: &mut Program,
+    importer: &Path,
+    resolver: &ModuleResolver,
+    parse_module: &dyn Fn(&Path) -> Result<Program, String>,
+) -> Result<(), String> {
+    let mut loading = vec![canonical(importer)];
+    load_imports_from(program, importer, resolver, parse_module, &mut loading)
+}
+
+// `loading` holds the files whose imports are being loaded, outermost first
+fn load_imports_from(
+    program: &mut Program,
+    importer: &Path,
+    resolver: &ModuleResolver,
+    parse_module: &dyn Fn(&Path) -> Result<Program, String>,
+    loading: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    let declared: HashSet<String> = program.statements.iter().filter_map(declared_name).map(str::to_string).collect();
+
+    // Imported declarations take the position of their `import`
+    let import_spans = std::mem::take(&mut program.spans);
+    let mut statements: Vec<Statement> = Vec::new();
+    let mut spans = Vec::new();
+    for (i, stmt) in program.statements.drain(..).enumerate() {
+        let span = import_spans.get(i);
+        if let Statement::Import { names, module } = &stmt {
+            let path = resolver.resolve(importer, module)?;
+            let key = canonical(&path);
+            if let Some(start) = loading.iter().position(|p| *p == key) {
+                let cycle: Vec<String> = loading[start..].iter().chain([&key]).map(|p| p.display().to_string()).collect();
+                return Err(format!("Import cycle: {}", cycle.join(" -> ")));
+            }
+
+            let mut module_program = parse_module(&path)?;
+            loading.push(key);
+            load_imports_from(&mut module_program, &path, resolver, parse_module, loading)?;
+            loading.pop();
+
+            let declarations = import_declarations(&module_program, &module_prefix(&path), names)
+                .map_err(|e| format!("{} ({})", e, path.display()))?;
+            for decl in declarations {
+                if let Some(name) = declared_name(&decl) {
+                    if declared.contains(name) {
+                        return Err(format!(
+                            "`{}` from module '{}' conflicts with a declaration in {}",
+                            name,
+                            module,
+                            importer.display()
+                        ));
+                    }
+                }
+                // The same declaration can arrive through several imports
+                if !statements.contains(&decl) {
+                    statements.push(decl);
+                    spans.extend(span);
+                }
+            }
+        }
+        statements.push(stmt);
+        spans.extend(span);
+    }
+
+    program.statements = statements;
+    program.spans = spans;
+    Ok(())
+}
+
+// The declarations an import of `names` brings in: the named ones keep
+// their names, and everything else they need is renamed to
+// `<prefix><name>`. Names already starting with `__` came from a deeper
+// module (or are compiler-reserved) and keep theirs. The copies are never
+// exported again by the importer.
+fn import_declarations(module: &Program, prefix: &str, names: &[String]) -> Result<Vec<Statement>, String> {
+    for name in names {
+        let exported = module.statements.iter().any(|stmt| is_exported(stmt) && declared_name(stmt) == Some(name.as_str()));
+        if !exported && module.statements.iter().any(|stmt| declared_name(stmt) == Some(name.as_str())) {
+            return Err(format!("`{}` is not exported", name));
+        }
+    }
+
+    let mut declarations = tree_shake(module, names)?;
+    let renames: HashMap<String, String> = declarations
+        .iter()
+        .filter_map(declared_name)
+        .filter(|name| !names.iter().any(|n| n == name) && !name.starts_with("__"))
+        .map(|name| (name.to_string(), format!("{}{}", prefix, name)))
+        .collect();
+
+    let mut renamer = Renamer { renames: &renames, shadowed: Vec::new() };
+    for decl in &mut declarations {
+        match decl {
+            Statement::FunctionDecl { name, is_export, .. }
+            | Statement::VariableDecl { name, is_export, .. }
+            | Statement::StructDecl { name, is_export, .. }
+            | Statement::EnumDecl { name, is_export, .. } => {
+                if let Some(renamed) = renames.get(name.as_str()) {
+                    *name = renamed.clone();
+                }
+                *is_export = false;
+            }
+            _ => {}
+        }
+        renamer.rename_statement(decl);
+    }
+    Ok(declarations)
+}
+
+// `__<file stem>_`, with anything that can't appear in a C identifier
+// replaced by `_`
+fn module_prefix(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let stem: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("__{}_", stem)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The top-level declarations of `module` needed by `names`: the named
+/// declarations plus every declaration they reach, in module order
+pub fn tree_shake(module: &Program, names: &[String]) -> Result<Vec<Statement>, String> {
+    let declarations: HashMap<&str, &Statement> = module
+        .statements
+        .iter()
+        .filter_map(|stmt| declared_name(stmt).map(|name| (name, stmt)))
+        .collect();
+
+    let mut needed = HashSet::new();
+    let mut pending: Vec<&str> = Vec::new();
+    for name in names {
+        if !declarations.contains_key(name.as_str()) {
+            return Err(format!("Module does not declare `{}`", name));
+        }
+        pending.push(name);
+    }
+
+    while let Some(name) = pending.pop() {
+        if !needed.insert(name) {
+            continue;
+        }
+        let mut references = ReferenceCollector::default();
+        references.visit_statement(declarations[name]);
+        pending.extend(references.names.into_iter().filter(|n| declarations.contains_key(n)));
+    }
+
+    Ok(module
+        .statements
+        .iter()
+        .filter(|stmt| declared_name(stmt).is_some_and(|name| needed.contains(name)))
+        .cloned()
+        .collect())
+}
+
+fn declared_name(stmt: &Statement) -> Option<&str> {
+    match stmt {
+        Statement::FunctionDecl { name, .. }
+        | Statement::VariableDecl { name, .. }
+        | Statement::StructDecl { name, .. }
+        | Statement::EnumDecl { name, .. } => Some(name),
+        _ => None,
+    }
+}
+
+fn is_exported(stmt: &Statement) -> bool {
+    matches!(
+        stmt,
+        Statement::FunctionDecl { is_export: true, .. }
+            | Statement::VariableDecl { is_export: true, .. }
+            | Statement::StructDecl { is_export: true, .. }
+            | Statement::EnumDecl { is_export: true, .. }
+    )
+}
+
+// Every identifier mentioned, including callees, and every type named
+#[derive(Default)]
+struct ReferenceCollector<'ast> {
+    names: Vec<&'ast str>,
+}
+
+impl<'ast> Visitor<'ast> for ReferenceCollector<'ast> {
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        if let Expression::Identifier(name) = expr {
+            self.names.push(name);
+        }
+        walk_expression(self, expr);
+    }
+
+    fn visit_type(&mut self, ty: &'ast Type) {
+        match ty {
+            Type::Named(name) => self.names.push(name),
+            Type::Array { element_type, .. } => self.visit_type(element_type),
+            Type::Function { params, return_type } => {
+                for param in params {
+                    self.visit_type(param);
+                }
+                self.visit_type(return_type);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Renames references to top-level declarations, leaving alone any that a
+// local binding of the same name shadows
+struct Renamer<'a> {
+    renames: &'a HashMap<String, String>,
+    // Names bound by enclosing functions, blocks and loops, innermost last
+    shadowed: Vec<HashSet<String>>,
+}
+
+impl Renamer<'_> {
+    fn in_scope(&mut self, bound: impl IntoIterator<Item = String>, f: impl FnOnce(&mut Self)) {
+        self.shadowed.push(bound.into_iter().collect());
+        f(self);
+        self.shadowed.pop();
+    }
+
+    fn bind(&mut self, name: &str) {
+        // Top-level declarations are the ones being renamed, not shadows
+        if let Some(scope) = self.shadowed.last_mut() {
+            scope.insert(name.to_string());
+        }
+    }
+
+    fn rename_block(&mut self, block: &mut Block) {
+        self.in_scope([], |renamer| {
+            for stmt in &mut block.statements {
+                renamer.rename_statement(stmt);
+            }
+        });
+    }
+
+    // Types can't be shadowed, so a renamed one is renamed everywhere
+    fn rename_type(&self, ty: &mut Type) {
+        match ty {
+            Type::Named(name) => {
+                if let Some(renamed) = self.renames.get(name.as_str()) {
+                    *name = renamed.clone();
+                }
+            }
+            Type::Array { element_type, .. } => self.rename_type(element_type),
+            Type::Function { params, return_type } => {
+                for param in params {
+                    self.rename_type(param);
+                }
+                self.rename_type(return_type);
+            }
+            _ => {}
+        }
+    }
+
+    fn rename_params(&self, params: &mut [Parameter], return_type: &mut Option<Type>) {
+        for param in params {
+            self.rename_type(&mut param.param_type);
+        }
+        if let Some(ty) = return_type {
+            self.rename_type(ty);
+        }
+    }
+
+    fn rename_statement(&mut self, stmt: &mut Statement) {
+        match stmt {
+            Statement::FunctionDecl { params, return_type, requires, ensures, body, .. } => {
+                self.rename_params(params, return_type);
+                let bound = params.iter().map(|p| p.name.clone()).chain(["result".to_string()]);
+                self.in_scope(bound, |renamer| {
+                    for condition in requires.iter_mut().chain(ensures) {
+                        renamer.rename_expression(condition);
+                    }
+                    renamer.rename_block(body);
+                });
+            }
+            Statement::VariableDecl { name, var_type, initializer, .. } => {
+                if let Some(ty) = var_type {
+                    self.rename_type(ty);
+                }
+                if let Some(init) = initializer {
+                    self.rename_expression(init);
+                }
+                self.bind(name);
+            }
+            Statement::Return { value: Some(expr) } | Statement::Expression(expr) => self.rename_expression(expr),
+            Statement::If { condition, then_branch, else_branch } => {
+                self.rename_expression(condition);
+                self.rename_block(then_branch);
+                if let Some(else_stmt) = else_branch {
+                    self.rename_statement(else_stmt);
+                }
+            }
+            Statement::While { condition, body } => {
+                self.rename_expression(condition);
+                self.rename_block(body);
+            }
+            Statement::For { init, condition, increment, body } => self.in_scope([], |renamer| {
+                if let Some(init_stmt) = init {
+                    renamer.rename_statement(init_stmt);
+                }
+                if let Some(cond) = condition {
+                    renamer.rename_expression(cond);
+                }
+                if let Some(inc) = increment {
+                    renamer.rename_expression(inc);
+                }
+                renamer.rename_block(body);
+            }),
+            Statement::ForIn { variable, iterable, body } => {
+                self.rename_expression(iterable);
+                self.in_scope([variable.clone()], |renamer| renamer.rename_block(body));
+            }
+            Statement::Switch { expr, cases, default } => {
+                self.rename_expression(expr);
+                for case in cases {
+                    self.rename_expression(&mut case.value);
+                    self.rename_block(&mut case.body);
+                }
+                if let Some(block) = default {
+                    self.rename_block(block);
+                }
+            }
+            Statement::Defer { statement } => self.rename_statement(statement),
+            Statement::Block(block) => self.rename_block(block),
+            Statement::StructDecl { fields, methods, .. } => {
+                self.rename_params(fields, &mut None);
+                for method in methods {
+                    self.rename_statement(method);
+                }
+            }
+            Statement::Return { value: None }
+            | Statement::Import { .. }
+            | Statement::Break
+            | Statement::Continue
+            | Statement::EnumDecl { .. } => {}
+        }
+    }
+
+    fn rename_expression(&mut self, expr: &mut Expression) {
+        match expr {
+            Expression::Identifier(name) => {
+                if !self.shadowed.iter().any(|scope| scope.contains(name.as_str())) {
+                    if let Some(renamed) = self.renames.get(name.as_str()) {
+                        *name = renamed.clone();
+                    }
+                }
+            }
+            Expression::FString { parts } => {
+                for part in parts {
+                    if let FStringPart::Expression(e, _) = part {
+                        self.rename_expression(e);
+                    }
+                }
+            }
+            Expression::Binary { left, right, .. } => {
+                self.rename_expression(left);
+                self.rename_expression(right);
+            }
+            Expression::Unary { operand: inner, .. } | Expression::PropertyAccess { object: inner, .. } => {
+                self.rename_expression(inner)
+            }
+            Expression::Cast { expr: inner, target_type } => {
+                self.rename_type(target_type);
+                self.rename_expression(inner);
+            }
+            Expression::Call { callee, args, .. } => {
+                self.rename_expression(callee);
+                for arg in args {
+                    self.rename_expression(arg);
+                }
+            }
+            Expression::Assignment { target: first, value: second }
+            | Expression::ArrayRepeat { value: first, count: second }
+            | Expression::Index { array: first, index: second }
+            | Expression::Range { start: first, end: second, .. } => {
+                self.rename_expression(first);
+                self.rename_expression(second);
+            }
+            Expression::Conditional { condition, then, otherwise } => {
+                self.rename_expression(condition);
+                self.rename_expression(then);
+                self.rename_expression(otherwise);
+            }
+            Expression::ArrayLiteral { elements } => {
+                for elem in elements {
+                    self.rename_expression(elem);
+                }
+            }
+            Expression::ObjectLiteral { properties } => {
+                for prop in properties {
+                    self.rename_expression(&mut prop.value);
+                }
+            }
+            Expression::MethodCall { object, args, .. } => {
+                self.rename_expression(object);
+                for arg in args {
+                    self.rename_expression(arg);
+                }
+            }
+            Expression::FunctionExpression { params, return_type, body } => {
+                self.rename_params(params, return_type);
+                let bound: Vec<String> = params.iter().map(|p| p.name.clone()).collect();
+                self.in_scope(bound, |renamer| renamer.rename_block(body));
+            }
+            Expression::Match { expr, arms } => {
+                self.rename_expression(expr);
+                for arm in arms {
+                    match &mut arm.pattern {
+                        MatchPattern::Literal(pattern) => self.rename_expression(pattern),
+                        MatchPattern::Binding(name) => {
+                            let bound = [name.clone()];
+                            self.in_scope(bound, |renamer| renamer.rename_expression(&mut arm.body));
+                            continue;
+                        }
+                        MatchPattern::Wildcard => {}
+                    }
+                    self.rename_expression(&mut arm.body);
+                }
+            }
+            // The block's bindings are visible in its value
+            Expression::Block { body, value } => self.in_scope([], |renamer| {
+                for stmt in &mut body.statements {
+                    renamer.rename_statement(stmt);
+                }
+                renamer.rename_expression(value);
+            }),
+            Expression::IntegerLiteral(_)
+            | Expression::FloatLiteral(_)
+            | Expression::StringLiteral(_)
+            | Expression::BooleanLiteral(_)
+            | Expression::CharLiteral(_)
+            | Expression::NothingLiteral => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::{CodeGenerator, CompileOptions};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::fs;
+
+    fn parse(source: &str) -> Program {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hilowc-modules-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_resolve_via_search_path() {
+        let root = scratch_dir("search");
+        let project = root.join("project");
+        let shared = root.join("shared");
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&shared).unwrap();
+        fs::write(shared.join("mathlib.hl"), "export function square(x: i32): i32 { return x * x; }").unwrap();
+        let importer = project.join("main.hl");
+
+        let resolver = ModuleResolver::new(vec![shared.clone()]);
+        assert_eq!(resolver.resolve(&importer, "mathlib").unwrap(), shared.join("mathlib.hl"));
+
+        // The importer's own directory takes precedence
+        fs::write(project.join("mathlib.hl"), "").unwrap();
+        assert_eq!(resolver.resolve(&importer, "mathlib.hl").unwrap(), project.join("mathlib.hl"));
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_unresolved_module_lists_tried_paths() {
+        let root = scratch_dir("missing");
+        let resolver = ModuleResolver::new(vec![root.join("a"), root.join("b")]);
+
+        let err = resolver.resolve(&root.join("main.hl"), "nowhere").unwrap_err();
+        assert!(err.contains(&root.join("nowhere.hl").display().to_string()), "{}", err);
+        assert!(err.contains(&root.join("a").join("nowhere.hl").display().to_string()), "{}", err);
+        assert!(err.contains(&root.join("b").join("nowhere.hl").display().to_string()), "{}", err);
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_import_includes_only_reachable_declarations() {
+        let root = scratch_dir("shake");
+        fs::write(
+            root.join("mathlib.hl"),
+            "let SCALE: i32 = 2;
+            function scale(x: i32): i32 { return x * SCALE; }
+            export function square(x: i32): i32 { return scale(x) * x; }
+            export function cube(x: i32): i32 { return x * x * x; }",
+        )
+        .unwrap();
+
+        let mut program = parse(
+            "import { square } from \"mathlib\";
+            function main(): i32 { return square(3); }",
+        );
+        let parse_module = |path: &Path| Ok(parse(&fs::read_to_string(path).unwrap()));
+        load_imports(&mut program, &root.join("main.hl"), &ModuleResolver::default(), &parse_module).unwrap();
+
+        let c_code = CodeGenerator::new(CompileOptions::default()).generate(&program).unwrap();
+        assert!(c_code.contains("int32_t square(int32_t x) {"), "{}", c_code);
+        assert!(c_code.contains("int32_t __mathlib_scale(int32_t x) {"), "{}", c_code);
+        assert!(c_code.contains("int32_t __mathlib_SCALE = 2;"), "{}", c_code);
+        assert!(!c_code.contains("cube"), "{}", c_code);
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_program_spans_files() {
+        let root = scratch_dir("e2e");
+        fs::write(
+            root.join("mathlib.hl"),
+            "import { twice } from \"helpers\";
+            function offset(x: i32): i32 { return x + 1; }
+            export function square(x: i32): i32 { return twice(x) * x / 2; }
+            export function shifted(x: i32): i32 { let offset: i32 = x; return offset + 10; }",
+        )
+        .unwrap();
+        fs::write(root.join("helpers.hl"), "export function twice(x: i32): i32 { return x * 2; }").unwrap();
+        let main_path = root.join("main.hl");
+        fs::write(
+            &main_path,
+            "import { square, shifted } from \"mathlib\";
+            import { twice } from \"helpers\";
+            function offset(x: i32): i32 { return x - 1; }
+            function main(): i32 { return square(5) + offset(twice(shifted(0))); }",
+        )
+        .unwrap();
+
+        let mut program = parse(&fs::read_to_string(&main_path).unwrap());
+        let parse_module = |path: &Path| Ok(parse(&fs::read_to_string(path).unwrap()));
+        load_imports(&mut program, &main_path, &ModuleResolver::default(), &parse_module).unwrap();
+        crate::sema::check_names(&program).unwrap();
+
+        let binary = root.join("main");
+        crate::codegen::compile(&program, binary.to_str().unwrap(), &CompileOptions::default()).unwrap();
+        let status = std::process::Command::new(&binary).status().unwrap();
+        // square(5) = 25, offset(twice(10)) = 19
+        assert_eq!(status.code(), Some(25 + 19));
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_import_types() {
+        let root = scratch_dir("types");
+        fs::write(
+            root.join("geo.hl"),
+            "struct Corner { x: i32, y: i32 }
+            export enum Kind { Square, Wide }
+            export struct Rect {
+                width: i32,
+                height: i32,
+                function area(): i32 { return self.width * self.height; }
+                function corner(): Corner { let c: Corner = { x: self.width, y: self.height }; return c; }
+            }
+            export function kind_of(r: Rect): Kind {
+                if (r.width ?= r.height) { return Kind.Square; }
+                return Kind.Wide;
+            }",
+        )
+        .unwrap();
+        let main_path = root.join("main.hl");
+        fs::write(
+            &main_path,
+            "import { Rect, Kind, kind_of } from \"geo\";
+            function main(): i32 {
+                let r: Rect = { width: 3, height: 4 };
+                let k: Kind = kind_of(r);
+                if (k ?= Kind.Wide) { return r.area() + r.corner().x; }
+                return 0;
+            }",
+        )
+        .unwrap();
+
+        let mut program = parse(&fs::read_to_string(&main_path).unwrap());
+        let parse_module = |path: &Path| Ok(parse(&fs::read_to_string(path).unwrap()));
+        load_imports(&mut program, &main_path, &ModuleResolver::default(), &parse_module).unwrap();
+        crate::sema::check_names(&program).unwrap();
+
+        let c_code = CodeGenerator::new(CompileOptions::default()).generate(&program).unwrap();
+        assert!(c_code.contains("} Rect;"), "{}", c_code);
+        assert!(c_code.contains("} __geo_Corner;"), "{}", c_code);
+        assert!(c_code.contains("typedef enum { Kind_Square, Kind_Wide } Kind;"), "{}", c_code);
+
+        let binary = root.join("main");
+        crate::codegen::compile(&program, binary.to_str().unwrap(), &CompileOptions::default()).unwrap();
+        let status = std::process::Command::new(&binary).status().unwrap();
+        assert_eq!(status.code(), Some(12 + 3));
+
+        // The private type doesn't come along under its own name
+        let mut program = parse("import { Corner } from \"geo\";");
+        let err = load_imports(&mut program, &main_path, &ModuleResolver::default(), &parse_module).unwrap_err();
+        assert!(err.starts_with("`Corner` is not exported"), "{}", err);
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_private_declarations_stay_private() {
+        let root = scratch_dir("private");
+        fs::write(
+            root.join("mathlib.hl"),
+            "function scale(x: i32): i32 { return x * 2; }
+            export function square(x: i32): i32 { return scale(x) * x; }",
+        )
+        .unwrap();
+        let parse_module = |path: &Path| Ok(parse(&fs::read_to_string(path).unwrap()));
+        let load = |source: &str| {
+            let mut program = parse(source);
+            load_imports(&mut program, &root.join("main.hl"), &ModuleResolver::default(), &parse_module).map(|_| program)
+        };
+
+        let err = load("import { scale } from \"mathlib\";").unwrap_err();
+        assert!(err.starts_with("`scale` is not exported"), "{}", err);
+
+        // A dependency comes along, but not under a name the importer can use
+        let program = load("import { square } from \"mathlib\"; function main(): i32 { let f = scale; return 0; }").unwrap();
+        let err = crate::sema::check_names(&program).unwrap_err();
+        assert!(err[0].message.starts_with("Unknown identifier `scale`"), "{}", err[0]);
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_import_cycle_is_reported() {
+        let root = scratch_dir("cycle");
+        fs::write(root.join("a.hl"), "import { b } from \"b\"; export function a(): i32 { return b(); }").unwrap();
+        fs::write(root.join("b.hl"), "import { a } from \"a\"; export function b(): i32 { return a(); }").unwrap();
+
+        let mut program = parse("import { a } from \"a\";");
+        let parse_module = |path: &Path| Ok(parse(&fs::read_to_string(path).unwrap()));
+        let err = load_imports(&mut program, &root.join("main.hl"), &ModuleResolver::default(), &parse_module)
+            .unwrap_err();
+        assert!(err.starts_with("Import cycle: "), "{}", err);
+        assert!(err.contains("b.hl -> ") && err.ends_with("a.hl"), "{}", err);
+
+        fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_import_of_undeclared_name() {
+        let module = parse("export function square(x: i32): i32 { return x * x; }");
+        let err = tree_shake(&module, &["cube".to_string()]).unwrap_err();
+        assert_eq!(err, "Module does not declare `cube`");
+    }
+}
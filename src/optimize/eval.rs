@@ -0,0 +1,345 @@
+//! A small interpreter for the integer/boolean subset of pure functions,
+//! used to fold calls with constant arguments at compile time.
+//!
+//! Anything outside the subset, or anything C would treat differently
+//! (overflow, division by zero, a value that doesn't fit its declared
+//! type), makes evaluation give up so the call is left for runtime.
+
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// Statements executed before a call is considered too expensive to fold
+const MAX_STEPS: usize = 100_000;
+
+/// Nested pure calls before a call is considered too deep to fold
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool),
+}
+
+impl Value {
+    pub fn to_expression(self) -> Expression {
+        match self {
+            Value::Int(n) => Expression::IntegerLiteral(n),
+            Value::Bool(b) => Expression::BooleanLiteral(b),
+        }
+    }
+
+    fn from_expression(expr: &Expression) -> Option<Value> {
+        match expr {
+            Expression::IntegerLiteral(n) => Some(Value::Int(*n)),
+            Expression::BooleanLiteral(b) => Some(Value::Bool(*b)),
+            _ => None,
+        }
+    }
+
+    fn as_int(self) -> Option<i64> {
+        match self {
+            Value::Int(n) => Some(n),
+            Value::Bool(_) => None,
+        }
+    }
+
+    pub fn is_truthy(self) -> bool {
+        match self {
+            Value::Int(n) => n != 0,
+            Value::Bool(b) => b,
+        }
+    }
+}
+
+pub struct PureFunction<'a> {
+    pub params: &'a [Parameter],
+    pub return_type: &'a Type,
+    pub body: &'a Block,
+}
+
+enum Flow {
+    Normal,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+pub struct Evaluator<'a> {
+    functions: &'a HashMap<String, PureFunction<'a>>,
+    steps: usize,
+    depth: usize,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(functions: &'a HashMap<String, PureFunction<'a>>) -> Self {
+        Evaluator { functions, steps: 0, depth: 0 }
+    }
+
+    /// Evaluate an expression that refers to no variables
+    pub fn constant(&mut self, expr: &'a Expression) -> Option<Value> {
+        self.expression(expr, &mut Frame::default())
+    }
+
+    /// Evaluate `name(args)` if every argument is a literal and the call
+    /// stays within the supported subset
+    pub fn call(&mut self, name: &str, args: &[Expression]) -> Option<Value> {
+        let args = args.iter().map(Value::from_expression).collect::<Option<Vec<_>>>()?;
+        self.call_values(name, &args)
+    }
+
+    fn call_values(&mut self, name: &str, args: &[Value]) -> Option<Value> {
+        let function = self.functions.get(name)?;
+        if function.params.len() != args.len() || self.depth >= MAX_DEPTH {
+            return None;
+        }
+
+        let mut frame = Frame::default();
+        for (param, value) in function.params.iter().zip(args) {
+            frame.declare(&param.name, Some(&param.param_type), *value)?;
+        }
+
+        self.depth += 1;
+        let flow = self.block(function.body, &mut frame);
+        self.depth -= 1;
+
+        match flow? {
+            Flow::Return(value) if fits(value, function.return_type) => Some(value),
+            _ => None,
+        }
+    }
+
+    fn block(&mut self, block: &'a Block, frame: &mut Frame<'a>) -> Option<Flow> {
+        frame.scopes.push(HashMap::new());
+        let mut flow = Some(Flow::Normal);
+        for stmt in &block.statements {
+            flow = self.statement(stmt, frame);
+            if !matches!(flow, Some(Flow::Normal)) {
+                break;
+            }
+        }
+        frame.scopes.pop();
+        flow
+    }
+
+    fn statement(&mut self, stmt: &'a Statement, frame: &mut Frame<'a>) -> Option<Flow> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return None;
+        }
+
+        match stmt {
+            Statement::VariableDecl { name, var_type, initializer: Some(init), .. } => {
+                let value = self.expression(init, frame)?;
+                frame.declare(name, var_type.as_ref(), value)?;
+                Some(Flow::Normal)
+            }
+            Statement::Expression(expr) => {
+                self.expression(expr, frame)?;
+                Some(Flow::Normal)
+            }
+            Statement::Return { value: Some(expr) } => Some(Flow::Return(self.expression(expr, frame)?)),
+            Statement::If { condition, then_branch, else_branch } => {
+                if self.expression(condition, frame)?.is_truthy() {
+                    self.block(then_branch, frame)
+                } else if let Some(else_branch) = else_branch {
+                    self.statement(else_branch, frame)
+                } else {
+                    Some(Flow::Normal)
+                }
+            }
+            Statement::While { condition, body } => {
+                while self.expression(condition, frame)?.is_truthy() {
+                    match self.block(body, frame)? {
+                        Flow::Break => break,
+                        Flow::Return(value) => return Some(Flow::Return(value)),
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    self.tick()?;
+                }
+                Some(Flow::Normal)
+            }
+            Statement::For { init, condition, increment, body } => {
+                frame.scopes.push(HashMap::new());
+                let flow = self.for_loop(init.as_deref(), condition.as_ref(), increment.as_ref(), body, frame);
+                frame.scopes.pop();
+                flow
+            }
+            Statement::Block(block) => self.block(block, frame),
+            Statement::Break => Some(Flow::Break),
+            Statement::Continue => Some(Flow::Continue),
+            _ => None,
+        }
+    }
+
+    fn for_loop(
+        &mut self,
+        init: Option<&'a Statement>,
+        condition: Option<&'a Expression>,
+        increment: Option<&'a Expression>,
+        body: &'a Block,
+        frame: &mut Frame<'a>,
+    ) -> Option<Flow> {
+        if let Some(init) = init {
+            self.statement(init, frame)?;
+        }
+        loop {
+            if let Some(condition) = condition {
+                if !self.expression(condition, frame)?.is_truthy() {
+                    break;
+                }
+            }
+            match self.block(body, frame)? {
+                Flow::Break => break,
+                Flow::Return(value) => return Some(Flow::Return(value)),
+                Flow::Normal | Flow::Continue => {}
+            }
+            if let Some(increment) = increment {
+                self.expression(increment, frame)?;
+            }
+            self.tick()?;
+        }
+        Some(Flow::Normal)
+    }
+
+    fn tick(&mut self) -> Option<()> {
+        self.steps += 1;
+        (self.steps <= MAX_STEPS).then_some(())
+    }
+
+    fn expression(&mut self, expr: &'a Expression, frame: &mut Frame<'a>) -> Option<Value> {
+        match expr {
+            Expression::IntegerLiteral(_) | Expression::BooleanLiteral(_) => Value::from_expression(expr),
+            Expression::Identifier(name) => frame.get(name),
+            Expression::Unary { op, operand } => {
+                let value = self.expression(operand, frame)?;
+                match op {
+                    UnaryOp::Negate => Some(Value::Int(value.as_int()?.checked_neg()?)),
+                    UnaryOp::Not => Some(Value::Bool(!value.is_truthy())),
+                    UnaryOp::BitwiseNot => Some(Value::Int(!value.as_int()?)),
+                }
+            }
+            Expression::Binary { left, op: BinaryOp::And, right } => {
+                let result = self.expression(left, frame)?.is_truthy() && self.expression(right, frame)?.is_truthy();
+                Some(Value::Bool(result))
+            }
+            Expression::Binary { left, op: BinaryOp::Or, right } => {
+                let result = self.expression(left, frame)?.is_truthy() || self.expression(right, frame)?.is_truthy();
+                Some(Value::Bool(result))
+            }
+            Expression::Binary { left, op, right } => {
+                let left = self.expression(left, frame)?;
+                let right = self.expression(right, frame)?;
+                binary(op, left, right)
+            }
+            Expression::Assignment { target, value } => {
+                let Expression::Identifier(name) = target.as_ref() else {
+                    return None;
+                };
+                let value = self.expression(value, frame)?;
+                frame.assign(name, value)?;
+                Some(value)
+            }
+            Expression::Call { callee, args, .. } => {
+                let Expression::Identifier(name) = callee.as_ref() else {
+                    return None;
+                };
+                let args = args.iter().map(|arg| self.expression(arg, frame)).collect::<Option<Vec<_>>>()?;
+                self.call_values(name, &args)
+            }
+            Expression::Cast { expr, target_type } => {
+                let value = self.expression(expr, frame)?;
+                fits(value, target_type).then_some(value)
+            }
+            Expression::Conditional { condition, then, otherwise } => {
+                if self.expression(condition, frame)?.is_truthy() {
+                    self.expression(then, frame)
+                } else {
+                    self.expression(otherwise, frame)
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn binary(op: &BinaryOp, left: Value, right: Value) -> Option<Value> {
+    if let (Value::Bool(l), Value::Bool(r)) = (left, right) {
+        return match op {
+            BinaryOp::Equal | BinaryOp::StrictEqual => Some(Value::Bool(l == r)),
+            BinaryOp::NotEqual | BinaryOp::StrictNotEqual => Some(Value::Bool(l != r)),
+            _ => None,
+        };
+    }
+
+    let (l, r) = (left.as_int()?, right.as_int()?);
+    let value = match op {
+        BinaryOp::Add => Value::Int(l.checked_add(r)?),
+        BinaryOp::Subtract => Value::Int(l.checked_sub(r)?),
+        BinaryOp::Multiply => Value::Int(l.checked_mul(r)?),
+        BinaryOp::Divide => Value::Int(l.checked_div(r)?),
+        BinaryOp::Modulo => Value::Int(l.checked_rem(r)?),
+        BinaryOp::Equal | BinaryOp::StrictEqual => Value::Bool(l == r),
+        BinaryOp::NotEqual | BinaryOp::StrictNotEqual => Value::Bool(l != r),
+        BinaryOp::Less => Value::Bool(l < r),
+        BinaryOp::LessEqual => Value::Bool(l <= r),
+        BinaryOp::Greater => Value::Bool(l > r),
+        BinaryOp::GreaterEqual => Value::Bool(l >= r),
+        BinaryOp::BitwiseAnd => Value::Int(l & r),
+        BinaryOp::BitwiseOr => Value::Int(l | r),
+        BinaryOp::BitwiseXor => Value::Int(l ^ r),
+        // Only shifts C defines for every integer width
+        BinaryOp::ShiftLeft if (0..31).contains(&r) && l >= 0 => Value::Int(l.checked_shl(r as u32)?),
+        BinaryOp::ShiftRight if (0..31).contains(&r) && l >= 0 => Value::Int(l >> r),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// Whether `value` is representable in `ty` without C converting it
+fn fits(value: Value, ty: &Type) -> bool {
+    let range = match ty {
+        Type::Bool => return matches!(value, Value::Bool(_)),
+        Type::I8 => (i8::MIN as i64, i8::MAX as i64),
+        Type::I16 => (i16::MIN as i64, i16::MAX as i64),
+        Type::I32 => (i32::MIN as i64, i32::MAX as i64),
+        Type::I64 | Type::I128 => (i64::MIN, i64::MAX),
+        Type::U8 => (0, u8::MAX as i64),
+        Type::U16 => (0, u16::MAX as i64),
+        Type::U32 => (0, u32::MAX as i64),
+        Type::U64 | Type::U128 => (0, i64::MAX),
+        _ => return false,
+    };
+    matches!(value, Value::Int(n) if n >= range.0 && n <= range.1)
+}
+
+// Local variables of one call, innermost scope last
+#[derive(Default)]
+struct Frame<'a> {
+    scopes: Vec<HashMap<String, (Option<&'a Type>, Value)>>,
+}
+
+impl<'a> Frame<'a> {
+    fn declare(&mut self, name: &str, ty: Option<&'a Type>, value: Value) -> Option<()> {
+        if ty.is_some_and(|ty| !fits(value, ty)) {
+            return None;
+        }
+        if self.scopes.is_empty() {
+            self.scopes.push(HashMap::new());
+        }
+        self.scopes.last_mut()?.insert(name.to_string(), (ty, value));
+        Some(())
+    }
+
+    fn get(&self, name: &str) -> Option<Value> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).map(|(_, value)| *value)
+    }
+
+    fn assign(&mut self, name: &str, value: Value) -> Option<()> {
+        let slot = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))?;
+        if slot.0.is_some_and(|ty| !fits(value, ty)) {
+            return None;
+        }
+        slot.1 = value;
+        Some(())
+    }
+}
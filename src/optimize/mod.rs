@@ -0,0 +1,673 @@
+mod eval;
+
+use crate::ast::*;
+use crate::codegen::CompileOptions;
+use eval::{Evaluator, PureFunction};
+use std::collections::HashMap;
+
+/// Largest function body, in expression nodes, that will be inlined
+const MAX_INLINE_SIZE: usize = 16;
+
+/// An AST pass and the lowest `-O` level it runs at
+struct Pass {
+    min_level: u8,
+    run: fn(&mut Program, &CompileOptions) -> usize,
+}
+
+/// Every AST pass, in the order they run. Folding comes first so that
+/// inlining sees calls that are already reduced to constants.
+const PIPELINE: &[Pass] = &[
+    Pass { min_level: 1, run: |program, _| fold_pure_calls(program) },
+    Pass { min_level: 2, run: |program, options| inline_functions(program, options.max_inline_depth) },
+];
+
+/// Run the AST optimization passes enabled by `options`
+pub fn optimize(program: &mut Program, options: &CompileOptions) {
+    if !options.optimize_ast {
+        return;
+    }
+    for pass in PIPELINE {
+        if options.optimization >= pass.min_level {
+            (pass.run)(program, options);
+        }
+    }
+}
+
+/// The truth value of a condition known at compile time, such as `true`
+/// or `1 < 2`, or None when it depends on runtime values
+pub fn constant_condition(condition: &Expression) -> Option<bool> {
+    let functions = HashMap::new();
+    Evaluator::new(&functions).constant(condition).map(|value| value.is_truthy())
+}
+
+// A function whose body is a single side-effect-free `return <expr>;`
+struct InlineCandidate {
+    params: Vec<Parameter>,
+    body: Expression,
+}
+
+/// Inline calls to tiny leaf functions inside function bodies.
+///
+/// Each round inlines the current leaf functions; a caller that becomes a
+/// leaf as a result can be inlined in the next round, so `max_depth`
+/// bounds how deeply nested the inlining goes. Returns the number of call
+/// sites replaced.
+pub fn inline_functions(program: &mut Program, max_depth: usize) -> usize {
+    let mut inlined = 0;
+    let mut temp_counter = 0;
+
+    for _ in 0..max_depth {
+        let candidates = collect_inline_candidates(program);
+        if candidates.is_empty() {
+            break;
+        }
+
+        let mut round = 0;
+        for stmt in &mut program.statements {
+            if let Statement::FunctionDecl { params, body, .. } = stmt {
+                let locals = local_types(params, body);
+                rewrite_block(body, &mut |expr| {
+                    if let Some(replacement) = inline_call(expr, &candidates, &locals, &mut temp_counter) {
+                        *expr = replacement;
+                        round += 1;
+                    }
+                });
+            }
+        }
+
+        if round == 0 {
+            break;
+        }
+        inlined += round;
+    }
+
+    inlined
+}
+
+/// Replace calls to `pure` functions whose arguments are all literals with
+/// the value they compute. Returns the number of calls folded.
+pub fn fold_pure_calls(program: &mut Program) -> usize {
+    let source = program.clone();
+    let functions: HashMap<String, PureFunction> = source
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::FunctionDecl { name, params, return_type: Some(return_type), body, is_pure: true, .. } => {
+                Some((name.clone(), PureFunction { params, return_type, body }))
+            }
+            _ => None,
+        })
+        .collect();
+    if functions.is_empty() {
+        return 0;
+    }
+
+    let mut folded = 0;
+    let mut fold = |expr: &mut Expression| {
+        if let Expression::Call { callee, args, .. } = expr {
+            if let Expression::Identifier(name) = callee.as_ref() {
+                if let Some(value) = Evaluator::new(&functions).call(name, args) {
+                    *expr = value.to_expression();
+                    folded += 1;
+                }
+            }
+        }
+    };
+
+    for stmt in &mut program.statements {
+        match stmt {
+            Statement::FunctionDecl { body, .. } => rewrite_block(body, &mut fold),
+            Statement::VariableDecl { initializer: Some(init), .. } => rewrite_expression(init, &mut fold),
+            _ => {}
+        }
+    }
+
+    folded
+}
+
+fn collect_inline_candidates(program: &Program) -> HashMap<String, InlineCandidate> {
+    let mut candidates = HashMap::new();
+
+    for stmt in &program.statements {
+        // Inlining would skip a function's contract checks
+        if let Statement::FunctionDecl { name, params, return_type: Some(_), requires, ensures, body, .. } = stmt {
+            if !requires.is_empty() || !ensures.is_empty() {
+                continue;
+            }
+            if let [Statement::Return { value: Some(expr) }] = body.statements.as_slice() {
+                if is_inlinable(expr, params) && expression_size(expr) <= MAX_INLINE_SIZE {
+                    candidates.insert(
+                        name.clone(),
+                        InlineCandidate {
+                            params: params.clone(),
+                            body: expr.clone(),
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    candidates
+}
+
+// Only pure expressions over the parameters qualify; globals are excluded
+// because a local at the call site could shadow them
+fn is_inlinable(expr: &Expression, params: &[Parameter]) -> bool {
+    match expr {
+        Expression::IntegerLiteral(_)
+        | Expression::FloatLiteral(_)
+        | Expression::BooleanLiteral(_)
+        | Expression::CharLiteral(_)
+        | Expression::StringLiteral(_) => true,
+        Expression::Identifier(name) => params.iter().any(|p| &p.name == name),
+        Expression::Binary { left, right, .. } => {
+            is_inlinable(left, params) && is_inlinable(right, params)
+        }
+        Expression::Unary { operand, .. } => is_inlinable(operand, params),
+        Expression::Cast { expr, .. } => is_inlinable(expr, params),
+        Expression::Conditional { condition, then, otherwise } => {
+            is_inlinable(condition, params) && is_inlinable(then, params) && is_inlinable(otherwise, params)
+        }
+        _ => false,
+    }
+}
+
+fn expression_size(expr: &Expression) -> usize {
+    match expr {
+        Expression::Binary { left, right, .. } => 1 + expression_size(left) + expression_size(right),
+        Expression::Unary { operand, .. } => 1 + expression_size(operand),
+        Expression::Cast { expr, .. } => 1 + expression_size(expr),
+        Expression::Conditional { condition, then, otherwise } => {
+            1 + expression_size(condition) + expression_size(then) + expression_size(otherwise)
+        }
+        _ => 1,
+    }
+}
+
+fn inline_call(
+    expr: &Expression,
+    candidates: &HashMap<String, InlineCandidate>,
+    locals: &HashMap<String, Type>,
+    temp_counter: &mut usize,
+) -> Option<Expression> {
+    let (name, args) = match expr {
+        Expression::Call { callee, args, .. } => match callee.as_ref() {
+            Expression::Identifier(name) => (name, args),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let candidate = candidates.get(name)?;
+    if candidate.params.len() != args.len() {
+        return None;
+    }
+
+    let is_simple = |arg: &Expression| {
+        matches!(
+            arg,
+            Expression::IntegerLiteral(_)
+                | Expression::FloatLiteral(_)
+                | Expression::BooleanLiteral(_)
+                | Expression::CharLiteral(_)
+                | Expression::StringLiteral(_)
+                | Expression::Identifier(_)
+        )
+    };
+
+    // Literals and variables can be substituted directly
+    if args.iter().all(is_simple) {
+        let bindings = candidate
+            .params
+            .iter()
+            .map(|p| p.name.clone())
+            .zip(candidate.params.iter().zip(args).map(|(param, arg)| convert_arg(arg, &param.param_type, locals)))
+            .collect();
+        return Some(substitute(&candidate.body, &bindings));
+    }
+
+    // Anything else is evaluated once into a temporary:
+    // `{ let __inline_0_x: T = arg; <body> }`
+    let mut statements = Vec::new();
+    let mut bindings = HashMap::new();
+    for (param, arg) in candidate.params.iter().zip(args) {
+        let temp = format!("__inline_{}_{}", temp_counter, param.name);
+        statements.push(Statement::VariableDecl {
+            name: temp.clone(),
+            var_type: Some(param.param_type.clone()),
+            initializer: Some(arg.clone()),
+            is_export: false,
+            is_mut: true,
+        });
+        bindings.insert(param.name.clone(), Expression::Identifier(temp));
+    }
+    *temp_counter += 1;
+
+    Some(Expression::Block {
+        body: Block {
+            statements,
+            ..Block::default()
+        },
+        value: Box::new(substitute(&candidate.body, &bindings)),
+    })
+}
+
+// A substituted argument still has to take the parameter's type, as
+// passing it would have done: `half(7)` with an f64 `x` divides 7.0
+fn convert_arg(arg: &Expression, param_type: &Type, locals: &HashMap<String, Type>) -> Expression {
+    let arg_type = match arg {
+        Expression::IntegerLiteral(_) => Some(&Type::I32),
+        Expression::FloatLiteral(_) => Some(&Type::F64),
+        Expression::Identifier(name) => locals.get(name),
+        _ => None,
+    };
+    let is_numeric = matches!(
+        param_type,
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 | Type::I128
+            | Type::U8 | Type::U16 | Type::U32 | Type::U64 | Type::U128
+            | Type::F32 | Type::F64
+    );
+    if is_numeric && arg_type != Some(param_type) {
+        Expression::Cast { expr: Box::new(arg.clone()), target_type: param_type.clone() }
+    } else {
+        arg.clone()
+    }
+}
+
+// The annotated type of each parameter and local of a function. A name
+// declared with different types in different blocks is left out, so its
+// arguments are always converted.
+fn local_types(params: &[Parameter], body: &Block) -> HashMap<String, Type> {
+    let mut collector = LocalTypes::default();
+    for param in params {
+        collector.record(&param.name, &param.param_type);
+    }
+    collector.visit_block(body);
+    collector.types.into_iter().filter_map(|(name, ty)| Some((name, ty?))).collect()
+}
+
+#[derive(Default)]
+struct LocalTypes {
+    types: HashMap<String, Option<Type>>,
+}
+
+impl LocalTypes {
+    fn record(&mut self, name: &str, ty: &Type) {
+        let entry = self.types.entry(name.to_string()).or_insert_with(|| Some(ty.clone()));
+        if entry.as_ref() != Some(ty) {
+            *entry = None;
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for LocalTypes {
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::VariableDecl { name, var_type: Some(ty), .. } => self.record(name, ty),
+            Statement::VariableDecl { name, var_type: None, .. } => {
+                self.types.insert(name.clone(), None);
+            }
+            _ => {}
+        }
+        walk_statement(self, stmt);
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        if let Expression::FunctionExpression { params, .. } = expr {
+            for param in params {
+                self.record(&param.name, &param.param_type);
+            }
+        }
+        walk_expression(self, expr);
+    }
+}
+
+fn substitute(expr: &Expression, bindings: &HashMap<String, Expression>) -> Expression {
+    match expr {
+        Expression::Identifier(name) => bindings.get(name).cloned().unwrap_or_else(|| expr.clone()),
+        Expression::Binary { left, op, right } => Expression::Binary {
+            left: Box::new(substitute(left, bindings)),
+            op: op.clone(),
+            right: Box::new(substitute(right, bindings)),
+        },
+        Expression::Unary { op, operand } => Expression::Unary {
+            op: op.clone(),
+            operand: Box::new(substitute(operand, bindings)),
+        },
+        Expression::Cast { expr, target_type } => Expression::Cast {
+            expr: Box::new(substitute(expr, bindings)),
+            target_type: target_type.clone(),
+        },
+        Expression::Conditional { condition, then, otherwise } => Expression::Conditional {
+            condition: Box::new(substitute(condition, bindings)),
+            then: Box::new(substitute(then, bindings)),
+            otherwise: Box::new(substitute(otherwise, bindings)),
+        },
+        _ => expr.clone(),
+    }
+}
+
+// Apply `f` to every expression in a block, children before parents
+fn rewrite_block(block: &mut Block, f: &mut dyn FnMut(&mut Expression)) {
+    for stmt in &mut block.statements {
+        rewrite_statement(stmt, f);
+    }
+}
+
+fn rewrite_statement(stmt: &mut Statement, f: &mut dyn FnMut(&mut Expression)) {
+    match stmt {
+        Statement::FunctionDecl { body, .. } => rewrite_block(body, f),
+        Statement::VariableDecl { initializer: Some(expr), .. }
+        | Statement::Return { value: Some(expr) }
+        | Statement::Expression(expr) => rewrite_expression(expr, f),
+        Statement::If { condition, then_branch, else_branch } => {
+            rewrite_expression(condition, f);
+            rewrite_block(then_branch, f);
+            if let Some(else_stmt) = else_branch {
+                rewrite_statement(else_stmt, f);
+            }
+        }
+        Statement::While { condition, body } => {
+            rewrite_expression(condition, f);
+            rewrite_block(body, f);
+        }
+        Statement::For { init, condition, increment, body } => {
+            if let Some(init_stmt) = init {
+                rewrite_statement(init_stmt, f);
+            }
+            if let Some(cond) = condition {
+                rewrite_expression(cond, f);
+            }
+            if let Some(inc) = increment {
+                rewrite_expression(inc, f);
+            }
+            rewrite_block(body, f);
+        }
+        Statement::ForIn { iterable, body, .. } => {
+            rewrite_expression(iterable, f);
+            rewrite_block(body, f);
+        }
+        Statement::Switch { expr, cases, default } => {
+            rewrite_expression(expr, f);
+            for case in cases {
+                rewrite_block(&mut case.body, f);
+            }
+            if let Some(block) = default {
+                rewrite_block(block, f);
+            }
+        }
+        Statement::Defer { statement } => rewrite_statement(statement, f),
+        Statement::Block(block) => rewrite_block(block, f),
+        _ => {}
+    }
+}
+
+fn rewrite_expression(expr: &mut Expression, f: &mut dyn FnMut(&mut Expression)) {
+    match expr {
+        Expression::FString { parts } => {
+            for part in parts {
+                if let FStringPart::Expression(e, _) = part {
+                    rewrite_expression(e, f);
+                }
+            }
+        }
+        Expression::Binary { left, right, .. } => {
+            rewrite_expression(left, f);
+            rewrite_expression(right, f);
+        }
+        Expression::Unary { operand, .. } => rewrite_expression(operand, f),
+        Expression::Call { callee, args, .. } => {
+            rewrite_expression(callee, f);
+            for arg in args {
+                rewrite_expression(arg, f);
+            }
+        }
+        Expression::Assignment { target, value } => {
+            rewrite_expression(target, f);
+            rewrite_expression(value, f);
+        }
+        Expression::ArrayLiteral { elements } => {
+            for elem in elements {
+                rewrite_expression(elem, f);
+            }
+        }
+        Expression::ArrayRepeat { value, count } => {
+            rewrite_expression(value, f);
+            rewrite_expression(count, f);
+        }
+        Expression::Range { start, end, .. } => {
+            rewrite_expression(start, f);
+            rewrite_expression(end, f);
+        }
+        Expression::Index { array, index } => {
+            rewrite_expression(array, f);
+            rewrite_expression(index, f);
+        }
+        Expression::ObjectLiteral { properties } => {
+            for prop in properties {
+                rewrite_expression(&mut prop.value, f);
+            }
+        }
+        Expression::PropertyAccess { object, .. } => rewrite_expression(object, f),
+        Expression::MethodCall { object, args, .. } => {
+            rewrite_expression(object, f);
+            for arg in args {
+                rewrite_expression(arg, f);
+            }
+        }
+        Expression::FunctionExpression { body, .. } => rewrite_block(body, f),
+        Expression::Match { expr, arms } => {
+            rewrite_expression(expr, f);
+            for arm in arms {
+                rewrite_expression(&mut arm.body, f);
+            }
+        }
+        Expression::Cast { expr, .. } => rewrite_expression(expr, f),
+        Expression::Conditional { condition, then, otherwise } => {
+            rewrite_expression(condition, f);
+            rewrite_expression(then, f);
+            rewrite_expression(otherwise, f);
+        }
+        Expression::Block { body, value } => {
+            rewrite_block(body, f);
+            rewrite_expression(value, f);
+        }
+        _ => {}
+    }
+
+    f(expr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codegen::CodeGenerator;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.parse().unwrap()
+    }
+
+    fn main_return_value(program: &Program) -> &Expression {
+        for stmt in &program.statements {
+            if let Statement::FunctionDecl { name, body, .. } = stmt {
+                if name == "main" {
+                    if let Some(Statement::Return { value: Some(expr) }) = body.statements.last() {
+                        return expr;
+                    }
+                }
+            }
+        }
+        panic!("main has no return value");
+    }
+
+    #[test]
+    fn test_inline_square() {
+        let mut program = parse(
+            "function square(x: i32): i32 { return x * x; }
+             function main(): i32 { let n: i32 = 4; return square(n); }",
+        );
+
+        assert_eq!(inline_functions(&mut program, 3), 1);
+        assert_eq!(
+            main_return_value(&program),
+            &Expression::Binary {
+                left: Box::new(Expression::Identifier("n".to_string())),
+                op: BinaryOp::Multiply,
+                right: Box::new(Expression::Identifier("n".to_string())),
+            }
+        );
+    }
+
+    #[test]
+    fn test_inline_evaluates_complex_arguments_once() {
+        let mut program = parse(
+            "function square(x: i32): i32 { return x * x; }
+             function main(): i32 { return square(next()); }",
+        );
+
+        inline_functions(&mut program, 3);
+        if let Expression::Block { body, .. } = main_return_value(&program) {
+            assert_eq!(body.statements.len(), 1);
+        } else {
+            panic!("Expected the argument to be bound to a temporary");
+        }
+    }
+
+    #[test]
+    fn test_inline_depth_limit() {
+        let source = "function square(x: i32): i32 { return x * x; }
+             function quad(x: i32): i32 { return square(x) * square(x); }
+             function main(): i32 { return quad(2); }";
+
+        let mut shallow = parse(source);
+        inline_functions(&mut shallow, 1);
+        assert!(matches!(main_return_value(&shallow), Expression::Call { .. }));
+
+        let mut deep = parse(source);
+        inline_functions(&mut deep, 2);
+        assert!(!matches!(main_return_value(&deep), Expression::Call { .. }));
+    }
+
+    #[test]
+    fn test_no_inline_with_side_effects_or_multiple_returns() {
+        let mut program = parse(
+            "function noisy(x: i32): i32 { print(\"hi\"); return x; }
+             function pick(x: i32): i32 { if (x > 0) { return 1; } return 0; }
+             function fact(n: i32): i32 { return n * fact(n - 1); }
+             function main(): i32 { return noisy(1) + pick(2) + fact(3); }",
+        );
+
+        assert_eq!(inline_functions(&mut program, 3), 0);
+    }
+
+    #[test]
+    fn test_optimize_inlines_only_at_o2() {
+        let source = "function square(x: i32): i32 { return x * x; }
+             function main(): i32 { let n: i32 = 4; return square(n + 1); }";
+
+        let mut options = CompileOptions { optimization: 1, ..CompileOptions::default() };
+        let mut program = parse(source);
+        optimize(&mut program, &options);
+        let c_code = CodeGenerator::new(options.clone()).generate(&program).unwrap();
+        assert!(c_code.contains("return square((n + 1));"), "{}", c_code);
+
+        options.optimization = 2;
+        let mut program = parse(source);
+        optimize(&mut program, &options);
+        let c_code = CodeGenerator::new(options).generate(&program).unwrap();
+        assert!(!c_code.contains("return square("), "{}", c_code);
+        assert!(c_code.contains("__inline_0_x"), "{}", c_code);
+    }
+
+    #[test]
+    fn test_inline_converts_arguments_to_parameter_types() {
+        let mut program = parse(
+            "function half(x: f64): f64 { return x / 2; }
+             function main(): i32 { let n: i32 = 7; let y: f64 = 3.0; return half(n) + half(y); }",
+        );
+
+        inline_functions(&mut program, 3);
+        let Expression::Binary { left, right, .. } = main_return_value(&program) else {
+            panic!("Expected both calls to be inlined");
+        };
+        let Expression::Binary { left: n, .. } = left.as_ref() else { panic!("{:?}", left) };
+        assert_eq!(
+            n.as_ref(),
+            &Expression::Cast { expr: Box::new(Expression::Identifier("n".to_string())), target_type: Type::F64 }
+        );
+        let Expression::Binary { left: y, .. } = right.as_ref() else { panic!("{:?}", right) };
+        assert_eq!(y.as_ref(), &Expression::Identifier("y".to_string()));
+    }
+
+    #[test]
+    fn test_optimized_pure_call_divides_as_declared() {
+        let source = "pure function half(x: f64): f64 { return x / 2; }
+             function main(): i32 { let n: i32 = 7; println(half(7)); println(half(n)); return 0; }";
+
+        let options = CompileOptions { optimization: 2, ..CompileOptions::default() };
+        let mut program = parse(source);
+        optimize(&mut program, &options);
+
+        let binary = std::env::temp_dir().join(format!("hilowc-half-{}", std::process::id()));
+        crate::codegen::compile(&program, binary.to_str().unwrap(), &options).unwrap();
+        let output = std::process::Command::new(&binary).output().unwrap();
+        std::fs::remove_file(&binary).ok();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "3.500000\n3.500000\n");
+    }
+
+    #[test]
+    fn test_fold_pure_calls() {
+        let source = "pure function add(a: i32, b: i32): i32 { return a + b; }
+             function main(): i32 { return add(2, 3); }";
+
+        let options = CompileOptions { optimization: 1, ..CompileOptions::default() };
+        let mut program = parse(source);
+        optimize(&mut program, &options);
+        assert_eq!(main_return_value(&program), &Expression::IntegerLiteral(5));
+        let c_code = CodeGenerator::new(options).generate(&program).unwrap();
+        assert!(c_code.contains("return 5;"), "{}", c_code);
+
+        // --no-optimize-ast leaves the call for GCC
+        let options = CompileOptions { optimization: 2, optimize_ast: false, ..CompileOptions::default() };
+        let mut program = parse(source);
+        optimize(&mut program, &options);
+        let c_code = CodeGenerator::new(options).generate(&program).unwrap();
+        assert!(c_code.contains("return add(2, 3);"), "{}", c_code);
+
+        // Loops and calls between pure functions are evaluated too
+        let mut program = parse(
+            "pure function square(x: i32): i32 { return x * x; }
+             pure function sum_squares(n: i32): i32 {
+                 let total: i32 = 0;
+                 for (let i: i32 = 1; i <= n; i = i + 1) { total = total + square(i); }
+                 return total;
+             }
+             function main(): i32 { return sum_squares(add_one(3)) + sum_squares(4); }",
+        );
+        assert_eq!(fold_pure_calls(&mut program), 1);
+        if let Expression::Binary { left, right, .. } = main_return_value(&program) {
+            assert!(matches!(left.as_ref(), Expression::Call { .. }));
+            assert_eq!(right.as_ref(), &Expression::IntegerLiteral(30));
+        } else {
+            panic!("Expected a sum");
+        }
+    }
+
+    #[test]
+    fn test_fold_leaves_calls_c_would_evaluate_differently() {
+        let mut program = parse(
+            "pure function quotient(a: i32, b: i32): i32 { return a / b; }
+             pure function narrow(x: i32): u8 { return x; }
+             pure function forever(x: i32): i32 { while (true) { x = x + 0; } return x; }
+             function main(): i32 { return quotient(1, 0) + narrow(300) + forever(1); }",
+        );
+
+        assert_eq!(fold_pure_calls(&mut program), 0);
+    }
+}
@@ -1,39 +1,273 @@
 use crate::ast::*;
+use crate::error::{CompileError, Span, Stage};
 use crate::lexer::token::{Token, TokenKind};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    strict_semicolons: bool,
+    defines: HashMap<String, Expression>,
+    // Structs and enums declared so far, to catch a second declaration
+    type_names: HashSet<String>,
+    // Every struct and enum the file declares or imports, which
+    // `parse_type` accepts by name, even before their declaration
+    known_types: HashSet<String>,
+    // The variants of each enum, so `Name.Variant` can be checked
+    enum_variants: HashMap<String, Vec<String>>,
+    // Object types written in place, `{ name: string }`, with where each
+    // first appears. Each distinct one is declared as a struct `RecordN`.
+    records: Vec<(Vec<Parameter>, Span)>,
+    // Errors from statements skipped during recovery
+    errors: Vec<CompileError>,
+    // The file being parsed, recorded on the program and its blocks for
+    // `#line` directives and error positions
+    source_name: Option<Rc<str>>,
+}
+
+/// Parse a `--define` argument: `NAME` means `NAME=true`, and the value of
+/// `NAME=VALUE` is read as a bool, an integer, or else a string
+pub fn parse_define(spec: &str) -> Result<(String, Expression), String> {
+    let (name, value) = match spec.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (spec, None),
+    };
+
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(format!("Invalid define '{}' (expected NAME or NAME=VALUE)", spec));
+    }
+
+    let value = match value {
+        None | Some("true") => Expression::BooleanLiteral(true),
+        Some("false") => Expression::BooleanLiteral(false),
+        Some(v) => match v.parse::<i64>() {
+            Ok(n) => Expression::IntegerLiteral(n),
+            Err(_) => Expression::StringLiteral(v.to_string()),
+        },
+    };
+
+    Ok((name.to_string(), value))
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            strict_semicolons: false,
+            defines: HashMap::new(),
+            type_names: HashSet::new(),
+            known_types: HashSet::new(),
+            enum_variants: HashMap::new(),
+            records: Vec::new(),
+            errors: Vec::new(),
+            source_name: None,
+        }
+    }
+
+    /// Name the file being parsed, so the generated C can point back to it
+    pub fn with_source_name(mut self, name: &str) -> Self {
+        self.source_name = Some(Rc::from(name));
+        self
+    }
+
+    /// Require every statement to end with a semicolon instead of
+    /// tolerating missing ones
+    pub fn with_strict_semicolons(mut self, strict: bool) -> Self {
+        self.strict_semicolons = strict;
+        self
+    }
+
+    /// Constants visible to top-level `when` conditions
+    pub fn with_defines(mut self, defines: HashMap<String, Expression>) -> Self {
+        self.defines = defines;
+        self
     }
 
-    pub fn parse(&mut self) -> Result<Program, String> {
+    /// Parse the whole program. A statement that fails to parse is
+    /// skipped so the errors after it are reported too.
+    pub fn parse(&mut self) -> Result<Program, Vec<CompileError>> {
+        self.known_types = self.declared_type_names();
         let mut statements = Vec::new();
+        let mut spans = Vec::new();
+
+        while !self.is_at_end() {
+            if let Err(error) = self.parse_top_level(&mut statements, &mut spans) {
+                self.recover(error);
+                // A stray `}` can't start anything at the top level
+                self.match_token(&TokenKind::RightBrace);
+            }
+        }
+
+        for (index, (fields, span)) in std::mem::take(&mut self.records).into_iter().enumerate() {
+            statements.push(Statement::StructDecl {
+                name: self.record_name(index),
+                fields,
+                methods: Vec::new(),
+                is_export: false,
+            });
+            spans.push(span);
+        }
+
+        if self.errors.is_empty() {
+            Ok(Program {
+                statements,
+                spans,
+                source: self.source_name.clone(),
+            })
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
 
+    // The names after every `struct` and `enum` keyword, so types can be
+    // used ahead of their declarations
+    fn declared_type_names(&self) -> HashSet<String> {
+        self.tokens
+            .windows(2)
+            .filter_map(|pair| match (&pair[0].kind, &pair[1].kind) {
+                (TokenKind::Struct | TokenKind::Enum, TokenKind::Identifier(name)) => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Record `error` and skip to where the next statement should start:
+    // past a `;` or a whole `{ ... }` at this level, or up to the `}` that
+    // closes the enclosing block
+    fn recover(&mut self, error: CompileError) {
+        self.errors.push(error);
+
+        let mut depth = 0;
         while !self.is_at_end() {
+            match self.peek().kind {
+                TokenKind::Semicolon if depth == 0 => {
+                    self.advance();
+                    return;
+                }
+                TokenKind::LeftBrace => depth += 1,
+                TokenKind::RightBrace if depth == 0 => return,
+                TokenKind::RightBrace => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.advance();
+                        return;
+                    }
+                }
+                _ => {}
+            }
+            self.advance();
+        }
+    }
+
+    // `when (CONDITION) { ... }` is folded here: its declarations are kept
+    // only if the condition holds for the current defines
+    fn parse_top_level(&mut self, statements: &mut Vec<Statement>, spans: &mut Vec<Span>) -> Result<(), CompileError> {
+        if !self.check(&TokenKind::When) {
+            let span = self.span();
             statements.push(self.parse_statement()?);
+            spans.push(span);
+            return Ok(());
+        }
+
+        let when_token = self.advance();
+        self.expect(TokenKind::LeftParen)?;
+        let condition = self.parse_expression()?;
+        self.expect(TokenKind::RightParen)?;
+        let included = is_truthy(
+            &self
+                .evaluate_define_condition(&condition)
+                .map_err(|e| error_at(&when_token, e))?,
+        );
+
+        self.expect(TokenKind::LeftBrace)?;
+        let mut body = Vec::new();
+        let mut body_spans = Vec::new();
+        while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
+            self.parse_top_level(&mut body, &mut body_spans)?;
         }
+        self.expect(TokenKind::RightBrace).map_err(|e| CompileError {
+            message: format!("{} (in when block starting at {}:{})", e.message, when_token.line, when_token.column),
+            ..e
+        })?;
+
+        if included {
+            statements.extend(body);
+            spans.extend(body_spans);
+        }
+        Ok(())
+    }
+
+    // Fold a `when` condition to a literal. Names that were never defined
+    // are false.
+    fn evaluate_define_condition(&self, expr: &Expression) -> Result<Expression, String> {
+        match expr {
+            Expression::BooleanLiteral(_) | Expression::IntegerLiteral(_) | Expression::StringLiteral(_) => {
+                Ok(expr.clone())
+            }
+            Expression::Identifier(name) => Ok(self
+                .defines
+                .get(name)
+                .cloned()
+                .unwrap_or(Expression::BooleanLiteral(false))),
+            Expression::Unary { op: UnaryOp::Not, operand } => {
+                let value = self.evaluate_define_condition(operand)?;
+                Ok(Expression::BooleanLiteral(!is_truthy(&value)))
+            }
+            Expression::Binary { left, op: BinaryOp::And, right } => {
+                let result = is_truthy(&self.evaluate_define_condition(left)?)
+                    && is_truthy(&self.evaluate_define_condition(right)?);
+                Ok(Expression::BooleanLiteral(result))
+            }
+            Expression::Binary { left, op: BinaryOp::Or, right } => {
+                let result = is_truthy(&self.evaluate_define_condition(left)?)
+                    || is_truthy(&self.evaluate_define_condition(right)?);
+                Ok(Expression::BooleanLiteral(result))
+            }
+            Expression::Binary { left, op, right } => {
+                let left = self.evaluate_define_condition(left)?;
+                let right = self.evaluate_define_condition(right)?;
+
+                // Booleans compare as 0/1 so undefined names order like zero
+                let ordering = match (&left, &right) {
+                    (Expression::StringLiteral(a), Expression::StringLiteral(b)) => a.cmp(b),
+                    (Expression::StringLiteral(_), _) | (_, Expression::StringLiteral(_)) => {
+                        return Err("when conditions cannot compare strings with numbers".to_string());
+                    }
+                    _ => define_as_int(&left).cmp(&define_as_int(&right)),
+                };
 
-        Ok(Program { statements })
+                let result = match op {
+                    BinaryOp::Equal | BinaryOp::StrictEqual => ordering.is_eq(),
+                    BinaryOp::NotEqual | BinaryOp::StrictNotEqual => ordering.is_ne(),
+                    BinaryOp::Less => ordering.is_lt(),
+                    BinaryOp::LessEqual => ordering.is_le(),
+                    BinaryOp::Greater => ordering.is_gt(),
+                    BinaryOp::GreaterEqual => ordering.is_ge(),
+                    _ => return Err(format!("Operator {:?} is not supported in when conditions", op)),
+                };
+                Ok(Expression::BooleanLiteral(result))
+            }
+            _ => Err("when conditions may only use literals and --define constants".to_string()),
+        }
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, String> {
+    fn parse_statement(&mut self) -> Result<Statement, CompileError> {
         match &self.peek().kind {
             TokenKind::Export => {
                 self.advance();
-                // After export, we expect function or let
+                // After export, we expect a function, variable or type
                 match &self.peek().kind {
-                    TokenKind::Function => self.parse_function_decl_with_export(true),
+                    TokenKind::Function | TokenKind::Pure => self.parse_function_decl_with_export(true),
                     TokenKind::Let => self.parse_variable_decl_with_export(true),
-                    _ => Err("Expected 'function' or 'let' after 'export'".to_string()),
+                    TokenKind::Struct => self.parse_struct(true),
+                    TokenKind::Enum => self.parse_enum(true),
+                    _ => Err(error_at(self.peek(), "Expected 'function', 'let', 'struct' or 'enum' after 'export'")),
                 }
             }
             TokenKind::Import => self.parse_import(),
-            TokenKind::Function => self.parse_function_decl_with_export(false),
+            TokenKind::Function | TokenKind::Pure => self.parse_function_decl_with_export(false),
             TokenKind::Let => self.parse_variable_decl_with_export(false),
             TokenKind::Return => self.parse_return(),
             TokenKind::If => self.parse_if(),
@@ -49,14 +283,14 @@ impl Parser {
                 self.consume_semicolon()?;
                 Ok(Statement::Continue)
             }
-            TokenKind::Defer => {
-                self.advance();
-                let stmt = self.parse_statement()?;
-                Ok(Statement::Defer {
-                    statement: Box::new(stmt),
-                })
-            }
+            TokenKind::Defer => self.parse_defer(),
             TokenKind::Switch => self.parse_switch(),
+            TokenKind::Using => self.parse_using(),
+            TokenKind::Struct => self.parse_struct(false),
+            TokenKind::Enum => self.parse_enum(false),
+            TokenKind::Fallthrough => {
+                Err(error_at(self.peek(), "fallthrough is only allowed at the end of a switch case"))
+            }
             TokenKind::LeftBrace => self.parse_block_statement(),
             _ => {
                 let expr = self.parse_expression()?;
@@ -66,9 +300,10 @@ impl Parser {
         }
     }
 
-    fn parse_import(&mut self) -> Result<Statement, String> {
+    fn parse_import(&mut self) -> Result<Statement, CompileError> {
         self.expect(TokenKind::Import)?;
-        self.expect(TokenKind::LeftBrace)?;
+        // Both `import { a, b } from ...` and `import a, b from ...`
+        let braced = self.match_token(&TokenKind::LeftBrace);
 
         let mut names = Vec::new();
         loop {
@@ -78,50 +313,32 @@ impl Parser {
             }
         }
 
-        self.expect(TokenKind::RightBrace)?;
+        if braced {
+            self.expect(TokenKind::RightBrace)?;
+        }
         self.expect(TokenKind::From)?;
 
-        let module = if let TokenKind::StringLiteral(s) = &self.advance().kind {
+        let token = self.advance();
+        let module = if let TokenKind::StringLiteral(s) = &token.kind {
             s.clone()
         } else {
-            return Err("Expected string literal for module path".to_string());
+            return Err(error_at(&token, "Expected string literal for module path"));
         };
 
         self.consume_semicolon()?;
 
+        // Any imported name may be a type; which ones are is only known
+        // once the module is loaded
+        self.known_types.extend(names.iter().cloned());
         Ok(Statement::Import { names, module })
     }
 
-    fn parse_function_decl(&mut self) -> Result<Statement, String> {
-        self.parse_function_decl_with_export(false)
-    }
-
-    fn parse_function_decl_with_export(&mut self, is_export: bool) -> Result<Statement, String> {
+    fn parse_function_decl_with_export(&mut self, is_export: bool) -> Result<Statement, CompileError> {
+        let is_pure = self.match_token(&TokenKind::Pure);
         self.expect(TokenKind::Function)?;
 
         let name = self.expect_identifier()?;
-
-        self.expect(TokenKind::LeftParen)?;
-
-        let mut params = Vec::new();
-        if !self.check(&TokenKind::RightParen) {
-            loop {
-                let param_name = self.expect_identifier()?;
-                self.expect(TokenKind::Colon)?;
-                let param_type = self.parse_type()?;
-
-                params.push(Parameter {
-                    name: param_name,
-                    param_type,
-                });
-
-                if !self.match_token(&TokenKind::Comma) {
-                    break;
-                }
-            }
-        }
-
-        self.expect(TokenKind::RightParen)?;
+        let params = self.parse_parameters()?;
 
         let return_type = if self.match_token(&TokenKind::Colon) {
             Some(self.parse_type()?)
@@ -129,24 +346,41 @@ impl Parser {
             None
         };
 
+        // Contract clauses: `requires cond` and `ensures cond`, each repeatable
+        let mut requires = Vec::new();
+        let mut ensures = Vec::new();
+        loop {
+            if self.match_token(&TokenKind::Requires) {
+                requires.push(self.parse_expression()?);
+            } else if self.match_token(&TokenKind::Ensures) {
+                ensures.push(self.parse_expression()?);
+            } else {
+                break;
+            }
+        }
+
         let body = self.parse_block()?;
 
         Ok(Statement::FunctionDecl {
             name,
             params,
             return_type,
+            requires,
+            ensures,
             body,
             is_export,
+            is_pure,
         })
     }
 
-    fn parse_variable_decl(&mut self) -> Result<Statement, String> {
+    fn parse_variable_decl(&mut self) -> Result<Statement, CompileError> {
         self.parse_variable_decl_with_export(false)
     }
 
-    fn parse_variable_decl_with_export(&mut self, is_export: bool) -> Result<Statement, String> {
+    fn parse_variable_decl_with_export(&mut self, is_export: bool) -> Result<Statement, CompileError> {
         self.expect(TokenKind::Let)?;
 
+        let is_mut = self.match_token(&TokenKind::Mut);
         let name = self.expect_identifier()?;
 
         let var_type = if self.match_token(&TokenKind::Colon) {
@@ -168,10 +402,11 @@ impl Parser {
             var_type,
             initializer,
             is_export,
+            is_mut,
         })
     }
 
-    fn parse_return(&mut self) -> Result<Statement, String> {
+    fn parse_return(&mut self) -> Result<Statement, CompileError> {
         self.expect(TokenKind::Return)?;
 
         let value = if !self.check(&TokenKind::Semicolon) {
@@ -185,7 +420,7 @@ impl Parser {
         Ok(Statement::Return { value })
     }
 
-    fn parse_if(&mut self) -> Result<Statement, String> {
+    fn parse_if(&mut self) -> Result<Statement, CompileError> {
         self.expect(TokenKind::If)?;
         self.expect(TokenKind::LeftParen)?;
         let condition = self.parse_expression()?;
@@ -210,7 +445,7 @@ impl Parser {
         })
     }
 
-    fn parse_while(&mut self) -> Result<Statement, String> {
+    fn parse_while(&mut self) -> Result<Statement, CompileError> {
         self.expect(TokenKind::While)?;
         self.expect(TokenKind::LeftParen)?;
         let condition = self.parse_expression()?;
@@ -221,8 +456,18 @@ impl Parser {
         Ok(Statement::While { condition, body })
     }
 
-    fn parse_for(&mut self) -> Result<Statement, String> {
+    fn parse_for(&mut self) -> Result<Statement, CompileError> {
         self.expect(TokenKind::For)?;
+
+        // for item in iterable { ... }
+        if !self.check(&TokenKind::LeftParen) {
+            let variable = self.expect_identifier()?;
+            self.expect(TokenKind::In)?;
+            let iterable = self.parse_expression()?;
+            let body = self.parse_block()?;
+            return Ok(Statement::ForIn { variable, iterable, body });
+        }
+
         self.expect(TokenKind::LeftParen)?;
 
         // Check if this is a for-in loop
@@ -283,7 +528,7 @@ impl Parser {
         })
     }
 
-    fn parse_switch(&mut self) -> Result<Statement, String> {
+    fn parse_switch(&mut self) -> Result<Statement, CompileError> {
         use crate::ast::SwitchCase;
 
         self.expect(TokenKind::Switch)?;
@@ -301,33 +546,51 @@ impl Parser {
                 self.expect(TokenKind::Colon)?;
 
                 let mut case_statements = Vec::new();
+                let mut case_spans = Vec::new();
+                let mut fallthrough = false;
                 while !self.check(&TokenKind::Case)
                     && !self.check(&TokenKind::Default)
                     && !self.check(&TokenKind::RightBrace) {
+                    if self.check(&TokenKind::Fallthrough) {
+                        let token = self.advance();
+                        self.consume_semicolon()?;
+                        if !self.check(&TokenKind::Case) && !self.check(&TokenKind::Default) {
+                            return Err(error_at(
+                                &token,
+                                "fallthrough must end a case that is followed by another case",
+                            ));
+                        }
+                        fallthrough = true;
+                        break;
+                    }
+                    case_spans.push(self.span());
                     case_statements.push(self.parse_statement()?);
                 }
 
                 cases.push(SwitchCase {
                     value,
-                    body: Block {
-                        statements: case_statements,
-                    },
+                    body: self.block(case_statements, case_spans),
+                    fallthrough,
                 });
             } else if self.match_token(&TokenKind::Default) {
                 self.expect(TokenKind::Colon)?;
 
                 let mut default_statements = Vec::new();
+                let mut default_spans = Vec::new();
                 while !self.check(&TokenKind::Case)
                     && !self.check(&TokenKind::Default)
                     && !self.check(&TokenKind::RightBrace) {
+                    default_spans.push(self.span());
                     default_statements.push(self.parse_statement()?);
                 }
 
-                default = Some(Block {
-                    statements: default_statements,
-                });
+                default = Some(self.block(default_statements, default_spans));
             } else {
-                return Err(format!("Expected 'case' or 'default' in switch statement, got {:?}", self.peek().kind));
+                let token = self.peek();
+                return Err(error_at(
+                    token,
+                    format!("Expected 'case' or 'default' in switch statement, got {:?}", token.kind),
+                ));
             }
         }
 
@@ -340,56 +603,262 @@ impl Parser {
         })
     }
 
-    fn parse_block_statement(&mut self) -> Result<Statement, String> {
+    fn parse_defer(&mut self) -> Result<Statement, CompileError> {
+        self.expect(TokenKind::Defer)?;
+
+        // A single statement or block; expression statements consume their own `;`
+        let statement = self.parse_statement()?;
+
+        Ok(Statement::Defer {
+            statement: Box::new(statement),
+        })
+    }
+
+    fn parse_using(&mut self) -> Result<Statement, CompileError> {
+        let span = self.span();
+        self.expect(TokenKind::Using)?;
+        self.expect(TokenKind::LeftParen)?;
+
+        let name = self.expect_identifier()?;
+        let var_type = if self.match_token(&TokenKind::Colon) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        self.expect(TokenKind::Equal)?;
+        let initializer = self.parse_expression()?;
+
+        // `using (r = acquire(); release(r))`; without a release
+        // expression the resource is simply freed, which type checking
+        // only allows for values it knows were allocated
+        let release = if self.match_token(&TokenKind::Semicolon) {
+            self.parse_expression()?
+        } else {
+            Expression::Call {
+                callee: Box::new(Expression::Identifier("free".to_string())),
+                args: vec![Expression::Identifier(name.clone())],
+                resolved_type: None,
+            }
+        };
+
+        self.expect(TokenKind::RightParen)?;
+        let body = self.parse_block()?;
+
+        // Transform into `{ let r = acquire(); defer release(r); ...body }`
+        let mut statements = vec![
+            Statement::VariableDecl {
+                name,
+                var_type,
+                initializer: Some(initializer),
+                is_export: false,
+                is_mut: false,
+            },
+            Statement::Defer {
+                statement: Box::new(Statement::Expression(release)),
+            },
+        ];
+        statements.extend(body.statements);
+        let spans = [span, span].into_iter().chain(body.spans).collect();
+
+        Ok(Statement::Block(self.block(statements, spans)))
+    }
+
+    // `struct Point { x: i32, y: i32 }`; the name can be used as a type
+    // anywhere in the file
+    fn parse_struct(&mut self, is_export: bool) -> Result<Statement, CompileError> {
+        self.expect(TokenKind::Struct)?;
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier()?;
+        if primitive_type(&name).is_some() || !self.type_names.insert(name.clone()) {
+            return Err(error_at(&name_token, format!("Type `{}` is already defined", name)));
+        }
+
+        self.expect(TokenKind::LeftBrace)?;
+        let mut fields: Vec<Parameter> = Vec::new();
+        let mut methods: Vec<Statement> = Vec::new();
+        while !self.check(&TokenKind::RightBrace) {
+            if self.check(&TokenKind::Function) || self.check(&TokenKind::Pure) {
+                methods.push(self.parse_method(&name, &fields, &methods)?);
+                continue;
+            }
+
+            let field_token = self.peek().clone();
+            let field = self.expect_identifier()?;
+            if fields.iter().any(|existing| existing.name == field) {
+                return Err(error_at(&field_token, format!("Field `{}` appears twice in struct `{}`", field, name)));
+            }
+            if methods.iter().any(|method| matches!(method, Statement::FunctionDecl { name: method, .. } if *method == field)) {
+                return Err(error_at(&field_token, format!("`{}` is already a member of struct `{}`", field, name)));
+            }
+            self.expect(TokenKind::Colon)?;
+            let param_type = self.parse_type()?;
+            fields.push(Parameter { name: field, param_type });
+            if !self.match_token(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightBrace)?;
+
+        Ok(Statement::StructDecl { name, fields, methods, is_export })
+    }
+
+    // The struct declared for the `index`th object type, `Record<index>`,
+    // numbered past any such name the file declares itself
+    fn record_name(&self, index: usize) -> String {
+        (0..)
+            .map(|n| format!("Record{}", n))
+            .filter(|name| !self.known_types.contains(name))
+            .nth(index)
+            .expect("record names are unbounded")
+    }
+
+    // A method in the body of struct `struct_name`. It is a function whose
+    // first parameter is the receiver, `self`.
+    fn parse_method(&mut self, struct_name: &str, fields: &[Parameter], methods: &[Statement]) -> Result<Statement, CompileError> {
+        let name_token = self.peek_at(if self.check(&TokenKind::Pure) { 2 } else { 1 }).clone();
+        let mut method = self.parse_function_decl_with_export(false)?;
+        let Statement::FunctionDecl { name, params, .. } = &mut method else {
+            unreachable!("parse_function_decl_with_export returns a function");
+        };
+
+        let is_taken = fields.iter().any(|field| field.name == *name)
+            || methods.iter().any(|other| matches!(other, Statement::FunctionDecl { name: other, .. } if other == name));
+        if is_taken {
+            return Err(error_at(&name_token, format!("`{}` is already a member of struct `{}`", name, struct_name)));
+        }
+        if let Some(param) = params.iter().find(|param| param.name == "self") {
+            return Err(error_at(&name_token, format!("Method `{}` cannot declare `{}` itself", name, param.name)));
+        }
+
+        params.insert(0, Parameter { name: "self".to_string(), param_type: Type::Named(struct_name.to_string()) });
+        Ok(method)
+    }
+
+    // `enum Color { Red, Green, Blue }`
+    fn parse_enum(&mut self, is_export: bool) -> Result<Statement, CompileError> {
+        self.expect(TokenKind::Enum)?;
+        let name_token = self.peek().clone();
+        let name = self.expect_identifier()?;
+        if primitive_type(&name).is_some() || !self.type_names.insert(name.clone()) {
+            return Err(error_at(&name_token, format!("Type `{}` is already defined", name)));
+        }
+
+        self.expect(TokenKind::LeftBrace)?;
+        let mut variants: Vec<String> = Vec::new();
+        while !self.check(&TokenKind::RightBrace) {
+            let variant_token = self.peek().clone();
+            let variant = self.expect_identifier()?;
+            if variants.contains(&variant) {
+                return Err(error_at(&variant_token, format!("Variant `{}` appears twice in enum `{}`", variant, name)));
+            }
+            variants.push(variant);
+            if !self.match_token(&TokenKind::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenKind::RightBrace)?;
+
+        self.enum_variants.insert(name.clone(), variants.clone());
+        Ok(Statement::EnumDecl { name, variants, is_export })
+    }
+
+    fn parse_block_statement(&mut self) -> Result<Statement, CompileError> {
         Ok(Statement::Block(self.parse_block()?))
     }
 
-    fn parse_block(&mut self) -> Result<Block, String> {
+    fn parse_block(&mut self) -> Result<Block, CompileError> {
         self.expect(TokenKind::LeftBrace)?;
 
         let mut statements = Vec::new();
+        let mut spans = Vec::new();
 
         while !self.check(&TokenKind::RightBrace) && !self.is_at_end() {
-            statements.push(self.parse_statement()?);
+            let span = self.span();
+            match self.parse_statement() {
+                Ok(statement) => {
+                    statements.push(statement);
+                    spans.push(span);
+                }
+                Err(error) => self.recover(error),
+            }
         }
 
         self.expect(TokenKind::RightBrace)?;
 
-        Ok(Block { statements })
+        Ok(self.block(statements, spans))
+    }
+
+    fn block(&self, statements: Vec<Statement>, spans: Vec<Span>) -> Block {
+        Block {
+            statements,
+            spans,
+            source: self.source_name.clone(),
+        }
     }
 
-    fn parse_type(&mut self) -> Result<Type, String> {
+    fn parse_type(&mut self) -> Result<Type, CompileError> {
         let token = self.advance();
 
         match &token.kind {
-            TokenKind::Identifier(name) => match name.as_str() {
-                "i8" => Ok(Type::I8),
-                "i16" => Ok(Type::I16),
-                "i32" => Ok(Type::I32),
-                "i64" => Ok(Type::I64),
-                "i128" => Ok(Type::I128),
-                "u8" => Ok(Type::U8),
-                "u16" => Ok(Type::U16),
-                "u32" => Ok(Type::U32),
-                "u64" => Ok(Type::U64),
-                "u128" => Ok(Type::U128),
-                "f32" => Ok(Type::F32),
-                "f64" => Ok(Type::F64),
-                "bool" => Ok(Type::Bool),
-                "string" => Ok(Type::String),
-                _ => Err(format!("Unknown type: {}", name)),
-            },
+            TokenKind::Identifier(name) if self.known_types.contains(name) => Ok(Type::Named(name.clone())),
+            TokenKind::Identifier(name) => primitive_type(name)
+                .ok_or_else(|| error_at(&token, format!("Unknown type `{}`", name))),
             TokenKind::Nothing => Ok(Type::Nothing),
             TokenKind::Unknown => Ok(Type::Unknown),
             TokenKind::Function => {
-                // For now, use a simple function type without full signature
-                // Full function types would be: function(i32, i32): i32
-                // For Phase 6, we'll use a simplified "function" type
-                Ok(Type::Function {
-                    params: vec![],
-                    return_type: Box::new(Type::I32), // Simplified
-                })
+                // `function(string, i32): bool` spells out the signature;
+                // a bare `function` is called as taking and returning i32
+                if !self.match_token(&TokenKind::LeftParen) {
+                    return Ok(Type::Function {
+                        params: vec![],
+                        return_type: Box::new(Type::I32),
+                    });
+                }
+
+                let mut params = Vec::new();
+                if !self.check(&TokenKind::RightParen) {
+                    loop {
+                        params.push(self.parse_type()?);
+                        if !self.match_token(&TokenKind::Comma) {
+                            break;
+                        }
+                    }
+                }
+                self.expect(TokenKind::RightParen)?;
+                self.expect(TokenKind::Colon)?;
+                let return_type = Box::new(self.parse_type()?);
+                Ok(Type::Function { params, return_type })
             },
+            // `{ name: string, scores: [i32] }` stands for a struct with
+            // those fields; the same fields give the same struct
+            TokenKind::LeftBrace => {
+                let mut fields: Vec<Parameter> = Vec::new();
+                while !self.check(&TokenKind::RightBrace) {
+                    let field_token = self.peek().clone();
+                    let field = self.expect_identifier()?;
+                    if fields.iter().any(|existing| existing.name == field) {
+                        return Err(error_at(&field_token, format!("Field `{}` appears twice in an object type", field)));
+                    }
+                    self.expect(TokenKind::Colon)?;
+                    let param_type = self.parse_type()?;
+                    fields.push(Parameter { name: field, param_type });
+                    if !self.match_token(&TokenKind::Comma) {
+                        break;
+                    }
+                }
+                self.expect(TokenKind::RightBrace)?;
+
+                let index = match self.records.iter().position(|(existing, _)| *existing == fields) {
+                    Some(index) => index,
+                    None => {
+                        let span = Span { line: token.line, column: token.column };
+                        self.records.push((fields, span));
+                        self.records.len() - 1
+                    }
+                };
+                Ok(Type::Named(self.record_name(index)))
+            }
             TokenKind::LeftBracket => {
                 let element_type = Box::new(self.parse_type()?);
 
@@ -402,7 +871,7 @@ impl Parser {
                             size: Some(size as usize),
                         })
                     } else {
-                        Err(format!("Expected array size, got {:?}", size_token.kind))
+                        Err(error_at(&size_token, format!("Expected array size, got {:?}", size_token.kind)))
                     }
                 } else {
                     self.expect(TokenKind::RightBracket)?;
@@ -412,16 +881,16 @@ impl Parser {
                     })
                 }
             }
-            _ => Err(format!("Expected type, got {:?}", token.kind)),
+            _ => Err(error_at(&token, format!("Expected type, got {:?}", token.kind))),
         }
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, String> {
+    fn parse_expression(&mut self) -> Result<Expression, CompileError> {
         self.parse_assignment()
     }
 
-    fn parse_assignment(&mut self) -> Result<Expression, String> {
-        let expr = self.parse_or()?;
+    fn parse_assignment(&mut self) -> Result<Expression, CompileError> {
+        let expr = self.parse_conditional()?;
 
         if self.match_token(&TokenKind::Equal) {
             let value = self.parse_assignment()?;
@@ -462,7 +931,41 @@ impl Parser {
         Ok(expr)
     }
 
-    fn parse_or(&mut self) -> Result<Expression, String> {
+    // `condition ? then : otherwise`, right-associative so that
+    // `a ? b : c ? d : e` nests in the `otherwise` branch
+    fn parse_conditional(&mut self) -> Result<Expression, CompileError> {
+        let condition = self.parse_range()?;
+        if !self.match_token(&TokenKind::Question) {
+            return Ok(condition);
+        }
+
+        let then = self.parse_expression()?;
+        self.expect(TokenKind::Colon)?;
+        let otherwise = self.parse_conditional()?;
+        Ok(Expression::Conditional {
+            condition: Box::new(condition),
+            then: Box::new(then),
+            otherwise: Box::new(otherwise),
+        })
+    }
+
+    fn parse_range(&mut self) -> Result<Expression, CompileError> {
+        let start = self.parse_or()?;
+
+        if self.check(&TokenKind::DotDot) || self.check(&TokenKind::DotDotEqual) {
+            let inclusive = self.advance().kind == TokenKind::DotDotEqual;
+            let end = self.parse_or()?;
+            return Ok(Expression::Range {
+                start: Box::new(start),
+                end: Box::new(end),
+                inclusive,
+            });
+        }
+
+        Ok(start)
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_and()?;
 
         while self.match_token(&TokenKind::Or) {
@@ -477,7 +980,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_and(&mut self) -> Result<Expression, String> {
+    fn parse_and(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_equality()?;
 
         while self.match_token(&TokenKind::And) {
@@ -492,7 +995,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_equality(&mut self) -> Result<Expression, String> {
+    fn parse_equality(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_comparison()?;
 
         loop {
@@ -519,7 +1022,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_comparison(&mut self) -> Result<Expression, String> {
+    fn parse_comparison(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_bitwise_or()?;
 
         loop {
@@ -546,7 +1049,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_bitwise_or(&mut self) -> Result<Expression, String> {
+    fn parse_bitwise_or(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_bitwise_xor()?;
 
         while self.match_token(&TokenKind::Pipe) {
@@ -561,7 +1064,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_bitwise_xor(&mut self) -> Result<Expression, String> {
+    fn parse_bitwise_xor(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_bitwise_and()?;
 
         while self.match_token(&TokenKind::Caret) {
@@ -576,7 +1079,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_bitwise_and(&mut self) -> Result<Expression, String> {
+    fn parse_bitwise_and(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_shift()?;
 
         while self.match_token(&TokenKind::Ampersand) {
@@ -591,7 +1094,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_shift(&mut self) -> Result<Expression, String> {
+    fn parse_shift(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_term()?;
 
         loop {
@@ -614,7 +1117,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_term(&mut self) -> Result<Expression, String> {
+    fn parse_term(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_factor()?;
 
         loop {
@@ -637,7 +1140,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_factor(&mut self) -> Result<Expression, String> {
+    fn parse_factor(&mut self) -> Result<Expression, CompileError> {
         let mut left = self.parse_unary()?;
 
         loop {
@@ -662,7 +1165,7 @@ impl Parser {
         Ok(left)
     }
 
-    fn parse_unary(&mut self) -> Result<Expression, String> {
+    fn parse_unary(&mut self) -> Result<Expression, CompileError> {
         if self.match_token(&TokenKind::Minus) {
             let operand = self.parse_unary()?;
             return Ok(Expression::Unary {
@@ -690,27 +1193,34 @@ impl Parser {
         self.parse_call()
     }
 
-    fn parse_call(&mut self) -> Result<Expression, String> {
-        let mut expr = self.parse_primary()?;
-
-        loop {
-            if self.match_token(&TokenKind::LeftParen) {
-                let mut args = Vec::new();
+    /// `(expr, ...)`, including the parentheses
+    fn parse_arguments(&mut self) -> Result<Vec<Expression>, CompileError> {
+        self.expect(TokenKind::LeftParen)?;
 
-                if !self.check(&TokenKind::RightParen) {
-                    loop {
-                        args.push(self.parse_expression()?);
-                        if !self.match_token(&TokenKind::Comma) {
-                            break;
-                        }
-                    }
+        let mut args = Vec::new();
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                args.push(self.parse_expression()?);
+                if !self.match_token(&TokenKind::Comma) {
+                    break;
                 }
+            }
+        }
 
-                self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::RightParen)?;
+        Ok(args)
+    }
+
+    fn parse_call(&mut self) -> Result<Expression, CompileError> {
+        let mut expr = self.parse_primary()?;
 
+        loop {
+            if self.check(&TokenKind::LeftParen) {
+                let args = self.parse_arguments()?;
                 expr = Expression::Call {
                     callee: Box::new(expr),
                     args,
+                    resolved_type: None,
                 };
             } else if self.match_token(&TokenKind::LeftBracket) {
                 let index = self.parse_expression()?;
@@ -722,28 +1232,24 @@ impl Parser {
                 };
             } else if self.match_token(&TokenKind::Dot) {
                 // Property access or method call
+                let property_token = self.peek().clone();
                 let property = self.expect_identifier()?;
 
-                // Check if this is a method call (property followed by parentheses)
-                if self.check(&TokenKind::LeftParen) {
-                    self.advance(); // consume '('
-                    let mut args = Vec::new();
-
-                    if !self.check(&TokenKind::RightParen) {
-                        loop {
-                            args.push(self.parse_expression()?);
-                            if !self.match_token(&TokenKind::Comma) {
-                                break;
-                            }
-                        }
+                if let Expression::Identifier(name) = &expr {
+                    let variants = self.enum_variants.get(name);
+                    if variants.is_some_and(|variants| !variants.contains(&property)) {
+                        return Err(error_at(&property_token, format!("Enum `{}` has no variant `{}`", name, property)));
                     }
+                }
 
-                    self.expect(TokenKind::RightParen)?;
-
+                // Check if this is a method call (property followed by parentheses)
+                if self.check(&TokenKind::LeftParen) {
+                    let args = self.parse_arguments()?;
                     expr = Expression::MethodCall {
                         object: Box::new(expr),
                         method: property,
                         args,
+                        resolved_type: None,
                     };
                 } else {
                     // Just property access
@@ -767,34 +1273,79 @@ impl Parser {
         Ok(expr)
     }
 
-    fn parse_primary(&mut self) -> Result<Expression, String> {
-        let token = self.peek();
-
-        match &token.kind {
-            TokenKind::Function => {
-                self.advance();
-                // Parse function expression
-                self.expect(TokenKind::LeftParen)?;
+    /// `(name: type, ...)`, including the parentheses
+    fn parse_parameters(&mut self) -> Result<Vec<Parameter>, CompileError> {
+        self.expect(TokenKind::LeftParen)?;
 
-                let mut params = Vec::new();
-                if !self.check(&TokenKind::RightParen) {
-                    loop {
-                        let param_name = self.expect_identifier()?;
-                        self.expect(TokenKind::Colon)?;
-                        let param_type = self.parse_type()?;
+        let mut params = Vec::new();
+        if !self.check(&TokenKind::RightParen) {
+            loop {
+                let param_name = self.expect_identifier()?;
+                self.expect(TokenKind::Colon)?;
+                let param_type = self.parse_type()?;
 
-                        params.push(crate::ast::Parameter {
-                            name: param_name,
-                            param_type,
-                        });
+                params.push(Parameter {
+                    name: param_name,
+                    param_type,
+                });
 
-                        if !self.match_token(&TokenKind::Comma) {
-                            break;
-                        }
-                    }
+                if !self.match_token(&TokenKind::Comma) {
+                    break;
                 }
+            }
+        }
 
-                self.expect(TokenKind::RightParen)?;
+        self.expect(TokenKind::RightParen)?;
+        Ok(params)
+    }
+
+    // `(` starts an arrow function rather than a parenthesized expression
+    // when it is followed by `name:` or by `)` and then `=>` or `:`
+    fn at_arrow_function(&self) -> bool {
+        let kind = |offset: usize| self.tokens.get(self.current + offset).map(|t| &t.kind);
+        match kind(1) {
+            Some(TokenKind::Identifier(_)) => matches!(kind(2), Some(TokenKind::Colon)),
+            Some(TokenKind::RightParen) => matches!(kind(2), Some(TokenKind::Arrow | TokenKind::Colon)),
+            _ => false,
+        }
+    }
+
+    /// `(x: i32): i32 => x * 2` or `(x: i32) => { ... }`; an expression
+    /// body becomes a single return statement
+    fn parse_arrow_function(&mut self) -> Result<Expression, CompileError> {
+        let params = self.parse_parameters()?;
+
+        let return_type = if self.match_token(&TokenKind::Colon) {
+            Some(self.parse_type()?)
+        } else {
+            None
+        };
+
+        self.expect(TokenKind::Arrow)?;
+
+        let body = if self.check(&TokenKind::LeftBrace) {
+            self.parse_block()?
+        } else {
+            let span = self.span();
+            let value = self.parse_expression()?;
+            self.block(vec![Statement::Return { value: Some(value) }], vec![span])
+        };
+
+        Ok(Expression::FunctionExpression {
+            params,
+            return_type,
+            body,
+        })
+    }
+
+    fn parse_primary(&mut self) -> Result<Expression, CompileError> {
+        let token = self.peek();
+
+        match &token.kind {
+            TokenKind::Function => {
+                self.advance();
+                // Parse function expression
+                let params = self.parse_parameters()?;
 
                 let return_type = if self.match_token(&TokenKind::Colon) {
                     Some(self.parse_type()?)
@@ -822,6 +1373,15 @@ impl Parser {
                     let pattern = if self.peek().kind == TokenKind::Identifier("_".to_string()) {
                         self.advance();
                         crate::ast::MatchPattern::Wildcard
+                    } else if matches!(self.peek().kind, TokenKind::Identifier(_))
+                        && matches!(self.peek_at(1).kind, TokenKind::Dot)
+                    {
+                        // An enum variant, `Color.Red`
+                        crate::ast::MatchPattern::Literal(self.parse_call()?)
+                    } else if let TokenKind::Identifier(name) = &self.peek().kind {
+                        let name = name.clone();
+                        self.advance();
+                        crate::ast::MatchPattern::Binding(name)
                     } else {
                         let pattern_expr = self.parse_primary()?;
                         crate::ast::MatchPattern::Literal(pattern_expr)
@@ -849,17 +1409,22 @@ impl Parser {
                 Ok(Expression::NothingLiteral)
             }
             TokenKind::IntegerLiteral(_) | TokenKind::FloatLiteral(_)
+            | TokenKind::TypedIntegerLiteral(..) | TokenKind::TypedFloatLiteral(..)
             | TokenKind::StringLiteral(_) | TokenKind::RawStringLiteral(_) | TokenKind::BooleanLiteral(_)
-            | TokenKind::Identifier(_) | TokenKind::FStringLiteral(_) => {
+            | TokenKind::CharLiteral(_) | TokenKind::Identifier(_) | TokenKind::FStringLiteral(_) => {
                 let token = self.advance();
                 match token.kind {
                     TokenKind::IntegerLiteral(n) => Ok(Expression::IntegerLiteral(n)),
                     TokenKind::FloatLiteral(f) => Ok(Expression::FloatLiteral(f)),
+                    TokenKind::TypedIntegerLiteral(n, suffix) => Ok(typed_literal(Expression::IntegerLiteral(n), &suffix)),
+                    TokenKind::TypedFloatLiteral(f, suffix) => Ok(typed_literal(Expression::FloatLiteral(f), &suffix)),
                     TokenKind::StringLiteral(s) => Ok(Expression::StringLiteral(s)),
-                    TokenKind::RawStringLiteral(s) => Ok(Expression::StringLiteral(s)), // Treat same as regular string
+                    // The content is already unescaped; codegen escapes it for C
+                    TokenKind::RawStringLiteral(s) => Ok(Expression::StringLiteral(s)),
                     TokenKind::BooleanLiteral(b) => Ok(Expression::BooleanLiteral(b)),
+                    TokenKind::CharLiteral(c) => Ok(Expression::CharLiteral(c)),
                     TokenKind::Identifier(name) => Ok(Expression::Identifier(name)),
-                    TokenKind::FStringLiteral(lexer_parts) => {
+                    TokenKind::FStringLiteral(ref lexer_parts) => {
                         // Convert lexer FStringParts to AST FStringParts
                         use crate::ast::FStringPart as AstFStringPart;
                         use crate::lexer::token::FStringPart as LexerFStringPart;
@@ -868,15 +1433,15 @@ impl Parser {
                         for part in lexer_parts {
                             match part {
                                 LexerFStringPart::Text(text) => {
-                                    ast_parts.push(AstFStringPart::Text(text));
+                                    ast_parts.push(AstFStringPart::Text(text.clone()));
                                 }
                                 LexerFStringPart::Expression(expr_str) => {
-                                    // Parse the expression string
-                                    let mut lexer = crate::lexer::Lexer::new(&expr_str);
-                                    let tokens = lexer.tokenize().map_err(|e| format!("Error lexing f-string expression: {}", e))?;
-                                    let mut parser = Parser::new(tokens);
-                                    let expr = parser.parse_expression()?;
-                                    ast_parts.push(AstFStringPart::Expression(Box::new(expr)));
+                                    let (source, spec) = split_format_spec(expr_str);
+                                    if let Some(spec) = spec {
+                                        validate_format_spec(spec).map_err(|e| error_at(&token, e))?;
+                                    }
+                                    let expr = Self::parse_interpolation(source, &token)?;
+                                    ast_parts.push(AstFStringPart::Expression(Box::new(expr), spec.map(str::to_string)));
                                 }
                             }
                         }
@@ -885,6 +1450,7 @@ impl Parser {
                     _ => unreachable!(),
                 }
             }
+            TokenKind::LeftParen if self.at_arrow_function() => self.parse_arrow_function(),
             TokenKind::LeftParen => {
                 self.advance();
                 let expr = self.parse_expression()?;
@@ -896,17 +1462,28 @@ impl Parser {
                 let mut elements = Vec::new();
 
                 if !self.check(&TokenKind::RightBracket) {
-                    loop {
+                    let first = self.parse_expression()?;
+
+                    // `[value; count]` repeats a single value
+                    if self.match_token(&TokenKind::Semicolon) {
+                        let count = self.parse_expression()?;
+                        self.expect(TokenKind::RightBracket)?;
+                        return Ok(Expression::ArrayRepeat {
+                            value: Box::new(first),
+                            count: Box::new(count),
+                        });
+                    }
+
+                    elements.push(first);
+                    while self.match_token(&TokenKind::Comma) {
                         elements.push(self.parse_expression()?);
-                        if !self.match_token(&TokenKind::Comma) {
-                            break;
-                        }
                     }
                 }
 
                 self.expect(TokenKind::RightBracket)?;
                 Ok(Expression::ArrayLiteral { elements })
             }
+            TokenKind::LeftBrace if !self.is_object_literal_start() => self.parse_block_expression(),
             TokenKind::LeftBrace => {
                 self.advance();
                 let mut properties = Vec::new();
@@ -931,18 +1508,123 @@ impl Parser {
                 Ok(Expression::ObjectLiteral { properties })
             }
             _ => {
-                let t = self.advance();
-                Err(format!("Unexpected token: {:?}", t.kind))
+                // Left in place so recovery can resume at a `;` or `}`
+                let t = self.peek();
+                Err(error_at(t, format!("Unexpected token: {:?}", t.kind)))
             }
         }
     }
 
+    // The text between an f-string's braces, which must be exactly one
+    // expression. Errors point at the f-string itself.
+    fn parse_interpolation(source: &str, fstring: &Token) -> Result<Expression, CompileError> {
+        let mut lexer = crate::lexer::Lexer::new(source);
+        let tokens = lexer
+            .tokenize()
+            .map_err(|e| error_at(fstring, format!("Error lexing f-string expression: {}", e.message)))?;
+        let mut parser = Parser::new(tokens);
+        let expr = parser.parse_expression().map_err(|e| CompileError {
+            span: Some(crate::error::Span { line: fstring.line, column: fstring.column }),
+            ..e
+        })?;
+        if let Some(error) = parser.errors.first().cloned() {
+            return Err(CompileError {
+                span: Some(crate::error::Span { line: fstring.line, column: fstring.column }),
+                ..error
+            });
+        }
+        if !parser.is_at_end() {
+            return Err(error_at(
+                fstring,
+                format!("Unexpected {:?} in f-string expression `{}`", parser.peek().kind, source),
+            ));
+        }
+        Ok(expr)
+    }
+
+    // `{}` and `{ key: ...` open an object literal; any other `{` in
+    // expression position is a block expression
+    fn is_object_literal_start(&self) -> bool {
+        match &self.peek_at(1).kind {
+            TokenKind::RightBrace => true,
+            TokenKind::Identifier(_) => matches!(self.peek_at(2).kind, TokenKind::Colon),
+            _ => false,
+        }
+    }
+
+    fn parse_block_expression(&mut self) -> Result<Expression, CompileError> {
+        self.expect(TokenKind::LeftBrace)?;
+
+        let mut statements = Vec::new();
+        let mut spans = Vec::new();
+
+        loop {
+            if self.check(&TokenKind::RightBrace) || self.is_at_end() {
+                return Err(error_at(self.peek(), "Block expression must end with a value expression"));
+            }
+
+            let span = self.span();
+            if !self.starts_expression_statement() {
+                statements.push(self.parse_statement()?);
+                spans.push(span);
+                continue;
+            }
+
+            let expr = self.parse_expression()?;
+            if self.check(&TokenKind::RightBrace) {
+                self.advance();
+                return Ok(Expression::Block {
+                    body: self.block(statements, spans),
+                    value: Box::new(expr),
+                });
+            }
+
+            self.consume_semicolon()?;
+            statements.push(Statement::Expression(expr));
+            spans.push(span);
+        }
+    }
+
+    fn starts_expression_statement(&self) -> bool {
+        !matches!(
+            self.peek().kind,
+            TokenKind::Export
+                | TokenKind::Import
+                | TokenKind::Function
+                | TokenKind::Pure
+                | TokenKind::Let
+                | TokenKind::Return
+                | TokenKind::If
+                | TokenKind::While
+                | TokenKind::For
+                | TokenKind::Break
+                | TokenKind::Continue
+                | TokenKind::Defer
+                | TokenKind::Switch
+                | TokenKind::Using
+                | TokenKind::Struct
+                | TokenKind::Enum
+                | TokenKind::LeftBrace
+        )
+    }
+
     // Helper methods
 
     fn peek(&self) -> &Token {
         &self.tokens[self.current]
     }
 
+    // Where the next token starts
+    fn span(&self) -> Span {
+        let token = self.peek();
+        Span { line: token.line, column: token.column }
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let index = (self.current + offset).min(self.tokens.len() - 1);
+        &self.tokens[index]
+    }
+
     fn advance(&mut self) -> Token {
         if !self.is_at_end() {
             self.current += 1;
@@ -970,40 +1652,140 @@ impl Parser {
         }
     }
 
-    fn expect(&mut self, kind: TokenKind) -> Result<Token, String> {
+    fn expect(&mut self, kind: TokenKind) -> Result<Token, CompileError> {
         if self.check(&kind) {
             Ok(self.advance())
         } else {
-            Err(format!(
-                "Expected {:?}, got {:?} at {}:{}",
-                kind,
-                self.peek().kind,
-                self.peek().line,
-                self.peek().column
-            ))
+            Err(error_at(self.peek(), format!("Expected {:?}, got {:?}", kind, self.peek().kind)))
         }
     }
 
-    fn expect_identifier(&mut self) -> Result<String, String> {
+    fn expect_identifier(&mut self) -> Result<String, CompileError> {
         let token = self.advance();
         if let TokenKind::Identifier(name) = token.kind {
             Ok(name)
         } else {
-            Err(format!(
-                "Expected identifier, got {:?} at {}:{}",
-                token.kind, token.line, token.column
-            ))
+            Err(error_at(&token, format!("Expected identifier, got {:?}", token.kind)))
         }
     }
 
-    fn consume_semicolon(&mut self) -> Result<(), String> {
+    fn consume_semicolon(&mut self) -> Result<(), CompileError> {
         if self.check(&TokenKind::Semicolon) {
             self.advance();
+        } else if self.strict_semicolons {
+            let last = &self.tokens[self.current.saturating_sub(1)];
+            return Err(error_at(last, format!("Expected ';' after '{}'", last.lexeme)));
         }
         Ok(())
     }
 }
 
+// A parser error at `token`, which the message cites as well
+fn error_at(token: &Token, message: impl Into<String>) -> CompileError {
+    CompileError::at(
+        Stage::Parser,
+        format!("{} at {}:{}", message.into(), token.line, token.column),
+        token.line,
+        token.column,
+    )
+}
+
+// The builtin type spelled `name`
+fn primitive_type(name: &str) -> Option<Type> {
+    let ty = match name {
+        "i8" => Type::I8,
+        "i16" => Type::I16,
+        "i32" => Type::I32,
+        "i64" => Type::I64,
+        "i128" => Type::I128,
+        "u8" => Type::U8,
+        "u16" => Type::U16,
+        "u32" => Type::U32,
+        "u64" => Type::U64,
+        "u128" => Type::U128,
+        "f32" => Type::F32,
+        "f64" => Type::F64,
+        "bool" => Type::Bool,
+        "string" => Type::String,
+        _ => return None,
+    };
+    Some(ty)
+}
+
+// A suffixed literal such as `42u8` is the literal cast to its suffix type
+fn typed_literal(literal: Expression, suffix: &str) -> Expression {
+    Expression::Cast {
+        expr: Box::new(literal),
+        target_type: primitive_type(suffix).expect("the lexer only accepts numeric type suffixes"),
+    }
+}
+
+fn define_as_int(value: &Expression) -> i64 {
+    match value {
+        Expression::BooleanLiteral(b) => *b as i64,
+        Expression::IntegerLiteral(n) => *n,
+        _ => 0,
+    }
+}
+
+fn is_truthy(value: &Expression) -> bool {
+    match value {
+        Expression::BooleanLiteral(b) => *b,
+        Expression::IntegerLiteral(n) => *n != 0,
+        Expression::StringLiteral(s) => !s.is_empty(),
+        _ => false,
+    }
+}
+
+// Split `expr:spec` at the last colon outside brackets and strings that
+// doesn't belong to a `? :`; the part after it is a spec only if it
+// contains no spaces
+fn split_format_spec(source: &str) -> (&str, Option<&str>) {
+    let mut depth = 0i32;
+    let mut in_string = false;
+    let mut open_conditionals = 0;
+    let mut split = None;
+    for (i, ch) in source.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '?' if depth == 0 && !source[i + 1..].starts_with(['=', '?']) && !source[..i].ends_with('?') => {
+                open_conditionals += 1
+            }
+            ':' if depth == 0 && open_conditionals > 0 => open_conditionals -= 1,
+            ':' if depth == 0 => split = Some(i),
+            _ => {}
+        }
+    }
+
+    match split {
+        Some(i) if !source[i + 1..].is_empty() && !source[i + 1..].contains(char::is_whitespace) => {
+            (source[..i].trim(), Some(&source[i + 1..]))
+        }
+        _ => (source, None),
+    }
+}
+
+// Specs follow printf: `[flags][width][.precision][conversion]`, with `b`
+// added for binary; which conversions suit which values is up to codegen
+fn validate_format_spec(spec: &str) -> Result<(), String> {
+    let is_digit = |c: char| c.is_ascii_digit();
+    let rest = spec.trim_start_matches(['-', '+', '#', '0']).trim_start_matches(is_digit);
+    let rest = match rest.strip_prefix('.') {
+        Some(precision) => precision.trim_start_matches(is_digit),
+        None => rest,
+    };
+    match rest {
+        "" | "d" | "i" | "u" | "x" | "X" | "o" | "b" | "f" | "F" | "e" | "E" | "g" | "G" | "s" | "c" => Ok(()),
+        _ => Err(format!(
+            "Unsupported f-string format spec `{}` (expected printf-style flags, width, precision and a conversion such as d, f, x or b)",
+            spec
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1031,6 +1813,28 @@ mod tests {
         assert!(matches!(program.statements[0], Statement::VariableDecl { .. }));
     }
 
+    #[test]
+    fn test_parse_let_mut() {
+        let tokens = Lexer::new("let mut x: i32 = 1; let y = 2;").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        assert!(matches!(program.statements[0], Statement::VariableDecl { is_mut: true, .. }));
+        assert!(matches!(program.statements[1], Statement::VariableDecl { is_mut: false, .. }));
+    }
+
+    #[test]
+    fn test_parse_raw_string() {
+        let tokens = Lexer::new(r#"let path = r"C:\dir";"#).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        match &program.statements[0] {
+            Statement::VariableDecl { initializer: Some(value), .. } => {
+                assert_eq!(value, &Expression::StringLiteral("C:\\dir".to_string()))
+            }
+            other => panic!("Expected a variable declaration, got {:?}", other),
+        }
+    }
+
     #[test]
     fn test_parse_expressions() {
         let mut lexer = Lexer::new("let result = 2 + 3 * 4;");
@@ -1038,4 +1842,604 @@ mod tests {
         let mut parser = Parser::new(tokens);
         let _program = parser.parse().unwrap();
     }
+
+    #[test]
+    fn test_parse_block_expression() {
+        let mut lexer = Lexer::new("let x: i32 = { let a: i32 = 2; a * 21 };");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::VariableDecl { initializer: Some(Expression::Block { body, value }), .. } = &program.statements[0] {
+            assert_eq!(body.statements.len(), 1);
+            assert!(matches!(value.as_ref(), Expression::Binary { op: BinaryOp::Multiply, .. }));
+        } else {
+            panic!("Expected block expression initializer");
+        }
+    }
+
+    #[test]
+    fn test_parse_import() {
+        let mut lexer = Lexer::new("import square, cube from \"mathlib\";");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        assert_eq!(
+            program.statements[0],
+            Statement::Import {
+                names: vec!["square".to_string(), "cube".to_string()],
+                module: "mathlib".to_string(),
+            }
+        );
+
+        let mut lexer = Lexer::new("import { square } from \"lib/mathlib\";");
+        let tokens = lexer.tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert!(matches!(&program.statements[0], Statement::Import { names, module }
+            if names == &["square"] && module == "lib/mathlib"));
+    }
+
+    #[test]
+    fn test_parse_contracts() {
+        let source = "function div(a: i32, b: i32): i32 requires b != 0 requires a >= 0 ensures result >= 0 { return a / b; }";
+        let mut lexer = Lexer::new(source);
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::FunctionDecl { requires, ensures, body, .. } = &program.statements[0] {
+            assert_eq!(requires.len(), 2);
+            assert!(matches!(&ensures[..], [Expression::Binary { op: BinaryOp::GreaterEqual, .. }]));
+            assert_eq!(body.statements.len(), 1);
+        } else {
+            panic!("Expected function declaration");
+        }
+    }
+
+    #[test]
+    fn test_parse_arrow_functions() {
+        let mut lexer = Lexer::new("let f = (x: i32) => x * 2; let g = (): i32 => { return 1; }; let h = (1 + 2) * 3;");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::VariableDecl { initializer: Some(Expression::FunctionExpression { params, return_type, body }), .. } =
+            &program.statements[0]
+        {
+            assert_eq!(params.len(), 1);
+            assert_eq!(return_type, &None);
+            assert!(matches!(&body.statements[..], [Statement::Return { value: Some(Expression::Binary { .. }) }]));
+        } else {
+            panic!("Expected an arrow function");
+        }
+        assert!(matches!(
+            &program.statements[1],
+            Statement::VariableDecl { initializer: Some(Expression::FunctionExpression { return_type: Some(Type::I32), .. }), .. }
+        ));
+        assert!(matches!(
+            &program.statements[2],
+            Statement::VariableDecl { initializer: Some(Expression::Binary { op: BinaryOp::Multiply, .. }), .. }
+        ));
+    }
+
+    #[test]
+    fn test_parse_object_literal() {
+        let mut lexer = Lexer::new("let p = { x: 1, y: 2 };");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::VariableDecl { initializer: Some(Expression::ObjectLiteral { properties }), .. } = &program.statements[0] {
+            assert_eq!(
+                properties,
+                &vec![
+                    Property { key: "x".to_string(), value: Expression::IntegerLiteral(1) },
+                    Property { key: "y".to_string(), value: Expression::IntegerLiteral(2) },
+                ]
+            );
+        } else {
+            panic!("Expected an object literal initializer");
+        }
+    }
+
+    #[test]
+    fn test_parse_property_access_and_method_calls() {
+        let parse_expr = |source: &str| {
+            let mut lexer = Lexer::new(source);
+            let tokens = lexer.tokenize().unwrap();
+            let mut parser = Parser::new(tokens);
+            match parser.parse().unwrap().statements.remove(0) {
+                Statement::Expression(expr) => expr,
+                other => panic!("Expected an expression statement, got {:?}", other),
+            }
+        };
+        let ident = |name: &str| Box::new(Expression::Identifier(name.to_string()));
+
+        assert_eq!(
+            parse_expr("arr.length;"),
+            Expression::PropertyAccess { object: ident("arr"), property: "length".to_string() }
+        );
+        assert_eq!(
+            parse_expr("s.trim();"),
+            Expression::MethodCall { object: ident("s"), method: "trim".to_string(), args: vec![], resolved_type: None }
+        );
+        assert_eq!(
+            parse_expr("a.b.c;"),
+            Expression::PropertyAccess {
+                object: Box::new(Expression::PropertyAccess { object: ident("a"), property: "b".to_string() }),
+                property: "c".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_fstring_interpolations() {
+        let parse_expr = |source: &str| -> Result<Expression, String> {
+            let mut lexer = Lexer::new(source);
+            let tokens = lexer.tokenize().unwrap();
+            let mut parser = Parser::new(tokens);
+            match parser.parse().map_err(|errors| errors[0].message.clone())?.statements.remove(0) {
+                Statement::Expression(expr) => Ok(expr),
+                other => panic!("Expected an expression statement, got {:?}", other),
+            }
+        };
+        let ident = |name: &str| Box::new(Expression::Identifier(name.to_string()));
+
+        assert_eq!(
+            parse_expr("f\"sum is {a + b}\";"),
+            Ok(Expression::FString {
+                parts: vec![
+                    FStringPart::Text("sum is ".to_string()),
+                    FStringPart::Expression(Box::new(Expression::Binary {
+                        left: ident("a"),
+                        op: BinaryOp::Add,
+                        right: ident("b"),
+                    }), None),
+                ],
+            })
+        );
+        assert_eq!(
+            parse_expr("f\"name={user.name}\";"),
+            Ok(Expression::FString {
+                parts: vec![
+                    FStringPart::Text("name=".to_string()),
+                    FStringPart::Expression(Box::new(Expression::PropertyAccess {
+                        object: ident("user"),
+                        property: "name".to_string(),
+                    }), None),
+                ],
+            })
+        );
+        assert_eq!(
+            parse_expr("f\"{s.substring(0, 2)}!\";"),
+            Ok(Expression::FString {
+                parts: vec![
+                    FStringPart::Expression(Box::new(Expression::MethodCall {
+                        object: ident("s"),
+                        method: "substring".to_string(),
+                        args: vec![Expression::IntegerLiteral(0), Expression::IntegerLiteral(2)],
+                        resolved_type: None,
+                    }), None),
+                    FStringPart::Text("!".to_string()),
+                ],
+            })
+        );
+        assert!(parse_expr("f\"{a b}\";").is_err());
+    }
+
+    #[test]
+    fn test_parse_fstring_format_specs() {
+        let parse_expr = |source: &str| -> Result<Expression, String> {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            let program = Parser::new(tokens).parse().map_err(|errors| errors[0].message.clone())?;
+            match program.statements.into_iter().next() {
+                Some(Statement::Expression(expr)) => Ok(expr),
+                other => panic!("Expected an expression statement, got {:?}", other),
+            }
+        };
+
+        assert_eq!(
+            parse_expr("f\"{n:x}\";"),
+            Ok(Expression::FString {
+                parts: vec![FStringPart::Expression(
+                    Box::new(Expression::Identifier("n".to_string())),
+                    Some("x".to_string()),
+                )],
+            })
+        );
+        // A colon inside brackets belongs to the expression
+        assert_eq!(
+            parse_expr("f\"{ {a: 1}.a }\";"),
+            Ok(Expression::FString {
+                parts: vec![FStringPart::Expression(
+                    Box::new(Expression::PropertyAccess {
+                        object: Box::new(Expression::ObjectLiteral {
+                            properties: vec![Property {
+                                key: "a".to_string(),
+                                value: Expression::IntegerLiteral(1),
+                            }],
+                        }),
+                        property: "a".to_string(),
+                    }),
+                    None,
+                )],
+            })
+        );
+        assert!(parse_expr("f\"{n:q}\";").is_err());
+
+        // printf-style width, flags and precision
+        for spec in ["04d", ".2f", "-8s", "+.3e", "#x", "10", ".1"] {
+            assert!(parse_expr(&format!("f\"{{n:{}}}\";", spec)).is_ok(), "{}", spec);
+        }
+        assert!(parse_expr("f\"{n:2.x3}\";").is_err());
+        assert!(parse_expr("f\"{n:d4}\";").is_err());
+    }
+
+    #[test]
+    fn test_parse_conditional() {
+        let parse_init = |source: &str| {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            match Parser::new(tokens).parse().unwrap().statements.remove(0) {
+                Statement::VariableDecl { initializer: Some(value), .. } => value,
+                other => panic!("Expected a variable declaration, got {:?}", other),
+            }
+        };
+        let ident = |name: &str| Box::new(Expression::Identifier(name.to_string()));
+
+        // Right-associative: the nested conditional is the `otherwise` branch
+        assert_eq!(
+            parse_init("let x = a ? b : c ? d : e;"),
+            Expression::Conditional {
+                condition: ident("a"),
+                then: ident("b"),
+                otherwise: Box::new(Expression::Conditional {
+                    condition: ident("c"),
+                    then: ident("d"),
+                    otherwise: ident("e"),
+                }),
+            }
+        );
+        // Looser than comparison, and `?=` is still its own operator
+        assert!(matches!(
+            parse_init("let x = a > b ? a : b;"),
+            Expression::Conditional { condition, .. } if matches!(*condition, Expression::Binary { op: BinaryOp::Greater, .. })
+        ));
+        assert!(matches!(parse_init("let x = a ?= b;"), Expression::Binary { .. }));
+
+        // A conditional's colon is not a format spec
+        let (expr, spec) = split_format_spec("a?b:c");
+        assert_eq!((expr, spec), ("a?b:c", None));
+        assert_eq!(split_format_spec("a ? b : c:x"), ("a ? b : c", Some("x")));
+    }
+
+    #[test]
+    fn test_parse_using() {
+        let mut lexer = Lexer::new("using (r = acquire(); release(r)) { work(r); }");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::Block(block) = &program.statements[0] {
+            assert_eq!(block.statements.len(), 3);
+            assert!(matches!(&block.statements[0], Statement::VariableDecl { name, .. } if name == "r"));
+            assert!(matches!(&block.statements[1], Statement::Defer { .. }));
+        } else {
+            panic!("Expected using to desugar into a block");
+        }
+    }
+
+    #[test]
+    fn test_parse_match_literal_and_wildcard() {
+        let mut lexer = Lexer::new("let y: i32 = match x { 1 => 10, _ => 0 };");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::VariableDecl { initializer: Some(Expression::Match { expr, arms }), .. } =
+            &program.statements[0]
+        {
+            assert_eq!(**expr, Expression::Identifier("x".to_string()));
+            assert_eq!(arms.len(), 2);
+            assert_eq!(arms[0].pattern, MatchPattern::Literal(Expression::IntegerLiteral(1)));
+            assert_eq!(arms[0].body, Expression::IntegerLiteral(10));
+            assert_eq!(arms[1].pattern, MatchPattern::Wildcard);
+            assert_eq!(arms[1].body, Expression::IntegerLiteral(0));
+        } else {
+            panic!("Expected a match initializer");
+        }
+    }
+
+    #[test]
+    fn test_parse_struct_declaration() {
+        let tokens = Lexer::new("struct Point { x: i32, y: f64 } let origin: Point = { x: 0, y: 0.0 };").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        assert_eq!(program.statements[0], Statement::StructDecl {
+            name: "Point".to_string(),
+            fields: vec![
+                Parameter { name: "x".to_string(), param_type: Type::I32 },
+                Parameter { name: "y".to_string(), param_type: Type::F64 },
+            ],
+            methods: vec![],
+            is_export: false,
+        });
+        assert!(matches!(&program.statements[1],
+            Statement::VariableDecl { var_type: Some(Type::Named(name)), .. } if name == "Point"));
+
+        // A type can be used before it is declared
+        let tokens = Lexer::new("let p: Point = 0; struct Point { x: i32 }").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert!(matches!(&program.statements[0],
+            Statement::VariableDecl { var_type: Some(Type::Named(name)), .. } if name == "Point"));
+
+        for (source, message) in [
+            ("struct P { x: i32 } struct P { y: i32 }", "Type `P` is already defined at 1:28"),
+            ("struct P { x: i32, x: i32 }", "Field `x` appears twice in struct `P` at 1:20"),
+            ("struct P { x: i32, function x(): i32 { return 1; } }", "`x` is already a member of struct `P` at 1:29"),
+            ("struct P { function x() {} x: i32 }", "`x` is already a member of struct `P` at 1:28"),
+            ("struct P { x: i32, function f(self: P) {} }", "Method `f` cannot declare `self` itself at 1:29"),
+        ] {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            assert_eq!(Parser::new(tokens).parse().unwrap_err()[0].message, message);
+        }
+    }
+
+    #[test]
+    fn test_parse_struct_methods() {
+        let tokens = Lexer::new("struct Counter { count: i32, function bump(by: i32) { self.count += by; } }").tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        let Statement::StructDecl { fields, methods, .. } = &program.statements[0] else {
+            panic!("Expected a struct: {:?}", program.statements[0]);
+        };
+        assert_eq!(fields.len(), 1);
+        let [Statement::FunctionDecl { name, params, .. }] = methods.as_slice() else {
+            panic!("Expected one method: {:?}", methods);
+        };
+        assert_eq!(name, "bump");
+        assert_eq!(params, &vec![
+            Parameter { name: "self".to_string(), param_type: Type::Named("Counter".to_string()) },
+            Parameter { name: "by".to_string(), param_type: Type::I32 },
+        ]);
+    }
+
+    #[test]
+    fn test_parse_match_binding() {
+        let mut lexer = Lexer::new("let y: i32 = match (compute()) { 0 => 1, n => n * 2 };");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::VariableDecl { initializer: Some(Expression::Match { arms, .. }), .. } =
+            &program.statements[0]
+        {
+            assert!(matches!(arms[0].pattern, MatchPattern::Literal(_)));
+            assert_eq!(arms[1].pattern, MatchPattern::Binding("n".to_string()));
+        } else {
+            panic!("Expected a match initializer");
+        }
+    }
+
+    #[test]
+    fn test_missing_semicolon_strict_and_lenient() {
+        let source = "function main(): i32 { let x: i32 = 1\n return x; }";
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        assert!(Parser::new(tokens).parse().is_ok());
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let err = Parser::new(tokens).with_strict_semicolons(true).parse().unwrap_err()[0].message.clone();
+        assert!(err.contains("Expected ';' after '1' at 1:"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_parse_array_repeat() {
+        let mut lexer = Lexer::new("let zeros: [i32; 10] = [0; 10];");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        assert!(matches!(
+            &program.statements[0],
+            Statement::VariableDecl { initializer: Some(Expression::ArrayRepeat { .. }), .. }
+        ));
+    }
+
+    #[test]
+    fn test_when_uses_defines() {
+        let source = "when (DEBUG) { function log_debug(): i32 { return 1; } }
+            when (not DEBUG and LEVEL > 1) { function quiet(): i32 { return 0; } }
+            function main(): i32 { return 0; }";
+
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        assert_eq!(program.statements.len(), 1);
+
+        let defines = HashMap::from([parse_define("DEBUG").unwrap()]);
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).with_defines(defines).parse().unwrap();
+        assert_eq!(program.statements.len(), 2);
+        assert!(matches!(&program.statements[0], Statement::FunctionDecl { name, .. } if name == "log_debug"));
+
+        let defines = HashMap::from([
+            parse_define("DEBUG=false").unwrap(),
+            parse_define("LEVEL=2").unwrap(),
+        ]);
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).with_defines(defines).parse().unwrap();
+        assert!(matches!(&program.statements[0], Statement::FunctionDecl { name, .. } if name == "quiet"));
+    }
+
+    #[test]
+    fn test_parse_for_in_range_without_parens() {
+        let mut lexer = Lexer::new("for i in 1..=n { total = total + i; }");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::ForIn { variable, iterable, body } = &program.statements[0] {
+            assert_eq!(variable, "i");
+            assert_eq!(
+                iterable,
+                &Expression::Range {
+                    start: Box::new(Expression::IntegerLiteral(1)),
+                    end: Box::new(Expression::Identifier("n".to_string())),
+                    inclusive: true,
+                }
+            );
+            assert_eq!(body.statements.len(), 1);
+        } else {
+            panic!("Expected a for-in loop");
+        }
+    }
+
+    #[test]
+    fn test_parse_range_collect() {
+        let mut lexer = Lexer::new("(0..n + 1).collect();");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        let program = parser.parse().unwrap();
+
+        if let Statement::Expression(Expression::MethodCall { object, method, .. }) = &program.statements[0] {
+            assert_eq!(method, "collect");
+            assert!(matches!(object.as_ref(), Expression::Range { end, .. }
+                if matches!(end.as_ref(), Expression::Binary { .. })));
+        } else {
+            panic!("Expected a collect() call on a range");
+        }
+    }
+
+    #[test]
+    fn test_unknown_type_reports_location() {
+        let mut lexer = Lexer::new("function main(): i32 {\n    let p: Point = 0;\n    return 0;\n}");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+
+        let err = parser.parse().unwrap_err()[0].message.clone();
+        assert_eq!(err, "Unknown type `Point` at 2:12");
+    }
+
+    #[test]
+    fn test_parse_object_types() {
+        let source = "struct Record0 { x: i32 }
+            let a: [{ name: string, scores: [i32] }] = [];
+            let b: { name: string, scores: [i32] } = a[0];
+            let c: { x: i32 } = 0;";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        // Each distinct object type is declared once, after the program,
+        // skipping names the file uses itself
+        let var_type = |index: usize| match &program.statements[index] {
+            Statement::VariableDecl { var_type: Some(ty), .. } => ty.clone(),
+            other => panic!("Expected a typed variable: {:?}", other),
+        };
+        let record = Type::Named("Record1".to_string());
+        assert_eq!(var_type(1), Type::Array { element_type: Box::new(record.clone()), size: None });
+        assert_eq!(var_type(2), record);
+        assert_eq!(var_type(3), Type::Named("Record2".to_string()));
+        assert_eq!(program.statements.len(), 6);
+        assert_eq!(program.statements[4], Statement::StructDecl {
+            name: "Record1".to_string(),
+            fields: vec![
+                Parameter { name: "name".to_string(), param_type: Type::String },
+                Parameter {
+                    name: "scores".to_string(),
+                    param_type: Type::Array { element_type: Box::new(Type::I32), size: None },
+                },
+            ],
+            methods: vec![],
+            is_export: false,
+        });
+
+        let tokens = Lexer::new("let p: { x: i32, x: i32 } = 0;").tokenize().unwrap();
+        assert_eq!(
+            Parser::new(tokens).parse().unwrap_err()[0].message,
+            "Field `x` appears twice in an object type at 1:18"
+        );
+    }
+
+    #[test]
+    fn test_parse_enum_declaration() {
+        let source = "enum Color { Red, Green, } let c: Color = match x { Color.Red => 1, _ => 2 };";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        assert_eq!(program.statements[0], Statement::EnumDecl {
+            name: "Color".to_string(),
+            variants: vec!["Red".to_string(), "Green".to_string()],
+            is_export: false,
+        });
+        if let Statement::VariableDecl { var_type, initializer: Some(Expression::Match { arms, .. }), .. } = &program.statements[1] {
+            assert_eq!(var_type, &Some(Type::Named("Color".to_string())));
+            assert_eq!(arms[0].pattern, MatchPattern::Literal(Expression::PropertyAccess {
+                object: Box::new(Expression::Identifier("Color".to_string())),
+                property: "Red".to_string(),
+            }));
+        } else {
+            panic!("Expected a match initializer");
+        }
+
+        for (source, message) in [
+            ("enum C { A } let x = C.B;", "Enum `C` has no variant `B` at 1:24"),
+            ("enum C { A, A }", "Variant `A` appears twice in enum `C` at 1:13"),
+            ("struct C { a: i32 } enum C { A }", "Type `C` is already defined at 1:26"),
+        ] {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            assert_eq!(Parser::new(tokens).parse().unwrap_err()[0].message, message);
+        }
+    }
+
+    #[test]
+    fn test_parse_fallthrough() {
+        let mut lexer = Lexer::new("switch (x) { case 1: a(); fallthrough; case 2: b(); }");
+        let tokens = lexer.tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+
+        if let Statement::Switch { cases, .. } = &program.statements[0] {
+            assert!(cases[0].fallthrough);
+            assert!(!cases[1].fallthrough);
+        } else {
+            panic!("Expected a switch statement");
+        }
+
+        for source in [
+            "switch (x) { case 1: fallthrough; }",
+            "switch (x) { case 1: fallthrough; a(); case 2: b(); }",
+            "function f(): i32 { fallthrough; }",
+        ] {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            assert!(Parser::new(tokens).parse().is_err(), "accepted: {}", source);
+        }
+    }
+
+    #[test]
+    fn test_block_expression_requires_value() {
+        let mut lexer = Lexer::new("let x: i32 = { let a: i32 = 2; };");
+        let tokens = lexer.tokenize().unwrap();
+        let mut parser = Parser::new(tokens);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn test_reports_every_broken_statement() {
+        let source = "function main(): i32 {
+    let a: i32 = (1 + ;
+    let = 5;
+    let b: i32 = 2;
+    return b;
+}
+function other(): i32 { return * 2; }
+function last(): i32 { return 3; }";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let errors = Parser::new(tokens).parse().unwrap_err();
+
+        let messages: Vec<&str> = errors.iter().map(|e| e.message.as_str()).collect();
+        assert_eq!(messages, [
+            "Unexpected token: Semicolon at 2:23",
+            "Expected identifier, got Equal at 3:9",
+            "Unexpected token: Star at 7:32",
+        ]);
+    }
 }
@@ -0,0 +1,181 @@
+//! Closures capture by value: creating a function expression copies the
+//! variables it uses into the closure. A local `let` the closure assigns
+//! is instead kept in a heap cell both sides share. Parameters, loop
+//! variables, arrays and globals have no cell, so assigning one inside a
+//! closure would never reach the variable it came from and is rejected.
+
+use super::Diagnostics;
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Check that no function expression assigns a captured variable that
+/// can't be shared with it
+pub fn check_captures(program: &Program) -> Result<(), Vec<CompileError>> {
+    let mut checker = CaptureChecker {
+        scopes: vec![HashMap::new()],
+        closure_depth: 0,
+        diagnostics: Diagnostics::new(program),
+    };
+    checker.visit_program(program);
+    checker.diagnostics.into_result()
+}
+
+#[derive(Clone, Copy)]
+struct Binding {
+    // How many function expressions enclose the declaration
+    closure_depth: usize,
+    // Whether codegen can keep it in a cell
+    shareable: bool,
+}
+
+struct CaptureChecker<'ast> {
+    // Innermost scope last
+    scopes: Vec<HashMap<&'ast str, Binding>>,
+    closure_depth: usize,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl<'ast> CaptureChecker<'ast> {
+    fn in_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &'ast str, shareable: bool) {
+        let binding = Binding { closure_depth: self.closure_depth, shareable };
+        self.scopes.last_mut().expect("global scope").insert(name, binding);
+    }
+
+    fn check_assignment(&mut self, name: &str) {
+        let binding = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+        if binding.is_some_and(|binding| binding.closure_depth < self.closure_depth && !binding.shareable) {
+            self.diagnostics.report(format!(
+                "Cannot assign to `{}` inside a closure: it is captured as a copy, so the change would be lost",
+                name
+            ));
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for CaptureChecker<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.in_scope(|checker| walk_block(checker, block));
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::FunctionDecl { params, .. } => self.in_scope(|checker| {
+                for param in params {
+                    checker.declare(&param.name, false);
+                }
+                walk_statement(checker, stmt);
+            }),
+            Statement::VariableDecl { name, var_type, .. } => {
+                walk_statement(self, stmt);
+                let is_global = self.scopes.len() == 1;
+                let is_array = matches!(var_type, Some(Type::Array { .. }));
+                self.declare(name, !is_global && !is_array);
+            }
+            Statement::For { init, condition, increment, body } => self.in_scope(|checker| {
+                if let Some(init) = init {
+                    walk_statement(checker, init);
+                    if let Statement::VariableDecl { name, .. } = init.as_ref() {
+                        checker.declare(name, false);
+                    }
+                }
+                if let Some(condition) = condition {
+                    checker.visit_expression(condition);
+                }
+                if let Some(increment) = increment {
+                    checker.visit_expression(increment);
+                }
+                checker.visit_block(body);
+            }),
+            Statement::ForIn { variable, iterable, body } => {
+                self.visit_expression(iterable);
+                self.in_scope(|checker| {
+                    checker.declare(variable, false);
+                    checker.visit_block(body);
+                });
+            }
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        match expr {
+            Expression::Assignment { target, .. } => {
+                if let Expression::Identifier(name) = target.as_ref() {
+                    self.check_assignment(name);
+                }
+                walk_expression(self, expr);
+            }
+            Expression::FunctionExpression { params, .. } => {
+                self.closure_depth += 1;
+                self.in_scope(|checker| {
+                    for param in params {
+                        checker.declare(&param.name, false);
+                    }
+                    walk_expression(checker, expr);
+                });
+                self.closure_depth -= 1;
+            }
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(source: &str) -> Result<(), String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        check_captures(&Parser::new(tokens).parse().unwrap()).map_err(crate::sema::messages)
+    }
+
+    #[test]
+    fn test_assigning_unshareable_capture_errors() {
+        assert_eq!(
+            check(
+                "function main(n: i32): i32 {
+                    let bump: function = function(a: i32, b: i32): i32 { n = n + a; return n; };
+                    return bump(1, 0);
+                }"
+            )
+            .unwrap_err(),
+            "Cannot assign to `n` inside a closure: it is captured as a copy, so the change would be lost at 2:74"
+        );
+        assert!(check("let mut total: i32 = 0; function main(): i32 { let f: function = function(): i32 { total += 1; return 0; }; return 0; }").is_err());
+        assert!(check("function main(): i32 { for (let mut i: i32 = 0; i < 3; i += 1) { let f: function = function(): i32 { i = 5; return 0; }; } return 0; }").is_err());
+    }
+
+    #[test]
+    fn test_closure_may_assign_captured_locals() {
+        check(
+            "function main(n: i32): i32 {
+                let mut count: i32 = 0;
+                let f: function = function(a: i32, b: i32): i32 {
+                    let mut local: i32 = a;
+                    local = local + count;
+                    count = count + 1;
+                    a = b;
+                    return local;
+                };
+                count = 5;
+                n = 1;
+                return f(1, 2);
+            }",
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,320 @@
+//! Definite-assignment checking for `let x: T;` declarations without an
+//! initializer: every read must come after an assignment on all paths.
+//!
+//! The analysis tracks, at each point, which such variables may still be
+//! unassigned. Branches merge by union, and code after `return`, `break`
+//! or `continue` is unreachable and merges as if absent. Deferred
+//! statements and closure bodies are not checked.
+
+use super::{finish, Diagnostics};
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Check that no variable declared without an initializer is read before
+/// it has been assigned on every path leading to the read
+pub fn check_definite_assignment(program: &Program) -> Result<(), Vec<CompileError>> {
+    let mut errors = Vec::new();
+    for stmt in &program.statements {
+        if let Statement::FunctionDecl { name, body, .. } = stmt {
+            let mut checker = AssignmentChecker {
+                function: name,
+                flow: Flow::entry(),
+                scopes: Vec::new(),
+                breaks: Vec::new(),
+                reported: HashSet::new(),
+                diagnostics: Diagnostics::new(program),
+            };
+            checker.visit_block(body);
+            errors.extend(checker.diagnostics.errors);
+        }
+    }
+    finish(errors)
+}
+
+// Variables that may be unassigned at one program point
+#[derive(Debug, Clone)]
+struct Flow {
+    unassigned: HashSet<String>,
+    reachable: bool,
+}
+
+impl Flow {
+    fn entry() -> Self {
+        Flow { unassigned: HashSet::new(), reachable: true }
+    }
+
+    fn unreachable() -> Self {
+        Flow { unassigned: HashSet::new(), reachable: false }
+    }
+
+    // The state where two paths join
+    fn merge(self, other: Flow) -> Flow {
+        match (self.reachable, other.reachable) {
+            (_, false) => self,
+            (false, true) => other,
+            (true, true) => Flow {
+                unassigned: self.unassigned.union(&other.unassigned).cloned().collect(),
+                reachable: true,
+            },
+        }
+    }
+}
+
+struct AssignmentChecker<'ast> {
+    function: &'ast str,
+    flow: Flow,
+    // Per block, each name declared in it and whether the name it shadows
+    // was unassigned, restored when the block ends
+    scopes: Vec<Vec<(&'ast str, bool)>>,
+    // Per enclosing loop or switch, the states at each `break`
+    breaks: Vec<Vec<Flow>>,
+    reported: HashSet<&'ast str>,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl<'ast> AssignmentChecker<'ast> {
+    fn declare(&mut self, name: &'ast str, unassigned: bool) {
+        let shadowed = self.flow.unassigned.contains(name);
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.push((name, shadowed));
+        }
+        if unassigned {
+            self.flow.unassigned.insert(name.to_string());
+        } else {
+            self.flow.unassigned.remove(name);
+        }
+    }
+
+    fn in_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(Vec::new());
+        f(self);
+        for (name, shadowed) in self.scopes.pop().unwrap_or_default().into_iter().rev() {
+            if shadowed {
+                self.flow.unassigned.insert(name.to_string());
+            } else {
+                self.flow.unassigned.remove(name);
+            }
+        }
+    }
+
+    // Run a loop body that may execute zero or more times; afterwards
+    // control is wherever the condition failed or a `break` jumped
+    fn visit_loop(&mut self, condition: Option<&'ast Expression>, body: impl FnOnce(&mut Self)) {
+        if let Some(condition) = condition {
+            self.visit_expression(condition);
+        }
+        let runs_forever = matches!(condition, None | Some(Expression::BooleanLiteral(true)));
+        self.visit_loop_body(runs_forever, body);
+    }
+
+    fn visit_loop_body(&mut self, runs_forever: bool, body: impl FnOnce(&mut Self)) {
+        let entry = self.flow.clone();
+
+        self.breaks.push(Vec::new());
+        body(self);
+        let breaks = self.breaks.pop().unwrap_or_default();
+
+        let exit = if runs_forever { Flow::unreachable() } else { entry };
+        self.flow = breaks.into_iter().fold(exit, Flow::merge);
+    }
+}
+
+impl<'ast> Visitor<'ast> for AssignmentChecker<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.in_scope(|checker| walk_block(checker, block));
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::VariableDecl { name, var_type, initializer, .. } => {
+                if let Some(init) = initializer {
+                    self.visit_expression(init);
+                }
+                // Arrays start out empty rather than uninitialized
+                let is_array = matches!(var_type, Some(Type::Array { .. }));
+                self.declare(name, initializer.is_none() && !is_array);
+            }
+            Statement::Return { value } => {
+                if let Some(value) = value {
+                    self.visit_expression(value);
+                }
+                self.flow = Flow::unreachable();
+            }
+            Statement::Break => {
+                let state = std::mem::replace(&mut self.flow, Flow::unreachable());
+                if let Some(breaks) = self.breaks.last_mut() {
+                    breaks.push(state);
+                }
+            }
+            Statement::Continue => self.flow = Flow::unreachable(),
+            Statement::If { condition, then_branch, else_branch } => {
+                self.visit_expression(condition);
+                let entry = self.flow.clone();
+                self.visit_block(then_branch);
+                let after_then = std::mem::replace(&mut self.flow, entry);
+                if let Some(else_branch) = else_branch {
+                    self.visit_statement(else_branch);
+                }
+                let after_else = std::mem::replace(&mut self.flow, Flow::unreachable());
+                self.flow = after_then.merge(after_else);
+            }
+            Statement::While { condition, body } => {
+                self.visit_loop(Some(condition), |checker| checker.visit_block(body));
+            }
+            Statement::For { init, condition, increment, body } => {
+                self.in_scope(|checker| {
+                    if let Some(init) = init {
+                        checker.visit_statement(init);
+                    }
+                    checker.visit_loop(condition.as_ref(), |checker| {
+                        checker.visit_block(body);
+                        if let Some(increment) = increment {
+                            checker.visit_expression(increment);
+                        }
+                    });
+                });
+            }
+            Statement::ForIn { variable, iterable, body } => {
+                self.visit_expression(iterable);
+                self.in_scope(|checker| {
+                    checker.declare(variable, false);
+                    checker.visit_loop_body(false, |checker| checker.visit_block(body));
+                });
+            }
+            Statement::Switch { expr, cases, default } => {
+                self.visit_expression(expr);
+                let entry = self.flow.clone();
+                // Without a default, no case may run at all
+                let mut exit = if default.is_some() { Flow::unreachable() } else { entry.clone() };
+
+                self.breaks.push(Vec::new());
+                for body in cases.iter().map(|case| &case.body).chain(default) {
+                    self.flow = entry.clone();
+                    self.visit_block(body);
+                    exit = exit.merge(std::mem::replace(&mut self.flow, Flow::unreachable()));
+                }
+                let breaks = self.breaks.pop().unwrap_or_default();
+                self.flow = breaks.into_iter().fold(exit, Flow::merge);
+            }
+            Statement::Defer { .. } | Statement::FunctionDecl { .. } => {}
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        match expr {
+            Expression::Identifier(name) => {
+                if self.flow.reachable && self.flow.unassigned.contains(name) && self.reported.insert(name) {
+                    self.diagnostics.report(format!(
+                        "Use of possibly-uninitialized variable `{}` in function `{}`",
+                        name, self.function
+                    ));
+                }
+            }
+            Expression::Assignment { target, value } => {
+                self.visit_expression(value);
+                match target.as_ref() {
+                    Expression::Identifier(name) => {
+                        self.flow.unassigned.remove(name);
+                    }
+                    other => self.visit_expression(other),
+                }
+            }
+            Expression::Binary { left, op: BinaryOp::And | BinaryOp::Or, right } => {
+                // The right operand may not run
+                self.visit_expression(left);
+                let entry = self.flow.clone();
+                self.visit_expression(right);
+                self.flow = entry;
+            }
+            Expression::Conditional { condition, then, otherwise } => {
+                // Only one branch runs
+                self.visit_expression(condition);
+                let entry = self.flow.clone();
+                self.visit_expression(then);
+                let after_then = std::mem::replace(&mut self.flow, entry);
+                self.visit_expression(otherwise);
+                self.flow = after_then.merge(std::mem::replace(&mut self.flow, Flow::unreachable()));
+            }
+            Expression::Match { expr, arms } => {
+                self.visit_expression(expr);
+                let entry = self.flow.clone();
+                let mut exit = Flow::unreachable();
+                for arm in arms {
+                    self.flow = entry.clone();
+                    self.in_scope(|checker| {
+                        match &arm.pattern {
+                            MatchPattern::Literal(pattern) => checker.visit_expression(pattern),
+                            MatchPattern::Binding(name) => checker.declare(name, false),
+                            MatchPattern::Wildcard => {}
+                        }
+                        checker.visit_expression(&arm.body);
+                    });
+                    exit = exit.merge(std::mem::replace(&mut self.flow, Flow::unreachable()));
+                }
+                self.flow = if arms.is_empty() { entry } else { exit };
+            }
+            Expression::Block { body, value } => {
+                self.in_scope(|checker| {
+                    walk_block(checker, body);
+                    checker.visit_expression(value);
+                });
+            }
+            Expression::FunctionExpression { .. } => {}
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(body: &str) -> Result<(), String> {
+        let source = format!("function main(): i32 {{ {} }}", body);
+        let tokens = Lexer::new(&source).tokenize().unwrap();
+        check_definite_assignment(&Parser::new(tokens).parse().unwrap()).map_err(crate::sema::messages)
+    }
+
+    #[test]
+    fn test_read_before_assignment_is_rejected() {
+        assert_eq!(
+            check("let x: i32; let y: i32 = x + 1; x = 2; return y;").unwrap_err(),
+            "Use of possibly-uninitialized variable `x` in function `main` at 1:36"
+        );
+
+        // Assigned on only one branch
+        let err = check("let x: i32; if (true) { x = 1; } return x;").unwrap_err();
+        assert!(err.contains("`x`"), "{}", err);
+
+        // A loop body may not run
+        let err = check("let x: i32; let n: i32 = 0; while (n < 3) { x = n; n = n + 1; } return x;").unwrap_err();
+        assert!(err.contains("`x`"), "{}", err);
+    }
+
+    #[test]
+    fn test_assignment_on_every_path_is_accepted() {
+        check("let x: i32; x = 5; return x;").unwrap();
+        check("let x: i32; if (true) { x = 1; } else { x = 2; } return x;").unwrap();
+        check("let x: i32; if (false) { return 0; } else { x = 2; } return x;").unwrap();
+        check("let x: i32; while (true) { x = 1; break; } return x;").unwrap();
+        check(
+            "let x: i32;
+            switch (3) {
+                case 1: x = 10;
+                default: x = 0;
+            }
+            return x;",
+        )
+        .unwrap();
+        check("let xs: [i32]; xs.push(1); return xs.length;").unwrap();
+    }
+}
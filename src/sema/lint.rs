@@ -0,0 +1,126 @@
+//! Warnings for code that compiles but is probably a mistake.
+
+use crate::ast::*;
+use crate::optimize::constant_condition;
+
+/// Warn about `while` loops whose condition is always true and whose body
+/// has no reachable `break` or `return`, so they can never finish
+pub fn lint_infinite_loops(program: &Program) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for stmt in &program.statements {
+        if let Statement::FunctionDecl { name, body, .. } = stmt {
+            let mut finder = InfiniteLoopFinder { function: name, warnings: Vec::new() };
+            finder.visit_block(body);
+            warnings.extend(finder.warnings);
+        }
+    }
+    warnings
+}
+
+struct InfiniteLoopFinder<'ast> {
+    function: &'ast str,
+    warnings: Vec<String>,
+}
+
+impl<'ast> Visitor<'ast> for InfiniteLoopFinder<'ast> {
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        if let Statement::While { condition, body } = stmt {
+            if constant_condition(condition) == Some(true) {
+                let mut exits = LoopExitFinder { nesting: 0, found: false };
+                exits.visit_block(body);
+                if !exits.found {
+                    self.warnings.push(format!(
+                        "`while` loop in function `{}` never terminates: its condition is always true and it has no reachable `break` or `return`",
+                        self.function
+                    ));
+                }
+            }
+        }
+        walk_statement(self, stmt);
+    }
+}
+
+// Looks for a reachable statement that leaves the loop being checked
+struct LoopExitFinder {
+    // Loops and switches entered inside the checked loop; a `break` in
+    // them only leaves them
+    nesting: usize,
+    found: bool,
+}
+
+impl<'ast> Visitor<'ast> for LoopExitFinder {
+    fn visit_block(&mut self, block: &'ast Block) {
+        for stmt in &block.statements {
+            self.visit_statement(stmt);
+            // Nothing after an unconditional jump runs
+            if matches!(stmt, Statement::Return { .. } | Statement::Break | Statement::Continue) {
+                break;
+            }
+        }
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::Return { .. } => self.found = true,
+            Statement::Break if self.nesting == 0 => self.found = true,
+            Statement::If { condition, then_branch, else_branch } => {
+                self.visit_expression(condition);
+                match constant_condition(condition) {
+                    Some(true) => self.visit_block(then_branch),
+                    Some(false) => {
+                        if let Some(else_branch) = else_branch {
+                            self.visit_statement(else_branch);
+                        }
+                    }
+                    None => walk_statement(self, stmt),
+                }
+            }
+            Statement::While { .. } | Statement::For { .. } | Statement::ForIn { .. } | Statement::Switch { .. } => {
+                self.nesting += 1;
+                walk_statement(self, stmt);
+                self.nesting -= 1;
+            }
+            Statement::Defer { .. } | Statement::FunctionDecl { .. } => {}
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        // A `return` in a closure only leaves the closure
+        if !matches!(expr, Expression::FunctionExpression { .. }) {
+            walk_expression(self, expr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn lint(body: &str) -> Vec<String> {
+        let source = format!("function main(): i32 {{ {} }}", body);
+        let tokens = Lexer::new(&source).tokenize().unwrap();
+        lint_infinite_loops(&Parser::new(tokens).parse().unwrap())
+    }
+
+    #[test]
+    fn test_loop_without_exit_warns() {
+        let warnings = lint("let x: i32 = 0; while (true) { x = x + 1; } return x;");
+        assert_eq!(warnings.len(), 1, "{:?}", warnings);
+        assert!(warnings[0].contains("never terminates"), "{}", warnings[0]);
+
+        // Exits that cannot run, or that only leave an inner loop
+        assert_eq!(lint("while (1 < 2) { if (false) { break; } }").len(), 1);
+        assert_eq!(lint("while (true) { while (true) { break; } }").len(), 1);
+        assert_eq!(lint("while (true) { continue; break; }").len(), 1);
+    }
+
+    #[test]
+    fn test_loop_with_exit_does_not_warn() {
+        assert!(lint("let x: i32 = 0; while (true) { x = x + 1; if (x > 3) { break; } } return x;").is_empty());
+        assert!(lint("while (true) { return 1; }").is_empty());
+        assert!(lint("let x: i32 = 0; while (x < 3) { x = x + 1; } return x;").is_empty());
+    }
+}
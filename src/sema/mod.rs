@@ -0,0 +1,639 @@
+mod captures;
+mod definite;
+mod lint;
+mod mutability;
+mod types;
+mod validate;
+
+use crate::ast::*;
+use crate::error::{CompileError, Span, Stage};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+pub use captures::check_captures;
+pub use definite::check_definite_assignment;
+pub use lint::lint_infinite_loops;
+pub use mutability::check_mutability;
+pub use types::{check_types, record_types};
+
+/// Names that are always in scope
+const BUILTIN_VALUES: &[&str] = &["inf", "nan"];
+
+/// Builtins without side effects, callable from pure functions
+const PURE_BUILTINS: &[&str] = &["abs", "min", "max", "pow", "sqrt", "is_nan", "is_inf", "hash"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArgKind {
+    Number,
+    String,
+    Any,
+}
+
+/// Builtins that codegen lowers itself: name, minimum argument count, and
+/// the expected kind of each argument up to the maximum count
+const BUILTIN_SIGNATURES: &[(&str, usize, &[ArgKind])] = &[
+    ("abs", 1, &[ArgKind::Number]),
+    ("min", 2, &[ArgKind::Number, ArgKind::Number]),
+    ("max", 2, &[ArgKind::Number, ArgKind::Number]),
+    ("pow", 2, &[ArgKind::Number, ArgKind::Number]),
+    ("sqrt", 1, &[ArgKind::Number]),
+    ("is_nan", 1, &[ArgKind::Number]),
+    ("is_inf", 1, &[ArgKind::Number]),
+    ("hash", 1, &[ArgKind::Any]),
+    ("to_int", 1, &[ArgKind::String]),
+    ("to_int_radix", 2, &[ArgKind::String, ArgKind::Number]),
+    ("is_int", 1, &[ArgKind::String]),
+    ("is_int_radix", 2, &[ArgKind::String, ArgKind::Number]),
+    ("string_length", 1, &[ArgKind::String]),
+    ("string_index_of", 2, &[ArgKind::String, ArgKind::String]),
+    ("string_concat", 2, &[ArgKind::String, ArgKind::String]),
+    ("string_compare", 2, &[ArgKind::String, ArgKind::String]),
+    ("string_from_bytes", 1, &[ArgKind::Any]),
+    ("make_unknown", 1, &[ArgKind::Any]),
+    ("fill", 2, &[ArgKind::Any, ArgKind::Number]),
+    ("args", 0, &[]),
+    ("env", 1, &[ArgKind::String]),
+    ("print_row", 2, &[ArgKind::Any, ArgKind::Any]),
+    ("assert", 1, &[ArgKind::Any, ArgKind::String]),
+    ("panic", 1, &[ArgKind::String]),
+    ("log", 2, &[ArgKind::String, ArgKind::Any]),
+];
+
+/// The errors a check has found, each citing where the statement it was
+/// found in starts. Statements of imported modules are in another file,
+/// so theirs have no position.
+struct Diagnostics<'ast> {
+    file: Option<&'ast Rc<str>>,
+    span: Option<Span>,
+    errors: Vec<CompileError>,
+}
+
+impl<'ast> Diagnostics<'ast> {
+    fn new(program: &'ast Program) -> Self {
+        Diagnostics { file: program.source.as_ref(), span: None, errors: Vec::new() }
+    }
+
+    // Called from `Visitor::visit_position` as each statement starts
+    fn enter(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.span = (source == self.file).then_some(span);
+    }
+
+    fn report(&mut self, message: String) {
+        let error = match self.span {
+            Some(Span { line, column }) => {
+                CompileError::at(Stage::Semantic, format!("{} at {}:{}", message, line, column), line, column)
+            }
+            None => CompileError::new(Stage::Semantic, message),
+        };
+        self.errors.push(error);
+    }
+
+    fn into_result(self) -> Result<(), Vec<CompileError>> {
+        finish(self.errors)
+    }
+}
+
+fn finish(errors: Vec<CompileError>) -> Result<(), Vec<CompileError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+// The messages of `errors`, one per line, for tests to compare
+#[cfg(test)]
+fn messages(errors: Vec<CompileError>) -> String {
+    errors.into_iter().map(|error| error.message).collect::<Vec<_>>().join("\n")
+}
+
+/// Check that every identifier used as a value refers to something
+/// declared in an enclosing scope, suggesting close matches for typos.
+///
+/// Callees are not checked: calls may target C library functions that
+/// HiLow doesn't declare.
+pub fn check_names(program: &Program) -> Result<(), Vec<CompileError>> {
+    let mut globals = HashSet::new();
+    for stmt in &program.statements {
+        match stmt {
+            Statement::FunctionDecl { name, .. }
+            | Statement::VariableDecl { name, .. }
+            | Statement::EnumDecl { name, .. } => {
+                globals.insert(name.clone());
+            }
+            Statement::Import { names, .. } => globals.extend(names.iter().cloned()),
+            _ => {}
+        }
+    }
+    globals.extend(BUILTIN_VALUES.iter().map(|s| s.to_string()));
+
+    let mut resolver = NameResolver {
+        scopes: vec![globals],
+        function: None,
+        diagnostics: Diagnostics::new(program),
+    };
+    resolver.visit_program(program);
+    resolver.diagnostics.into_result()
+}
+
+/// Check that functions declared `pure` have no side effects: they may
+/// only read their parameters and locals, assign to locals, and call other
+/// pure functions.
+pub fn check_purity(program: &Program) -> Result<(), Vec<CompileError>> {
+    let pure_functions: HashSet<&str> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::FunctionDecl { name, is_pure: true, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let enums: HashSet<&str> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::EnumDecl { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut errors = Vec::new();
+    for stmt in &program.statements {
+        if let Statement::FunctionDecl { name, params, body, is_pure: true, .. } = stmt {
+            let mut checker = PurityChecker {
+                function: name,
+                pure_functions: &pure_functions,
+                enums: &enums,
+                locals: params.iter().map(|p| p.name.as_str()).collect(),
+                diagnostics: Diagnostics::new(program),
+            };
+            checker.visit_block(body);
+            errors.extend(checker.diagnostics.errors);
+        }
+    }
+    finish(errors)
+}
+
+/// Check the argument count of every call to a builtin, and the kind of
+/// any literal argument. A program function with a builtin's name
+/// replaces the builtin and is left alone.
+pub fn check_builtin_calls(program: &Program) -> Result<(), Vec<CompileError>> {
+    let user_functions: HashSet<&str> = program
+        .statements
+        .iter()
+        .filter_map(|stmt| match stmt {
+            Statement::FunctionDecl { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut checker = BuiltinCallChecker {
+        user_functions,
+        function: None,
+        diagnostics: Diagnostics::new(program),
+    };
+    checker.visit_program(program);
+    checker.diagnostics.into_result()
+}
+
+struct BuiltinCallChecker<'ast> {
+    user_functions: HashSet<&'ast str>,
+    function: Option<&'ast str>,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl BuiltinCallChecker<'_> {
+    fn check_call(&mut self, name: &str, args: &[Expression]) {
+        let Some(&(_, min_args, kinds)) = BUILTIN_SIGNATURES.iter().find(|(builtin, ..)| *builtin == name) else {
+            return;
+        };
+
+        let mut problem = None;
+        if args.len() < min_args || args.len() > kinds.len() {
+            let expected = match (min_args, kinds.len()) {
+                (1, 1) => "1 argument".to_string(),
+                (min, max) if min == max => format!("{} arguments", min),
+                (min, max) => format!("{} or {} arguments", min, max),
+            };
+            problem = Some(format!("`{}` takes {} but was given {}", name, expected, args.len()));
+        } else {
+            for (i, (arg, kind)) in args.iter().zip(kinds).enumerate() {
+                let given = match arg {
+                    Expression::StringLiteral(_) | Expression::FString { .. } => ArgKind::String,
+                    Expression::IntegerLiteral(_) | Expression::FloatLiteral(_) => ArgKind::Number,
+                    _ => continue,
+                };
+                if *kind != ArgKind::Any && given != *kind {
+                    let describe = |kind: ArgKind| if kind == ArgKind::String { "a string" } else { "a number" };
+                    problem = Some(format!(
+                        "argument {} of `{}` must be {}, not {}",
+                        i + 1,
+                        name,
+                        describe(*kind),
+                        describe(given)
+                    ));
+                    break;
+                }
+            }
+        }
+
+        if let Some(mut message) = problem {
+            if let Some(function) = self.function {
+                message.push_str(&format!(" in function `{}`", function));
+            }
+            self.diagnostics.report(message);
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for BuiltinCallChecker<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        if let Statement::FunctionDecl { name, .. } = stmt {
+            let outer = self.function.replace(name);
+            walk_statement(self, stmt);
+            self.function = outer;
+        } else {
+            walk_statement(self, stmt);
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        if let Expression::Call { callee, args, .. } = expr {
+            if let Expression::Identifier(name) = callee.as_ref() {
+                if !self.user_functions.contains(name.as_str()) {
+                    self.check_call(name, args);
+                }
+            }
+        }
+        walk_expression(self, expr);
+    }
+}
+
+struct PurityChecker<'ast> {
+    function: &'ast str,
+    pure_functions: &'ast HashSet<&'ast str>,
+    // Enum variants are constants, so reading them is pure
+    enums: &'ast HashSet<&'ast str>,
+    locals: HashSet<&'ast str>,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl PurityChecker<'_> {
+    fn report(&mut self, what: String) {
+        self.diagnostics.report(format!("Pure function `{}` {}", self.function, what));
+    }
+}
+
+impl<'ast> Visitor<'ast> for PurityChecker<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::VariableDecl { name, .. } => {
+                walk_statement(self, stmt);
+                self.locals.insert(name);
+            }
+            Statement::ForIn { variable, .. } => {
+                self.locals.insert(variable);
+                walk_statement(self, stmt);
+            }
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        match expr {
+            Expression::Identifier(name) => {
+                let name = name.as_str();
+                if !self.locals.contains(name)
+                    && !self.pure_functions.contains(name)
+                    && !self.enums.contains(name)
+                    && !BUILTIN_VALUES.contains(&name)
+                {
+                    self.report(format!("reads `{}`, which is not a parameter or local", name));
+                }
+            }
+            Expression::Call { callee, args, .. } => {
+                match callee.as_ref() {
+                    Expression::Identifier(name)
+                        if self.pure_functions.contains(name.as_str()) || PURE_BUILTINS.contains(&name.as_str()) => {}
+                    Expression::Identifier(name) => self.report(format!("calls `{}`, which is not pure", name)),
+                    _ => self.report("calls a function value, which may not be pure".to_string()),
+                }
+                for arg in args {
+                    self.visit_expression(arg);
+                }
+            }
+            Expression::MethodCall { method, .. } => {
+                self.report(format!("calls method `{}`, which may have side effects", method));
+            }
+            Expression::Assignment { target, value } => {
+                match target.as_ref() {
+                    Expression::Identifier(name) if self.locals.contains(name.as_str()) => {}
+                    Expression::Identifier(name) => self.report(format!("assigns to `{}`, which is not a local", name)),
+                    _ => self.report("assigns through an index or property".to_string()),
+                }
+                self.visit_expression(value);
+            }
+            Expression::FunctionExpression { .. } => {
+                self.report("creates a closure".to_string());
+            }
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+struct NameResolver<'ast> {
+    scopes: Vec<HashSet<String>>,
+    function: Option<&'ast str>,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl NameResolver<'_> {
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.to_string());
+        }
+    }
+
+    fn is_declared(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn report_unknown(&mut self, name: &str) {
+        let mut message = format!("Unknown identifier `{}`", name);
+        if let Some(function) = self.function {
+            message.push_str(&format!(" in function `{}`", function));
+        }
+        if let Some(suggestion) = self.suggest(name) {
+            message.push_str(&format!(" (did you mean `{}`?)", suggestion));
+        }
+        self.diagnostics.report(message);
+    }
+
+    // The closest in-scope name within two edits, if any
+    fn suggest(&self, name: &str) -> Option<String> {
+        let mut candidates: Vec<&String> = self.scopes.iter().flatten().collect();
+        candidates.sort();
+
+        candidates
+            .into_iter()
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(distance, _)| *distance <= 2 && *distance < name.len())
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate.clone())
+    }
+
+    fn with_scope(&mut self, names: &[&str], f: impl FnOnce(&mut Self)) {
+        self.scopes.push(names.iter().map(|n| n.to_string()).collect());
+        f(self);
+        self.scopes.pop();
+    }
+}
+
+impl<'ast> Visitor<'ast> for NameResolver<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.with_scope(&[], |resolver| walk_block(resolver, block));
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::FunctionDecl { name, params, requires, ensures, body, .. } => {
+                let outer = self.function.replace(name);
+                let mut param_names: Vec<&str> = params.iter().map(|p| p.name.as_str()).collect();
+                self.with_scope(&param_names, |resolver| {
+                    for condition in requires {
+                        resolver.visit_expression(condition);
+                    }
+                    resolver.visit_block(body);
+                });
+                param_names.push("result");
+                self.with_scope(&param_names, |resolver| {
+                    for condition in ensures {
+                        resolver.visit_expression(condition);
+                    }
+                });
+                self.function = outer;
+            }
+            Statement::VariableDecl { name, initializer, .. } => {
+                if let Some(init) = initializer {
+                    self.visit_expression(init);
+                }
+                self.declare(name);
+            }
+            Statement::For { .. } => {
+                self.with_scope(&[], |resolver| walk_statement(resolver, stmt));
+            }
+            Statement::ForIn { variable, iterable, body } => {
+                self.visit_expression(iterable);
+                self.with_scope(&[variable], |resolver| resolver.visit_block(body));
+            }
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        match expr {
+            Expression::Identifier(name) => {
+                if !self.is_declared(name) {
+                    self.report_unknown(name);
+                }
+            }
+            Expression::Call { callee, args, .. } => {
+                if !matches!(callee.as_ref(), Expression::Identifier(_)) {
+                    self.visit_expression(callee);
+                }
+                for arg in args {
+                    self.visit_expression(arg);
+                }
+            }
+            Expression::FunctionExpression { params, body, .. } => {
+                let param_names: Vec<&str> = params.iter().map(|p| p.name.as_str()).collect();
+                self.with_scope(&param_names, |resolver| resolver.visit_block(body));
+            }
+            Expression::Match { expr, arms } => {
+                self.visit_expression(expr);
+                for arm in arms {
+                    match &arm.pattern {
+                        MatchPattern::Binding(name) => {
+                            self.with_scope(&[name], |resolver| resolver.visit_expression(&arm.body));
+                        }
+                        MatchPattern::Literal(pattern) => {
+                            self.visit_expression(pattern);
+                            self.visit_expression(&arm.body);
+                        }
+                        MatchPattern::Wildcard => self.visit_expression(&arm.body),
+                    }
+                }
+            }
+            Expression::Block { body, value } => {
+                // The value sees the block's declarations
+                self.with_scope(&[], |resolver| {
+                    walk_block(resolver, body);
+                    resolver.visit_expression(value);
+                });
+            }
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+// Levenshtein distance, counting a swap of adjacent characters as one edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(source: &str) -> Result<(), String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        check_names(&program).map_err(messages)
+    }
+
+    #[test]
+    fn test_misspelled_identifier_suggests_match() {
+        let err = check(
+            "function main(): i32 {
+                let length: i32 = 5;
+                return lenght;
+            }",
+        )
+        .unwrap_err();
+
+        assert_eq!(err, "Unknown identifier `lenght` in function `main` (did you mean `length`?) at 3:17");
+    }
+
+    #[test]
+    fn test_block_locals_do_not_leak() {
+        let err = check(
+            "function main(): i32 {
+                if (true) { let inner: i32 = 1; }
+                return inner;
+            }",
+        )
+        .unwrap_err();
+        assert!(err.starts_with("Unknown identifier `inner`"), "{}", err);
+
+        check(
+            "let limit: i32 = 10;
+            function helper(n: i32): i32 { return n; }
+            function main(): i32 {
+                let total: i32 = 0;
+                for (let i: i32 = 0; i < limit; i = i + 1) { total = total + i; }
+                for (item in [1, 2]) { total = total + item; }
+                let twice: i32 = match (total) { 0 => 0, n => n * 2 };
+                let f = function(x: i32): i32 { return x + total; };
+                return helper(twice) + free_c_function(total);
+            }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_pure_functions_reject_side_effects() {
+        let parse = |source: &str| {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            Parser::new(tokens).parse().unwrap()
+        };
+
+        let program = parse(
+            "pure function square(x: i32): i32 { return x * x; }
+            pure function sum_squares(n: i32): i32 {
+                let total: i32 = 0;
+                for (let i: i32 = 1; i <= n; i = i + 1) { total = total + square(i); }
+                return total;
+            }",
+        );
+        check_purity(&program).unwrap();
+
+        let program = parse(
+            "let counter: i32 = 0;
+            pure function noisy(x: i32): i32 {
+                print(\"hi\");
+                counter = counter + x;
+                return x;
+            }",
+        );
+        let err = messages(check_purity(&program).unwrap_err());
+        assert!(err.contains("Pure function `noisy` calls `print`, which is not pure"), "{}", err);
+        assert!(err.contains("Pure function `noisy` assigns to `counter`, which is not a local"), "{}", err);
+    }
+
+    #[test]
+    fn test_builtin_call_arity_and_argument_kinds() {
+        let check_calls = |source: &str| {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            check_builtin_calls(&Parser::new(tokens).parse().unwrap()).map_err(messages)
+        };
+
+        assert_eq!(
+            check_calls("function main(): i32 { return min(1); }").unwrap_err(),
+            "`min` takes 2 arguments but was given 1 in function `main` at 1:24"
+        );
+        assert_eq!(
+            check_calls("function main(): i32 { return sqrt(1, 2); }").unwrap_err(),
+            "`sqrt` takes 1 argument but was given 2 in function `main` at 1:24"
+        );
+        assert_eq!(
+            check_calls("function main(): i32 { assert(true, \"a\", \"b\"); return 0; }").unwrap_err(),
+            "`assert` takes 1 or 2 arguments but was given 3 in function `main` at 1:24"
+        );
+        assert_eq!(
+            check_calls("function main(): i32 { return sqrt(\"four\"); }").unwrap_err(),
+            "argument 1 of `sqrt` must be a number, not a string in function `main` at 1:24"
+        );
+
+        check_calls(
+            "function main(): i32 {
+                let n: i32 = 3;
+                assert(n > 0);
+                return min(n, 2) + to_int_radix(\"ff\", 16);
+            }",
+        )
+        .unwrap();
+        check_calls("function min(x: i32): i32 { return x; } function main(): i32 { return min(1); }").unwrap();
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("lenght", "length"), 1);
+        assert_eq!(edit_distance("count", "count"), 0);
+        assert_eq!(edit_distance("cat", "dog"), 3);
+    }
+}
@@ -0,0 +1,156 @@
+//! Strict mutability: a `let` binding may only be assigned after its
+//! declaration when it is declared `let mut`.
+//!
+//! Only the binding is protected; array elements and object properties
+//! can still be changed through it.
+
+use super::Diagnostics;
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Check that no binding declared without `mut` is assigned. Parameters
+/// are mutable, and so is a `let` without an initializer, which can only
+/// get its value by assignment.
+pub fn check_mutability(program: &Program) -> Result<(), Vec<CompileError>> {
+    let mut checker = MutabilityChecker {
+        scopes: vec![HashMap::new()],
+        function: None,
+        diagnostics: Diagnostics::new(program),
+    };
+    checker.visit_program(program);
+    checker.diagnostics.into_result()
+}
+
+struct MutabilityChecker<'ast> {
+    // Innermost scope last; each name maps to whether it may be assigned
+    scopes: Vec<HashMap<&'ast str, bool>>,
+    function: Option<&'ast str>,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl<'ast> MutabilityChecker<'ast> {
+    fn in_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(HashMap::new());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &'ast str, is_mut: bool) {
+        self.scopes.last_mut().expect("global scope").insert(name, is_mut);
+    }
+
+    fn check_assignment(&mut self, name: &str) {
+        let is_mut = self.scopes.iter().rev().find_map(|scope| scope.get(name));
+        if is_mut == Some(&false) {
+            let mut message = format!("Cannot assign to `{}`: it is not declared `let mut`", name);
+            if let Some(function) = self.function {
+                message.push_str(&format!(" in function `{}`", function));
+            }
+            self.diagnostics.report(message);
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for MutabilityChecker<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.in_scope(|checker| walk_block(checker, block));
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::FunctionDecl { name, params, .. } => {
+                let outer = self.function.replace(name);
+                self.in_scope(|checker| {
+                    for param in params {
+                        checker.declare(&param.name, true);
+                    }
+                    walk_statement(checker, stmt);
+                });
+                self.function = outer;
+            }
+            Statement::VariableDecl { name, initializer, is_mut, .. } => {
+                walk_statement(self, stmt);
+                self.declare(name, *is_mut || initializer.is_none());
+            }
+            Statement::For { .. } => self.in_scope(|checker| walk_statement(checker, stmt)),
+            Statement::ForIn { variable, iterable, body } => {
+                self.visit_expression(iterable);
+                self.in_scope(|checker| {
+                    checker.declare(variable, false);
+                    checker.visit_block(body);
+                });
+            }
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        match expr {
+            Expression::Assignment { target, .. } => {
+                if let Expression::Identifier(name) = target.as_ref() {
+                    self.check_assignment(name);
+                }
+                walk_expression(self, expr);
+            }
+            Expression::FunctionExpression { params, .. } => self.in_scope(|checker| {
+                for param in params {
+                    checker.declare(&param.name, true);
+                }
+                walk_expression(checker, expr);
+            }),
+            _ => walk_expression(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(source: &str) -> Result<(), String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        check_mutability(&Parser::new(tokens).parse().unwrap()).map_err(crate::sema::messages)
+    }
+
+    #[test]
+    fn test_assigning_immutable_binding_errors() {
+        assert_eq!(
+            check("function main(): i32 { let x: i32 = 1; x = 2; return x; }").unwrap_err(),
+            "Cannot assign to `x`: it is not declared `let mut` in function `main` at 1:40"
+        );
+        // Compound assignment and loop variables count too
+        assert!(check("function main(): i32 { let x: i32 = 1; x += 2; return x; }").is_err());
+        assert!(check("function main(): i32 { for (n in 0..3) { n = 1; } return 0; }").is_err());
+        assert!(check("let limit: i32 = 3; function main(): i32 { limit = 4; return 0; }").is_err());
+    }
+
+    #[test]
+    fn test_mutable_bindings_can_be_assigned() {
+        check(
+            "function main(n: i32): i32 {
+                let mut x: i32 = 1;
+                x = x + 1;
+                let later: i32;
+                later = 5;
+                n = n + 1;
+                let xs: [i32] = [1, 2];
+                xs[0] = 3;
+                { let mut x: i32 = 0; x = 1; }
+                for (let mut i: i32 = 0; i < 3; i = i + 1) {}
+                return x + later;
+            }",
+        )
+        .unwrap();
+
+        // An inner `let mut` doesn't make the outer binding mutable
+        assert!(check("function main(): i32 { let x: i32 = 1; { let mut x: i32 = 2; x = 3; } x = 4; return x; }").is_err());
+    }
+}
@@ -0,0 +1,957 @@
+//! Type checking: resolves the type of each expression from declarations
+//! and literals, and reports operations that can only go wrong in C.
+//!
+//! Types that can't be resolved (unknown receivers, closures with a block
+//! body and no return type) are left open and never cause an error. Numbers and bools mix freely,
+//! as they do in C; strings, arrays and functions only mix with their own
+//! kind.
+
+use super::Diagnostics;
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Check calls to program functions for their argument count, binary
+/// operators for operands of compatible kinds, and `return` values
+/// against the declared return type
+pub fn check_types(program: &Program) -> Result<(), Vec<CompileError>> {
+    let mut checker = TypeChecker::new(program);
+    checker.visit_program(program);
+    checker.diagnostics.into_result()
+}
+
+/// Write the types type checking resolves into the program, so later
+/// passes read them instead of working them out again: the result type of
+/// each call and method call, and the type of each `let` declared without
+/// one. A `let` only takes a number, bool, string, growable array or named
+/// type: an array literal, for one, resolves to a fixed-size array where
+/// codegen grows a dynamic one.
+pub fn record_types(program: &mut Program) {
+    let resolved = {
+        let mut checker = TypeChecker::new(program);
+        checker.visit_program(program);
+        checker.resolved
+    };
+    for stmt in &mut program.statements {
+        record_in_statement(stmt, &resolved);
+    }
+}
+
+// What `record_types` writes back, by the address of the node it is for
+#[derive(Default)]
+struct Resolved {
+    declarations: HashMap<*const Statement, Type>,
+    calls: HashMap<*const Expression, Type>,
+}
+
+fn record_in_block(block: &mut Block, resolved: &Resolved) {
+    for stmt in &mut block.statements {
+        record_in_statement(stmt, resolved);
+    }
+}
+
+fn record_in_statement(stmt: &mut Statement, resolved: &Resolved) {
+    if let Some(ty) = resolved.declarations.get(&(stmt as *const Statement)) {
+        if let Statement::VariableDecl { var_type: var_type @ None, .. } = stmt {
+            *var_type = Some(ty.clone());
+        }
+    }
+
+    match stmt {
+        Statement::FunctionDecl { body, .. } => record_in_block(body, resolved),
+        Statement::VariableDecl { initializer: Some(expr), .. }
+        | Statement::Return { value: Some(expr) }
+        | Statement::Expression(expr) => record_in_expression(expr, resolved),
+        Statement::If { condition, then_branch, else_branch } => {
+            record_in_expression(condition, resolved);
+            record_in_block(then_branch, resolved);
+            if let Some(else_stmt) = else_branch {
+                record_in_statement(else_stmt, resolved);
+            }
+        }
+        Statement::While { condition, body } => {
+            record_in_expression(condition, resolved);
+            record_in_block(body, resolved);
+        }
+        Statement::For { init, condition, increment, body } => {
+            if let Some(init) = init {
+                record_in_statement(init, resolved);
+            }
+            for expr in condition.iter_mut().chain(increment) {
+                record_in_expression(expr, resolved);
+            }
+            record_in_block(body, resolved);
+        }
+        Statement::ForIn { iterable, body, .. } => {
+            record_in_expression(iterable, resolved);
+            record_in_block(body, resolved);
+        }
+        Statement::Switch { expr, cases, default } => {
+            record_in_expression(expr, resolved);
+            for body in cases.iter_mut().map(|case| &mut case.body).chain(default) {
+                record_in_block(body, resolved);
+            }
+        }
+        Statement::Defer { statement } => record_in_statement(statement, resolved),
+        Statement::Block(block) => record_in_block(block, resolved),
+        Statement::StructDecl { methods, .. } => {
+            for method in methods {
+                record_in_statement(method, resolved);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn record_in_expression(expr: &mut Expression, resolved: &Resolved) {
+    if let Some(ty) = resolved.calls.get(&(expr as *const Expression)) {
+        if let Expression::Call { resolved_type, .. } | Expression::MethodCall { resolved_type, .. } = expr {
+            *resolved_type = Some(ty.clone());
+        }
+    }
+
+    match expr {
+        Expression::FunctionExpression { body, .. } => record_in_block(body, resolved),
+        Expression::Block { body, value } => {
+            record_in_block(body, resolved);
+            record_in_expression(value, resolved);
+        }
+        Expression::FString { parts } => {
+            for part in parts {
+                if let FStringPart::Expression(expr, _) = part {
+                    record_in_expression(expr, resolved);
+                }
+            }
+        }
+        Expression::Binary { left, right, .. } | Expression::Assignment { target: left, value: right } => {
+            record_in_expression(left, resolved);
+            record_in_expression(right, resolved);
+        }
+        Expression::Index { array: left, index: right }
+        | Expression::ArrayRepeat { value: left, count: right }
+        | Expression::Range { start: left, end: right, .. } => {
+            record_in_expression(left, resolved);
+            record_in_expression(right, resolved);
+        }
+        Expression::Unary { operand: expr, .. }
+        | Expression::PropertyAccess { object: expr, .. }
+        | Expression::Cast { expr, .. } => record_in_expression(expr, resolved),
+        Expression::Call { callee: object, args, .. } | Expression::MethodCall { object, args, .. } => {
+            record_in_expression(object, resolved);
+            for arg in args {
+                record_in_expression(arg, resolved);
+            }
+        }
+        Expression::ArrayLiteral { elements } => {
+            for element in elements {
+                record_in_expression(element, resolved);
+            }
+        }
+        Expression::ObjectLiteral { properties } => {
+            for property in properties {
+                record_in_expression(&mut property.value, resolved);
+            }
+        }
+        Expression::Match { expr, arms } => {
+            record_in_expression(expr, resolved);
+            for arm in arms {
+                record_in_expression(&mut arm.body, resolved);
+            }
+        }
+        Expression::Conditional { condition, then, otherwise } => {
+            for expr in [condition, then, otherwise] {
+                record_in_expression(expr, resolved);
+            }
+        }
+        _ => {}
+    }
+}
+
+// What may be combined with what: numbers and bools convert into each
+// other, nothing else converts
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Scalar,
+    String,
+    Array,
+    Function,
+}
+
+fn kind(ty: &Type) -> Option<Kind> {
+    match ty {
+        Type::I8
+        | Type::I16
+        | Type::I32
+        | Type::I64
+        | Type::I128
+        | Type::U8
+        | Type::U16
+        | Type::U32
+        | Type::U64
+        | Type::U128
+        | Type::F32
+        | Type::F64
+        | Type::Bool => Some(Kind::Scalar),
+        Type::String => Some(Kind::String),
+        Type::Array { .. } => Some(Kind::Array),
+        Type::Function { .. } => Some(Kind::Function),
+        Type::Nothing | Type::Unknown | Type::Object | Type::Named(_) => None,
+    }
+}
+
+// Strings, growable arrays and closures are pointers to memory from
+// malloc; numbers, fixed-size arrays, structs and enums are C values
+fn is_heap_allocated(ty: &Type) -> bool {
+    match ty {
+        Type::Array { size, .. } => size.is_none(),
+        Type::String | Type::Function { .. } | Type::Unknown | Type::Object => true,
+        _ => false,
+    }
+}
+
+// String methods and builtins whose result codegen allocates afresh;
+// anything else may be a literal, which `free` would abort on
+const ALLOCATING_STRING_METHODS: &[&str] = &[
+    "toUpperCase", "toLowerCase", "trim", "trimStart", "trimEnd", "charAt", "substring",
+    "concat", "padStart", "padEnd", "replace", "replaceAll", "join",
+];
+
+// Result types of the builtins codegen lowers itself
+fn builtin_type(name: &str, arg_types: &[Option<Type>]) -> Option<Type> {
+    match name {
+        "to_int" | "to_int_radix" => Some(Type::I64),
+        "is_int" | "is_int_radix" | "is_nan" | "is_inf" => Some(Type::Bool),
+        "string_length" | "string_index_of" | "string_compare" => Some(Type::I32),
+        "string_concat" | "string_from_bytes" | "env" => Some(Type::String),
+        "hash" => Some(Type::U64),
+        "args" => Some(Type::Array { element_type: Box::new(Type::String), size: None }),
+        "fill" => arg_types.first().cloned().flatten().map(|element_type| Type::Array {
+            element_type: Box::new(element_type),
+            size: None,
+        }),
+        _ => None,
+    }
+}
+
+fn is_number(ty: &Type) -> bool {
+    kind(ty) == Some(Kind::Scalar) && *ty != Type::Bool
+}
+
+fn is_float(ty: &Type) -> bool {
+    matches!(ty, Type::F32 | Type::F64)
+}
+
+pub struct TypeChecker<'ast> {
+    // Program functions by name, for arity checks and call result types
+    functions: HashMap<&'ast str, (&'ast [Parameter], Option<&'ast Type>)>,
+    // Return types of struct methods, by struct and method name
+    methods: HashMap<(&'ast str, &'ast str), Option<&'ast Type>>,
+    // Innermost scope last; a name maps to None when its type is unknown
+    scopes: Vec<HashMap<&'ast str, Option<Type>>>,
+    function: Option<&'ast str>,
+    // Declared return type of the function or closure being checked
+    return_type: Option<&'ast Type>,
+    // Type of the first value it returns that resolved, which is the type
+    // of an arrow function without a declared one
+    returned: Option<Type>,
+    // The `let` just checked, whose value a `using` frees in the next
+    // statement when it has no release expression
+    declared: Option<(&'ast str, &'ast Expression, Option<Type>)>,
+    diagnostics: Diagnostics<'ast>,
+    // The types `record_types` writes back
+    resolved: Resolved,
+}
+
+impl<'ast> TypeChecker<'ast> {
+    pub fn new(program: &'ast Program) -> Self {
+        let mut functions = HashMap::new();
+        let mut methods = HashMap::new();
+        let mut globals = HashMap::new();
+        for stmt in &program.statements {
+            match stmt {
+                Statement::FunctionDecl { name, params, return_type, .. } => {
+                    functions.insert(name.as_str(), (params.as_slice(), return_type.as_ref()));
+                }
+                Statement::StructDecl { name: struct_name, methods: declared, .. } => {
+                    for method in declared {
+                        if let Statement::FunctionDecl { name, return_type, .. } = method {
+                            methods.insert((struct_name.as_str(), name.as_str()), return_type.as_ref());
+                        }
+                    }
+                }
+                Statement::VariableDecl { name, var_type, .. } => {
+                    globals.insert(name.as_str(), var_type.clone());
+                }
+                _ => {}
+            }
+        }
+
+        TypeChecker {
+            functions,
+            methods,
+            scopes: vec![globals],
+            function: None,
+            return_type: None,
+            returned: None,
+            declared: None,
+            diagnostics: Diagnostics::new(program),
+            resolved: Resolved::default(),
+        }
+    }
+
+    fn report(&mut self, mut message: String) {
+        if let Some(function) = self.function {
+            message.push_str(&format!(" in function `{}`", function));
+        }
+        self.diagnostics.report(message);
+    }
+
+    fn declare(&mut self, name: &'ast str, ty: Option<Type>) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name, ty);
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<Option<Type>> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn in_scope<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.scopes.push(HashMap::new());
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+
+    // Check a function or closure body against its declared return type,
+    // giving the type of the first value it returns that resolved
+    fn check_body(&mut self, params: &'ast [Parameter], return_type: Option<&'ast Type>, body: &'ast Block) -> Option<Type> {
+        let outer = std::mem::replace(&mut self.return_type, return_type);
+        let outer_returned = self.returned.take();
+        self.in_scope(|checker| {
+            for param in params {
+                checker.declare(&param.name, Some(param.param_type.clone()));
+            }
+            checker.visit_block(body);
+        });
+        self.return_type = outer;
+        std::mem::replace(&mut self.returned, outer_returned)
+    }
+
+    /// The type of `expr`, if it can be resolved, checking it on the way
+    pub fn type_of(&mut self, expr: &'ast Expression) -> Option<Type> {
+        match expr {
+            Expression::IntegerLiteral(_) => Some(Type::I32),
+            Expression::FloatLiteral(_) => Some(Type::F64),
+            Expression::StringLiteral(_) => Some(Type::String),
+            Expression::BooleanLiteral(_) => Some(Type::Bool),
+            Expression::CharLiteral(_) => Some(Type::I8),
+            Expression::FString { .. } => {
+                walk_expression(self, expr);
+                Some(Type::String)
+            }
+            Expression::Identifier(name) => match self.lookup(name) {
+                Some(ty) => ty,
+                None => self.functions.get(name.as_str()).map(|(params, return_type)| Type::Function {
+                    params: params.iter().map(|param| param.param_type.clone()).collect(),
+                    return_type: Box::new(return_type.cloned().unwrap_or(Type::Nothing)),
+                }),
+            },
+            Expression::Binary { left, op, right } => {
+                let left = self.type_of(left);
+                let right = self.type_of(right);
+                self.binary_type(op, left, right)
+            }
+            Expression::Unary { op, operand } => {
+                let operand = self.type_of(operand);
+                match op {
+                    UnaryOp::Not => Some(Type::Bool),
+                    _ => operand,
+                }
+            }
+            Expression::Call { callee, args, .. } => {
+                let ty = self.call_type(callee, args);
+                self.record_call(expr, &ty);
+                ty
+            }
+            Expression::MethodCall { object, method, args, .. } => {
+                let ty = self.method_type(object, method, args);
+                self.record_call(expr, &ty);
+                ty
+            }
+            Expression::Assignment { target, value } => {
+                self.type_of(target);
+                self.type_of(value)
+            }
+            Expression::ArrayLiteral { elements } => {
+                let element_types: Vec<Option<Type>> = elements.iter().map(|element| self.type_of(element)).collect();
+                match element_types.first() {
+                    Some(Some(first)) => Some(Type::Array {
+                        element_type: Box::new(first.clone()),
+                        size: Some(elements.len()),
+                    }),
+                    _ => None,
+                }
+            }
+            Expression::Index { array, index } => {
+                let array = self.type_of(array);
+                self.type_of(index);
+                match array {
+                    Some(Type::Array { element_type, .. }) => Some(*element_type),
+                    _ => None,
+                }
+            }
+            Expression::PropertyAccess { object, property } => {
+                self.type_of(object);
+                (property == "length").then_some(Type::I32)
+            }
+            Expression::Cast { expr, target_type } => {
+                // Numbers and bools convert to each other; anything else
+                // can only be "cast" to its own type
+                if let Some(from) = self.type_of(expr) {
+                    let (from_kind, to_kind) = (kind(&from), kind(target_type));
+                    let numeric = from_kind == Some(Kind::Scalar) && to_kind == Some(Kind::Scalar);
+                    if from_kind.is_some() && to_kind.is_some() && !numeric && from != *target_type {
+                        self.report(format!("Cannot cast {} to {}", from, target_type));
+                    }
+                }
+                Some(target_type.clone())
+            }
+            Expression::FunctionExpression { params, return_type, body } => {
+                let returned = self.check_body(params, return_type.as_ref(), body);
+                // An arrow function with an expression body has the type
+                // of that expression
+                let is_arrow = matches!(body.statements.as_slice(), [Statement::Return { value: Some(_) }]);
+                let return_type = return_type.clone().or(returned.filter(|_| is_arrow))?;
+                Some(Type::Function {
+                    params: params.iter().map(|param| param.param_type.clone()).collect(),
+                    return_type: Box::new(return_type),
+                })
+            }
+            Expression::Match { expr, arms } => {
+                let scrutinee = self.type_of(expr);
+                for arm in arms {
+                    self.in_scope(|checker| {
+                        match &arm.pattern {
+                            MatchPattern::Binding(name) => checker.declare(name, scrutinee.clone()),
+                            MatchPattern::Literal(pattern) => {
+                                checker.type_of(pattern);
+                            }
+                            MatchPattern::Wildcard => {}
+                        }
+                        checker.type_of(&arm.body);
+                    });
+                }
+                None
+            }
+            Expression::Conditional { condition, then, otherwise } => {
+                self.type_of(condition);
+                let (then_type, otherwise_type) = (self.type_of(then), self.type_of(otherwise));
+                match (then_type, otherwise_type) {
+                    (Some(l), Some(r)) if kind(&l).is_some() && kind(&r).is_some() && kind(&l) != kind(&r) => {
+                        self.report(format!("Conditional branches have different types: {} and {}", l, r));
+                        None
+                    }
+                    (Some(l), Some(r)) if is_float(&r) && !is_float(&l) => Some(r),
+                    (l, r) => l.or(r),
+                }
+            }
+            Expression::Block { body, value } => self.in_scope(|checker| {
+                walk_block(checker, body);
+                checker.type_of(value)
+            }),
+            _ => {
+                walk_expression(self, expr);
+                None
+            }
+        }
+    }
+
+    fn binary_type(&mut self, op: &BinaryOp, left: Option<Type>, right: Option<Type>) -> Option<Type> {
+        let arithmetic = matches!(
+            op,
+            BinaryOp::Add
+                | BinaryOp::Subtract
+                | BinaryOp::Multiply
+                | BinaryOp::Divide
+                | BinaryOp::Modulo
+                | BinaryOp::BitwiseAnd
+                | BinaryOp::BitwiseOr
+                | BinaryOp::BitwiseXor
+                | BinaryOp::ShiftLeft
+                | BinaryOp::ShiftRight
+        );
+
+        if let (Some(l), Some(r)) = (&left, &right) {
+            let (left_kind, right_kind) = (kind(l), kind(r));
+            // Strings only add to strings
+            let string_add = matches!(op, BinaryOp::Add) && left_kind == Some(Kind::String) && right_kind == Some(Kind::String);
+            let scalar_arithmetic = arithmetic && left_kind == Some(Kind::Scalar) && right_kind == Some(Kind::Scalar);
+            let comparable = !arithmetic && left_kind == right_kind;
+            if left_kind.is_some() && right_kind.is_some() && !string_add && !scalar_arithmetic && !comparable {
+                self.report(format!("Cannot apply `{}` to {} and {}", binary_symbol(op), l, r));
+                return None;
+            }
+        }
+
+        match op {
+            _ if !arithmetic => Some(Type::Bool),
+            BinaryOp::And | BinaryOp::Or => Some(Type::Bool),
+            _ => match (left, right) {
+                (Some(l), Some(r)) if is_float(&r) && !is_float(&l) => Some(r),
+                (Some(l), _) => Some(l),
+                _ => None,
+            },
+        }
+    }
+
+    fn call_type(&mut self, callee: &'ast Expression, args: &'ast [Expression]) -> Option<Type> {
+        let arg_types: Vec<Option<Type>> = args.iter().map(|arg| self.type_of(arg)).collect();
+
+        let Expression::Identifier(name) = callee else {
+            self.type_of(callee);
+            return None;
+        };
+
+        // A variable holding a closure
+        if let Some(ty) = self.lookup(name) {
+            return match ty {
+                Some(Type::Function { params, return_type }) => {
+                    // A bare `function` declares no parameters
+                    if !params.is_empty() && params.len() != args.len() {
+                        self.report_arity(name, params.len(), args.len());
+                    }
+                    Some(*return_type)
+                }
+                _ => None,
+            };
+        }
+
+        // `free`, written out or implied by a `using` without a release
+        // expression, only applies to what lives on the heap
+        if name == "free" && !self.functions.contains_key(name.as_str()) {
+            if let [Some(ty)] = arg_types.as_slice() {
+                if !is_heap_allocated(ty) {
+                    self.report(format!(
+                        "`free` releases strings and growable arrays, not {}; give `using` an explicit release expression",
+                        ty
+                    ));
+                }
+            }
+            return None;
+        }
+
+        let Some(&(params, return_type)) = self.functions.get(name.as_str()) else {
+            return builtin_type(name, &arg_types);
+        };
+        if params.len() != args.len() {
+            self.report_arity(name, params.len(), args.len());
+        }
+        return_type.cloned()
+    }
+
+    // Whether `expr` is a string allocated where it's evaluated: a
+    // concatenation, an f-string or the result of an allocating builtin
+    fn is_fresh_string(&self, expr: &Expression) -> bool {
+        match expr {
+            Expression::Binary { op: BinaryOp::Add, .. } | Expression::FString { .. } => true,
+            Expression::MethodCall { method, .. } => {
+                ALLOCATING_STRING_METHODS.contains(&method.as_str())
+                    && !self.methods.keys().any(|&(_, declared)| declared == method)
+            }
+            Expression::Call { callee, .. } => matches!(
+                callee.as_ref(),
+                Expression::Identifier(name)
+                    if matches!(name.as_str(), "string_concat" | "string_from_bytes")
+                        && !self.functions.contains_key(name.as_str())
+            ),
+            _ => false,
+        }
+    }
+
+    // The result of calling `method` on `object`: a builtin string or
+    // array method, or a method of the struct
+    fn method_type(&mut self, object: &'ast Expression, method: &str, args: &'ast [Expression]) -> Option<Type> {
+        let receiver = self.type_of(object);
+        let arg_types: Vec<Option<Type>> = args.iter().map(|arg| self.type_of(arg)).collect();
+        let dynamic_array = |element_type: Type| Type::Array { element_type: Box::new(element_type), size: None };
+
+        match receiver? {
+            Type::String => match method {
+                "toUpperCase" | "toLowerCase" | "trim" | "trimStart" | "trimEnd" | "charAt" | "substring" | "slice"
+                | "concat" | "padStart" | "padEnd" | "replace" | "replaceAll" => Some(Type::String),
+                "indexOf" | "lastIndexOf" | "compare" | "count" | "occurrences" => Some(Type::I32),
+                "split" | "splitLines" => Some(dynamic_array(Type::String)),
+                "bytes" => Some(dynamic_array(Type::U8)),
+                "hash" => Some(Type::U64),
+                _ => None,
+            },
+            Type::Array { element_type, .. } => match method {
+                "sum" | "min" | "max" | "avg" if !is_number(&element_type) => {
+                    self.report(format!("`{}` needs an array of numbers, not [{}]", method, element_type));
+                    None
+                }
+                "pop" | "find" | "sum" | "min" | "max" => Some(*element_type),
+                "avg" => Some(Type::F64),
+                "indexOf" | "lastIndexOf" | "count" => Some(Type::I32),
+                "contains" => Some(Type::Bool),
+                "join" => Some(Type::String),
+                "unique" | "dedup" | "filter" => Some(dynamic_array(*element_type)),
+                "map" => match arg_types.first() {
+                    Some(Some(Type::Function { return_type, .. })) => Some(dynamic_array(*return_type.clone())),
+                    _ => None,
+                },
+                "reduce" => arg_types.get(1).cloned().flatten(),
+                _ => None,
+            },
+            Type::Named(name) => self.methods.get(&(name.as_str(), method)).copied().flatten().cloned(),
+            _ => None,
+        }
+    }
+
+    fn record_call(&mut self, call: &'ast Expression, ty: &Option<Type>) {
+        if let Some(ty) = ty {
+            self.resolved.calls.insert(call, ty.clone());
+        }
+    }
+
+    fn report_arity(&mut self, name: &str, expected: usize, given: usize) {
+        let expected = match expected {
+            1 => "1 argument".to_string(),
+            n => format!("{} arguments", n),
+        };
+        self.report(format!("`{}` takes {} but was given {}", name, expected, given));
+    }
+}
+
+impl<'ast> Visitor<'ast> for TypeChecker<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.in_scope(|checker| walk_block(checker, block));
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        let previous = self.declared.take();
+        match stmt {
+            Statement::FunctionDecl { name, params, return_type, requires, ensures, body, .. } => {
+                let outer = self.function.replace(name);
+                self.in_scope(|checker| {
+                    for param in params {
+                        checker.declare(&param.name, Some(param.param_type.clone()));
+                    }
+                    for condition in requires {
+                        checker.type_of(condition);
+                    }
+                    checker.declare("result", return_type.clone());
+                    for condition in ensures {
+                        checker.type_of(condition);
+                    }
+                });
+                self.check_body(params, return_type.as_ref(), body);
+                self.function = outer;
+            }
+            Statement::VariableDecl { name, var_type, initializer, .. } => {
+                let inferred = initializer.as_ref().and_then(|init| self.type_of(init));
+                // A char literal stays a C `char`, which prints as a character
+                let is_char = matches!(initializer, Some(Expression::CharLiteral(_)));
+                if let (None, Some(ty), false) = (var_type, &inferred, is_char) {
+                    let recorded = matches!(kind(ty), Some(Kind::Scalar | Kind::String))
+                        || matches!(ty, Type::Array { size: None, .. } | Type::Named(_));
+                    if recorded {
+                        self.resolved.declarations.insert(stmt, ty.clone());
+                    }
+                }
+                let ty = var_type.clone().or(inferred);
+                if let Some(init) = initializer {
+                    self.declared = Some((name, init, ty.clone()));
+                }
+                self.declare(name, ty);
+            }
+            // `using (s = ...)` without a release expression frees `s`,
+            // which is only sound for a string known to be allocated
+            Statement::Defer { statement } => {
+                if let (Some((name, init, Some(Type::String))), Statement::Expression(Expression::Call { callee, args, .. })) =
+                    (previous, statement.as_ref())
+                {
+                    let frees_declared = matches!(callee.as_ref(), Expression::Identifier(f) if f == "free")
+                        && matches!(args.as_slice(), [Expression::Identifier(arg)] if arg == name)
+                        && !self.functions.contains_key("free");
+                    if frees_declared && !self.is_fresh_string(init) {
+                        self.report(format!(
+                            "Cannot free `{}`, which may be a string literal; only strings built in place, like concatenations, are freed automatically, so give `using` an explicit release expression",
+                            name
+                        ));
+                    }
+                }
+                walk_statement(self, stmt);
+            }
+            Statement::Return { value: Some(value) } => {
+                let ty = self.type_of(value);
+                if self.returned.is_none() {
+                    self.returned = ty.clone();
+                }
+                if let (Some(ty), Some(declared)) = (ty, self.return_type) {
+                    if kind(&ty).is_some() && kind(declared).is_some() && kind(&ty) != kind(declared) {
+                        self.report(format!("Returning {} where the return type is {}", ty, declared));
+                    }
+                }
+            }
+            Statement::For { .. } => self.in_scope(|checker| walk_statement(checker, stmt)),
+            Statement::ForIn { variable, iterable, body } => {
+                let element_type = match self.type_of(iterable) {
+                    Some(Type::Array { element_type, .. }) => Some(*element_type),
+                    _ if matches!(iterable, Expression::Range { .. }) => Some(Type::I32),
+                    _ => None,
+                };
+                self.in_scope(|checker| {
+                    checker.declare(variable, element_type);
+                    checker.visit_block(body);
+                });
+            }
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        self.type_of(expr);
+    }
+}
+
+fn binary_symbol(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Subtract => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::Modulo => "%",
+        BinaryOp::Equal => "?=",
+        BinaryOp::StrictEqual => "??=",
+        BinaryOp::NotEqual => "!=",
+        BinaryOp::StrictNotEqual => "!!=",
+        BinaryOp::Less => "<",
+        BinaryOp::LessEqual => "<=",
+        BinaryOp::Greater => ">",
+        BinaryOp::GreaterEqual => ">=",
+        BinaryOp::And => "and",
+        BinaryOp::Or => "or",
+        BinaryOp::BitwiseAnd => "&",
+        BinaryOp::BitwiseOr => "|",
+        BinaryOp::BitwiseXor => "^",
+        BinaryOp::ShiftLeft => "<<",
+        BinaryOp::ShiftRight => ">>",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn check(source: &str) -> Result<(), String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        check_types(&Parser::new(tokens).parse().unwrap()).map_err(crate::sema::messages)
+    }
+
+    #[test]
+    fn test_mismatched_return_type() {
+        assert_eq!(
+            check("function name(): i32 { return \"Ada\"; }").unwrap_err(),
+            "Returning string where the return type is i32 in function `name` at 1:24"
+        );
+        assert_eq!(
+            check("function main(): i32 { let f = function(x: i32): string { return x + 1; }; return 0; }").unwrap_err(),
+            "Returning i32 where the return type is string in function `main` at 1:59"
+        );
+        check("function half(x: i32): f64 { return x / 2.0; }").unwrap();
+    }
+
+    #[test]
+    fn test_binary_operand_mismatch() {
+        assert_eq!(
+            check("function main(): i32 { let s: string = \"a\"; let n: i32 = s * 2; return n; }").unwrap_err(),
+            "Cannot apply `*` to string and i32 in function `main` at 1:45"
+        );
+        let err = check("function main(): i32 { let xs: [i32] = [1]; if (xs < 2) { return 1; } return 0; }").unwrap_err();
+        assert!(err.starts_with("Cannot apply `<` to [i32] and i32"), "{}", err);
+
+        check("function main(): i32 { let s: string = \"a\" + \"b\"; let b: bool = 1 < 2 and true; return s.length + 1; }")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_conditional_branch_types() {
+        assert_eq!(
+            check("function main(): i32 { let n: i32 = 1; let x = n > 0 ? \"yes\" : n; return 0; }").unwrap_err(),
+            "Conditional branches have different types: string and i32 in function `main` at 1:40"
+        );
+        // Numbers mix, and the result takes part in later checks
+        check("function main(): i32 { let n: i32 = 1; let x = n > 0 ? 2.5 : n; return n; }").unwrap();
+        assert!(check("function main(): i32 { let n: i32 = 1; return n > 0 ? \"a\" : \"b\"; }").is_err());
+    }
+
+    #[test]
+    fn test_casts() {
+        assert_eq!(
+            check("function main(): i32 { let s: string = \"7\"; return s as i32; }").unwrap_err(),
+            "Cannot cast string to i32 in function `main` at 1:45"
+        );
+        assert!(check("function main(): i32 { let xs: [i32] = [1]; let f: f64 = xs as f64; return 0; }").is_err());
+        assert!(check("function main(): i32 { let n: i32 = 1; let s: string = n as string; return 0; }").is_err());
+
+        check("function main(): i32 { let y: i32 = 7; let x: f64 = (y as f64) / 2.0; let n: i64 = y as i64; let b: bool = y as bool; return x as i32; }")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_call_arity() {
+        assert_eq!(
+            check("function add(a: i32, b: i32): i32 { return a + b; } function main(): i32 { return add(1); }")
+                .unwrap_err(),
+            "`add` takes 2 arguments but was given 1 in function `main` at 1:76"
+        );
+        // A local shadows the function, and bare `function` values take anything
+        check(
+            "function add(a: i32, b: i32): i32 { return a + b; }
+            function main(): i32 {
+                let f: function = function(x: i32, y: i32): i32 { return x; };
+                return f(1, 2) + add(1, 2);
+            }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_reductions_need_numbers() {
+        assert_eq!(
+            check("function main(names: [string]): i32 { println(names.sum()); return 0; }").unwrap_err(),
+            "`sum` needs an array of numbers, not [string] in function `main` at 1:39"
+        );
+        assert!(check("function main(flags: [bool]): f64 { return flags.avg(); }").is_err());
+        check("function main(xs: [f64], ns: [u8]): f64 { return xs.sum() + xs.avg() + ns.max(); }").unwrap();
+    }
+
+    #[test]
+    fn test_using_frees_only_heap_resources() {
+        assert_eq!(
+            check("function main(): i32 { using (n = 5) { println(n); } return 0; }").unwrap_err(),
+            "`free` releases strings and growable arrays, not i32; give `using` an explicit release expression in function `main` at 1:24"
+        );
+        assert!(check("function main(): i32 { let xs: [i32; 2] = [1, 2]; free(xs); return 0; }").is_err());
+
+        // A string literal isn't on the heap, nor is what a function
+        // returning one gives back
+        assert_eq!(
+            check("function main(): i32 { using (s = \"abc\") { println(s); } return 0; }").unwrap_err(),
+            "Cannot free `s`, which may be a string literal; only strings built in place, like concatenations, are freed automatically, so give `using` an explicit release expression in function `main` at 1:24"
+        );
+        assert!(check(
+            "function name(): string { return \"abc\"; }
+            function main(): i32 { using (s = name()) { println(s); } return 0; }"
+        )
+        .is_err());
+
+        check(
+            "function close(fd: i32) {}
+            function main(): i32 {
+                using (fd = 3; close(fd)) { println(fd); }
+                using (xs: [i32] = [1, 2]) { println(xs.length); }
+                using (s = \"abc\".toUpperCase()) { println(s); }
+                using (s = \"ab\" + \"c\") { println(s); }
+                using (s = \"abc\"; println(s)) { println(s); }
+                return 0;
+            }",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_record_types_of_untyped_lets() {
+        let source = "function main(): i32 {
+            let n = 7;
+            let half = n / 2.0;
+            let xs: [i32] = [1];
+            let first = xs[0];
+            let f = function(): string { let s = \"a\" + \"b\"; return s; };
+            let c = 'c';
+            let parts = \"a,b\".split(\",\");
+            return n;
+        }";
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        let mut program = Parser::new(tokens).parse().unwrap();
+        record_types(&mut program);
+
+        let Statement::FunctionDecl { body, .. } = &program.statements[0] else { panic!() };
+        let types: Vec<Option<&Type>> = body
+            .statements
+            .iter()
+            .filter_map(|stmt| match stmt {
+                Statement::VariableDecl { var_type, .. } => Some(var_type.as_ref()),
+                _ => None,
+            })
+            .collect();
+        // A closure keeps its function type for codegen to infer, and a
+        // char literal stays a C char
+        assert_eq!(
+            types,
+            [
+                Some(&Type::I32),
+                Some(&Type::F64),
+                Some(&Type::Array { element_type: Box::new(Type::I32), size: None }),
+                Some(&Type::I32),
+                None,
+                None,
+                Some(&Type::Array { element_type: Box::new(Type::String), size: None })
+            ]
+        );
+
+        let Statement::VariableDecl { initializer: Some(Expression::FunctionExpression { body, .. }), .. } = &body.statements[4] else {
+            panic!()
+        };
+        assert!(matches!(&body.statements[0], Statement::VariableDecl { var_type: Some(Type::String), .. }));
+    }
+
+    #[test]
+    fn test_record_types_of_calls() {
+        // The type recorded on the call the last function returns
+        let call_type = |source: &str| -> Option<Type> {
+            let tokens = Lexer::new(source).tokenize().unwrap();
+            let mut program = Parser::new(tokens).parse().unwrap();
+            record_types(&mut program);
+            let Some(Statement::FunctionDecl { body, .. }) = program.statements.last() else { panic!() };
+            let [Statement::Return { value: Some(call) }] = body.statements.as_slice() else { panic!() };
+            resolved_call_type(call)
+        };
+        let dynamic_array = |element_type: Type| Some(Type::Array { element_type: Box::new(element_type), size: None });
+
+        assert_eq!(call_type("function f(): [string] { return args(); }"), dynamic_array(Type::String));
+        assert_eq!(call_type("function f(): string { return env(\"HOME\"); }"), Some(Type::String));
+        assert_eq!(call_type("function f(xs: [f64]): f64 { return xs.sum(); }"), Some(Type::F64));
+        assert_eq!(call_type("function f(xs: [i32]): f64 { return xs.avg(); }"), Some(Type::F64));
+        assert_eq!(call_type("function f(xs: [string]): [string] { return xs.unique(); }"), dynamic_array(Type::String));
+        assert_eq!(call_type("function f(s: string): [string] { return s.split(\",\"); }"), dynamic_array(Type::String));
+        assert_eq!(call_type("function f(xs: [i32]) { return xs.push(1); }"), None);
+
+        // A struct method returns its declared type, and `map` makes an
+        // array of what the callback returns
+        assert_eq!(
+            call_type("struct P { x: f64, function scaled(by: f64): f64 { return self.x * by; } } function f(p: P): f64 { return p.scaled(2.0); }"),
+            Some(Type::F64)
+        );
+        assert_eq!(
+            call_type("function f(names: [string]): [i32] { return names.map((n: string) => n.length); }"),
+            dynamic_array(Type::I32)
+        );
+    }
+
+    fn resolved_call_type(expr: &Expression) -> Option<Type> {
+        match expr {
+            Expression::Call { resolved_type, .. } | Expression::MethodCall { resolved_type, .. } => resolved_type.clone(),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Context rules for control flow: where `break`, `continue` and `return`
+//! may appear, and what a `defer` may contain.
+//!
+//! `fallthrough` is checked by the parser, which only accepts it at the end
+//! of a switch case that has a case after it.
+
+use super::Diagnostics;
+use crate::ast::*;
+use crate::error::{CompileError, Span};
+use std::rc::Rc;
+
+impl Program {
+    /// Check every control-flow context rule, collecting all violations:
+    ///
+    /// - `break` only inside a loop or switch, `continue` only inside a loop
+    /// - a deferred statement cannot `return`, or `break`/`continue` out of
+    ///   the defer
+    /// - a function with a return type returns a value on every `return`,
+    ///   and one without returns none
+    pub fn validate(&self) -> Result<(), Vec<CompileError>> {
+        let mut validator = Validator {
+            function: None,
+            returns_value: None,
+            loops: 0,
+            switches: 0,
+            in_defer: false,
+            diagnostics: Diagnostics::new(self),
+        };
+        validator.visit_program(self);
+        validator.diagnostics.into_result()
+    }
+}
+
+struct Validator<'ast> {
+    function: Option<&'ast str>,
+    // Whether each `return` in the current function must carry a value;
+    // `None` in closures without a declared return type, which take theirs
+    // from the body
+    returns_value: Option<bool>,
+    // Loops and switches enclosing the current statement, counted from the
+    // innermost function or defer
+    loops: usize,
+    switches: usize,
+    in_defer: bool,
+    diagnostics: Diagnostics<'ast>,
+}
+
+impl<'ast> Validator<'ast> {
+    fn report(&mut self, mut message: String) {
+        if let Some(function) = self.function {
+            message.push_str(&format!(" in function `{}`", function));
+        }
+        self.diagnostics.report(message);
+    }
+
+    fn check_jump(&mut self, keyword: &str, allowed: bool) {
+        if allowed {
+            return;
+        }
+        let message = if self.in_defer {
+            format!("`{}` cannot leave a `defer`", keyword)
+        } else if keyword == "break" {
+            "`break` outside a loop or switch".to_string()
+        } else {
+            "`continue` outside a loop".to_string()
+        };
+        self.report(message);
+    }
+
+    // Check a function or closure body in a fresh control-flow context
+    fn visit_body(&mut self, body: &'ast Block, returns_value: Option<bool>) {
+        let outer = (self.returns_value, self.loops, self.switches, self.in_defer);
+        self.returns_value = returns_value;
+        self.loops = 0;
+        self.switches = 0;
+        self.in_defer = false;
+        self.visit_block(body);
+        (self.returns_value, self.loops, self.switches, self.in_defer) = outer;
+    }
+}
+
+impl<'ast> Visitor<'ast> for Validator<'ast> {
+    fn visit_position(&mut self, source: Option<&'ast Rc<str>>, span: Span) {
+        self.diagnostics.enter(source, span);
+    }
+
+    fn visit_statement(&mut self, stmt: &'ast Statement) {
+        match stmt {
+            Statement::FunctionDecl { name, return_type, requires, ensures, body, .. } => {
+                let outer = self.function.replace(name);
+                for condition in requires.iter().chain(ensures) {
+                    self.visit_expression(condition);
+                }
+                let returns_value = !matches!(return_type, None | Some(Type::Nothing));
+                self.visit_body(body, Some(returns_value));
+                self.function = outer;
+            }
+            Statement::Break => self.check_jump("break", self.loops + self.switches > 0),
+            Statement::Continue => self.check_jump("continue", self.loops > 0),
+            Statement::Return { value } => {
+                if self.in_defer {
+                    self.report("`return` is not allowed inside `defer`".to_string());
+                }
+                match (self.returns_value, value) {
+                    (Some(true), None) => self.report("`return` without a value in a function that returns one".to_string()),
+                    (Some(false), Some(_)) => {
+                        self.report("`return` with a value in a function that returns nothing".to_string())
+                    }
+                    _ => {}
+                }
+                walk_statement(self, stmt);
+            }
+            Statement::While { .. } | Statement::For { .. } | Statement::ForIn { .. } => {
+                self.loops += 1;
+                walk_statement(self, stmt);
+                self.loops -= 1;
+            }
+            Statement::Switch { .. } => {
+                self.switches += 1;
+                walk_statement(self, stmt);
+                self.switches -= 1;
+            }
+            Statement::Defer { statement } => {
+                let outer = (self.loops, self.switches, self.in_defer);
+                self.loops = 0;
+                self.switches = 0;
+                self.in_defer = true;
+                self.visit_statement(statement);
+                (self.loops, self.switches, self.in_defer) = outer;
+            }
+            _ => walk_statement(self, stmt),
+        }
+    }
+
+    fn visit_expression(&mut self, expr: &'ast Expression) {
+        if let Expression::FunctionExpression { return_type, body, .. } = expr {
+            let returns_value = return_type.as_ref().map(|ty| *ty != Type::Nothing);
+            self.visit_body(body, returns_value);
+        } else {
+            walk_expression(self, expr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn validate(source: &str) -> Result<(), String> {
+        let tokens = Lexer::new(source).tokenize().unwrap();
+        Parser::new(tokens).parse().unwrap().validate().map_err(crate::sema::messages)
+    }
+
+    #[test]
+    fn test_break_and_continue_need_a_loop() {
+        assert_eq!(
+            validate("function main(): i32 { break; return 0; }").unwrap_err(),
+            "`break` outside a loop or switch in function `main` at 1:24"
+        );
+        assert_eq!(
+            validate("function main(): i32 { switch (1) { case 1: continue; } return 0; }").unwrap_err(),
+            "`continue` outside a loop in function `main` at 1:45"
+        );
+        // A closure body is outside the loop it is written in
+        let err = validate("function main(): i32 { while (true) { let f = function(): i32 { break; return 1; }; } return 0; }")
+            .unwrap_err();
+        assert_eq!(err, "`break` outside a loop or switch in function `main` at 1:65");
+    }
+
+    #[test]
+    fn test_defer_cannot_jump() {
+        assert_eq!(
+            validate("function main(): i32 { defer return 1; return 0; }").unwrap_err(),
+            "`return` is not allowed inside `defer` in function `main` at 1:24"
+        );
+        assert_eq!(
+            validate("function main(): i32 { while (true) { defer { break; } } return 0; }").unwrap_err(),
+            "`break` cannot leave a `defer` in function `main` at 1:47"
+        );
+    }
+
+    #[test]
+    fn test_return_matches_function() {
+        assert_eq!(
+            validate("function f(): i32 { return; }").unwrap_err(),
+            "`return` without a value in a function that returns one in function `f` at 1:21"
+        );
+        assert_eq!(
+            validate("function g() { return 1; }").unwrap_err(),
+            "`return` with a value in a function that returns nothing in function `g` at 1:16"
+        );
+    }
+
+    #[test]
+    fn test_violations_are_collected() {
+        let err = validate("function f(): i32 { continue; return; }").unwrap_err();
+        assert_eq!(err.lines().count(), 2, "{}", err);
+    }
+
+    #[test]
+    fn test_valid_program_passes() {
+        validate(
+            "function g() { return; }
+            function main(): i32 {
+                defer { for (let i: i32 = 0; i < 3; i = i + 1) { if (i > 1) { break; } continue; } }
+                switch (2) { case 1: break; default: g(); }
+                while (true) { break; }
+                let f = function(x: i32) { return x * 2; };
+                return 0;
+            }",
+        )
+        .unwrap();
+    }
+}